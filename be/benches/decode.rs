@@ -0,0 +1,137 @@
+use base64::Engine;
+use be::feed::{
+    decode_bus_data, parse_bus_positions, parse_bus_positions_from_payload, DecodeContext,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rust_socketio::Payload;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Counts heap allocations so the buffer-reuse bench can report them next to the timings.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations_per_call(calls: usize, mut f: impl FnMut()) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..calls {
+        f();
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) / calls
+}
+
+// A single route sends tens of vehicles per frame; the all-buses feed sends a few thousand.
+const FLEET_SIZES: [usize; 3] = [50, 500, 3_000];
+
+fn encoded_fleet(vehicles: usize) -> String {
+    let fleet: Vec<serde_json::Value> = (0..vehicles)
+        .map(|index| {
+            serde_json::json!({
+                "dt_received": "2024-05-01 08:15:02",
+                "dt_gps": "2024-05-01 08:15:00",
+                "latitude": 3.1478 + index as f64 * 0.0001,
+                "longitude": 101.6953 - index as f64 * 0.0001,
+                "dir": "0",
+                "speed": (index % 60) as f64,
+                "angle": (index % 360) as f64,
+                "route": format!("{}", 300 + index % 40),
+                "bus_no": format!("WXX{:04}", index),
+                "trip_no": null,
+                "captain_id": null,
+                "trip_rev_kind": null,
+                "engine_status": 1,
+                "accessibility": 1,
+                "busstop_id": null,
+                "provider": "RKL",
+            })
+        })
+        .collect();
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(serde_json::to_string(&fleet).unwrap().as_bytes())
+        .unwrap();
+    base64::engine::general_purpose::STANDARD.encode(encoder.finish().unwrap())
+}
+
+fn decode_bus_data_by_fleet_size(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_bus_data");
+    for vehicles in FLEET_SIZES {
+        let encoded = encoded_fleet(vehicles);
+        group.throughput(Throughput::Bytes(encoded.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(vehicles),
+            &encoded,
+            |b, encoded| b.iter(|| decode_bus_data(black_box(encoded))),
+        );
+    }
+    group.finish();
+}
+
+// Multi-route subscriptions deliver several values in one Text payload.
+fn parse_text_batch(c: &mut Criterion) {
+    let values: Vec<serde_json::Value> = (0..8)
+        .map(|_| serde_json::Value::String(encoded_fleet(500)))
+        .collect();
+    c.bench_function("parse_bus_positions_from_payload/8x500", |b| {
+        b.iter(|| parse_bus_positions_from_payload(black_box(Payload::Text(values.clone()))))
+    });
+}
+
+// A fresh decode per payload against a DecodeContext kept across payloads, as the pipeline
+// task does. Most of what's left after reuse is the BusPosition values themselves.
+fn reused_decode_buffers(c: &mut Criterion) {
+    let payload = Payload::Text(vec![serde_json::Value::String(encoded_fleet(500))]);
+    let mut context = DecodeContext::default();
+    context.parse(payload.clone(), false);
+
+    let fresh = allocations_per_call(50, || {
+        black_box(parse_bus_positions(payload.clone(), false));
+    });
+    let reused = allocations_per_call(50, || {
+        black_box(context.parse(payload.clone(), false));
+    });
+    eprintln!(
+        "allocations per 500-vehicle payload: fresh {}, reused {}",
+        fresh, reused
+    );
+
+    let mut group = c.benchmark_group("decode_context/500");
+    group.bench_function("fresh", |b| {
+        b.iter(|| parse_bus_positions(black_box(payload.clone()), false))
+    });
+    group.bench_function("reused", |b| {
+        b.iter(|| context.parse(black_box(payload.clone()), false))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    decode_bus_data_by_fleet_size,
+    parse_text_batch,
+    reused_decode_buffers
+);
+criterion_main!(benches);
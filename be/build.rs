@@ -0,0 +1,11 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    // Only the grpc feature needs generated code (and protoc on the PATH).
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/rapidbro.proto");
+        if let Err(error) = tonic_prost_build::compile_protos("proto/rapidbro.proto") {
+            panic!("failed to compile proto/rapidbro.proto: {}", error);
+        }
+    }
+}
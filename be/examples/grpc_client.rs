@@ -0,0 +1,43 @@
+// Prints live updates from a server built with `--features grpc` and started with GRPC_BIND.
+//
+//   cargo run --example grpc_client --features grpc -- http://127.0.0.1:50051 T789 T790
+//
+// With no routes it follows every route.
+use be::grpc::proto::bus_feed_client::BusFeedClient;
+use be::grpc::proto::{SnapshotRequest, SubscribeRequest};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let endpoint = args
+        .next()
+        .unwrap_or_else(|| "http://127.0.0.1:50051".to_string());
+    let routes: Vec<String> = args.collect();
+
+    let mut client = BusFeedClient::connect(endpoint).await?;
+    let snapshot = client
+        .get_snapshot(SnapshotRequest {
+            routes: routes.clone(),
+        })
+        .await?
+        .into_inner();
+    println!("{} buses in the snapshot", snapshot.buses.len());
+
+    let mut updates = client
+        .subscribe(SubscribeRequest { routes })
+        .await?
+        .into_inner();
+    while let Some(update) = updates.message().await? {
+        println!(
+            "{:<8} {:<10} {:>9.5},{:>10.5} {:>5.1} km/h {}",
+            update.route,
+            update.bus_no,
+            update.latitude,
+            update.longitude,
+            update.speed,
+            update.dt_gps.as_deref().unwrap_or("-")
+        );
+    }
+    println!("Stream ended");
+    Ok(())
+}
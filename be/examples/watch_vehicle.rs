@@ -0,0 +1,47 @@
+// Follows one vehicle on one route straight from the Rapid KL socket.
+//
+//   cargo run --example watch_vehicle -- T789 WXX1234
+use be::client::RapidbroClient;
+use be::updates::BusUpdate;
+use futures_util::StreamExt;
+
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let route = args.next().unwrap_or_else(|| "T789".to_string());
+    let Some(vehicle) = args.next() else {
+        eprintln!("usage: watch_vehicle <route> <bus_no>");
+        std::process::exit(2);
+    };
+
+    let client = match RapidbroClient::builder().route(route).build() {
+        Ok(client) => client,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }
+    };
+    let runner = client.clone();
+    tokio::spawn(async move { runner.run().await });
+
+    let mut updates = client.updates().filter(move |update| {
+        let wanted = match update {
+            BusUpdate::Position(bus) => bus.bus_no == vehicle,
+            BusUpdate::Gap { .. } => true,
+        };
+        std::future::ready(wanted)
+    });
+    while let Some(update) = updates.next().await {
+        match update {
+            BusUpdate::Position(bus) => println!(
+                "{} {} at {:.5},{:.5} {:.0} km/h",
+                bus.dt_gps.as_deref().unwrap_or("-"),
+                bus.bus_no,
+                bus.latitude,
+                bus.longitude,
+                bus.speed
+            ),
+            BusUpdate::Gap { missed } => println!("fell behind; missed {} updates", missed),
+        }
+    }
+}
@@ -0,0 +1,426 @@
+use crate::feed::BusPosition;
+use crate::queue::{BoundedQueue, OverflowPolicy};
+use crate::timestamp::parse_feed_timestamp;
+use arrow_array::{
+    Array, ArrayRef, BooleanArray, Float64Array, RecordBatch, StringArray,
+    TimestampMillisecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use chrono::{DateTime, Datelike, Timelike};
+use metrics::counter;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+const MS_PER_HOUR: i64 = 3_600_000;
+pub const DEFAULT_QUEUE_CAPACITY: usize = 10_000;
+// A busy hour is split across files rather than held in memory whole.
+pub const DEFAULT_BUFFER_RECORDS: usize = 200_000;
+pub const ARCHIVE_RECORDS_DROPPED_TOTAL: &str = "rapidbro_archive_records_dropped_total";
+
+#[derive(Debug, Clone)]
+pub struct ArchiveRecord {
+    pub provider: String,
+    pub route: String,
+    pub vehicle_id: String,
+    pub lat: f64,
+    pub lon: f64,
+    pub speed: f64,
+    pub bearing: f64,
+    pub gps_ts: Option<i64>,
+    pub recv_ts: i64,
+    pub off_route: bool,
+}
+
+impl ArchiveRecord {
+    pub fn from_bus(bus: &BusPosition, received_at_unix_ms: i64) -> Self {
+        Self {
+            provider: bus.provider.clone(),
+            route: bus.route.clone(),
+            vehicle_id: bus.bus_no.clone(),
+            lat: bus.latitude,
+            lon: bus.longitude,
+            speed: bus.speed,
+            bearing: bus.angle,
+            gps_ts: bus
+                .dt_gps
+                .as_deref()
+                .and_then(parse_feed_timestamp)
+                .map(|timestamp| timestamp.timestamp_millis()),
+            recv_ts: received_at_unix_ms,
+            off_route: bus.off_route,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveSummary {
+    pub rows: usize,
+    // Receive-time range in unix ms; None for an empty file.
+    pub first_recv_ts: Option<i64>,
+    pub last_recv_ts: Option<i64>,
+}
+
+// Buffers records for the current receive hour. A record from a later hour hands back
+// the finished hour for writing, so each file covers at most one hour; so does reaching
+// `max_records`, which splits the hour over several files.
+#[derive(Debug)]
+pub struct HourlyBuffer {
+    records: Vec<ArchiveRecord>,
+    hour: Option<i64>,
+    max_records: usize,
+}
+
+impl Default for HourlyBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUFFER_RECORDS)
+    }
+}
+
+impl HourlyBuffer {
+    pub fn new(max_records: usize) -> Self {
+        Self {
+            records: Vec::new(),
+            hour: None,
+            max_records: max_records.max(1),
+        }
+    }
+
+    pub fn push(&mut self, record: ArchiveRecord) -> Option<Vec<ArchiveRecord>> {
+        let hour = record.recv_ts.div_euclid(MS_PER_HOUR);
+        let finished = match self.hour {
+            Some(current) if current != hour || self.records.len() >= self.max_records => {
+                Some(self.take())
+            }
+            _ => None,
+        };
+        self.hour = Some(hour);
+        self.records.push(record);
+        finished.filter(|records| !records.is_empty())
+    }
+
+    // Empties the buffer, e.g. to flush a partial hour at shutdown.
+    pub fn take(&mut self) -> Vec<ArchiveRecord> {
+        self.hour = None;
+        std::mem::take(&mut self.records)
+    }
+}
+
+// Hands records to a background task that writes one Parquet file per receive hour.
+// At shutdown, `stop` the task and wait for `run` to return before `flush` writes
+// whatever is still queued or buffered, so the two never write the same hour.
+#[derive(Debug, Clone)]
+pub struct ArchiveSink {
+    root: PathBuf,
+    queue: BoundedQueue<ArchiveRecord>,
+    buffer: Arc<Mutex<HourlyBuffer>>,
+    stop: CancellationToken,
+}
+
+impl ArchiveSink {
+    pub fn new(root: impl Into<PathBuf>, overflow_policy: OverflowPolicy) -> Self {
+        Self {
+            root: root.into(),
+            queue: BoundedQueue::new(DEFAULT_QUEUE_CAPACITY, overflow_policy, Duration::ZERO)
+                .named("archive"),
+            buffer: Arc::new(Mutex::new(HourlyBuffer::default())),
+            stop: CancellationToken::new(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub async fn enqueue(&self, bus: &BusPosition, received_at_unix_ms: i64) {
+        let accepted = self
+            .queue
+            .push(ArchiveRecord::from_bus(bus, received_at_unix_ms))
+            .await;
+        if !accepted {
+            counter!(ARCHIVE_RECORDS_DROPPED_TOTAL, "reason" => "queue_full").increment(1);
+        }
+    }
+
+    // Returns once `stop` is called, after finishing any file it was writing.
+    pub async fn run(&self) {
+        loop {
+            let record = tokio::select! {
+                record = self.queue.pop() => record,
+                _ = self.stop.cancelled() => return,
+            };
+            let finished = self.lock_buffer().push(record);
+            if let Some(records) = finished {
+                self.write(records).await;
+            }
+        }
+    }
+
+    pub fn stop(&self) {
+        self.stop.cancel();
+    }
+
+    pub async fn flush(&self) {
+        let mut batches = Vec::new();
+        {
+            let mut buffer = self.lock_buffer();
+            for record in self.queue.drain(usize::MAX) {
+                batches.extend(buffer.push(record));
+            }
+            batches.push(buffer.take());
+        }
+        for records in batches.into_iter().filter(|records| !records.is_empty()) {
+            self.write(records).await;
+        }
+    }
+
+    fn lock_buffer(&self) -> std::sync::MutexGuard<'_, HourlyBuffer> {
+        self.buffer
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+
+    async fn write(&self, records: Vec<ArchiveRecord>) {
+        let root = self.root.clone();
+        let rows = records.len();
+        match tokio::task::spawn_blocking(move || write_parquet(&root, &records)).await {
+            Ok(Ok(path)) => println!("Archived {} rows to {}", rows, path.display()),
+            Ok(Err(error)) => {
+                println!("Failed to archive {} rows: {}", rows, error);
+                counter!(ARCHIVE_RECORDS_DROPPED_TOTAL, "reason" => "write_failed")
+                    .increment(rows as u64);
+            }
+            Err(error) => {
+                println!("Archive writer task failed: {}", error);
+                counter!(ARCHIVE_RECORDS_DROPPED_TOTAL, "reason" => "write_failed")
+                    .increment(rows as u64);
+            }
+        }
+    }
+}
+
+pub fn archive_schema() -> Schema {
+    let timestamp = DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()));
+    Schema::new(vec![
+        Field::new("provider", DataType::Utf8, false),
+        Field::new("route", DataType::Utf8, false),
+        Field::new("vehicle_id", DataType::Utf8, false),
+        Field::new("lat", DataType::Float64, false),
+        Field::new("lon", DataType::Float64, false),
+        Field::new("speed", DataType::Float64, false),
+        Field::new("bearing", DataType::Float64, false),
+        Field::new("gps_ts", timestamp.clone(), true),
+        Field::new("recv_ts", timestamp, false),
+        Field::new("off_route", DataType::Boolean, false),
+    ])
+}
+
+// Writes records to root/year=YYYY/month=MM/day=DD/HH-<first recv ms>.parquet through a
+// temporary file, so readers only ever see complete files. Partitions use the first
+// record's receive time (UTC).
+pub fn write_parquet(root: &Path, records: &[ArchiveRecord]) -> Result<PathBuf, String> {
+    let first = records
+        .first()
+        .ok_or_else(|| "nothing to archive".to_string())?;
+    let started = DateTime::from_timestamp_millis(first.recv_ts)
+        .ok_or_else(|| format!("invalid receive time {}", first.recv_ts))?;
+    let directory = root
+        .join(format!("year={:04}", started.year()))
+        .join(format!("month={:02}", started.month()))
+        .join(format!("day={:02}", started.day()));
+    std::fs::create_dir_all(&directory)
+        .map_err(|error| format!("failed to create '{}': {}", directory.display(), error))?;
+    let path = directory.join(format!("{:02}-{}.parquet", started.hour(), first.recv_ts));
+    let temp_path = path.with_extension("parquet.tmp");
+
+    let schema = Arc::new(archive_schema());
+    let strings = |value: fn(&ArchiveRecord) -> &str| -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(records.iter().map(value)))
+    };
+    let floats = |value: fn(&ArchiveRecord) -> f64| -> ArrayRef {
+        Arc::new(Float64Array::from_iter_values(records.iter().map(value)))
+    };
+    let columns: Vec<ArrayRef> = vec![
+        strings(|record| &record.provider),
+        strings(|record| &record.route),
+        strings(|record| &record.vehicle_id),
+        floats(|record| record.lat),
+        floats(|record| record.lon),
+        floats(|record| record.speed),
+        floats(|record| record.bearing),
+        Arc::new(
+            TimestampMillisecondArray::from(
+                records
+                    .iter()
+                    .map(|record| record.gps_ts)
+                    .collect::<Vec<_>>(),
+            )
+            .with_timezone("UTC"),
+        ),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(
+                records.iter().map(|record| record.recv_ts),
+            )
+            .with_timezone("UTC"),
+        ),
+        Arc::new(BooleanArray::from(
+            records
+                .iter()
+                .map(|record| record.off_route)
+                .collect::<Vec<_>>(),
+        )),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns)
+        .map_err(|error| format!("failed to build record batch: {}", error))?;
+
+    let file = File::create(&temp_path)
+        .map_err(|error| format!("failed to create '{}': {}", temp_path.display(), error))?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(file, schema, Some(properties))
+        .map_err(|error| format!("failed to start parquet writer: {}", error))?;
+    writer
+        .write(&batch)
+        .and_then(|_| writer.close().map(|_| ()))
+        .map_err(|error| format!("failed to write '{}': {}", temp_path.display(), error))?;
+
+    std::fs::rename(&temp_path, &path)
+        .map_err(|error| format!("failed to replace '{}': {}", path.display(), error))?;
+    Ok(path)
+}
+
+pub fn read_summary(path: &Path) -> Result<ArchiveSummary, String> {
+    let file = File::open(path)
+        .map_err(|error| format!("failed to open '{}': {}", path.display(), error))?;
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+        .and_then(|builder| builder.build())
+        .map_err(|error| format!("failed to read '{}': {}", path.display(), error))?;
+
+    let mut summary = ArchiveSummary {
+        rows: 0,
+        first_recv_ts: None,
+        last_recv_ts: None,
+    };
+    for batch in reader {
+        let batch = batch.map_err(|error| format!("failed to read batch: {}", error))?;
+        summary.rows += batch.num_rows();
+        let Some(recv_ts) = batch
+            .column_by_name("recv_ts")
+            .and_then(|column| column.as_any().downcast_ref::<TimestampMillisecondArray>())
+        else {
+            return Err("recv_ts column missing or not a millisecond timestamp".to_string());
+        };
+        for value in recv_ts.iter().flatten() {
+            summary.first_recv_ts = Some(
+                summary
+                    .first_recv_ts
+                    .map_or(value, |first| first.min(value)),
+            );
+            summary.last_recv_ts = Some(summary.last_recv_ts.map_or(value, |last| last.max(value)));
+        }
+    }
+    Ok(summary)
+}
+
+// Every finished archive file under root, in path order (which is time order).
+pub fn archive_files(root: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(directory) = pending.pop() {
+        let entries = std::fs::read_dir(&directory)
+            .map_err(|error| format!("failed to list '{}': {}", directory.display(), error))?;
+        for entry in entries {
+            let path = entry
+                .map_err(|error| format!("failed to list '{}': {}", directory.display(), error))?
+                .path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path
+                .extension()
+                .is_some_and(|extension| extension == "parquet")
+            {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Files written before the off_route column existed read back as on-route.
+pub fn read_records(path: &Path) -> Result<Vec<ArchiveRecord>, String> {
+    let file = File::open(path)
+        .map_err(|error| format!("failed to open '{}': {}", path.display(), error))?;
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+        .and_then(|builder| builder.build())
+        .map_err(|error| format!("failed to read '{}': {}", path.display(), error))?;
+
+    let mut records = Vec::new();
+    for batch in reader {
+        let batch = batch.map_err(|error| format!("failed to read batch: {}", error))?;
+        let column = |name: &str| {
+            batch
+                .column_by_name(name)
+                .ok_or_else(|| format!("{} column missing in '{}'", name, path.display()))
+        };
+        let strings = |name: &str| {
+            column(name)?
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .cloned()
+                .ok_or_else(|| format!("{} column is not a string", name))
+        };
+        let floats = |name: &str| {
+            column(name)?
+                .as_any()
+                .downcast_ref::<Float64Array>()
+                .cloned()
+                .ok_or_else(|| format!("{} column is not a float", name))
+        };
+        let timestamps = |name: &str| {
+            column(name)?
+                .as_any()
+                .downcast_ref::<TimestampMillisecondArray>()
+                .cloned()
+                .ok_or_else(|| format!("{} column is not a millisecond timestamp", name))
+        };
+        let (provider, route, vehicle_id) = (
+            strings("provider")?,
+            strings("route")?,
+            strings("vehicle_id")?,
+        );
+        let (lat, lon, speed, bearing) = (
+            floats("lat")?,
+            floats("lon")?,
+            floats("speed")?,
+            floats("bearing")?,
+        );
+        let (gps_ts, recv_ts) = (timestamps("gps_ts")?, timestamps("recv_ts")?);
+        let off_route = batch
+            .column_by_name("off_route")
+            .and_then(|column| column.as_any().downcast_ref::<BooleanArray>());
+
+        for row in 0..batch.num_rows() {
+            records.push(ArchiveRecord {
+                provider: provider.value(row).to_string(),
+                route: route.value(row).to_string(),
+                vehicle_id: vehicle_id.value(row).to_string(),
+                lat: lat.value(row),
+                lon: lon.value(row),
+                speed: speed.value(row),
+                bearing: bearing.value(row),
+                gps_ts: (!gps_ts.is_null(row)).then(|| gps_ts.value(row)),
+                recv_ts: recv_ts.value(row),
+                off_route: off_route.is_some_and(|column| column.value(row)),
+            });
+        }
+    }
+    Ok(records)
+}
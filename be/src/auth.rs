@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+// Browsers can't set headers on an EventSource, so the key is also accepted as
+// ?api_key=...; that puts it in access logs, so prefer the header where possible.
+pub const API_KEY_QUERY: &str = "api_key";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiKeyCheck {
+    Allowed,
+    // No key presented: 401, the client should authenticate.
+    Missing,
+    // A key that isn't configured: 403, authenticating again won't help.
+    Rejected,
+}
+
+// The keys a request may present in X-Api-Key. With none configured every request is
+// allowed, which keeps a local or development server open by default.
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeys {
+    keys: Arc<HashSet<String>>,
+}
+
+impl ApiKeys {
+    pub fn new(keys: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            keys: Arc::new(
+                keys.into_iter()
+                    .map(|key| key.trim().to_string())
+                    .filter(|key| !key.is_empty())
+                    .collect(),
+            ),
+        }
+    }
+
+    // Comma-separated, as in API_KEYS.
+    pub fn parse(keys: &str) -> Self {
+        Self::new(keys.split(',').map(str::to_string))
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn check(&self, presented: Option<&str>) -> ApiKeyCheck {
+        if self.is_empty() {
+            return ApiKeyCheck::Allowed;
+        }
+        let Some(presented) = presented.map(str::trim).filter(|key| !key.is_empty()) else {
+            return ApiKeyCheck::Missing;
+        };
+        // Every key is compared in full so the time taken doesn't hint at a prefix match.
+        let matched = self.keys.iter().fold(false, |matched, key| {
+            matched | constant_time_eq(key, presented)
+        });
+        if matched {
+            ApiKeyCheck::Allowed
+        } else {
+            ApiKeyCheck::Rejected
+        }
+    }
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+// Origins the browser may call the API from. `*` allows any origin and is meant for
+// development; otherwise origins are compared exactly, e.g. `https://bus.example.com`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AllowedOrigins {
+    #[default]
+    Any,
+    List(Vec<String>),
+}
+
+impl AllowedOrigins {
+    pub fn new(origins: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut list = Vec::new();
+        for origin in origins {
+            let origin = origin.trim().trim_end_matches('/');
+            if origin == "*" {
+                return Ok(AllowedOrigins::Any);
+            }
+            if origin.is_empty() {
+                continue;
+            }
+            if !(origin.starts_with("http://") || origin.starts_with("https://")) {
+                return Err(format!(
+                    "CORS origin `{}` must start with http:// or https://",
+                    origin
+                ));
+            }
+            list.push(origin.to_string());
+        }
+        if list.is_empty() {
+            return Err("no CORS origins given; use `*` to allow any".to_string());
+        }
+        Ok(AllowedOrigins::List(list))
+    }
+
+    // Comma-separated, as in CORS_ORIGINS.
+    pub fn parse(origins: &str) -> Result<Self, String> {
+        Self::new(origins.split(',').map(str::to_string))
+    }
+}
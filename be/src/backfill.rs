@@ -0,0 +1,76 @@
+use crate::feed::{BusPosition, PositionSource};
+use std::collections::HashSet;
+
+pub const BACKFILL_POSITIONS_TOTAL: &str = "rapidbro_backfill_positions_total";
+// Several route clients coming back from the same outage would otherwise each ask for
+// their own snapshot.
+pub const DEFAULT_BACKFILL_MIN_INTERVAL_MS: i64 = 20_000;
+
+// Bridges the gap after the websocket reconnects: one GTFS-rt snapshot repopulates the
+// map while the socket warms up. The snapshot takes a moment to arrive and the socket may
+// have sent fresh positions for some vehicles by then; those vehicles are left out of the
+// snapshot so it never overwrites them.
+#[derive(Debug)]
+pub struct BackfillGuard {
+    min_interval_ms: i64,
+    last_started_ms: Option<i64>,
+    pending: bool,
+    // Vehicles the socket reported while the snapshot was being fetched.
+    live: HashSet<String>,
+}
+
+impl BackfillGuard {
+    pub fn new(min_interval_ms: i64) -> Self {
+        Self {
+            min_interval_ms,
+            last_started_ms: None,
+            pending: false,
+            live: HashSet::new(),
+        }
+    }
+
+    // Whether to fetch a snapshot now; false while one is pending or if the last one was
+    // started less than the minimum interval ago.
+    pub fn start(&mut self, now_ms: i64) -> bool {
+        if self.pending
+            || self
+                .last_started_ms
+                .is_some_and(|started| now_ms - started < self.min_interval_ms)
+        {
+            return false;
+        }
+        self.pending = true;
+        self.last_started_ms = Some(now_ms);
+        self.live.clear();
+        true
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    // Call with every socket batch.
+    pub fn observe_live(&mut self, buses: &[BusPosition]) {
+        if self.pending {
+            self.live.extend(buses.iter().map(|bus| bus.bus_no.clone()));
+        }
+    }
+
+    // Drops the vehicles the socket has reported since `start` from the snapshot and
+    // marks the rest as GTFS-rt positions; returns how many were dropped.
+    pub fn finish(&mut self, snapshot: &mut Vec<BusPosition>) -> usize {
+        let before = snapshot.len();
+        snapshot.retain(|bus| !self.live.contains(&bus.bus_no));
+        for bus in snapshot.iter_mut() {
+            bus.source = PositionSource::Gtfs;
+        }
+        self.cancel();
+        before - snapshot.len()
+    }
+
+    // The fetch failed; the next reconnect may try again.
+    pub fn cancel(&mut self) {
+        self.pending = false;
+        self.live.clear();
+    }
+}
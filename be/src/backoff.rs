@@ -0,0 +1,56 @@
+use crate::rate_limit::{jittered, random_unit};
+use std::time::Duration;
+
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// Exponential reconnect delay: initial, twice that, and so on up to `max`, until `reset`.
+// Pure timing state with no I/O, so the sequence can be checked without a socket. Jitter
+// scales each delay by up to ±fraction around the nominal value; the cap applies to the
+// nominal value, not the jittered one.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    jitter: f64,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        let max = max.max(initial);
+        Self {
+            initial,
+            max,
+            jitter: 0.0,
+            next: initial,
+        }
+    }
+
+    pub fn with_jitter(mut self, fraction: f64) -> Self {
+        self.jitter = fraction.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        self.next_delay_with(random_unit())
+    }
+
+    // `unit` in [0, 1] picks where in the jitter range the delay lands, as in `jittered`.
+    pub fn next_delay_with(&mut self, unit: f64) -> Duration {
+        let nominal = self.next;
+        self.next = (nominal * 2).min(self.max);
+        jittered(nominal, self.jitter, unit)
+    }
+
+    // Back to the initial delay, after a session that got as far as subscribing.
+    pub fn reset(&mut self) {
+        self.next = self.initial;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(DEFAULT_INITIAL_BACKOFF, DEFAULT_MAX_BACKOFF)
+    }
+}
@@ -0,0 +1,98 @@
+// Turns one payload from a --payload-log-dir capture into a golden-test fixture:
+//
+//     cargo run --bin make-fixture -- <payloads.jsonl> <line> <name>
+//
+// writes tests/fixtures/payloads/<name>.b64, re-encoded the way the socket sends it, with
+// driver ids blanked. Then run `UPDATE_GOLDEN=1 cargo test --test golden` to write its
+// golden file, and check the result before committing both.
+
+use base64::Engine;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::Value;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/payloads");
+// Per-driver, so it has no place in a fixture checked into the repo.
+const SANITIZED_FIELDS: &[&str] = &["captain_id"];
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [log, line, name] = &args[..] else {
+        eprintln!("usage: make-fixture <payloads.jsonl> <line> <name>");
+        return ExitCode::from(2);
+    };
+    match make_fixture(Path::new(log), line, name) {
+        Ok(path) => {
+            println!("Wrote {}", path.display());
+            println!(
+                "Now run `UPDATE_GOLDEN=1 cargo test --test golden` and review the golden file"
+            );
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn make_fixture(log: &Path, line: &str, name: &str) -> Result<PathBuf, String> {
+    let line_number: usize = line
+        .parse()
+        .ok()
+        .filter(|number| *number > 0)
+        .ok_or_else(|| format!("line `{}` is not a line number (counting from 1)", line))?;
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!(
+            "fixture name `{}` should be letters, digits and underscores",
+            name
+        ));
+    }
+    let contents = std::fs::read_to_string(log)
+        .map_err(|error| format!("failed to read '{}': {}", log.display(), error))?;
+    let entry = contents
+        .lines()
+        .nth(line_number - 1)
+        .ok_or_else(|| format!("'{}' has no line {}", log.display(), line_number))?;
+    let mut entry: Value = serde_json::from_str(entry)
+        .map_err(|error| format!("line {} is not a payload log entry: {}", line_number, error))?;
+
+    // The log embeds the payload as JSON when it parsed and as a string when it didn't;
+    // either way the fixture gets the bytes the socket would have sent.
+    let json = match entry.get_mut("payload").map(Value::take) {
+        Some(Value::String(unparsed)) => unparsed,
+        Some(mut payload) => {
+            sanitize(&mut payload);
+            payload.to_string()
+        }
+        None => return Err(format!("line {} has no payload", line_number)),
+    };
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    let compressed = encoder
+        .write_all(json.as_bytes())
+        .and_then(|_| encoder.finish())
+        .map_err(|error| format!("failed to compress payload: {}", error))?;
+    let path = Path::new(FIXTURES).join(format!("{}.b64", name));
+    let encoded = base64::engine::general_purpose::STANDARD.encode(compressed);
+    std::fs::write(&path, encoded + "\n")
+        .map_err(|error| format!("failed to write '{}': {}", path.display(), error))?;
+    Ok(path)
+}
+
+fn sanitize(payload: &mut Value) {
+    match payload {
+        Value::Array(entries) => entries.iter_mut().for_each(sanitize),
+        Value::Object(object) => {
+            for field in SANITIZED_FIELDS {
+                if let Some(value) = object.get_mut(*field).filter(|value| !value.is_null()) {
+                    *value = Value::String("0".to_string());
+                }
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,135 @@
+use crate::feed::{BusPosition, BUS_POSITION_FIELDS};
+use crate::validate::BoundingBox;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// A ?fields= projection: BusPosition's own field names, plus lat, lon and lng for short.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSelection {
+    fields: Vec<&'static str>,
+}
+
+impl FieldSelection {
+    // Comma-separated, e.g. `lat,lon,route`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut fields = Vec::new();
+        for name in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let field = match name {
+                "lat" => "latitude",
+                "lon" | "lng" => "longitude",
+                name => BUS_POSITION_FIELDS
+                    .iter()
+                    .copied()
+                    .find(|field| *field == name)
+                    .ok_or_else(|| format!("unknown field `{}`", name))?,
+            };
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+        if fields.is_empty() {
+            return Err("no fields given".to_string());
+        }
+        Ok(Self { fields })
+    }
+
+    // Fields that are skipped when empty (e.g. trip_id) are left out here too.
+    pub fn project(&self, bus: &BusPosition) -> Map<String, Value> {
+        let Ok(Value::Object(mut all)) = serde_json::to_value(bus) else {
+            return Map::new();
+        };
+        self.fields
+            .iter()
+            .filter_map(|field| all.remove(*field).map(|value| (field.to_string(), value)))
+            .collect()
+    }
+}
+
+// Groups positions by `route_key(route)` for the bulk /buses endpoint. Every route in
+// `tracked` is listed, with no buses if none are on it, so a dashboard can tell an empty
+// route from one it doesn't know; `also_listed_on` adds the routes a reassigned vehicle
+// is still shown on (see RouteAssignments).
+pub fn group_by_route(
+    buses: Vec<BusPosition>,
+    tracked: &[String],
+    also_listed_on: &HashMap<String, Vec<String>>,
+    bbox: Option<&BoundingBox>,
+    route_key: fn(&str) -> String,
+) -> BTreeMap<String, Vec<BusPosition>> {
+    let mut groups: BTreeMap<String, Vec<BusPosition>> = tracked
+        .iter()
+        .map(|route| route_key(route))
+        .filter(|route| !route.is_empty())
+        .map(|route| (route, Vec::new()))
+        .collect();
+    for bus in buses {
+        if bbox.is_some_and(|bbox| !bbox.contains(bus.latitude, bus.longitude)) {
+            continue;
+        }
+        let mut routes = vec![route_key(&bus.route)];
+        for route in also_listed_on.get(&bus.bus_no).into_iter().flatten() {
+            let route = route_key(route);
+            if !routes.contains(&route) {
+                routes.push(route);
+            }
+        }
+        for route in routes.into_iter().filter(|route| !route.is_empty()) {
+            groups.entry(route).or_default().push(bus.clone());
+        }
+    }
+    groups
+}
+
+pub fn route_map(
+    groups: &BTreeMap<String, Vec<BusPosition>>,
+    fields: Option<&FieldSelection>,
+) -> BTreeMap<String, Vec<Value>> {
+    groups
+        .iter()
+        .map(|(route, buses)| {
+            let buses = buses.iter().map(|bus| properties(bus, fields)).collect();
+            (route.clone(), buses)
+        })
+        .collect()
+}
+
+// One FeatureCollection across every route, with a Point feature per vehicle even when it
+// is listed on more than one route. Coordinates are always included, whatever `fields`
+// selects.
+pub fn feature_collection(
+    groups: &BTreeMap<String, Vec<BusPosition>>,
+    fields: Option<&FieldSelection>,
+) -> Value {
+    let mut seen = HashSet::new();
+    let features: Vec<Value> = groups
+        .values()
+        .flatten()
+        .filter(|bus| seen.insert(bus.bus_no.as_str()))
+        .map(|bus| {
+            json!({
+                "type": "Feature",
+                "id": bus.bus_no,
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": [bus.longitude, bus.latitude],
+                },
+                "properties": properties(bus, fields),
+            })
+        })
+        .collect();
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+fn properties(bus: &BusPosition, fields: Option<&FieldSelection>) -> Value {
+    match fields {
+        Some(fields) => Value::Object(fields.project(bus)),
+        None => serde_json::to_value(bus).unwrap_or(Value::Null),
+    }
+}
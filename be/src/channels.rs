@@ -0,0 +1,91 @@
+use crate::feed::BusPosition;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 16;
+pub const DEFAULT_IDLE_GRACE: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct RouteChannel {
+    sender: broadcast::Sender<Vec<BusPosition>>,
+    // When the last subscriber left; None while anyone is listening.
+    idle_since: Option<Instant>,
+}
+
+// One broadcast channel per route, created on first subscription, so a subscriber
+// only receives frames for the route it asked for. Channels nobody has listened to
+// for the grace period are dropped by `sweep`; a quick reconnect keeps its channel.
+#[derive(Debug, Clone)]
+pub struct ChannelRegistry {
+    channels: Arc<Mutex<HashMap<String, RouteChannel>>>,
+    capacity: usize,
+    idle_grace: Duration,
+}
+
+impl ChannelRegistry {
+    pub fn new(capacity: usize, idle_grace: Duration) -> Self {
+        Self {
+            channels: Arc::new(Mutex::new(HashMap::new())),
+            capacity: capacity.max(1),
+            idle_grace,
+        }
+    }
+
+    pub fn subscribe(&self, route: &str) -> broadcast::Receiver<Vec<BusPosition>> {
+        let mut channels = self.lock();
+        let channel = channels
+            .entry(route.to_string())
+            .or_insert_with(|| RouteChannel {
+                sender: broadcast::channel(self.capacity).0,
+                idle_since: None,
+            });
+        channel.idle_since = None;
+        channel.sender.subscribe()
+    }
+
+    // Sends to the route's channel if one exists; returns how many subscribers got it.
+    pub fn publish(&self, route: &str, buses: Vec<BusPosition>) -> usize {
+        self.lock()
+            .get(route)
+            .and_then(|channel| channel.sender.send(buses).ok())
+            .unwrap_or(0)
+    }
+
+    pub fn has_subscribers(&self, route: &str) -> bool {
+        self.lock()
+            .get(route)
+            .is_some_and(|channel| channel.sender.receiver_count() > 0)
+    }
+
+    // Removes channels that have had no subscribers for the grace period; returns how
+    // many were removed.
+    pub fn sweep(&self, now: Instant) -> usize {
+        let mut channels = self.lock();
+        let before = channels.len();
+        channels.retain(|_, channel| {
+            if channel.sender.receiver_count() > 0 {
+                channel.idle_since = None;
+                return true;
+            }
+            let idle_since = *channel.idle_since.get_or_insert(now);
+            now.duration_since(idle_since) < self.idle_grace
+        });
+        before - channels.len()
+    }
+
+    pub async fn run_cleanup(&self) {
+        let mut interval = tokio::time::interval((self.idle_grace / 2).max(Duration::from_secs(1)));
+        loop {
+            interval.tick().await;
+            self.sweep(Instant::now());
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, RouteChannel>> {
+        self.channels
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
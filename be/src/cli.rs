@@ -0,0 +1,1073 @@
+use crate::is_bus_on_route;
+use be::archive::read_summary;
+use be::client::{
+    is_valid_route, ClientConfigError, ClientEvent, RapidbroClient, RapidbroClientBuilder,
+    DEFAULT_DATA_EVENT, DEFAULT_RELOAD_EVENT, DEFAULT_RELOAD_INTERVAL,
+};
+use be::export::{query_track, to_gpx, to_kml, TrackQuery};
+use be::feed::BusPosition;
+use be::field_map::FieldMap;
+use be::gtfs_rt::{
+    build_http_client, check_auth, fetch_feed, poll_vehicle_positions, ApiToken,
+    PRASARANA_VEHICLE_POSITIONS_URL,
+};
+use be::nats::NatsMode;
+use be::now_unix_ms;
+use be::parse_failures::ParseFailureLog;
+use be::payload_log::{PayloadLog, RotationPeriod, DEFAULT_MAX_FILES};
+use be::proxy::ProxyOptions;
+use be::rate_limit::{spread_offset, EmitLimiter};
+use be::reconcile::reconcile;
+use be::schema::SchemaMonitor;
+use be::session::{RegexExtractor, SessionExtractors};
+use be::session_store::SessionStore;
+use be::timestamp::parse_feed_timestamp;
+use be::tls::TlsOptions;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing_appender::non_blocking::WorkerGuard;
+
+const DEFAULT_COMPARE_REPORT_SECONDS: u64 = 60;
+const DEFAULT_COMPARE_STALE_SECONDS: u64 = 120;
+// How long --dry-run waits for each of the session, the handshake and the first batch.
+const DRY_RUN_STEP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Parser)]
+#[command(name = "rapidbro", about = "Rapid KL live bus backend")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub http: HttpOptions,
+
+    #[command(flatten)]
+    pub subscriptions: RouteOptions,
+
+    #[command(flatten)]
+    pub nats: NatsOptions,
+
+    #[command(flatten)]
+    pub redis: RedisOptions,
+
+    /// Where live positions come from
+    #[arg(long, value_enum, default_value_t = Source::Websocket)]
+    pub source: Source,
+
+    /// What the ingestor prints about the positions it processes
+    #[arg(long, value_enum, default_value_t = LogFormat::Summary)]
+    pub format: LogFormat,
+
+    /// Write per-route service statistics as JSON here on shutdown (and every STATS_INTERVAL_MINUTES)
+    #[arg(long)]
+    pub stats_file: Option<String>,
+
+    /// Rewrite the /healthz report as JSON here every 5 seconds, for supervisors that
+    /// can't probe HTTP
+    #[arg(long)]
+    pub status_file: Option<String>,
+
+    /// Also write every position as a line of JSON to this file ("-" for stdout)
+    #[arg(long)]
+    pub jsonl_output: Option<PathBuf>,
+
+    /// Serve GET /debug/sessions and /debug/parse-failures. They show the live kiosk
+    /// sid/prm per route and raw feed payloads, so keep them off where the API is reachable
+    /// by untrusted clients
+    #[arg(long)]
+    pub debug_endpoints: bool,
+
+    /// Pass the feed's coordinates through unchecked (no bounding box, swap or 0,0 checks),
+    /// for debugging the raw feed; wins over VALIDATE_COORDINATES and the config file
+    #[arg(long)]
+    pub no_validate: bool,
+
+    /// Leave vehicles whose last fix is older than this out of every positions endpoint
+    /// (by default they are served, with age_seconds showing how old they are)
+    #[arg(long)]
+    pub max_position_age_seconds: Option<u64>,
+
+    /// TOML config file; on SIGHUP it is re-read and routes, filters and intervals applied live
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Check the config and that the feed is reachable (kiosk session, socket, first data),
+    /// print a summary and exit nonzero if any step failed
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct HttpOptions {
+    /// User-Agent for the kiosk fetch and socket handshake (defaults to desktop Safari)
+    #[arg(long, global = true)]
+    pub user_agent: Option<String>,
+
+    /// Extra request header as "Key: Value"; repeatable
+    #[arg(long = "header", global = true, value_parser = parse_header)]
+    pub headers: Vec<(String, String)>,
+
+    /// Extra root CA (PEM) to trust for the Prasarana endpoints; repeatable
+    #[arg(long = "ca-cert", global = true)]
+    pub ca_certs: Vec<PathBuf>,
+
+    /// INSECURE: skip TLS certificate verification (debugging against a local mock only)
+    #[arg(long, visible_alias = "insecure", global = true)]
+    pub danger_accept_invalid_certs: bool,
+
+    /// HTTP(S) proxy for the kiosk fetch and GTFS-rt polls (defaults to HTTPS_PROXY, which also covers the socket)
+    #[arg(long = "proxy", global = true)]
+    pub proxy_url: Option<String>,
+
+    /// Keep feed fields rapidbro doesn't know yet under "extra" in each position
+    #[arg(long, global = true)]
+    pub capture_extra_fields: bool,
+
+    /// Socket.IO event the server sends positions on
+    #[arg(long, global = true, default_value = DEFAULT_DATA_EVENT)]
+    pub data_event: String,
+
+    /// Socket.IO event emitted to subscribe to a route
+    #[arg(long, global = true, default_value = DEFAULT_RELOAD_EVENT)]
+    pub reload_event: String,
+
+    /// data.gov.my API key for the GTFS-realtime requests (defaults to DATAGOVMY_TOKEN)
+    #[arg(long, global = true)]
+    pub api_token: Option<ApiToken>,
+
+    /// Write every raw socket payload as JSON lines to rotated files in this directory
+    #[arg(long, global = true)]
+    pub payload_log_dir: Option<PathBuf>,
+
+    /// How often --payload-log-dir starts a new file: hourly or daily
+    #[arg(long, global = true, default_value_t = RotationPeriod::Daily, requires = "payload_log_dir")]
+    pub payload_log_rotation: RotationPeriod,
+
+    /// How many rotated payload files to keep; older ones are deleted
+    #[arg(long, global = true, default_value_t = DEFAULT_MAX_FILES, requires = "payload_log_dir")]
+    pub payload_log_max_files: usize,
+
+    /// File to keep kiosk sessions and cookies in across restarts (written owner-only)
+    #[arg(long, global = true)]
+    pub session_state: Option<PathBuf>,
+
+    /// IANA time zone for each position's local_time; the UTC timestamp is unchanged
+    #[arg(long, global = true, default_value = "Asia/Kuala_Lumpur", value_parser = parse_timezone)]
+    pub timezone: Option<Tz>,
+
+    /// Fail payloads with unknown, missing or mistyped fields instead of only warning, e.g.
+    /// in CI against recorded fixtures
+    #[arg(long, global = true)]
+    pub strict_schema: bool,
+
+    /// Reject a payload that inflates past this many bytes [default: 16 MiB]; raise it if
+    /// all-routes batches ever get that big
+    #[arg(long, global = true, value_parser = parse_decompression_limit)]
+    pub max_decompressed_bytes: Option<usize>,
+
+    /// Failed sessions to allow before the first one subscribes, then exit instead of waiting
+    /// on an unreachable feed; 0 retries forever [default: 5, about 30s]
+    #[arg(long, global = true)]
+    pub startup_attempts: Option<u32>,
+
+    /// Regex for the kiosk page's sid, with a capture group around the value; tried before
+    /// the built-in patterns, for when the page layout changes
+    #[arg(long, global = true, value_parser = parse_session_pattern)]
+    pub sid_pattern: Option<Regex>,
+
+    /// Regex for the kiosk page's prm, as --sid-pattern
+    #[arg(long, global = true, value_parser = parse_session_pattern)]
+    pub prm_pattern: Option<Regex>,
+
+    /// Regex for the kiosk page's route (no_route), as --sid-pattern
+    #[arg(long, global = true, value_parser = parse_session_pattern)]
+    pub route_pattern: Option<Regex>,
+
+    #[arg(skip)]
+    pub tls: TlsOptions,
+
+    #[arg(skip)]
+    pub proxy: ProxyOptions,
+
+    // From the config file's [field_map]; the default is the Rapid KL layout.
+    #[arg(skip)]
+    pub field_map: FieldMap,
+
+    #[arg(skip)]
+    pub session_store: Option<SessionStore>,
+
+    #[arg(skip)]
+    pub payload_log: Option<PayloadLog>,
+
+    // Shared by every client built from these options; see /debug/parse-failures.
+    #[arg(skip)]
+    pub parse_failures: ParseFailureLog,
+
+    // Shared by every client built from these options, so unknown fields are logged once.
+    #[arg(skip)]
+    pub schema: SchemaMonitor,
+
+    // Stops every client built from these options; see RapidbroClientBuilder::cancellation_token.
+    #[arg(skip)]
+    pub cancellation: Option<CancellationToken>,
+}
+
+impl HttpOptions {
+    // Reads the --ca-cert files; call once after parsing.
+    pub fn load_tls(&mut self) -> Result<(), String> {
+        self.tls = TlsOptions::load(&self.ca_certs, self.danger_accept_invalid_certs)?;
+        if self.danger_accept_invalid_certs {
+            eprintln!("warning: TLS certificate verification is disabled");
+        }
+        Ok(())
+    }
+
+    // Resolves --proxy or HTTPS_PROXY; call once after parsing, before any client is built.
+    pub fn load_proxy(&mut self) -> Result<(), String> {
+        self.proxy = ProxyOptions::load(self.proxy_url.as_deref())?;
+        if self.proxy.is_enabled() && !self.proxy.covers_socket() {
+            eprintln!(
+                "warning: --proxy covers the kiosk fetch and GTFS-rt polls only; set HTTPS_PROXY to the same URL to proxy the socket too"
+            );
+        }
+        Ok(())
+    }
+
+    // --api-token, else DATAGOVMY_TOKEN.
+    pub fn api_token(&self) -> Option<ApiToken> {
+        self.api_token.clone().or_else(|| {
+            std::env::var("DATAGOVMY_TOKEN")
+                .ok()
+                .and_then(ApiToken::new)
+        })
+    }
+
+    // The client for every GTFS-realtime request, with the API token when there is one.
+    pub fn gtfs_http_client(&self) -> Result<reqwest::Client, String> {
+        build_http_client(&self.tls, &self.proxy, self.api_token().as_ref())
+    }
+
+    // Opens the --session-state file; call once after parsing so every client shares it.
+    pub fn load_session_store(&mut self) {
+        self.session_store = self.session_state.clone().map(SessionStore::load);
+    }
+
+    // Opens --payload-log-dir; call once after parsing. Hold on to the guard until exit so
+    // the last buffered payloads reach the file.
+    pub fn load_payload_log(&mut self) -> Result<Option<WorkerGuard>, String> {
+        let Some(dir) = &self.payload_log_dir else {
+            return Ok(None);
+        };
+        let (payload_log, guard) =
+            PayloadLog::open(dir, self.payload_log_rotation, self.payload_log_max_files)?;
+        println!(
+            "Logging raw payloads to {} ({}, keeping {} files)",
+            dir.display(),
+            self.payload_log_rotation,
+            self.payload_log_max_files
+        );
+        self.payload_log = Some(payload_log);
+        Ok(Some(guard))
+    }
+
+    // The built-in extractors, after one built from --sid-pattern and friends if any was given.
+    pub fn session_extractors(&self) -> SessionExtractors {
+        let extractors = SessionExtractors::default();
+        if self.sid_pattern.is_none() && self.prm_pattern.is_none() && self.route_pattern.is_none()
+        {
+            return extractors;
+        }
+        extractors.try_first(Arc::new(RegexExtractor::custom(
+            self.sid_pattern.clone(),
+            self.prm_pattern.clone(),
+            self.route_pattern.clone(),
+        )))
+    }
+
+    pub fn apply(&self, mut builder: RapidbroClientBuilder) -> RapidbroClientBuilder {
+        builder = builder
+            .tls(self.tls.clone())
+            .proxy(self.proxy.clone())
+            .capture_extra_fields(self.capture_extra_fields)
+            .field_map(self.field_map.clone())
+            .payload_log(self.payload_log.clone())
+            .parse_failures(self.parse_failures.clone())
+            .schema_monitor(self.schema.clone().strict(self.strict_schema))
+            .timezone(self.timezone)
+            .session_extractors(self.session_extractors())
+            .session_store(self.session_store.clone());
+        // Empty only for a defaulted HttpOptions that never went through clap.
+        if !self.data_event.is_empty() {
+            builder = builder.data_event(self.data_event.clone());
+        }
+        if !self.reload_event.is_empty() {
+            builder = builder.reload_event(self.reload_event.clone());
+        }
+        if let Some(limit) = self.max_decompressed_bytes {
+            builder = builder.max_decompressed_bytes(limit);
+        }
+        if let Some(attempts) = self.startup_attempts {
+            builder = builder.startup_attempts(attempts);
+        }
+        if let Some(token) = &self.cancellation {
+            builder = builder.cancellation_token(token.clone());
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent.clone());
+        }
+        for (name, value) in &self.headers {
+            builder = builder.header(name.clone(), value.clone());
+        }
+        builder
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Source {
+    /// The kiosk's socket.io feed: fresher, per-route subscriptions
+    Websocket,
+    /// The data.gov.my GTFS-realtime feed, polled on the reload interval
+    Gtfs,
+    /// The socket feed, falling back to polling GTFS-realtime while the socket is down
+    Hybrid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Connection events and periodic summaries only
+    Summary,
+    /// Also a line per material vehicle change (moved, speed, route), and when a vehicle
+    /// appears or is lost (DIFF_LOST_AFTER_BATCHES reloads without it, default 3)
+    Diff,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct NatsOptions {
+    /// NATS server to publish every update to, e.g. nats://127.0.0.1:4222
+    #[arg(long)]
+    pub nats_url: Option<String>,
+
+    /// Publish through JetStream (persisted, at-least-once) instead of core NATS
+    #[arg(long, requires = "nats_url")]
+    pub nats_jetstream: bool,
+}
+
+impl NatsOptions {
+    pub fn mode(&self) -> NatsMode {
+        if self.nats_jetstream {
+            NatsMode::JetStream
+        } else {
+            NatsMode::Core
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct RedisOptions {
+    /// Redis to store positions in (and publish to); wins over the config file and REDIS_URL
+    #[arg(long)]
+    pub redis_url: Option<String>,
+
+    /// Also publish each batch as JSON to the rapidbro:buses:<route> pub/sub channel
+    #[arg(long)]
+    pub redis_pubsub: bool,
+
+    /// Keep each route's latest buses in rapidbro:snapshot:<route> for this many seconds,
+    /// so new instances can warm-start from it
+    #[arg(long, requires = "redis_pubsub")]
+    pub redis_snapshot_ttl: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct RouteOptions {
+    /// Route to subscribe to; repeat for several, omit to follow every bus
+    #[arg(long = "route")]
+    pub route: Vec<String>,
+
+    /// Comma-separated routes to subscribe to, e.g. 300,302,402
+    #[arg(long, value_delimiter = ',')]
+    pub routes: Vec<String>,
+
+    /// File with one route per line; blank lines and # comments are ignored
+    #[arg(long)]
+    pub routes_file: Option<PathBuf>,
+
+    /// Open at most this many feed connections, one per route; routes past the limit are
+    /// skipped with a warning and POST /control/routes refuses to go over it
+    #[arg(long, value_parser = parse_connection_limit)]
+    pub max_connections: Option<usize>,
+}
+
+impl RouteOptions {
+    // Merges every source into one de-duplicated list. Bad entries are reported and
+    // skipped so one typo doesn't stop the other subscriptions.
+    pub fn resolve(&self) -> Vec<String> {
+        let mut candidates: Vec<String> = self.route.iter().chain(&self.routes).cloned().collect();
+        if let Some(path) = &self.routes_file {
+            match std::fs::read_to_string(path) {
+                Ok(contents) => candidates.extend(
+                    contents
+                        .lines()
+                        .map(|line| line.split('#').next().unwrap_or("").trim())
+                        .filter(|line| !line.is_empty())
+                        .map(str::to_string),
+                ),
+                Err(error) => {
+                    eprintln!("Skipping routes file '{}': {}", path.display(), error)
+                }
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let mut routes = Vec::new();
+        for candidate in candidates {
+            let route = candidate.trim().to_uppercase();
+            if !is_valid_route_id(&route) {
+                eprintln!("Skipping invalid route id `{}`", candidate);
+                continue;
+            }
+            if seen.insert(route.clone()) {
+                routes.push(route);
+            }
+        }
+        limit_connections(&mut routes, self.max_connections);
+        routes
+    }
+}
+
+// Keeps the first `limit` routes, one connection each, and warns about the rest.
+pub fn limit_connections(routes: &mut Vec<String>, limit: Option<usize>) {
+    if let Some(limit) = limit.filter(|&limit| routes.len() > limit) {
+        eprintln!(
+            "Skipping routes {} past --max-connections {}",
+            routes[limit..].join(","),
+            limit
+        );
+        routes.truncate(limit);
+    }
+}
+
+pub fn is_valid_route_id(route: &str) -> bool {
+    !route.is_empty() && is_valid_route(route)
+}
+
+// One running client per route, sharing an emit budget; no routes means the all-buses feed.
+// Each client keeps its own event stream so callers can tell the routes' sockets apart.
+pub async fn spawn_route_clients(
+    routes: &[String],
+    http: &HttpOptions,
+    emit_limiter: &EmitLimiter,
+) -> Result<Vec<(RapidbroClient, BoxStream<'static, ClientEvent>)>, ClientConfigError> {
+    let client_routes = if routes.is_empty() {
+        vec![String::new()]
+    } else {
+        routes.to_vec()
+    };
+
+    let mut clients = Vec::new();
+    let count = client_routes.len();
+    for (index, route) in client_routes.into_iter().enumerate() {
+        let reload_offset = spread_offset(DEFAULT_RELOAD_INTERVAL, index, count);
+        clients.push(spawn_route_client(route, http, emit_limiter, Some(reload_offset)).await?);
+    }
+    Ok(clients)
+}
+
+// Starts one client in the background; `stop` on the returned handle ends it. Without a
+// reload offset its first reload comes one (jittered) interval after connecting.
+pub async fn spawn_route_client(
+    route: String,
+    http: &HttpOptions,
+    emit_limiter: &EmitLimiter,
+    reload_offset: Option<Duration>,
+) -> Result<(RapidbroClient, BoxStream<'static, ClientEvent>), ClientConfigError> {
+    let mut builder = http
+        .apply(RapidbroClient::builder())
+        .route(route)
+        .emit_limiter(emit_limiter.clone());
+    if let Some(reload_offset) = reload_offset {
+        builder = builder.reload_offset(reload_offset);
+    }
+    let client = builder.build()?;
+    let events = client.subscribe().await;
+    let runner = client.clone();
+    tokio::spawn(async move { runner.run().await });
+    Ok((client, events))
+}
+
+fn parse_header(raw: &str) -> Result<(String, String), String> {
+    let (name, value) = raw
+        .split_once(':')
+        .ok_or_else(|| format!("expected \"Key: Value\", got `{}`", raw))?;
+    let (name, value) = (name.trim(), value.trim());
+    reqwest::header::HeaderName::from_bytes(name.as_bytes())
+        .map_err(|error| format!("invalid header name `{}`: {}", name, error))?;
+    reqwest::header::HeaderValue::from_str(value)
+        .map_err(|error| format!("invalid header value for `{}`: {}", name, error))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+fn parse_session_pattern(raw: &str) -> Result<Regex, String> {
+    let pattern = Regex::new(raw).map_err(|error| format!("invalid regex: {}", error))?;
+    if pattern.captures_len() < 2 {
+        return Err(format!(
+            "`{}` has no capture group; wrap the value in parentheses",
+            raw
+        ));
+    }
+    Ok(pattern)
+}
+
+fn parse_connection_limit(raw: &str) -> Result<usize, String> {
+    match raw.trim().parse::<usize>() {
+        Ok(limit) if limit > 0 => Ok(limit),
+        _ => Err(format!("`{}` is not a positive number of connections", raw)),
+    }
+}
+
+fn parse_decompression_limit(raw: &str) -> Result<usize, String> {
+    match raw.trim().parse::<usize>() {
+        Ok(limit) if limit > 0 => Ok(limit),
+        _ => Err(format!("`{}` is not a positive number of bytes", raw)),
+    }
+}
+
+fn parse_timezone(raw: &str) -> Result<Tz, String> {
+    raw.trim().parse::<Tz>().map_err(|_| {
+        format!(
+            "unknown time zone `{}`; expected e.g. Asia/Kuala_Lumpur",
+            raw
+        )
+    })
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print the first decoded data-event payload exactly as received, then exit
+    Inspect {
+        /// Route to subscribe to; empty subscribes to every bus
+        #[arg(long, default_value = "")]
+        route: String,
+    },
+    /// Live terminal dashboard of vehicles per route
+    Tui {
+        /// Routes to show, one tab each
+        #[command(flatten)]
+        subscriptions: RouteOptions,
+    },
+    /// Work with the hourly Parquet archive written when ARCHIVE_DIR is set
+    Archive {
+        #[command(subcommand)]
+        command: ArchiveCommand,
+    },
+    /// Export data from the Parquet archive
+    Export {
+        #[command(subcommand)]
+        command: ExportCommand,
+    },
+    /// Work with the data.gov.my GTFS-realtime feed
+    Gtfs {
+        #[command(subcommand)]
+        command: GtfsCommand,
+    },
+    /// Follow the websocket and GTFS-realtime feeds side by side and report where they disagree
+    Compare {
+        /// Route to compare; empty compares every bus
+        #[arg(long, default_value = "")]
+        route: String,
+
+        /// Seconds between reconciliation reports
+        #[arg(long, default_value_t = DEFAULT_COMPARE_REPORT_SECONDS)]
+        report_seconds: u64,
+
+        /// A vehicle not heard from in this many seconds no longer counts as in service
+        #[arg(long, default_value_t = DEFAULT_COMPARE_STALE_SECONDS)]
+        stale_seconds: u64,
+    },
+    /// Print the OpenAPI spec the server serves at /openapi.json, then exit
+    Openapi,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum GtfsCommand {
+    /// Make one request with the configured API token and report whether it was accepted
+    CheckAuth,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ArchiveCommand {
+    /// Print the row count and receive-time range of an archive file
+    Inspect { file: PathBuf },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ExportCommand {
+    /// Write one vehicle's trajectory as a GPX (or KML) track
+    Gpx(TrackExportArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct TrackExportArgs {
+    /// Vehicle registration, e.g. WXX1234
+    #[arg(long)]
+    pub vehicle: String,
+
+    /// Start of the window: RFC 3339, or "YYYY-MM-DD HH:MM[:SS]" in Kuala Lumpur time
+    #[arg(long, value_parser = parse_export_time)]
+    pub from: DateTime<Utc>,
+
+    /// End of the window, same formats as --from
+    #[arg(long, value_parser = parse_export_time)]
+    pub to: DateTime<Utc>,
+
+    #[arg(long, value_enum, default_value_t = ExportFormat::Gpx)]
+    pub format: ExportFormat,
+
+    /// Keep points flagged off-route or with coordinates outside Malaysia
+    #[arg(long)]
+    pub include_invalid: bool,
+
+    /// Archive root (defaults to ARCHIVE_DIR)
+    #[arg(long)]
+    pub archive_dir: Option<PathBuf>,
+
+    /// Write here instead of stdout
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Gpx,
+    Kml,
+}
+
+fn parse_export_time(raw: &str) -> Result<DateTime<Utc>, String> {
+    parse_feed_timestamp(raw).ok_or_else(|| format!("unrecognised time `{}`", raw))
+}
+
+pub fn run_export(command: ExportCommand) -> i32 {
+    let ExportCommand::Gpx(args) = command;
+    let Some(archive_dir) = args
+        .archive_dir
+        .or_else(|| std::env::var("ARCHIVE_DIR").ok().map(PathBuf::from))
+    else {
+        eprintln!("export failed: pass --archive-dir or set ARCHIVE_DIR");
+        return 1;
+    };
+    if args.from > args.to {
+        eprintln!("export failed: --from is after --to");
+        return 1;
+    }
+
+    let query = TrackQuery {
+        vehicle: args.vehicle.to_uppercase(),
+        from_ms: args.from.timestamp_millis(),
+        to_ms: args.to.timestamp_millis(),
+        include_invalid: args.include_invalid,
+    };
+    let points = match query_track(&archive_dir, &query) {
+        Ok(points) => points,
+        Err(error) => {
+            eprintln!("export failed: {}", error);
+            return 1;
+        }
+    };
+    if points.is_empty() {
+        eprintln!(
+            "no points for {} between {} and {}",
+            query.vehicle, args.from, args.to
+        );
+    }
+    let document = match args.format {
+        ExportFormat::Gpx => to_gpx(&query.vehicle, &points),
+        ExportFormat::Kml => to_kml(&query.vehicle, &points),
+    };
+
+    match args.output {
+        Some(path) => match std::fs::write(&path, document) {
+            Ok(()) => {
+                eprintln!("wrote {} points to {}", points.len(), path.display());
+                0
+            }
+            Err(error) => {
+                eprintln!(
+                    "export failed: cannot write '{}': {}",
+                    path.display(),
+                    error
+                );
+                1
+            }
+        },
+        None => {
+            print!("{}", document);
+            0
+        }
+    }
+}
+
+pub fn run_archive(command: ArchiveCommand) -> i32 {
+    match command {
+        ArchiveCommand::Inspect { file } => match read_summary(&file) {
+            Ok(summary) => {
+                let format_ts = |value: Option<i64>| {
+                    value
+                        .and_then(DateTime::from_timestamp_millis)
+                        .map(|timestamp| timestamp.to_rfc3339())
+                        .unwrap_or_else(|| "-".to_string())
+                };
+                println!(
+                    "rows={} first_recv={} last_recv={}",
+                    summary.rows,
+                    format_ts(summary.first_recv_ts),
+                    format_ts(summary.last_recv_ts)
+                );
+                0
+            }
+            Err(error) => {
+                eprintln!("archive inspect failed: {}", error);
+                1
+            }
+        },
+    }
+}
+
+// Returns the process exit code: 0 when the feed answered 2xx.
+pub async fn run_gtfs(command: GtfsCommand, http: &HttpOptions) -> i32 {
+    match command {
+        GtfsCommand::CheckAuth => {
+            let has_token = http.api_token().is_some();
+            let client = match http.gtfs_http_client() {
+                Ok(client) => client,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    return 2;
+                }
+            };
+            let status = match check_auth(&client, PRASARANA_VEHICLE_POSITIONS_URL).await {
+                Ok(status) => status,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    return 1;
+                }
+            };
+            let verdict = match (has_token, status) {
+                (true, 200..=299) => "token accepted",
+                (true, 401 | 403) => "token rejected",
+                (true, 429) => "token accepted but rate limited",
+                (false, 200..=299) => "no token configured; anonymous access works",
+                (false, 401 | 403) => "no token configured and the feed requires one",
+                (false, 429) => "no token configured and rate limited; a token raises the limit",
+                _ => "unexpected response",
+            };
+            println!("HTTP {}: {}", status, verdict);
+            if (200..300).contains(&status) {
+                0
+            } else {
+                1
+            }
+        }
+    }
+}
+
+// Runs until Ctrl-C. Each feed keeps the last position of every vehicle it reported
+// within `stale_after`; every `report_every` the two sets are reconciled and printed.
+pub async fn run_compare(
+    route: String,
+    http: &HttpOptions,
+    report_every: Duration,
+    stale_after: Duration,
+) -> i32 {
+    let client = match http
+        .apply(RapidbroClient::builder())
+        .route(route.clone())
+        .build()
+    {
+        Ok(client) => client,
+        Err(error) => {
+            eprintln!("{}", error);
+            return 2;
+        }
+    };
+    let gtfs_http = match http.gtfs_http_client() {
+        Ok(gtfs_http) => gtfs_http,
+        Err(error) => {
+            eprintln!("{}", error);
+            return 2;
+        }
+    };
+    let mut socket_events = client.subscribe().await;
+    let runner = client.clone();
+    let run = tokio::spawn(async move { runner.run().await });
+    let mut gtfs_events = poll_vehicle_positions(
+        gtfs_http,
+        PRASARANA_VEHICLE_POSITIONS_URL,
+        DEFAULT_RELOAD_INTERVAL,
+        http.timezone,
+    );
+
+    let mut socket_buses: HashMap<String, (i64, BusPosition)> = HashMap::new();
+    let mut gtfs_buses: HashMap<String, (i64, BusPosition)> = HashMap::new();
+    let mut report = tokio::time::interval(report_every.max(Duration::from_secs(1)));
+    report.reset();
+    println!(
+        "Comparing {} on the websocket and GTFS-rt; reporting every {}s",
+        if route.is_empty() {
+            "every route"
+        } else {
+            route.as_str()
+        },
+        report_every.as_secs()
+    );
+
+    let exit_code = loop {
+        tokio::select! {
+            event = socket_events.next() => match event {
+                Some(ClientEvent::Buses { buses, received_at_unix_ms, .. }) => {
+                    remember_buses(&mut socket_buses, buses, &route, received_at_unix_ms);
+                }
+                Some(ClientEvent::SessionFailed { reason } | ClientEvent::Disconnected { reason }) => {
+                    println!("websocket: {}", reason);
+                }
+                Some(_) => {}
+                None => break 1,
+            },
+            event = gtfs_events.next() => match event {
+                Some(ClientEvent::Buses { buses, received_at_unix_ms, .. }) => {
+                    remember_buses(&mut gtfs_buses, buses, &route, received_at_unix_ms);
+                }
+                Some(ClientEvent::Disconnected { reason }) => println!("gtfs-rt: {}", reason),
+                Some(_) => {}
+                None => break 1,
+            },
+            _ = report.tick() => {
+                let cutoff_ms = now_unix_ms() - stale_after.as_millis() as i64;
+                for buses in [&mut socket_buses, &mut gtfs_buses] {
+                    buses.retain(|_, (seen_ms, _)| *seen_ms >= cutoff_ms);
+                }
+                let socket: Vec<BusPosition> =
+                    socket_buses.values().map(|(_, bus)| bus.clone()).collect();
+                let gtfs: Vec<BusPosition> =
+                    gtfs_buses.values().map(|(_, bus)| bus.clone()).collect();
+                println!("{}", reconcile(&socket, &gtfs));
+            }
+            _ = tokio::signal::ctrl_c() => break 0,
+        }
+    };
+
+    client.stop();
+    run.abort();
+    exit_code
+}
+
+fn remember_buses(
+    seen: &mut HashMap<String, (i64, BusPosition)>,
+    buses: Vec<BusPosition>,
+    route: &str,
+    received_at_unix_ms: i64,
+) {
+    for bus in buses {
+        if bus.bus_no.is_empty() || !(route.is_empty() || is_bus_on_route(&bus.route, route)) {
+            continue;
+        }
+        seen.insert(bus.bus_no.clone(), (received_at_unix_ms, bus));
+    }
+}
+
+// Returns the process exit code.
+pub async fn run_inspect(route: String, http: &HttpOptions) -> i32 {
+    let client = http
+        .apply(RapidbroClient::builder())
+        .route(route)
+        .reconnect(false)
+        .raw_payloads(true)
+        .build();
+    let client = match client {
+        Ok(client) => client,
+        Err(error) => {
+            eprintln!("{}", error);
+            return 2;
+        }
+    };
+    let mut events = client.subscribe().await;
+    let runner = client.clone();
+    let run = tokio::spawn(async move { runner.run().await });
+
+    let exit_code = loop {
+        let Some(event) = events.next().await else {
+            break 1;
+        };
+
+        match event {
+            ClientEvent::RawPayloads { event, payloads } => {
+                if event != http.data_event || payloads.is_empty() {
+                    continue;
+                }
+
+                for payload in payloads {
+                    println!(
+                        "event={} base64_bytes={} gzip_bytes={} json_bytes={}",
+                        event,
+                        payload.base64_len,
+                        payload.gzip_len,
+                        payload.json.len()
+                    );
+                    println!("{}", payload.json);
+                }
+                break 0;
+            }
+            ClientEvent::SessionFailed { reason } | ClientEvent::Disconnected { reason } => {
+                eprintln!("inspect failed: {}", reason);
+                break 1;
+            }
+            _ => {}
+        }
+    };
+
+    run.abort();
+    exit_code
+}
+
+#[derive(Debug, Default)]
+struct DryRunSummary {
+    session: Option<Result<(), String>>,
+    socket: Option<Result<(), String>>,
+    first_data: Option<usize>,
+    gtfs: Option<Result<usize, String>>,
+}
+
+impl DryRunSummary {
+    // Steps that didn't run don't count against the result.
+    fn passed(&self) -> bool {
+        !matches!(self.session, Some(Err(_)))
+            && !matches!(self.socket, Some(Err(_)))
+            && !matches!(self.gtfs, Some(Err(_)))
+    }
+
+    fn print(&self) {
+        fn line<T>(name: &str, step: &Option<Result<T, String>>, detail: impl Fn(&T) -> String) {
+            match step {
+                Some(Ok(value)) => println!("{:<12} ok {}", name, detail(value)),
+                Some(Err(reason)) => println!("{:<12} FAILED {}", name, reason),
+                None => {}
+            }
+        }
+        line("session", &self.session, |_| String::new());
+        line("socket", &self.socket, |_| String::new());
+        if self.socket.as_ref().is_some_and(Result::is_ok) {
+            match self.first_data {
+                Some(buses) => println!("{:<12} ok {} buses", "first data", buses),
+                None => println!("{:<12} none yet (handshake was clean)", "first data"),
+            }
+        }
+        line("gtfs-rt", &self.gtfs, |vehicles| {
+            format!("{} vehicles", vehicles)
+        });
+    }
+}
+
+// One pass over what serve depends on, for deployment smoke tests. The socket steps run
+// for the websocket and hybrid sources, the GTFS-rt fetch for gtfs and hybrid. A silent
+// route after a clean handshake still passes. Returns the process exit code.
+pub async fn run_dry_run(route: String, http: &HttpOptions, source: Source) -> i32 {
+    let mut summary = DryRunSummary::default();
+    if source != Source::Gtfs {
+        dry_run_socket(route, http, &mut summary).await;
+    }
+    if source != Source::Websocket {
+        summary.gtfs = Some(dry_run_gtfs(http).await);
+    }
+
+    summary.print();
+    if summary.passed() {
+        0
+    } else {
+        1
+    }
+}
+
+async fn dry_run_gtfs(http: &HttpOptions) -> Result<usize, String> {
+    let client = http.gtfs_http_client()?;
+    let fetch = fetch_feed(&client, PRASARANA_VEHICLE_POSITIONS_URL);
+    match tokio::time::timeout(DRY_RUN_STEP_TIMEOUT, fetch).await {
+        Ok(feed) => feed
+            .map(|feed| feed.entity.len())
+            .map_err(|error| error.to_string()),
+        Err(_) => Err(format!(
+            "no response in {}s",
+            DRY_RUN_STEP_TIMEOUT.as_secs()
+        )),
+    }
+}
+
+async fn dry_run_socket(route: String, http: &HttpOptions, summary: &mut DryRunSummary) {
+    let client = http
+        .apply(RapidbroClient::builder())
+        .route(route)
+        .reconnect(false)
+        .build();
+    let client = match client {
+        Ok(client) => client,
+        Err(error) => {
+            summary.session = Some(Err(error.to_string()));
+            return;
+        }
+    };
+    let mut events = client.subscribe().await;
+    let runner = client.clone();
+    let run = tokio::spawn(async move { runner.run().await });
+
+    loop {
+        let event = match tokio::time::timeout(DRY_RUN_STEP_TIMEOUT, events.next()).await {
+            Ok(Some(event)) => event,
+            Ok(None) => break,
+            Err(_) => {
+                let reason = format!("no response in {}s", DRY_RUN_STEP_TIMEOUT.as_secs());
+                if summary.session.is_none() {
+                    summary.session = Some(Err(reason));
+                } else if summary.socket.is_none() {
+                    summary.socket = Some(Err(reason));
+                }
+                break;
+            }
+        };
+
+        match event {
+            ClientEvent::SessionEstablished { .. } => summary.session = Some(Ok(())),
+            ClientEvent::SessionFailed { reason } => {
+                summary.session = Some(Err(reason));
+                break;
+            }
+            ClientEvent::Connected => summary.socket = Some(Ok(())),
+            ClientEvent::Disconnected { reason } => {
+                if summary.socket.is_none() {
+                    summary.socket = Some(Err(reason));
+                }
+                break;
+            }
+            ClientEvent::Buses { buses, .. } => {
+                summary.first_data = Some(buses.len());
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    client.stop();
+    run.abort();
+    // The client gave up without reporting why, e.g. its event stream closed.
+    if summary.session.is_none() {
+        summary.session = Some(Err("client stopped before a session".to_string()));
+    } else if summary.session.as_ref().is_some_and(Result::is_ok) && summary.socket.is_none() {
+        summary.socket = Some(Err("client stopped before connecting".to_string()));
+    }
+}
@@ -0,0 +1,1204 @@
+use crate::backoff::Backoff;
+use crate::connection::{ConnectionInput, ConnectionMachine, ConnectionState};
+use crate::feed::{
+    decode_raw_payload_within, BusPosition, RawPayload, DEFAULT_MAX_DECOMPRESSED_BYTES,
+};
+use crate::field_map::FieldMap;
+use crate::layover::TripEvent;
+use crate::now_unix_ms;
+use crate::parse_failures::ParseFailureLog;
+use crate::payload_log::PayloadLog;
+use crate::pipeline::{
+    decode_frame, run_decode_pipeline, DecodeOptions, RawFrame, DEFAULT_FRAME_QUEUE_CAPACITY,
+};
+use crate::presence::PresenceEvent;
+use crate::proxy::ProxyOptions;
+use crate::queue::{BoundedQueue, OverflowPolicy};
+use crate::rate_limit::{jittered, random_unit, EmitLimiter, RELOAD_JITTER};
+use crate::schema::SchemaMonitor;
+use crate::session::{
+    fetch_session, RapidbroError, Session, SessionExtractors, SessionInfo, DEFAULT_KIOSK_URL,
+    DEFAULT_USER_AGENT,
+};
+use crate::session_store::SessionStore;
+use crate::sink::{Sink, SinkSet};
+use crate::tls::TlsOptions;
+use crate::updates::{BusUpdate, BusUpdates};
+use chrono_tz::Tz;
+use futures_util::stream::{self, BoxStream};
+use futures_util::{FutureExt, StreamExt};
+use metrics::histogram;
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::Url;
+use rust_socketio::{asynchronous::ClientBuilder, Payload, TransportType};
+use serde_json::json;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, Notify};
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+pub const DEFAULT_SOCKET_URL: &str = "https://rapidbus-socketio-avl.prasarana.com.my";
+pub const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(20);
+pub const DEFAULT_DEGRADED_AFTER: Duration = Duration::from_secs(30);
+const SILENCE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// How long a session restored from the state file gets to produce data before it's
+// dropped in favour of a fresh kiosk fetch.
+const CACHED_SESSION_GRACE: Duration = Duration::from_secs(15);
+// Server -> client position updates and client -> server subscribe, as the kiosk names them.
+pub const DEFAULT_DATA_EVENT: &str = "onFts-client";
+pub const DEFAULT_RELOAD_EVENT: &str = "onFts-reload";
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+const LOGGED_JSON_PREVIEW_CHARS: usize = 200;
+// Keeps routes that dropped together from reconnecting in lockstep.
+const RECONNECT_JITTER: f64 = 0.1;
+// With the default backoff, five attempts span about 30s (1+2+4+8+16) before giving up.
+pub const DEFAULT_STARTUP_ATTEMPTS: u32 = 5;
+
+// Route codes the kiosk hands out are short, e.g. T789 or 300.
+pub const MAX_ROUTE_LEN: usize = 16;
+
+pub const FIRST_PAYLOAD_SECONDS: &str = "rapidbro_socket_first_payload_seconds";
+pub const DECODE_BATCH_SECONDS: &str = "rapidbro_decode_batch_seconds";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    PrasaranaRapidKL,
+}
+
+impl Provider {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Provider::PrasaranaRapidKL => "RKL",
+        }
+    }
+}
+
+// What RapidbroClientBuilder::build rejects, rather than failing once the client runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientConfigError {
+    // Up to MAX_ROUTE_LEN letters, digits and dashes; empty for every bus.
+    InvalidRoute(String),
+    ZeroReloadInterval,
+    ZeroDecompressionLimit,
+    InvalidUrl {
+        name: &'static str,
+        url: String,
+        reason: String,
+    },
+    EmptyEventName(&'static str),
+    InvalidHeader {
+        name: String,
+        reason: String,
+    },
+    // reqwest refused the TLS or proxy settings.
+    HttpClient(String),
+}
+
+impl fmt::Display for ClientConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientConfigError::InvalidRoute(route) => write!(
+                f,
+                "invalid route `{}`: expected up to {} letters, digits or dashes",
+                route, MAX_ROUTE_LEN
+            ),
+            ClientConfigError::ZeroReloadInterval => {
+                write!(f, "the reload interval must be greater than zero")
+            }
+            ClientConfigError::ZeroDecompressionLimit => {
+                write!(
+                    f,
+                    "the decompressed payload limit must be greater than zero"
+                )
+            }
+            ClientConfigError::InvalidUrl { name, url, reason } => {
+                write!(f, "invalid {} `{}`: {}", name, url, reason)
+            }
+            ClientConfigError::EmptyEventName(name) => write!(f, "the {} is empty", name),
+            ClientConfigError::InvalidHeader { name, reason } => {
+                write!(f, "invalid header `{}`: {}", name, reason)
+            }
+            ClientConfigError::HttpClient(reason) => {
+                write!(f, "failed to build the HTTP client: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientConfigError {}
+
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    SessionEstablished {
+        route: String,
+    },
+    SessionFailed {
+        reason: String,
+    },
+    // No session ever got as far as subscribing within the startup budget, and the client
+    // has stopped; `reason` is the last attempt's error.
+    StartupFailed {
+        route: String,
+        attempts: u32,
+        reason: String,
+    },
+    Connected,
+    Disconnected {
+        reason: String,
+    },
+    // Published on every connection state transition.
+    Status(ConnectionState),
+    Buses {
+        buses: Vec<BusPosition>,
+        decode_failures: u64,
+        received_at_unix_ms: i64,
+    },
+    // Only published when the client is built with raw_payloads(true).
+    RawPayloads {
+        event: String,
+        payloads: Vec<RawPayload>,
+    },
+    // Derived downstream (see publish) rather than read off the socket.
+    Trip(TripEvent),
+    Presence(PresenceEvent),
+}
+
+// How run_session ended, which decides the wait before the next attempt.
+enum SessionEnd {
+    // The socket got as far as a successful subscribe.
+    Subscribed,
+    Failed,
+    // The kiosk rate-limited or refused us and this is how long to wait.
+    Throttled(Duration),
+}
+
+#[derive(Debug, Clone)]
+struct ClientConfig {
+    socket_url: String,
+    kiosk_url: String,
+    provider: Provider,
+    route: String,
+    reload_interval: Duration,
+    reload_offset: Option<Duration>,
+    degraded_after: Duration,
+    reconnect: bool,
+    startup_attempts: u32,
+    raw_payloads: bool,
+    capture_extra: bool,
+    field_map: Arc<FieldMap>,
+    payload_log: Option<PayloadLog>,
+    parse_failures: ParseFailureLog,
+    schema: SchemaMonitor,
+    max_decompressed_bytes: usize,
+    timezone: Option<Tz>,
+    data_event: String,
+    reload_event: String,
+    user_agent: String,
+    headers: Vec<(String, String)>,
+    tls: TlsOptions,
+    proxy: ProxyOptions,
+    session_store: Option<SessionStore>,
+    session_extractors: SessionExtractors,
+}
+
+#[derive(Debug, Clone)]
+pub struct RapidbroClientBuilder {
+    config: ClientConfig,
+    emit_limiter: EmitLimiter,
+    cancellation: Option<CancellationToken>,
+    sinks: SinkSet,
+}
+
+impl Default for RapidbroClientBuilder {
+    fn default() -> Self {
+        Self {
+            config: ClientConfig {
+                socket_url: DEFAULT_SOCKET_URL.to_string(),
+                kiosk_url: DEFAULT_KIOSK_URL.to_string(),
+                provider: Provider::PrasaranaRapidKL,
+                route: String::new(),
+                reload_interval: DEFAULT_RELOAD_INTERVAL,
+                reload_offset: None,
+                degraded_after: DEFAULT_DEGRADED_AFTER,
+                reconnect: true,
+                startup_attempts: DEFAULT_STARTUP_ATTEMPTS,
+                raw_payloads: false,
+                capture_extra: false,
+                field_map: Arc::new(FieldMap::rapid_kl()),
+                payload_log: None,
+                parse_failures: ParseFailureLog::default(),
+                schema: SchemaMonitor::default(),
+                max_decompressed_bytes: DEFAULT_MAX_DECOMPRESSED_BYTES,
+                timezone: None,
+                data_event: DEFAULT_DATA_EVENT.to_string(),
+                reload_event: DEFAULT_RELOAD_EVENT.to_string(),
+                user_agent: DEFAULT_USER_AGENT.to_string(),
+                headers: Vec::new(),
+                tls: TlsOptions::default(),
+                proxy: ProxyOptions::default(),
+                session_store: None,
+                session_extractors: SessionExtractors::default(),
+            },
+            emit_limiter: EmitLimiter::default(),
+            cancellation: None,
+            sinks: SinkSet::default(),
+        }
+    }
+}
+
+impl RapidbroClientBuilder {
+    pub fn socket_url(mut self, socket_url: impl Into<String>) -> Self {
+        self.config.socket_url = socket_url.into();
+        self
+    }
+
+    pub fn kiosk_url(mut self, kiosk_url: impl Into<String>) -> Self {
+        self.config.kiosk_url = kiosk_url.into();
+        self
+    }
+
+    pub fn provider(mut self, provider: Provider) -> Self {
+        self.config.provider = provider;
+        self
+    }
+
+    // An empty route subscribes to every bus the provider publishes.
+    pub fn route(mut self, route: impl Into<String>) -> Self {
+        self.config.route = route.into();
+        self
+    }
+
+    pub fn reload_interval(mut self, reload_interval: Duration) -> Self {
+        self.config.reload_interval = reload_interval;
+        self
+    }
+
+    // Delay before the first periodic reload after connecting; see spread_offset. Later
+    // reloads follow every reload_interval, jittered by ±10%.
+    pub fn reload_offset(mut self, reload_offset: Duration) -> Self {
+        self.config.reload_offset = Some(reload_offset);
+        self
+    }
+
+    // A connected socket that delivers nothing for this long is reported as Degraded.
+    pub fn degraded_after(mut self, degraded_after: Duration) -> Self {
+        self.config.degraded_after = degraded_after;
+        self
+    }
+
+    pub fn reconnect(mut self, reconnect: bool) -> Self {
+        self.config.reconnect = reconnect;
+        self
+    }
+
+    // Failed sessions tolerated before the first one subscribes, e.g. while DNS or the
+    // network is still coming up in a container. 0 retries forever. Once a session has
+    // subscribed, reconnects are never given up on.
+    pub fn startup_attempts(mut self, startup_attempts: u32) -> Self {
+        self.config.startup_attempts = startup_attempts;
+        self
+    }
+
+    pub fn raw_payloads(mut self, raw_payloads: bool) -> Self {
+        self.config.raw_payloads = raw_payloads;
+        self
+    }
+
+    // Keep feed fields BusPosition doesn't model in BusPosition::extra.
+    pub fn capture_extra_fields(mut self, capture_extra: bool) -> Self {
+        self.config.capture_extra = capture_extra;
+        self
+    }
+
+    // For providers whose payload keys differ from the kiosk's; see FieldMap.
+    pub fn field_map(mut self, field_map: FieldMap) -> Self {
+        self.config.field_map = Arc::new(field_map);
+        self
+    }
+
+    // Also write every decompressed payload to a rotated file; see PayloadLog.
+    pub fn payload_log(mut self, payload_log: Option<PayloadLog>) -> Self {
+        self.config.payload_log = payload_log;
+        self
+    }
+
+    // Where payloads that fail to decode are kept; share one log across clients to see
+    // every route's failures together.
+    pub fn parse_failures(mut self, parse_failures: ParseFailureLog) -> Self {
+        self.config.parse_failures = parse_failures;
+        self
+    }
+
+    // Reports payload fields that are new, missing or of the wrong type; share one monitor
+    // across clients to count them together. A strict monitor fails such payloads.
+    pub fn schema_monitor(mut self, schema: SchemaMonitor) -> Self {
+        self.config.schema = schema;
+        self
+    }
+
+    // Payload values that inflate past this many bytes fail to decode (DecodeError::TooLarge)
+    // instead of being read into memory; DEFAULT_MAX_DECOMPRESSED_BYTES by default.
+    pub fn max_decompressed_bytes(mut self, limit: usize) -> Self {
+        self.config.max_decompressed_bytes = limit;
+        self
+    }
+
+    // Adds BusPosition::local_time in this zone, e.g. DEFAULT_TIMEZONE; off by default.
+    pub fn timezone(mut self, timezone: Option<Tz>) -> Self {
+        self.config.timezone = timezone;
+        self
+    }
+
+    // Event the server sends positions on; other events are only logged.
+    pub fn data_event(mut self, data_event: impl Into<String>) -> Self {
+        self.config.data_event = data_event.into();
+        self
+    }
+
+    // Event emitted to subscribe and to keep the subscription alive.
+    pub fn reload_event(mut self, reload_event: impl Into<String>) -> Self {
+        self.config.reload_event = reload_event.into();
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.config.user_agent = user_agent.into();
+        self
+    }
+
+    // Sent on the kiosk fetch and the Socket.IO handshake, e.g. Origin or Referer.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.config.headers.push((name.into(), value.into()));
+        self
+    }
+
+    // Applied to both the kiosk fetch and the Socket.IO connection.
+    pub fn tls(mut self, tls: TlsOptions) -> Self {
+        self.config.tls = tls;
+        self
+    }
+
+    // Applied to the kiosk fetch. The socket switches to the polling transport when the
+    // proxy is also in HTTPS_PROXY, since the websocket transport always dials direct.
+    pub fn proxy(mut self, proxy: ProxyOptions) -> Self {
+        self.config.proxy = proxy;
+        self
+    }
+
+    // Reuse the last working session (and its kiosk cookies) across restarts. Share one
+    // store between all clients writing to the same file.
+    pub fn session_store(mut self, session_store: Option<SessionStore>) -> Self {
+        self.config.session_store = session_store;
+        self
+    }
+
+    // How the sid, prm and route are read off the kiosk page; see SessionExtractors.
+    pub fn session_extractors(mut self, session_extractors: SessionExtractors) -> Self {
+        self.config.session_extractors = session_extractors;
+        self
+    }
+
+    // Pass the same limiter to every client that should share one emit budget.
+    pub fn emit_limiter(mut self, emit_limiter: EmitLimiter) -> Self {
+        self.emit_limiter = emit_limiter;
+        self
+    }
+
+    // Cancelling `token` stops every client built from here on, as `stop` does; a client's
+    // own `stop` doesn't cancel it. For shutting down with the service embedding the client.
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    // Every batch of positions is also handed to `sink` while the client runs; see Sink.
+    // Repeat for several.
+    pub fn sink(mut self, sink: Box<dyn Sink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    // Checks the options together; the defaults build a client for every bus, as
+    // `RapidbroClient::builder().build()`.
+    pub fn build(self) -> Result<RapidbroClient, ClientConfigError> {
+        self.validate()?;
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let stop = self
+            .cancellation
+            .as_ref()
+            .map(CancellationToken::child_token)
+            .unwrap_or_default();
+        // Checked by validate.
+        let default_headers: HeaderMap = self
+            .config
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((
+                    HeaderName::from_bytes(name.as_bytes()).ok()?,
+                    HeaderValue::from_str(value).ok()?,
+                ))
+            })
+            .collect();
+        let cookies = Arc::new(Jar::default());
+        let builder = self.config.tls.apply(reqwest::Client::builder());
+        let http = self
+            .config
+            .proxy
+            .apply(builder)
+            .cookie_provider(cookies.clone())
+            .user_agent(self.config.user_agent.as_str())
+            .default_headers(default_headers)
+            .build()
+            .map_err(|error| ClientConfigError::HttpClient(error.to_string()))?;
+        Ok(RapidbroClient {
+            events,
+            http,
+            cookies,
+            cache_tried: Arc::new(AtomicBool::new(false)),
+            emit_limiter: self.emit_limiter,
+            sinks: self.sinks,
+            stop: stop.clone(),
+            _stop_on_drop: Arc::new(stop.drop_guard()),
+            connection: Arc::new(Mutex::new(ConnectionMachine::new())),
+            frames: BoundedQueue::new(
+                DEFAULT_FRAME_QUEUE_CAPACITY,
+                OverflowPolicy::DropOldest,
+                Duration::ZERO,
+            )
+            .named("socket_frames"),
+            session_info: Arc::new(Mutex::new(SessionInfo {
+                route: self.config.route.clone(),
+                ..SessionInfo::default()
+            })),
+            config: Arc::new(self.config),
+        })
+    }
+
+    fn validate(&self) -> Result<(), ClientConfigError> {
+        let config = &self.config;
+        if !is_valid_route(&config.route) {
+            return Err(ClientConfigError::InvalidRoute(config.route.clone()));
+        }
+        if config.reload_interval.is_zero() {
+            return Err(ClientConfigError::ZeroReloadInterval);
+        }
+        if config.max_decompressed_bytes == 0 {
+            return Err(ClientConfigError::ZeroDecompressionLimit);
+        }
+        for (name, url) in [
+            ("socket URL", &config.socket_url),
+            ("kiosk URL", &config.kiosk_url),
+        ] {
+            check_url(name, url)?;
+        }
+        if config.data_event.is_empty() {
+            return Err(ClientConfigError::EmptyEventName("data event"));
+        }
+        if config.reload_event.is_empty() {
+            return Err(ClientConfigError::EmptyEventName("reload event"));
+        }
+        for (name, value) in &config.headers {
+            let invalid = |reason: String| ClientConfigError::InvalidHeader {
+                name: name.clone(),
+                reason,
+            };
+            HeaderName::from_bytes(name.as_bytes()).map_err(|error| invalid(error.to_string()))?;
+            HeaderValue::from_str(value).map_err(|error| invalid(error.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+// Empty is valid: the client then follows every bus.
+pub fn is_valid_route(route: &str) -> bool {
+    route.len() <= MAX_ROUTE_LEN
+        && route
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || character == '-')
+}
+
+fn check_url(name: &'static str, url: &str) -> Result<(), ClientConfigError> {
+    let invalid = |reason: String| ClientConfigError::InvalidUrl {
+        name,
+        url: url.to_string(),
+        reason,
+    };
+    let parsed = Url::parse(url).map_err(|error| invalid(error.to_string()))?;
+    if !matches!(parsed.scheme(), "http" | "https" | "ws" | "wss") {
+        return Err(invalid(format!("unsupported scheme `{}`", parsed.scheme())));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct RapidbroClient {
+    config: Arc<ClientConfig>,
+    events: broadcast::Sender<ClientEvent>,
+    http: reqwest::Client,
+    cookies: Arc<Jar>,
+    // The state file is only consulted for the first session; reconnects fetch afresh.
+    cache_tried: Arc<AtomicBool>,
+    emit_limiter: EmitLimiter,
+    sinks: SinkSet,
+    // A child of the builder's cancellation token, if it was given one.
+    stop: CancellationToken,
+    // Dropping the last clone stops the client, which ends its `updates` streams.
+    _stop_on_drop: Arc<DropGuard>,
+    session_info: Arc<Mutex<SessionInfo>>,
+    connection: Arc<Mutex<ConnectionMachine>>,
+    // Socket callback -> decode pipeline; drops the oldest frame when decoding falls behind.
+    frames: BoundedQueue<RawFrame>,
+}
+
+impl RapidbroClient {
+    pub fn builder() -> RapidbroClientBuilder {
+        RapidbroClientBuilder::default()
+    }
+
+    // Events published after this call are delivered in order. Slow consumers skip
+    // whatever fell out of the channel instead of blocking the socket.
+    pub async fn subscribe(&self) -> BoxStream<'static, ClientEvent> {
+        let receiver = self.events.subscribe();
+        stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+        .boxed()
+    }
+
+    // The same positions as `subscribe`, one per item, as a Stream that can be cloned and
+    // moved into spawned tasks. Falling behind yields BusUpdate::Gap rather than silently
+    // skipping.
+    pub fn updates(&self) -> BusUpdates {
+        BusUpdates::new(self.events.clone(), self.stop.clone())
+    }
+
+    // Connects, subscribes and keeps reloading until the socket drops. With reconnect
+    // enabled this only returns after `stop` or cancellation; otherwise it returns after
+    // the first disconnect.
+    pub async fn run(&self) {
+        let options = DecodeOptions {
+            raw_payloads: self.config.raw_payloads,
+            capture_extra: self.config.capture_extra,
+            field_map: self.config.field_map.clone(),
+            payload_log: self.config.payload_log.clone(),
+            route: self.config.route.clone(),
+            parse_failures: self.config.parse_failures.clone(),
+            timezone: self.config.timezone,
+            schema: Some(self.config.schema.clone()),
+            session_info: Some(self.session_info.clone()),
+            max_decompressed_bytes: self.config.max_decompressed_bytes,
+        };
+        let pipeline = tokio::spawn(run_decode_pipeline(
+            self.frames.clone(),
+            self.events.clone(),
+            options.clone(),
+        ));
+        let sinks = (!self.sinks.is_empty()).then(|| {
+            let done = CancellationToken::new();
+            let task = tokio::spawn(run_sinks(
+                self.sinks.clone(),
+                self.events.subscribe(),
+                done.clone(),
+            ));
+            (task, done)
+        });
+        self.run_sessions().await;
+        self.save_session_state();
+        self.transition(ConnectionInput::Stopped);
+        pipeline.abort();
+        // Frames the callback queued just before the last disconnect still get decoded.
+        for frame in self.frames.drain(usize::MAX) {
+            decode_frame(frame, &self.events, &options);
+        }
+        if let Some((task, done)) = sinks {
+            done.cancel();
+            let _ = task.await;
+        }
+    }
+
+    // (sink name, failed calls so far) for each registered sink.
+    pub fn sink_errors(&self) -> Vec<(String, u64)> {
+        self.sinks.error_counts()
+    }
+
+    async fn run_sessions(&self) {
+        let mut backoff = Backoff::default().with_jitter(RECONNECT_JITTER);
+        let mut started = false;
+        let mut startup_failures = 0;
+
+        while !self.is_stopped() {
+            let end = self.run_session().await;
+            if !self.config.reconnect || self.is_stopped() {
+                return;
+            }
+
+            if !started && !matches!(end, SessionEnd::Subscribed) {
+                startup_failures += 1;
+                if startup_failures == self.config.startup_attempts {
+                    let reason = lock_session_info(&self.session_info)
+                        .last_error
+                        .clone()
+                        .unwrap_or_else(|| "no session established".to_string());
+                    let _ = self.events.send(ClientEvent::StartupFailed {
+                        route: self.config.route.clone(),
+                        attempts: startup_failures,
+                        reason,
+                    });
+                    return;
+                }
+            }
+            let wait = match end {
+                SessionEnd::Subscribed => {
+                    started = true;
+                    backoff.reset();
+                    continue;
+                }
+                SessionEnd::Failed => backoff.next_delay(),
+                // The kiosk said how long to stay away; retrying sooner only extends the block.
+                SessionEnd::Throttled(wait) => wait.max(backoff.next_delay()),
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = self.stopped() => return,
+            }
+        }
+    }
+
+    // Disconnects and makes `run` return, abandoning a session that is still being set up.
+    pub fn stop(&self) {
+        self.stop.cancel();
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        lock_connection(&self.connection).state()
+    }
+
+    fn transition(&self, input: ConnectionInput) {
+        apply_connection_input(&self.connection, &self.events, input);
+    }
+
+    pub fn session_info(&self) -> SessionInfo {
+        lock_session_info(&self.session_info).clone()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.is_cancelled()
+    }
+
+    async fn stopped(&self) {
+        self.stop.cancelled().await;
+    }
+
+    // None if the client is stopped first; for the steps of setting up a session, which
+    // can otherwise hang on an unresponsive kiosk or socket for a long time.
+    async fn unless_stopped<T>(&self, step: impl std::future::Future<Output = T>) -> Option<T> {
+        tokio::select! {
+            biased;
+            _ = self.stopped() => None,
+            value = step => Some(value),
+        }
+    }
+
+    async fn run_session(&self) -> SessionEnd {
+        self.transition(ConnectionInput::SessionRefreshStarted);
+        let Some(resolved) = self.unless_stopped(self.resolve_session()).await else {
+            return SessionEnd::Failed;
+        };
+        let (session, from_cache) = match resolved {
+            Ok((session, from_cache)) => {
+                let mut info = lock_session_info(&self.session_info);
+                info.sid = Some(session.sid.clone());
+                info.prm = Some(session.prm.clone());
+                info.last_fetch_unix_ms = Some(now_unix_ms());
+                (session, from_cache)
+            }
+            Err(error) => {
+                let reason = error.to_string();
+                lock_session_info(&self.session_info).last_error = Some(reason.clone());
+                let _ = self.events.send(ClientEvent::SessionFailed { reason });
+                self.transition(ConnectionInput::SessionFailed);
+                return match error.retry_after() {
+                    Some(wait) => SessionEnd::Throttled(wait),
+                    None => SessionEnd::Failed,
+                };
+            }
+        };
+        let _ = self.events.send(ClientEvent::SessionEstablished {
+            route: session.route.clone(),
+        });
+
+        let disconnect_notify = Arc::new(Notify::new());
+        let on_any_frames = self.frames.clone();
+        // Taken by the first payload so the handshake latency is recorded once per session.
+        let connect_started = Arc::new(Mutex::new(Some(Instant::now())));
+        let on_any_connect_started = connect_started.clone();
+        let data_event = self.config.data_event.clone();
+        let max_decompressed_bytes = self.config.max_decompressed_bytes;
+
+        let on_any = move |event: rust_socketio::Event,
+                           payload: Payload,
+                           _socket: rust_socketio::asynchronous::Client| {
+            let frames = on_any_frames.clone();
+            let connect_started = on_any_connect_started.clone();
+            let is_data_event = event.as_str() == data_event;
+            async move {
+                if !is_data_event {
+                    log_other_event(event.as_str(), &payload, max_decompressed_bytes);
+                    return;
+                }
+                // Whether the frame counts as data for the silence check is up to the
+                // decode pipeline, which records it in session_info once it has decoded.
+                let received_at_unix_ms = now_unix_ms();
+                let first_payload_started = connect_started
+                    .lock()
+                    .ok()
+                    .and_then(|mut started| started.take());
+                if let Some(started) = first_payload_started {
+                    histogram!(FIRST_PAYLOAD_SECONDS).record(started.elapsed().as_secs_f64());
+                }
+                frames
+                    .push(RawFrame {
+                        event: event.as_str().to_string(),
+                        payload,
+                        received_at_unix_ms,
+                    })
+                    .await;
+            }
+            .boxed()
+        };
+
+        let disconnect_events = self.events.clone();
+        let disconnect_signal = disconnect_notify.clone();
+        let disconnect_connection = self.connection.clone();
+        let error_events = self.events.clone();
+        let error_signal = disconnect_notify.clone();
+        let error_connection = self.connection.clone();
+
+        // Only the polling transport goes through reqwest and so through HTTPS_PROXY.
+        //
+        // The websocket can't negotiate permessage-deflate: rust_socketio's transport is
+        // built on tungstenite, which doesn't implement the extension. The kiosk already
+        // gzips each batch, so it would only win back the base64 overhead, about a
+        // quarter of the frame.
+        let transport = if self.config.proxy.covers_socket() {
+            TransportType::Polling
+        } else {
+            TransportType::Websocket
+        };
+        let mut socket_builder = ClientBuilder::new(self.config.socket_url.as_str())
+            .transport_type(transport)
+            .opening_header("User-Agent", self.config.user_agent.as_str());
+        for (name, value) in &self.config.headers {
+            socket_builder = socket_builder.opening_header(name.as_str(), value.as_str());
+        }
+        match self.config.tls.connector() {
+            Ok(Some(connector)) => socket_builder = socket_builder.tls_config(connector),
+            Ok(None) => {}
+            Err(reason) => {
+                let _ = self.events.send(ClientEvent::SessionFailed { reason });
+                self.transition(ConnectionInput::SessionFailed);
+                return SessionEnd::Failed;
+            }
+        }
+
+        let socket = socket_builder
+            .on_any(on_any)
+            .on("disconnect", move |_, _| {
+                let events = disconnect_events.clone();
+                let notify = disconnect_signal.clone();
+                let connection = disconnect_connection.clone();
+                async move {
+                    let _ = events.send(ClientEvent::Disconnected {
+                        reason: "Socket disconnected".to_string(),
+                    });
+                    apply_connection_input(&connection, &events, ConnectionInput::Disconnected);
+                    notify.notify_one();
+                }
+                .boxed()
+            })
+            .on("error", move |_, _| {
+                let events = error_events.clone();
+                let notify = error_signal.clone();
+                let connection = error_connection.clone();
+                async move {
+                    let _ = events.send(ClientEvent::Disconnected {
+                        reason: "Socket error event".to_string(),
+                    });
+                    apply_connection_input(&connection, &events, ConnectionInput::Disconnected);
+                    notify.notify_one();
+                }
+                .boxed()
+            })
+            .connect();
+        let Some(socket) = self.unless_stopped(socket).await else {
+            return SessionEnd::Failed;
+        };
+
+        let socket = match socket {
+            Ok(socket) => socket,
+            Err(error) => {
+                self.publish_disconnect(
+                    format!("Socket connection failed: {}", error),
+                    ConnectionInput::Disconnected,
+                );
+                return SessionEnd::Failed;
+            }
+        };
+
+        self.emit_limiter.acquire().await;
+        if let Err(error) = socket
+            .emit(
+                self.config.reload_event.as_str(),
+                self.reload_payload(&session),
+            )
+            .await
+        {
+            self.publish_disconnect(
+                format!("Socket subscribe emit failed: {}", error),
+                ConnectionInput::EmitFailed,
+            );
+            return SessionEnd::Failed;
+        }
+
+        lock_session_info(&self.session_info).connected = true;
+        let _ = self.events.send(ClientEvent::Connected);
+        self.transition(ConnectionInput::Connected);
+        let connected_at_unix_ms = now_unix_ms();
+        let mut awaiting_cached_data = from_cache;
+        let cached_grace = tokio::time::sleep(CACHED_SESSION_GRACE);
+        tokio::pin!(cached_grace);
+        let mut silence_check = tokio::time::interval(SILENCE_CHECK_INTERVAL);
+        silence_check.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        let first_reload = self
+            .config
+            .reload_offset
+            .unwrap_or_else(|| self.next_reload_delay());
+        let next_reload = tokio::time::sleep(first_reload);
+        tokio::pin!(next_reload);
+
+        loop {
+            tokio::select! {
+                _ = disconnect_notify.notified() => {
+                    break;
+                }
+                _ = self.stopped() => {
+                    let _ = socket.disconnect().await;
+                    break;
+                }
+                _ = &mut next_reload => {
+                    // A throttled reload waits for its token rather than being skipped.
+                    self.emit_limiter.acquire().await;
+                    if let Err(error) = socket.emit(self.config.reload_event.as_str(), self.reload_payload(&session)).await {
+                        self.publish_disconnect(
+                            format!("Periodic socket reload emit failed: {}", error),
+                            ConnectionInput::EmitFailed,
+                        );
+                        break;
+                    }
+                    next_reload
+                        .as_mut()
+                        .reset(tokio::time::Instant::now() + self.next_reload_delay());
+                }
+                _ = &mut cached_grace, if awaiting_cached_data => {
+                    awaiting_cached_data = false;
+                    let had_data = lock_session_info(&self.session_info)
+                        .last_data_unix_ms
+                        .is_some_and(|last_data| last_data >= connected_at_unix_ms);
+                    if !had_data {
+                        if let Some(store) = &self.config.session_store {
+                            store.forget(&self.config.route);
+                        }
+                        let _ = socket.disconnect().await;
+                        self.publish_disconnect(
+                            "Cached session produced no data, fetching a fresh one".to_string(),
+                            ConnectionInput::Disconnected,
+                        );
+                        break;
+                    }
+                }
+                _ = silence_check.tick() => {
+                    let last_data_unix_ms = lock_session_info(&self.session_info)
+                        .last_data_unix_ms
+                        .unwrap_or(connected_at_unix_ms)
+                        .max(connected_at_unix_ms);
+                    // Recovery is noticed here too rather than per frame, since only the
+                    // pipeline knows whether a frame decoded.
+                    if now_unix_ms() - last_data_unix_ms >= self.config.degraded_after.as_millis() as i64 {
+                        self.transition(ConnectionInput::SilenceTimeout);
+                    } else {
+                        self.transition(ConnectionInput::MessageReceived);
+                    }
+                }
+            }
+        }
+
+        lock_session_info(&self.session_info).connected = false;
+        drop(socket);
+        SessionEnd::Subscribed
+    }
+
+    // The all-buses feed works with an empty sid; a single route needs the sid the
+    // kiosk page hands out for it.
+    // The bool is whether the session came from the state file.
+    async fn resolve_session(&self) -> Result<(Session, bool), RapidbroError> {
+        if self.config.route.is_empty() {
+            return Ok((
+                Session {
+                    sid: String::new(),
+                    prm: String::new(),
+                    route: String::new(),
+                },
+                false,
+            ));
+        }
+
+        if let Some(cached) = self.take_cached_session() {
+            return Ok((cached, true));
+        }
+
+        let session = fetch_session(
+            &self.http,
+            &self.config.kiosk_url,
+            &self.config.route,
+            &self.config.session_extractors,
+        )
+        .await?;
+        if let Some(store) = &self.config.session_store {
+            store.save(&self.config.route, &session, &self.kiosk_cookies());
+        }
+        Ok((session, false))
+    }
+
+    fn take_cached_session(&self) -> Option<Session> {
+        let store = self.config.session_store.as_ref()?;
+        if self.cache_tried.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        let cached = store.get(&self.config.route)?;
+        if let Ok(kiosk_url) = Url::parse(&self.config.kiosk_url) {
+            for cookie in cached
+                .cookies
+                .split(';')
+                .map(str::trim)
+                .filter(|cookie| !cookie.is_empty())
+            {
+                self.cookies.add_cookie_str(cookie, &kiosk_url);
+            }
+        }
+        println!(
+            "Route {}: reusing cached kiosk session from {}",
+            self.config.route,
+            store.path().display()
+        );
+        Some(cached.session())
+    }
+
+    fn kiosk_cookies(&self) -> String {
+        Url::parse(&self.config.kiosk_url)
+            .ok()
+            .and_then(|kiosk_url| self.cookies.cookies(&kiosk_url))
+            .and_then(|header| header.to_str().ok().map(str::to_string))
+            .unwrap_or_default()
+    }
+
+    // On shutdown, keep the current session if it was producing data, so the next start
+    // can pick it up.
+    pub fn save_session_state(&self) {
+        let Some(store) = &self.config.session_store else {
+            return;
+        };
+        if self.config.route.is_empty() {
+            return;
+        }
+        let info = self.session_info();
+        let (Some(sid), Some(prm)) = (info.sid, info.prm) else {
+            return;
+        };
+        let produced_data = match (info.last_data_unix_ms, info.last_fetch_unix_ms) {
+            (Some(last_data), Some(last_fetch)) => last_data >= last_fetch,
+            _ => false,
+        };
+        if produced_data {
+            let route = store
+                .get(&self.config.route)
+                .map(|cached| cached.route)
+                .unwrap_or_else(|| self.config.route.clone());
+            store.save(
+                &self.config.route,
+                &Session { sid, prm, route },
+                &self.kiosk_cookies(),
+            );
+        }
+    }
+
+    fn next_reload_delay(&self) -> Duration {
+        jittered(self.config.reload_interval, RELOAD_JITTER, random_unit())
+    }
+
+    fn reload_payload(&self, session: &Session) -> serde_json::Value {
+        json!({
+            "sid": session.sid,
+            "uid": "",
+            "provider": self.config.provider.code(),
+            "route": session.route
+        })
+    }
+
+    // Lets downstream stages put derived events on the same channel subscribers read.
+    pub fn publish(&self, event: ClientEvent) {
+        let _ = self.events.send(event);
+    }
+
+    fn publish_disconnect(&self, reason: String, input: ConnectionInput) {
+        {
+            let mut info = lock_session_info(&self.session_info);
+            info.connected = false;
+            info.last_error = Some(reason.clone());
+        }
+        let _ = self.events.send(ClientEvent::Disconnected { reason });
+        self.transition(input);
+    }
+}
+
+// Hands each batch to the sinks until `done`, then shuts them down. Batches already
+// published when `done` is cancelled are still handled, since recv is polled first.
+async fn run_sinks(
+    sinks: SinkSet,
+    mut receiver: broadcast::Receiver<ClientEvent>,
+    done: CancellationToken,
+) {
+    let workers = sinks.spawn();
+    loop {
+        let event = tokio::select! {
+            biased;
+            event = receiver.recv() => event,
+            _ = done.cancelled() => break,
+        };
+        let batch: Vec<BusUpdate> = match event {
+            Ok(ClientEvent::Buses { buses, .. }) => {
+                buses.into_iter().map(BusUpdate::Position).collect()
+            }
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(missed)) => vec![BusUpdate::Gap { missed }],
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if !batch.is_empty() {
+            workers.send(batch).await;
+        }
+    }
+    workers.shutdown().await;
+}
+
+fn lock_connection(
+    connection: &Mutex<ConnectionMachine>,
+) -> std::sync::MutexGuard<'_, ConnectionMachine> {
+    connection.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+// Shared by the client and its socket callbacks, which only hold clones of the pieces.
+fn apply_connection_input(
+    connection: &Mutex<ConnectionMachine>,
+    events: &broadcast::Sender<ClientEvent>,
+    input: ConnectionInput,
+) {
+    let changed = lock_connection(connection).apply(input);
+    if let Some(state) = changed {
+        let _ = events.send(ClientEvent::Status(state));
+    }
+}
+
+fn lock_session_info(session_info: &Mutex<SessionInfo>) -> std::sync::MutexGuard<'_, SessionInfo> {
+    session_info
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+}
+
+// Anything other than the data event is logged with its payload's shape, so a server-side
+// rename or a new notice shows up in the logs. Base64-looking strings are decoded in case
+// positions moved to another event name.
+// Describing the payload is cheap; peeking inside base64+gzip values is not, so that
+// runs on the blocking pool instead of holding up the socket callback.
+fn log_other_event(event: &str, payload: &Payload, max_decompressed_bytes: usize) {
+    match event {
+        // Already reported through the disconnect handler.
+        "disconnect" => return,
+        "reconnect" => println!("Socket reconnect event: {}", describe_payload(payload)),
+        _ => println!(
+            "Unhandled socket event '{}': {}",
+            event,
+            describe_payload(payload)
+        ),
+    }
+    let Payload::Text(values) = payload else {
+        return;
+    };
+    let encoded: Vec<String> = values
+        .iter()
+        .filter_map(|value| value.as_str())
+        .filter(|value| looks_like_base64(value))
+        .map(str::to_string)
+        .collect();
+    if encoded.is_empty() {
+        return;
+    }
+    let event = event.to_string();
+    tokio::task::spawn_blocking(move || {
+        for value in encoded {
+            let Some(raw) = decode_raw_payload_within(&value, max_decompressed_bytes) else {
+                continue;
+            };
+            let preview: String = raw.json.chars().take(LOGGED_JSON_PREVIEW_CHARS).collect();
+            println!(
+                "  '{}' carries {} bytes of base64+gzip JSON: {}",
+                event,
+                raw.json.len(),
+                preview
+            );
+        }
+    });
+}
+
+fn describe_payload(payload: &Payload) -> String {
+    match payload {
+        Payload::Text(values) => {
+            let shapes: Vec<String> = values.iter().map(describe_value).collect();
+            format!("text[{}]", shapes.join(", "))
+        }
+        Payload::Binary(bytes) => format!("binary({} bytes)", bytes.len()),
+        _ => "string".to_string(),
+    }
+}
+
+fn describe_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => "null".to_string(),
+        serde_json::Value::Bool(_) => "bool".to_string(),
+        serde_json::Value::Number(_) => "number".to_string(),
+        serde_json::Value::String(text) => format!("string({} chars)", text.len()),
+        serde_json::Value::Array(items) => format!("array({})", items.len()),
+        serde_json::Value::Object(fields) => {
+            let keys: Vec<&str> = fields.keys().map(String::as_str).collect();
+            format!("object{{{}}}", keys.join(","))
+        }
+    }
+}
+
+fn looks_like_base64(value: &str) -> bool {
+    value.len() >= 16
+        && value.len().is_multiple_of(4)
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'+' | b'/' | b'='))
+}
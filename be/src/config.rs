@@ -0,0 +1,117 @@
+use crate::auth::AllowedOrigins;
+use crate::field_map::FieldMap;
+use crate::validate::BoundingBox;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+// Settings read from the --config TOML file. Every key is optional; one left out falls
+// back to its environment variable or default. Only the `HOT_RELOAD` keys are applied
+// on SIGHUP, the rest are read once at startup.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub bind: Option<String>,
+    pub redis_url: Option<String>,
+    pub archive_dir: Option<String>,
+    pub routes: Option<Vec<String>>,
+    pub validate_coordinates: Option<bool>,
+    pub coordinate_bbox: Option<String>,
+    pub headway_log_seconds: Option<u64>,
+    // Provider key -> BusPosition field, e.g. `lat = "latitude"`.
+    pub field_map: Option<HashMap<String, String>>,
+    // Browser origins allowed to call the API; `["*"]` allows any.
+    pub cors_origins: Option<Vec<String>>,
+    // Keys accepted in X-Api-Key; none leaves the API open.
+    pub api_keys: Option<Vec<String>>,
+}
+
+pub const HOT_RELOAD: [&str; 4] = [
+    "routes",
+    "validate_coordinates",
+    "coordinate_bbox",
+    "headway_log_seconds",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDiff {
+    pub hot: Vec<&'static str>,
+    pub restart: Vec<&'static str>,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.hot.is_empty() && self.restart.is_empty()
+    }
+}
+
+impl FileConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|error| format!("failed to read '{}': {}", path.display(), error))?;
+        let config: FileConfig = toml::from_str(&contents)
+            .map_err(|error| format!("invalid config '{}': {}", path.display(), error))?;
+        if let Some(bbox) = &config.coordinate_bbox {
+            if BoundingBox::parse(bbox).is_none() {
+                return Err(format!(
+                    "invalid config '{}': coordinate_bbox `{}` is not min_lon,min_lat,max_lon,max_lat",
+                    path.display(),
+                    bbox
+                ));
+            }
+        }
+        config
+            .field_map()
+            .map_err(|error| format!("invalid config '{}': {}", path.display(), error))?;
+        if let Some(origins) = &config.cors_origins {
+            AllowedOrigins::new(origins.clone())
+                .map_err(|error| format!("invalid config '{}': {}", path.display(), error))?;
+        }
+        Ok(config)
+    }
+
+    pub fn field_map(&self) -> Result<FieldMap, String> {
+        match &self.field_map {
+            Some(entries) => FieldMap::from_entries(entries),
+            None => Ok(FieldMap::rapid_kl()),
+        }
+    }
+
+    pub fn coordinate_bounds(&self) -> Option<BoundingBox> {
+        self.coordinate_bbox.as_deref().and_then(BoundingBox::parse)
+    }
+
+    // Names the settings that differ in `new`, split by whether they can be hot-applied.
+    pub fn diff(&self, new: &FileConfig) -> ConfigDiff {
+        let changed = [
+            ("bind", self.bind != new.bind),
+            ("redis_url", self.redis_url != new.redis_url),
+            ("archive_dir", self.archive_dir != new.archive_dir),
+            ("routes", self.routes != new.routes),
+            (
+                "validate_coordinates",
+                self.validate_coordinates != new.validate_coordinates,
+            ),
+            (
+                "coordinate_bbox",
+                self.coordinate_bbox != new.coordinate_bbox,
+            ),
+            (
+                "headway_log_seconds",
+                self.headway_log_seconds != new.headway_log_seconds,
+            ),
+            ("field_map", self.field_map != new.field_map),
+            ("cors_origins", self.cors_origins != new.cors_origins),
+            ("api_keys", self.api_keys != new.api_keys),
+        ];
+        let mut diff = ConfigDiff::default();
+        for (name, _) in changed.into_iter().filter(|(_, changed)| *changed) {
+            if HOT_RELOAD.contains(&name) {
+                diff.hot.push(name);
+            } else {
+                diff.restart.push(name);
+            }
+        }
+        diff
+    }
+}
@@ -0,0 +1,103 @@
+use serde::Serialize;
+use std::fmt;
+#[cfg(feature = "server")]
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    // Built but not run yet.
+    Idle,
+    // Fetching the kiosk session before (re)connecting the socket.
+    SessionRefreshing,
+    Connected,
+    // Connected, but no data has arrived for longer than the silence threshold.
+    Degraded,
+    // The last attempt failed or the socket dropped; waiting to retry.
+    Reconnecting,
+    Stopped,
+}
+
+impl ConnectionState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConnectionState::Idle => "idle",
+            ConnectionState::SessionRefreshing => "session_refreshing",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Degraded => "degraded",
+            ConnectionState::Reconnecting => "reconnecting",
+            ConnectionState::Stopped => "stopped",
+        }
+    }
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionInput {
+    SessionRefreshStarted,
+    SessionFailed,
+    Connected,
+    MessageReceived,
+    EmitFailed,
+    Disconnected,
+    // Fired by the client when nothing has arrived for the silence threshold.
+    SilenceTimeout,
+    Stopped,
+}
+
+// The client's lifecycle as a pure state machine; the client feeds it inputs and
+// publishes whatever transitions come out.
+#[derive(Debug, Clone)]
+pub struct ConnectionMachine {
+    state: ConnectionState,
+}
+
+impl Default for ConnectionMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConnectionMachine {
+    pub fn new() -> Self {
+        Self {
+            state: ConnectionState::Idle,
+        }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    // Returns the new state when the input changed it. Stopped is final.
+    pub fn apply(&mut self, input: ConnectionInput) -> Option<ConnectionState> {
+        use ConnectionInput as Input;
+        use ConnectionState as State;
+
+        let next = match (self.state, input) {
+            (State::Stopped, _) => return None,
+            (_, Input::Stopped) => State::Stopped,
+            (_, Input::SessionRefreshStarted) => State::SessionRefreshing,
+            (_, Input::SessionFailed | Input::EmitFailed | Input::Disconnected) => {
+                State::Reconnecting
+            }
+            (_, Input::Connected) => State::Connected,
+            (State::Degraded, Input::MessageReceived) => State::Connected,
+            (State::Connected, Input::SilenceTimeout) => State::Degraded,
+            // Late messages and timeouts outside a live connection don't move anything.
+            (state, Input::MessageReceived | Input::SilenceTimeout) => state,
+        };
+
+        if next == self.state {
+            return None;
+        }
+        self.state = next;
+        Some(next)
+    }
+}
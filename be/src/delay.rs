@@ -0,0 +1,188 @@
+use serde::Serialize;
+#[cfg(feature = "server")]
+use utoipa::ToSchema;
+
+pub const DEFAULT_AMBIGUITY_MARGIN_SECS: i64 = 180;
+// Anything further off schedule than this is a different trip, not a late one.
+const MAX_PLAUSIBLE_DEVIATION_SECS: i64 = 3_600;
+const SECONDS_PER_DAY: i64 = 86_400;
+
+#[derive(Debug, Clone)]
+pub struct ScheduledStop {
+    pub stop_id: String,
+    pub progress_m: f64,
+    // Seconds after the trip's start.
+    pub offset_secs: i64,
+}
+
+// One stop pattern and every time it starts: a single start for timetabled trips,
+// many for frequency-based ones.
+#[derive(Debug, Clone)]
+pub struct ScheduledTrip {
+    pub trip_id: String,
+    pub service_id: String,
+    pub direction_id: Option<u8>,
+    // In stop_sequence order.
+    pub stops: Vec<ScheduledStop>,
+    // Seconds after local midnight; may exceed 24h as GTFS allows.
+    pub start_times: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+pub struct DelayEstimate {
+    pub trip_id: String,
+    pub scheduled_start: String,
+    pub next_stop_id: String,
+    pub delay_min: f64,
+    pub confidence: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum MatchOutcome {
+    Matched {
+        #[serde(flatten)]
+        estimate: DelayEstimate,
+    },
+    // Several trips fit about equally well; guessing would make the delay meaningless.
+    Ambiguous {
+        candidates: usize,
+    },
+    NoCandidate,
+}
+
+struct Candidate<'a> {
+    trip: &'a ScheduledTrip,
+    start_secs: i64,
+    deviation_secs: i64,
+    next_stop_id: &'a str,
+}
+
+// Matches a vehicle at `progress_m` along the route shape to the scheduled trip whose
+// timetable puts a bus there closest to `now_secs`, and reports how late it runs. The
+// match is refused when the runner-up is within `ambiguity_margin_secs` of the best.
+pub fn match_trip<'a>(
+    trips: impl IntoIterator<Item = &'a ScheduledTrip>,
+    direction_id: Option<u8>,
+    progress_m: f64,
+    now_secs: i64,
+    ambiguity_margin_secs: i64,
+) -> MatchOutcome {
+    let mut candidates: Vec<Candidate> = Vec::new();
+
+    for trip in trips {
+        if direction_id.is_some()
+            && trip.direction_id.is_some()
+            && direction_id != trip.direction_id
+        {
+            continue;
+        }
+
+        for pair in trip.stops.windows(2) {
+            let (from, to) = (&pair[0], &pair[1]);
+            let (low, high) = if from.progress_m <= to.progress_m {
+                (from.progress_m, to.progress_m)
+            } else {
+                (to.progress_m, from.progress_m)
+            };
+            if !(low..=high).contains(&progress_m) {
+                continue;
+            }
+
+            let fraction = if high > low {
+                (progress_m - from.progress_m) / (to.progress_m - from.progress_m)
+            } else {
+                0.0
+            };
+            let offset_secs = from.offset_secs
+                + ((to.offset_secs - from.offset_secs) as f64 * fraction).round() as i64;
+
+            for &start_secs in &trip.start_times {
+                let scheduled_secs = start_secs + offset_secs;
+                // Trips after midnight are scheduled as 24:xx; try both readings of now.
+                let deviation_secs = [
+                    now_secs - scheduled_secs,
+                    now_secs + SECONDS_PER_DAY - scheduled_secs,
+                ]
+                .into_iter()
+                .min_by_key(|deviation| deviation.abs())
+                .unwrap_or(i64::MAX);
+                if deviation_secs.abs() > MAX_PLAUSIBLE_DEVIATION_SECS {
+                    continue;
+                }
+
+                // Loop routes pass the same point twice; keep the better reading per run.
+                match candidates.iter_mut().find(|candidate| {
+                    std::ptr::eq(candidate.trip, trip) && candidate.start_secs == start_secs
+                }) {
+                    Some(existing) if existing.deviation_secs.abs() <= deviation_secs.abs() => {}
+                    Some(existing) => {
+                        existing.deviation_secs = deviation_secs;
+                        existing.next_stop_id = &to.stop_id;
+                    }
+                    None => candidates.push(Candidate {
+                        trip,
+                        start_secs,
+                        deviation_secs,
+                        next_stop_id: &to.stop_id,
+                    }),
+                }
+            }
+        }
+    }
+
+    candidates.sort_by_key(|candidate| candidate.deviation_secs.abs());
+    let Some(best) = candidates.first() else {
+        return MatchOutcome::NoCandidate;
+    };
+
+    let best_abs = best.deviation_secs.abs();
+    let confidence = match candidates.get(1) {
+        Some(runner_up) => {
+            let runner_up_abs = runner_up.deviation_secs.abs();
+            if runner_up_abs - best_abs < ambiguity_margin_secs {
+                return MatchOutcome::Ambiguous {
+                    candidates: candidates
+                        .iter()
+                        .filter(|candidate| {
+                            candidate.deviation_secs.abs() - best_abs < ambiguity_margin_secs
+                        })
+                        .count(),
+                };
+            }
+            1.0 - best_abs as f64 / runner_up_abs as f64
+        }
+        None => 1.0,
+    };
+
+    MatchOutcome::Matched {
+        estimate: DelayEstimate {
+            trip_id: best.trip.trip_id.clone(),
+            scheduled_start: format_gtfs_time(best.start_secs),
+            next_stop_id: best.next_stop_id.to_string(),
+            delay_min: (best.deviation_secs as f64 / 6.0).round() / 10.0,
+            confidence: (confidence * 100.0).round() / 100.0,
+        },
+    }
+}
+
+// Parses GTFS "HH:MM:SS", where hours may run past 24 for trips after midnight.
+pub fn parse_gtfs_time(value: &str) -> Option<i64> {
+    let mut parts = value.trim().split(':').map(|part| part.parse::<i64>().ok());
+    let (hours, minutes, seconds) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || minutes >= 60 || seconds >= 60 {
+        return None;
+    }
+    Some(hours * 3_600 + minutes * 60 + seconds)
+}
+
+pub fn format_gtfs_time(secs: i64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3_600,
+        (secs % 3_600) / 60,
+        secs % 60
+    )
+}
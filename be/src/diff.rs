@@ -0,0 +1,126 @@
+use crate::feed::BusPosition;
+use crate::geo::haversine_meters;
+use std::collections::HashMap;
+use std::fmt;
+
+pub const DEFAULT_LOST_AFTER_BATCHES: u64 = 3;
+const MIN_MOVE_METERS: f64 = 25.0;
+const MIN_SPEED_CHANGE_KMH: f64 = 1.0;
+
+#[derive(Debug, Clone)]
+pub enum VehicleChange {
+    Appeared(BusPosition),
+    Changed {
+        bus: BusPosition,
+        moved_meters: f64,
+        previous_speed: f64,
+        previous_route: String,
+    },
+    Lost(BusPosition),
+}
+
+impl fmt::Display for VehicleChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VehicleChange::Appeared(bus) => {
+                write!(f, "{} route={} appeared", bus.bus_no, bus.route)
+            }
+            VehicleChange::Lost(bus) => write!(f, "{} route={} lost", bus.bus_no, bus.route),
+            VehicleChange::Changed {
+                bus,
+                moved_meters,
+                previous_speed,
+                previous_route,
+            } => {
+                write!(f, "{} route={}", bus.bus_no, bus.route)?;
+                if *previous_route != bus.route {
+                    write!(f, " route {}→{}", previous_route, bus.route)?;
+                }
+                if *moved_meters >= MIN_MOVE_METERS {
+                    write!(f, " moved {:.0}m", moved_meters)?;
+                }
+                if (bus.speed - previous_speed).abs() >= MIN_SPEED_CHANGE_KMH {
+                    write!(f, " speed {:.0}→{:.0} km/h", previous_speed, bus.speed)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+struct TrackedVehicle {
+    last_emitted: BusPosition,
+    last_seen_batch: u64,
+}
+
+// Compares each batch with the last record emitted per vehicle and reports only
+// material changes, plus vehicles that appear or go missing for several batches.
+pub struct DiffEngine {
+    vehicles: HashMap<String, TrackedVehicle>,
+    batch: u64,
+    lost_after_batches: u64,
+}
+
+impl DiffEngine {
+    pub fn new(lost_after_batches: u64) -> Self {
+        Self {
+            vehicles: HashMap::new(),
+            batch: 0,
+            lost_after_batches: lost_after_batches.max(1),
+        }
+    }
+
+    pub fn apply(&mut self, buses: &[BusPosition]) -> Vec<VehicleChange> {
+        self.batch += 1;
+        let mut changes = Vec::new();
+
+        for bus in buses.iter().filter(|bus| !bus.bus_no.is_empty()) {
+            let Some(tracked) = self.vehicles.get_mut(&bus.bus_no) else {
+                self.vehicles.insert(
+                    bus.bus_no.clone(),
+                    TrackedVehicle {
+                        last_emitted: bus.clone(),
+                        last_seen_batch: self.batch,
+                    },
+                );
+                changes.push(VehicleChange::Appeared(bus.clone()));
+                continue;
+            };
+
+            tracked.last_seen_batch = self.batch;
+            let previous = &tracked.last_emitted;
+            let moved_meters = haversine_meters(
+                (previous.latitude, previous.longitude),
+                (bus.latitude, bus.longitude),
+            );
+            let is_material = moved_meters >= MIN_MOVE_METERS
+                || (bus.speed - previous.speed).abs() >= MIN_SPEED_CHANGE_KMH
+                || previous.route != bus.route;
+
+            if is_material {
+                changes.push(VehicleChange::Changed {
+                    bus: bus.clone(),
+                    moved_meters,
+                    previous_speed: previous.speed,
+                    previous_route: previous.route.clone(),
+                });
+                tracked.last_emitted = bus.clone();
+            }
+        }
+
+        let lost_before = self.batch.saturating_sub(self.lost_after_batches);
+        let lost_ids: Vec<String> = self
+            .vehicles
+            .iter()
+            .filter(|(_, tracked)| tracked.last_seen_batch <= lost_before)
+            .map(|(bus_no, _)| bus_no.clone())
+            .collect();
+        for bus_no in lost_ids {
+            if let Some(tracked) = self.vehicles.remove(&bus_no) {
+                changes.push(VehicleChange::Lost(tracked.last_emitted));
+            }
+        }
+
+        changes
+    }
+}
@@ -0,0 +1,112 @@
+use crate::feed::BusPosition;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+#[cfg(feature = "server")]
+use utoipa::ToSchema;
+
+// Movement smaller than this between updates is GPS jitter, not an observation.
+const MIN_PROGRESS_DELTA_METERS: f64 = 30.0;
+// No bus covers this between two updates; a jump this size is a terminal turnaround.
+const TURNAROUND_JUMP_METERS: f64 = 1_500.0;
+pub const DEFAULT_CONSISTENT_OBSERVATIONS: u32 = 3;
+
+// Outbound follows the shape of the route's first trip (GTFS direction_id 0).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+pub enum Direction {
+    Inbound,
+    Outbound,
+    #[default]
+    Unknown,
+}
+
+impl Direction {
+    pub fn direction_id(&self) -> Option<u8> {
+        match self {
+            Direction::Outbound => Some(0),
+            Direction::Inbound => Some(1),
+            Direction::Unknown => None,
+        }
+    }
+}
+
+struct TrackedDirection {
+    reference_meters: f64,
+    observed: Direction,
+    streak: u32,
+    direction: Direction,
+}
+
+// Infers direction from progress_m over successive updates. The reported direction only
+// changes after several consistent observations, so jitter at a stop doesn't flip it.
+pub struct DirectionTracker {
+    vehicles: HashMap<String, TrackedDirection>,
+    consistent_observations: u32,
+}
+
+impl DirectionTracker {
+    pub fn new(consistent_observations: u32) -> Self {
+        Self {
+            vehicles: HashMap::new(),
+            consistent_observations: consistent_observations.max(1),
+        }
+    }
+
+    pub fn annotate(&mut self, bus: &mut BusPosition) {
+        let Some(progress_meters) = bus.progress_m else {
+            bus.direction = self
+                .vehicles
+                .get(&bus.bus_no)
+                .map(|tracked| tracked.direction)
+                .unwrap_or_default();
+            return;
+        };
+
+        let Some(tracked) = self.vehicles.get_mut(&bus.bus_no) else {
+            self.vehicles.insert(
+                bus.bus_no.clone(),
+                TrackedDirection {
+                    reference_meters: progress_meters,
+                    observed: Direction::Unknown,
+                    streak: 0,
+                    direction: Direction::Unknown,
+                },
+            );
+            bus.direction = Direction::Unknown;
+            return;
+        };
+
+        let delta = progress_meters - tracked.reference_meters;
+        if delta.abs() >= TURNAROUND_JUMP_METERS {
+            *tracked = TrackedDirection {
+                reference_meters: progress_meters,
+                observed: Direction::Unknown,
+                streak: 0,
+                direction: Direction::Unknown,
+            };
+        } else if delta.abs() >= MIN_PROGRESS_DELTA_METERS {
+            let observed = if delta > 0.0 {
+                Direction::Outbound
+            } else {
+                Direction::Inbound
+            };
+            if observed == tracked.observed {
+                tracked.streak += 1;
+            } else {
+                tracked.observed = observed;
+                tracked.streak = 1;
+            }
+            if tracked.streak >= self.consistent_observations {
+                tracked.direction = observed;
+            }
+            tracked.reference_meters = progress_meters;
+        }
+
+        bus.direction = tracked.direction;
+    }
+
+    // Progress along another route's shape says nothing about this one.
+    pub fn forget(&mut self, bus_no: &str) {
+        self.vehicles.remove(bus_no);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::feed::{BusPosition, PositionSource};
+use crate::geo::haversine_meters;
+use crate::reconcile::normalize_vehicle_id;
+use std::collections::HashMap;
+
+pub const DEFAULT_MAX_METADATA_AGE_MS: i64 = 120_000;
+pub const DEFAULT_MATCH_TOLERANCE_METERS: f64 = 500.0;
+
+#[derive(Debug, Clone)]
+struct GtfsRecord {
+    received_at_ms: i64,
+    latitude: f64,
+    longitude: f64,
+    trip_id: Option<String>,
+    route_id: Option<String>,
+    current_stop_sequence: Option<u32>,
+}
+
+// Joins GTFS-rt trip metadata onto socket updates, which don't carry it. Keeps the latest
+// GTFS-rt position per vehicle and copies its trip_id, route_id and current_stop_sequence
+// onto a socket update for the same vehicle, as long as the record is younger than
+// `max_age_ms` and places the bus within `tolerance_meters` of the socket's fix. The
+// distance check keeps an id that both feeds happen to reuse from pairing two buses.
+//
+// Updates without a match pass through untouched.
+#[derive(Debug, Clone)]
+pub struct GtfsEnricher {
+    records: HashMap<String, GtfsRecord>,
+    max_age_ms: i64,
+    tolerance_meters: f64,
+}
+
+impl GtfsEnricher {
+    pub fn new(max_age_ms: i64, tolerance_meters: f64) -> Self {
+        Self {
+            records: HashMap::new(),
+            max_age_ms,
+            tolerance_meters,
+        }
+    }
+
+    // Takes one GTFS-rt poll's positions. Records older than the max age are dropped here
+    // so vehicles that left the feed don't pile up.
+    pub fn update(&mut self, vehicles: &[BusPosition], received_at_ms: i64) {
+        for vehicle in vehicles {
+            let id = normalize_vehicle_id(&vehicle.bus_no);
+            if id.is_empty() {
+                continue;
+            }
+            self.records.insert(
+                id,
+                GtfsRecord {
+                    received_at_ms,
+                    latitude: vehicle.latitude,
+                    longitude: vehicle.longitude,
+                    trip_id: vehicle.trip_id.clone(),
+                    route_id: vehicle.route_id.clone(),
+                    current_stop_sequence: vehicle.current_stop_sequence,
+                },
+            );
+        }
+        let max_age_ms = self.max_age_ms;
+        self.records
+            .retain(|_, record| received_at_ms - record.received_at_ms < max_age_ms);
+    }
+
+    // Fills in the metadata the bus doesn't already have. Returns whether it matched.
+    pub fn enrich(&self, bus: &mut BusPosition, now_ms: i64) -> bool {
+        if bus.source == PositionSource::Gtfs {
+            return false;
+        }
+        let Some(record) = self.records.get(&normalize_vehicle_id(&bus.bus_no)) else {
+            return false;
+        };
+        if now_ms - record.received_at_ms >= self.max_age_ms {
+            return false;
+        }
+        let distance = haversine_meters(
+            (bus.latitude, bus.longitude),
+            (record.latitude, record.longitude),
+        );
+        if distance > self.tolerance_meters {
+            return false;
+        }
+        if bus.trip_id.is_none() {
+            bus.trip_id = record.trip_id.clone();
+        }
+        if bus.route_id.is_none() {
+            bus.route_id = record.route_id.clone();
+        }
+        if bus.current_stop_sequence.is_none() {
+            bus.current_stop_sequence = record.current_stop_sequence;
+        }
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+impl Default for GtfsEnricher {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_METADATA_AGE_MS, DEFAULT_MATCH_TOLERANCE_METERS)
+    }
+}
@@ -0,0 +1,282 @@
+use crate::archive::{archive_files, read_records, ArchiveRecord};
+use crate::validate::MALAYSIA_BBOX;
+use chrono::{DateTime, SecondsFormat};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "server")]
+use utoipa::ToSchema;
+
+const MS_PER_HOUR: i64 = 3_600_000;
+
+#[derive(Debug, Clone)]
+pub struct TrackQuery {
+    pub vehicle: String,
+    // Inclusive bounds on the point time, unix ms.
+    pub from_ms: i64,
+    pub to_ms: i64,
+    // Keep off-route points and coordinates outside Malaysia.
+    pub include_invalid: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+pub struct TrackPoint {
+    pub lat: f64,
+    pub lon: f64,
+    pub speed_kmh: f64,
+    pub bearing: f64,
+    // GPS fix time, or the receive time when the fix had none.
+    pub time_ms: i64,
+}
+
+impl TrackPoint {
+    fn from_record(record: &ArchiveRecord) -> Self {
+        Self {
+            lat: record.lat,
+            lon: record.lon,
+            speed_kmh: record.speed,
+            bearing: record.bearing,
+            time_ms: record.gps_ts.unwrap_or(record.recv_ts),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+pub struct ActiveVehicle {
+    pub vehicle_id: String,
+    pub route: String,
+    pub provider: String,
+    // The vehicle's last point at or before the requested moment.
+    pub last_point: TrackPoint,
+}
+
+// Reads one vehicle's points from the Parquet archive, ordered by time with repeated
+// fixes dropped.
+pub fn query_track(archive_root: &Path, query: &TrackQuery) -> Result<Vec<TrackPoint>, String> {
+    let mut points = Vec::new();
+    for path in overlapping_files(archive_root, query.from_ms, query.to_ms)? {
+        points.extend(track_points(&path, query)?);
+    }
+    points.sort_by_key(|point| point.time_ms);
+    points.dedup_by_key(|point| point.time_ms);
+    Ok(points)
+}
+
+// The first `limit` points of the track, and whether more follow. Files are read in time
+// order and, once a full page is in hand, a file whose points all come after it is
+// skipped unopened, so paging through a long window doesn't re-read all of it per page.
+pub fn query_track_page(
+    archive_root: &Path,
+    query: &TrackQuery,
+    limit: usize,
+) -> Result<(Vec<TrackPoint>, bool), String> {
+    let mut points: Vec<TrackPoint> = Vec::new();
+    for path in overlapping_files(archive_root, query.from_ms, query.to_ms)? {
+        // Points in a file are at most an hour older than its first receive time.
+        let page_full = first_recv_ms(&path).is_some_and(|first| {
+            points
+                .get(limit)
+                .is_some_and(|last| last.time_ms < first - MS_PER_HOUR)
+        });
+        if page_full {
+            continue;
+        }
+        points.extend(track_points(&path, query)?);
+        points.sort_by_key(|point| point.time_ms);
+        points.dedup_by_key(|point| point.time_ms);
+        points.truncate(limit + 1);
+    }
+    let more = points.len() > limit;
+    points.truncate(limit);
+    Ok((points, more))
+}
+
+fn track_points(path: &Path, query: &TrackQuery) -> Result<Vec<TrackPoint>, String> {
+    let mut points = Vec::new();
+    for record in read_records(path)? {
+        if !record.vehicle_id.eq_ignore_ascii_case(&query.vehicle) {
+            continue;
+        }
+        if !query.include_invalid && !is_valid(&record) {
+            continue;
+        }
+        let point = TrackPoint::from_record(&record);
+        if (query.from_ms..=query.to_ms).contains(&point.time_ms) {
+            points.push(point);
+        }
+    }
+    Ok(points)
+}
+
+// Vehicles on a route with a valid point in the `window_ms` before `at_ms`, ordered by
+// vehicle id. Routes are compared after `normalize`, so T789 matches T7890.
+pub fn query_active(
+    archive_root: &Path,
+    route: &str,
+    at_ms: i64,
+    window_ms: i64,
+    normalize: fn(&str) -> String,
+) -> Result<Vec<ActiveVehicle>, String> {
+    let wanted = normalize(route);
+    let from_ms = at_ms - window_ms;
+    let mut latest: HashMap<String, ActiveVehicle> = HashMap::new();
+    for path in overlapping_files(archive_root, from_ms, at_ms)? {
+        for record in read_records(&path)? {
+            if !is_valid(&record) || normalize(&record.route) != wanted {
+                continue;
+            }
+            let point = TrackPoint::from_record(&record);
+            if !(from_ms..=at_ms).contains(&point.time_ms) {
+                continue;
+            }
+            let newer = latest
+                .get(&record.vehicle_id)
+                .is_none_or(|active| active.last_point.time_ms < point.time_ms);
+            if newer {
+                latest.insert(
+                    record.vehicle_id.clone(),
+                    ActiveVehicle {
+                        vehicle_id: record.vehicle_id,
+                        route: record.route,
+                        provider: record.provider,
+                        last_point: point,
+                    },
+                );
+            }
+        }
+    }
+    let mut vehicles: Vec<ActiveVehicle> = latest.into_values().collect();
+    vehicles.sort_by(|a, b| a.vehicle_id.cmp(&b.vehicle_id));
+    Ok(vehicles)
+}
+
+// Files are named by their first receive time, so only files that can overlap the
+// window are opened; the extra hour covers GPS times lagging receipt.
+fn overlapping_files(
+    archive_root: &Path,
+    from_ms: i64,
+    to_ms: i64,
+) -> Result<Vec<PathBuf>, String> {
+    Ok(archive_files(archive_root)?
+        .into_iter()
+        .filter(|path| {
+            first_recv_ms(path).is_none_or(|first| {
+                first <= to_ms + MS_PER_HOUR && first + 2 * MS_PER_HOUR >= from_ms
+            })
+        })
+        .collect())
+}
+
+fn first_recv_ms(path: &Path) -> Option<i64> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.split_once('-'))
+        .and_then(|(_, first)| first.parse::<i64>().ok())
+}
+
+fn is_valid(record: &ArchiveRecord) -> bool {
+    !record.off_route
+        && record.lat.is_finite()
+        && record.lon.is_finite()
+        && MALAYSIA_BBOX.contains(record.lat, record.lon)
+}
+
+// GPX 1.1 with a single track. Speed (m/s) and course ride in Garmin's
+// TrackPointExtension, which most GIS tools read.
+pub fn to_gpx(vehicle: &str, points: &[TrackPoint]) -> String {
+    let mut gpx = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<gpx version=\"1.1\" creator=\"rapidbro\" xmlns=\"http://www.topografix.com/GPX/1/1\" ",
+        "xmlns:gpxtpx=\"http://www.garmin.com/xmlschemas/TrackPointExtension/v2\">\n",
+    ));
+    gpx.push_str(&format!(
+        "  <trk>\n    <name>{}</name>\n    <trkseg>\n",
+        escape_xml(vehicle)
+    ));
+    for point in points {
+        gpx.push_str(&format!(
+            concat!(
+                "      <trkpt lat=\"{:.6}\" lon=\"{:.6}\">\n",
+                "        <time>{}</time>\n",
+                "        <extensions><gpxtpx:TrackPointExtension>",
+                "<gpxtpx:speed>{:.2}</gpxtpx:speed><gpxtpx:course>{:.1}</gpxtpx:course>",
+                "</gpxtpx:TrackPointExtension></extensions>\n",
+                "      </trkpt>\n"
+            ),
+            point.lat,
+            point.lon,
+            format_time(point.time_ms),
+            point.speed_kmh / 3.6,
+            point.bearing.rem_euclid(360.0)
+        ));
+    }
+    gpx.push_str("    </trkseg>\n  </trk>\n</gpx>\n");
+    gpx
+}
+
+// KML 2.2 gx:Track, so Google Earth and QGIS get a time slider; speed (km/h) is
+// attached per point as extended data.
+pub fn to_kml(vehicle: &str, points: &[TrackPoint]) -> String {
+    let name = escape_xml(vehicle);
+    let mut kml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<kml xmlns=\"http://www.opengis.net/kml/2.2\" xmlns:gx=\"http://www.google.com/kml/ext/2.2\">\n",
+        "  <Document>\n",
+        "    <Schema id=\"rapidbro\">\n",
+        "      <gx:SimpleArrayField name=\"speed\" type=\"float\"><displayName>Speed (km/h)</displayName></gx:SimpleArrayField>\n",
+        "    </Schema>\n",
+    ));
+    kml.push_str(&format!(
+        "    <name>{}</name>\n    <Placemark>\n      <name>{}</name>\n      <gx:Track>\n",
+        name, name
+    ));
+    for point in points {
+        kml.push_str(&format!(
+            "        <when>{}</when>\n",
+            format_time(point.time_ms)
+        ));
+    }
+    for point in points {
+        kml.push_str(&format!(
+            "        <gx:coord>{:.6} {:.6} 0</gx:coord>\n",
+            point.lon, point.lat
+        ));
+    }
+    kml.push_str(concat!(
+        "        <ExtendedData>\n",
+        "          <SchemaData schemaUrl=\"#rapidbro\">\n",
+        "            <gx:SimpleArrayData name=\"speed\">\n",
+    ));
+    for point in points {
+        kml.push_str(&format!(
+            "              <gx:value>{:.1}</gx:value>\n",
+            point.speed_kmh
+        ));
+    }
+    kml.push_str(concat!(
+        "            </gx:SimpleArrayData>\n",
+        "          </SchemaData>\n",
+        "        </ExtendedData>\n",
+        "      </gx:Track>\n",
+        "    </Placemark>\n",
+        "  </Document>\n",
+        "</kml>\n",
+    ));
+    kml
+}
+
+fn format_time(unix_ms: i64) -> String {
+    DateTime::from_timestamp_millis(unix_ms)
+        .map(|timestamp| timestamp.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_default()
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
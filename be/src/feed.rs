@@ -0,0 +1,572 @@
+use crate::direction::Direction;
+use crate::field_map::FieldMap;
+use crate::schema::SchemaMonitor;
+use base64::Engine;
+use flate2::read::GzDecoder;
+use rayon::prelude::*;
+use rust_socketio::Payload;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+#[cfg(feature = "server")]
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+pub struct BusPosition {
+    pub dt_received: Option<String>,
+    pub dt_gps: Option<String>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub dir: Option<String>,
+    pub speed: f64,
+    // Moving average of `speed` over the vehicle's last few fixes.
+    pub smoothed_speed_kmh: Option<f64>,
+    pub angle: f64,
+    pub route: String,
+    pub bus_no: String,
+    pub trip_no: Option<String>,
+    pub captain_id: Option<String>,
+    pub trip_rev_kind: Option<String>,
+    pub engine_status: i32,
+    pub accessibility: i32,
+    pub busstop_id: Option<String>,
+    pub provider: String,
+    pub route_color: Option<String>,
+    pub route_text_color: Option<String>,
+    pub timestamp_rfc3339: Option<String>,
+    // timestamp_rfc3339 in the configured local zone (see --timezone), for display only;
+    // the UTC value stays canonical.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_time: Option<String>,
+    pub age_seconds: Option<i64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub timestamp_parse_error: bool,
+    pub progress_m: Option<f64>,
+    pub progress_pct: Option<f64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub off_route: bool,
+    // Set in the HTTP snapshot while the vehicle is missing from the feed (see presence).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub inactive: bool,
+    #[serde(default)]
+    pub direction: Direction,
+    pub delay_min: Option<f64>,
+    // GTFS-rt trip metadata. Set on GTFS-rt positions, and joined onto socket updates when
+    // enrichment is on (see enrich).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trip_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_stop_sequence: Option<u32>,
+    #[serde(default)]
+    pub source: PositionSource,
+    // Feed keys this struct doesn't model; only filled when extra-field capture is enabled.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    #[cfg_attr(feature = "server", schema(value_type = Object))]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+// BusPosition's serialized field names, for checking ?fields= projections. Keep in step
+// with the struct; tests/bulk.rs compares the two.
+pub const BUS_POSITION_FIELDS: &[&str] = &[
+    "dt_received",
+    "dt_gps",
+    "latitude",
+    "longitude",
+    "dir",
+    "speed",
+    "smoothed_speed_kmh",
+    "angle",
+    "route",
+    "bus_no",
+    "trip_no",
+    "captain_id",
+    "trip_rev_kind",
+    "engine_status",
+    "accessibility",
+    "busstop_id",
+    "provider",
+    "route_color",
+    "route_text_color",
+    "timestamp_rfc3339",
+    "local_time",
+    "age_seconds",
+    "timestamp_parse_error",
+    "progress_m",
+    "progress_pct",
+    "off_route",
+    "inactive",
+    "direction",
+    "delay_min",
+    "trip_id",
+    "route_id",
+    "current_stop_sequence",
+    "source",
+    "extra",
+];
+
+// Which feed a position came from; socket payloads don't carry it, so they default to websocket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum PositionSource {
+    #[default]
+    Websocket,
+    Gtfs,
+}
+
+// Captures the keys BusPosition doesn't know: the struct takes its own fields first and
+// `extra` gets whatever is left.
+#[derive(Deserialize)]
+struct BusPositionWithExtra {
+    #[serde(flatten)]
+    bus: BusPosition,
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
+}
+
+impl From<BusPositionWithExtra> for BusPosition {
+    fn from(parsed: BusPositionWithExtra) -> Self {
+        let mut bus = parsed.bus;
+        bus.extra = parsed.extra;
+        bus
+    }
+}
+
+pub fn parse_bus_positions_from_payload(payload: Payload) -> (Vec<BusPosition>, u64) {
+    parse_bus_positions(payload, false)
+}
+
+// With capture_extra, unrecognized feed keys are kept in BusPosition::extra. It's off by
+// default because each object is buffered before being split into known and extra keys.
+pub fn parse_bus_positions(payload: Payload, capture_extra: bool) -> (Vec<BusPosition>, u64) {
+    DecodeContext::default().parse(payload, capture_extra)
+}
+
+// Same, renaming the provider's keys through `field_map` first.
+pub fn parse_bus_positions_mapped(
+    payload: Payload,
+    capture_extra: bool,
+    field_map: &FieldMap,
+) -> (Vec<BusPosition>, u64) {
+    DecodeContext::default().parse_mapped(payload, capture_extra, field_map)
+}
+
+// How much of a failing payload is kept for inspection.
+const MAX_FAILURE_PAYLOAD_CHARS: usize = 4_096;
+
+// How far one payload value may inflate before decoding gives up on it. An all-routes batch
+// is a few hundred KiB of JSON, so this only stops corrupt or hostile gzip streams; see
+// --max-decompressed-bytes.
+pub const DEFAULT_MAX_DECOMPRESSED_BYTES: usize = 16 * 1024 * 1024;
+
+// Why a payload value produced no positions, and where decoding gave up.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum DecodeError {
+    Base64 {
+        message: String,
+    },
+    Gzip {
+        message: String,
+    },
+    // `category` is serde_json's: syntax, data, eof or io.
+    Json {
+        category: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    // Only with a strict SchemaMonitor; `message` lists the payload's violations.
+    Schema {
+        message: String,
+    },
+    // Inflating stopped at `limit` bytes; `compressed_bytes` is the gzip stream's size.
+    TooLarge {
+        limit: usize,
+        compressed_bytes: usize,
+    },
+}
+
+impl From<serde_json::Error> for DecodeError {
+    fn from(error: serde_json::Error) -> Self {
+        let category = match error.classify() {
+            serde_json::error::Category::Io => "io",
+            serde_json::error::Category::Syntax => "syntax",
+            serde_json::error::Category::Data => "data",
+            serde_json::error::Category::Eof => "eof",
+        };
+        DecodeError::Json {
+            category: category.to_string(),
+            line: error.line(),
+            column: error.column(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Base64 { message } => write!(f, "invalid base64: {}", message),
+            DecodeError::Gzip { message } => write!(f, "invalid gzip: {}", message),
+            DecodeError::Schema { message } => write!(f, "schema violation: {}", message),
+            DecodeError::TooLarge {
+                limit,
+                compressed_bytes,
+            } => write!(
+                f,
+                "{} bytes of gzip inflate past the {}-byte limit",
+                compressed_bytes, limit
+            ),
+            DecodeError::Json {
+                category,
+                line,
+                column,
+                message,
+            } => write!(
+                f,
+                "{} error at line {} column {}: {}",
+                category, line, column, message
+            ),
+        }
+    }
+}
+
+// One payload value that failed to decode, with the start of what was received: the
+// inflated JSON when it got that far, otherwise the value as it arrived.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+pub struct ParseFailure {
+    pub error: DecodeError,
+    pub payload_bytes: usize,
+    pub payload: String,
+}
+
+impl ParseFailure {
+    fn new(error: DecodeError, payload: &[u8]) -> Self {
+        Self {
+            error,
+            payload_bytes: payload.len(),
+            payload: String::from_utf8_lossy(payload)
+                .chars()
+                .take(MAX_FAILURE_PAYLOAD_CHARS)
+                .collect(),
+        }
+    }
+}
+
+// Scratch space for one payload value: the base64-decoded gzip bytes and the inflated JSON.
+#[derive(Debug, Default)]
+struct DecodeBuffers {
+    compressed: Vec<u8>,
+    json: Vec<u8>,
+}
+
+impl DecodeBuffers {
+    fn decode_text(
+        &mut self,
+        encoded: &str,
+        capture_extra: bool,
+        field_map: &FieldMap,
+        schema: Option<&SchemaMonitor>,
+        max_decompressed_bytes: usize,
+    ) -> Result<Vec<BusPosition>, ParseFailure> {
+        self.compressed.clear();
+        base64::engine::general_purpose::STANDARD
+            .decode_vec(encoded, &mut self.compressed)
+            .map_err(|error| {
+                ParseFailure::new(
+                    DecodeError::Base64 {
+                        message: error.to_string(),
+                    },
+                    encoded.as_bytes(),
+                )
+            })?;
+        inflate(&self.compressed, &mut self.json, max_decompressed_bytes)
+            .map_err(|error| ParseFailure::new(error, encoded.as_bytes()))?;
+        self.parse_json(capture_extra, field_map, schema)
+    }
+
+    fn decode_binary(
+        &mut self,
+        compressed: &[u8],
+        capture_extra: bool,
+        field_map: &FieldMap,
+        schema: Option<&SchemaMonitor>,
+        max_decompressed_bytes: usize,
+    ) -> Result<Vec<BusPosition>, ParseFailure> {
+        inflate(compressed, &mut self.json, max_decompressed_bytes)
+            .map_err(|error| ParseFailure::new(error, compressed))?;
+        self.parse_json(capture_extra, field_map, schema)
+    }
+}
+
+// Inflates into `json`, reading at most one byte past `limit` so a gzip bomb costs no more
+// memory than a payload at the limit.
+pub(crate) fn inflate(
+    compressed: &[u8],
+    json: &mut Vec<u8>,
+    limit: usize,
+) -> Result<(), DecodeError> {
+    json.clear();
+    GzDecoder::new(compressed)
+        .take((limit as u64).saturating_add(1))
+        .read_to_end(json)
+        .map_err(|error| DecodeError::Gzip {
+            message: error.to_string(),
+        })?;
+    if json.len() > limit {
+        // A reused buffer shouldn't hang on to what the bomb made it grow to.
+        json.clear();
+        json.shrink_to(limit);
+        return Err(DecodeError::TooLarge {
+            limit,
+            compressed_bytes: compressed.len(),
+        });
+    }
+    Ok(())
+}
+
+impl DecodeBuffers {
+    fn parse_json(
+        &self,
+        capture_extra: bool,
+        field_map: &FieldMap,
+        schema: Option<&SchemaMonitor>,
+    ) -> Result<Vec<BusPosition>, ParseFailure> {
+        if let Some(schema) = schema {
+            schema.check(&self.json, field_map).map_err(|message| {
+                ParseFailure::new(DecodeError::Schema { message }, &self.json)
+            })?;
+        }
+        parse_json_bytes(&self.json, capture_extra, field_map)
+            .map_err(|error| ParseFailure::new(error.into(), &self.json))
+    }
+}
+
+// Reusable buffers for the decode path, so a long-lived pipeline stops allocating a fresh
+// Vec and String per payload. Holds one set per value of the largest Text batch seen.
+#[derive(Debug)]
+pub struct DecodeContext {
+    buffers: Vec<DecodeBuffers>,
+    failures: Vec<ParseFailure>,
+    schema: Option<SchemaMonitor>,
+    max_decompressed_bytes: usize,
+}
+
+impl Default for DecodeContext {
+    fn default() -> Self {
+        Self {
+            buffers: Vec::new(),
+            failures: Vec::new(),
+            schema: None,
+            max_decompressed_bytes: DEFAULT_MAX_DECOMPRESSED_BYTES,
+        }
+    }
+}
+
+impl DecodeContext {
+    // Also checks every payload's shape; see SchemaMonitor.
+    pub fn with_schema(schema: Option<SchemaMonitor>) -> Self {
+        Self {
+            schema,
+            ..Self::default()
+        }
+    }
+
+    // Payload values that inflate past `limit` bytes fail with DecodeError::TooLarge.
+    pub fn max_decompressed_bytes(mut self, limit: usize) -> Self {
+        self.max_decompressed_bytes = limit;
+        self
+    }
+
+    pub fn parse(&mut self, payload: Payload, capture_extra: bool) -> (Vec<BusPosition>, u64) {
+        self.parse_mapped(payload, capture_extra, &FieldMap::rapid_kl())
+    }
+
+    // The u64 counts the values that failed; why they failed is kept for take_failures.
+    pub fn parse_mapped(
+        &mut self,
+        payload: Payload,
+        capture_extra: bool,
+        field_map: &FieldMap,
+    ) -> (Vec<BusPosition>, u64) {
+        let schema = self.schema.as_ref();
+        let limit = self.max_decompressed_bytes;
+        let parsed: Vec<Result<Vec<BusPosition>, ParseFailure>> = match payload {
+            // Values decode in parallel; collect keeps them in the order they arrived.
+            Payload::Text(values) => {
+                let values: Vec<&str> = values.iter().filter_map(|value| value.as_str()).collect();
+                if self.buffers.len() < values.len() {
+                    self.buffers
+                        .resize_with(values.len(), DecodeBuffers::default);
+                }
+                values
+                    .par_iter()
+                    .zip(self.buffers.par_iter_mut())
+                    .map(|(encoded, buffers)| {
+                        buffers.decode_text(encoded, capture_extra, field_map, schema, limit)
+                    })
+                    .collect()
+            }
+            // Binary attachments carry the gzip bytes directly, without the base64 layer.
+            Payload::Binary(bytes) => {
+                if self.buffers.is_empty() {
+                    self.buffers.push(DecodeBuffers::default());
+                }
+                vec![self.buffers[0].decode_binary(&bytes, capture_extra, field_map, schema, limit)]
+            }
+            _ => Vec::new(),
+        };
+
+        let mut buses = Vec::new();
+        let mut decode_failures = 0;
+        for parsed in parsed {
+            match parsed {
+                Ok(mut parsed_buses) => buses.append(&mut parsed_buses),
+                Err(failure) => {
+                    decode_failures += 1;
+                    self.failures.push(failure);
+                }
+            }
+        }
+
+        (buses, decode_failures)
+    }
+
+    // The failures since the last call, oldest first.
+    pub fn take_failures(&mut self) -> Vec<ParseFailure> {
+        std::mem::take(&mut self.failures)
+    }
+}
+
+fn parse_json_bytes(
+    decoded: &[u8],
+    capture_extra: bool,
+    field_map: &FieldMap,
+) -> Result<Vec<BusPosition>, serde_json::Error> {
+    // Two stages only when there is something to rename: to a Value, then mapped.
+    if !field_map.is_identity() {
+        let value = field_map.apply(serde_json::from_slice::<serde_json::Value>(decoded)?);
+        return if capture_extra {
+            positions_from_value::<BusPositionWithExtra>(value)
+                .map(|buses| buses.into_iter().map(BusPosition::from).collect())
+        } else {
+            positions_from_value(value)
+        };
+    }
+    if capture_extra {
+        parse_positions::<BusPositionWithExtra>(decoded)
+            .map(|buses| buses.into_iter().map(BusPosition::from).collect())
+    } else {
+        parse_positions(decoded)
+    }
+}
+
+pub fn parse_bus_positions_from_json(decoded: &str) -> Option<Vec<BusPosition>> {
+    parse_positions(decoded.as_bytes()).ok()
+}
+
+// On failure, reports the error that says most about the payload: the syntax error for
+// invalid JSON, otherwise why the object (or the first array entry) didn't fit.
+fn parse_positions<T: DeserializeOwned>(decoded: &[u8]) -> Result<Vec<T>, serde_json::Error> {
+    let single_error = match serde_json::from_slice::<T>(decoded) {
+        Ok(single_bus) => return Ok(vec![single_bus]),
+        Err(error) => error,
+    };
+
+    if let Ok(bus_list) = serde_json::from_slice::<Vec<T>>(decoded) {
+        return Ok(bus_list);
+    }
+
+    match serde_json::from_slice::<serde_json::Value>(decoded)? {
+        serde_json::Value::Array(entries) => lenient_entries(entries),
+        _ => Err(single_error),
+    }
+}
+
+fn positions_from_value<T: DeserializeOwned>(
+    value: serde_json::Value,
+) -> Result<Vec<T>, serde_json::Error> {
+    match value {
+        serde_json::Value::Array(entries) => lenient_entries(entries),
+        single => serde_json::from_value::<T>(single).map(|bus| vec![bus]),
+    }
+}
+
+// Keeps the entries that deserialize; fails with the first entry's error when none do.
+fn lenient_entries<T: DeserializeOwned>(
+    entries: Vec<serde_json::Value>,
+) -> Result<Vec<T>, serde_json::Error> {
+    let mut first_error = None;
+    let buses: Vec<T> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            serde_json::from_value::<T>(entry)
+                .map_err(|error| {
+                    first_error.get_or_insert(error);
+                })
+                .ok()
+        })
+        .collect();
+
+    if !buses.is_empty() {
+        return Ok(buses);
+    }
+    Err(first_error
+        .unwrap_or_else(|| <serde_json::Error as serde::de::Error>::custom("empty position array")))
+}
+
+#[derive(Debug, Clone)]
+pub struct RawPayload {
+    pub base64_len: usize,
+    pub gzip_len: usize,
+    pub json: String,
+}
+
+// Decode base64 + gzip compressed data from the websocket
+pub fn decode_bus_data(encoded: &str) -> Option<String> {
+    decode_raw_payload(encoded).map(|raw| raw.json)
+}
+
+// Same as decode_bus_data but keeps the intermediate sizes for debugging compression.
+pub fn decode_raw_payload(encoded: &str) -> Option<RawPayload> {
+    decode_raw_payload_within(encoded, DEFAULT_MAX_DECOMPRESSED_BYTES)
+}
+
+pub fn decode_raw_payload_within(
+    encoded: &str,
+    max_decompressed_bytes: usize,
+) -> Option<RawPayload> {
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .ok()?;
+
+    Some(RawPayload {
+        base64_len: encoded.len(),
+        gzip_len: decoded.len(),
+        json: decode_gzip_within(&decoded, max_decompressed_bytes).ok()?,
+    })
+}
+
+pub fn decode_gzip(compressed: &[u8]) -> Option<String> {
+    decode_gzip_within(compressed, DEFAULT_MAX_DECOMPRESSED_BYTES).ok()
+}
+
+// Fails with DecodeError::TooLarge rather than inflating past `max_decompressed_bytes`.
+pub fn decode_gzip_within(
+    compressed: &[u8],
+    max_decompressed_bytes: usize,
+) -> Result<String, DecodeError> {
+    let mut decompressed = Vec::new();
+    inflate(compressed, &mut decompressed, max_decompressed_bytes)?;
+    String::from_utf8(decompressed).map_err(|error| DecodeError::Gzip {
+        message: error.to_string(),
+    })
+}
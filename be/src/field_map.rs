@@ -0,0 +1,99 @@
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+// The kiosk's onFts-client keys, which are also BusPosition's field names. Every target
+// in a FieldMap has to be one of these.
+pub const RAPID_KL_FIELDS: &[&str] = &[
+    "dt_received",
+    "dt_gps",
+    "latitude",
+    "longitude",
+    "dir",
+    "speed",
+    "angle",
+    "route",
+    "bus_no",
+    "trip_no",
+    "captain_id",
+    "trip_rev_kind",
+    "engine_status",
+    "accessibility",
+    "busstop_id",
+    "provider",
+];
+
+// Renames a provider's payload keys to BusPosition's before deserializing, e.g.
+// `lat = "latitude"` or `no_route = "route"`. Keys without an entry pass through as they
+// are, so the default (empty) map is the Rapid KL layout and costs nothing: payloads are
+// only parsed into a Value first when there is something to rename.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldMap {
+    // Source key -> canonical field; identity entries are dropped.
+    renames: HashMap<String, String>,
+}
+
+impl FieldMap {
+    // The map matching the Rapid KL kiosk, whose keys already are the canonical ones.
+    pub fn rapid_kl() -> Self {
+        Self::default()
+    }
+
+    // Entries are `source = "canonical"`. Fails on a target BusPosition doesn't have, or
+    // two sources feeding the same target.
+    pub fn from_entries(entries: &HashMap<String, String>) -> Result<Self, String> {
+        let mut renames = HashMap::new();
+        let mut targets: HashMap<&str, &str> = HashMap::new();
+        for (source, target) in entries {
+            if !RAPID_KL_FIELDS.contains(&target.as_str()) {
+                return Err(format!(
+                    "field_map: `{}` maps to unknown field `{}`",
+                    source, target
+                ));
+            }
+            if let Some(other) = targets.insert(target, source) {
+                return Err(format!(
+                    "field_map: both `{}` and `{}` map to `{}`",
+                    other, source, target
+                ));
+            }
+            if source != target {
+                renames.insert(source.clone(), target.clone());
+            }
+        }
+        Ok(Self { renames })
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.renames.is_empty()
+    }
+
+    // Renames the keys of one object, or of every object in an array. A renamed key
+    // replaces any key already using the canonical name.
+    pub fn apply(&self, value: Value) -> Value {
+        if self.is_identity() {
+            return value;
+        }
+        match value {
+            Value::Object(object) => Value::Object(self.apply_object(object)),
+            Value::Array(entries) => {
+                Value::Array(entries.into_iter().map(|entry| self.apply(entry)).collect())
+            }
+            other => other,
+        }
+    }
+
+    fn apply_object(&self, object: Map<String, Value>) -> Map<String, Value> {
+        let mut mapped = Map::with_capacity(object.len());
+        let mut renamed = Vec::new();
+        for (key, value) in object {
+            match self.renames.get(&key) {
+                Some(target) => renamed.push((target.clone(), value)),
+                None => {
+                    mapped.insert(key, value);
+                }
+            }
+        }
+        mapped.extend(renamed);
+        mapped
+    }
+}
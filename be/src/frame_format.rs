@@ -0,0 +1,50 @@
+use crate::feed::BusPosition;
+
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+
+// How the streaming endpoints encode each batch of positions. MessagePack carries the same
+// BusPosition fields under the same names (maps, not arrays, since several fields are left
+// out when unset), so only the bytes differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrameFormat {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+impl FrameFormat {
+    // `?format=json|msgpack` wins over the Accept header; JSON unless the client asks for
+    // MessagePack by name. Accept quality values aren't weighed.
+    pub fn negotiate(format: Option<&str>, accept: Option<&str>) -> Result<Self, String> {
+        if let Some(format) = format {
+            return match format.trim().to_ascii_lowercase().as_str() {
+                "json" => Ok(FrameFormat::Json),
+                "msgpack" | "messagepack" => Ok(FrameFormat::MessagePack),
+                _ => Err(format!(
+                    "unknown format `{}`; expected json or msgpack",
+                    format
+                )),
+            };
+        }
+        let wants_msgpack = accept.is_some_and(|accept| {
+            accept.split(',').any(|media_type| {
+                let media_type = media_type.split(';').next().unwrap_or_default().trim();
+                media_type.eq_ignore_ascii_case(MSGPACK_CONTENT_TYPE)
+                    || media_type.eq_ignore_ascii_case("application/x-msgpack")
+            })
+        });
+        Ok(if wants_msgpack {
+            FrameFormat::MessagePack
+        } else {
+            FrameFormat::Json
+        })
+    }
+}
+
+pub fn to_msgpack(buses: &[BusPosition]) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+    rmp_serde::to_vec_named(buses)
+}
+
+pub fn from_msgpack(bytes: &[u8]) -> Result<Vec<BusPosition>, rmp_serde::decode::Error> {
+    rmp_serde::from_slice(bytes)
+}
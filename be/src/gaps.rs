@@ -0,0 +1,200 @@
+use crate::feed::BusPosition;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "server")]
+use utoipa::ToSchema;
+
+pub const VEHICLE_GAPS_TOTAL: &str = "rapidbro_vehicle_gaps_total";
+// A vehicle silent for this many reload intervals has a gap in its track.
+pub const DEFAULT_GAP_INTERVALS: u32 = 3;
+pub const GAP_LOG_FILE: &str = "gaps.jsonl";
+// Only the latest outages per link are kept; an older one could only explain a very long gap.
+const MAX_OUTAGES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum GapCause {
+    // The feed stopped sending the vehicle while we were connected.
+    Upstream,
+    // Our socket was down for part of the gap, so the feed may well have had the vehicle.
+    LocalDisconnect,
+}
+
+impl GapCause {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GapCause::Upstream => "upstream",
+            GapCause::LocalDisconnect => "local_disconnect",
+        }
+    }
+}
+
+// Times are receive times, so they line up with our own disconnects.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+pub struct Gap {
+    pub vehicle_id: String,
+    pub route: String,
+    pub from_unix_ms: i64,
+    pub to_unix_ms: i64,
+    pub duration_ms: i64,
+    pub cause: GapCause,
+}
+
+impl fmt::Display for Gap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} route={} gap of {}s ({})",
+            self.vehicle_id,
+            self.route,
+            self.duration_ms / 1_000,
+            self.cause.as_str()
+        )
+    }
+}
+
+struct LastUpdate {
+    received_at_ms: i64,
+    fix: Option<String>,
+}
+
+// Reports a gap when a vehicle's next new fix arrives more than `threshold_ms` after its
+// previous one. A replayed fix isn't an update, so it neither ends nor hides a gap. The
+// caller reports each socket's ("link", normally its route) disconnects and reconnects; a
+// gap overlapping an outage of the link the vehicle arrived on is put down to us rather
+// than the feed.
+pub struct GapDetector {
+    threshold_ms: i64,
+    vehicles: HashMap<String, LastUpdate>,
+    // (disconnected, reconnected) receive times by link; the current outage has no end yet.
+    outages: HashMap<String, VecDeque<(i64, Option<i64>)>>,
+}
+
+impl GapDetector {
+    pub fn new(threshold_ms: i64) -> Self {
+        Self {
+            threshold_ms: threshold_ms.max(1),
+            vehicles: HashMap::new(),
+            outages: HashMap::new(),
+        }
+    }
+
+    pub fn on_disconnected(&mut self, link: &str, now_ms: i64) {
+        let outages = self.outages.entry(link.to_string()).or_default();
+        if matches!(outages.back(), Some((_, None))) {
+            return;
+        }
+        if outages.len() == MAX_OUTAGES {
+            outages.pop_front();
+        }
+        outages.push_back((now_ms, None));
+    }
+
+    pub fn on_connected(&mut self, link: &str, now_ms: i64) {
+        let outage = self.outages.get_mut(link).and_then(VecDeque::back_mut);
+        if let Some((_, end @ None)) = outage {
+            *end = Some(now_ms);
+        }
+    }
+
+    pub fn observe(&mut self, bus: &BusPosition, link: &str, received_at_ms: i64) -> Option<Gap> {
+        if bus.bus_no.is_empty() {
+            return None;
+        }
+        let fix = bus.timestamp_rfc3339.clone().or_else(|| bus.dt_gps.clone());
+        let update = LastUpdate {
+            received_at_ms,
+            fix,
+        };
+        let Some(last) = self.vehicles.get_mut(&bus.bus_no) else {
+            self.vehicles.insert(bus.bus_no.clone(), update);
+            return None;
+        };
+        if update.fix.is_some() && update.fix == last.fix {
+            return None;
+        }
+
+        let from_ms = last.received_at_ms;
+        *last = update;
+        let duration_ms = received_at_ms - from_ms;
+        if duration_ms <= self.threshold_ms {
+            return None;
+        }
+        let local = self.outages.get(link).is_some_and(|outages| {
+            outages
+                .iter()
+                .any(|&(start, end)| start < received_at_ms && end.unwrap_or(i64::MAX) > from_ms)
+        });
+        Some(Gap {
+            vehicle_id: bus.bus_no.clone(),
+            route: bus.route.clone(),
+            from_unix_ms: from_ms,
+            to_unix_ms: received_at_ms,
+            duration_ms,
+            cause: if local {
+                GapCause::LocalDisconnect
+            } else {
+                GapCause::Upstream
+            },
+        })
+    }
+}
+
+// Gaps as JSON lines in one append-only file. Gaps are rare next to positions, so the
+// file stays small and a query simply reads it through.
+#[derive(Debug, Clone)]
+pub struct GapLog {
+    path: PathBuf,
+}
+
+impl GapLog {
+    // The log lives in `dir`, normally ARCHIVE_DIR next to the Parquet files.
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            path: dir.join(GAP_LOG_FILE),
+        }
+    }
+
+    pub fn append(&self, gap: &Gap) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(gap)?;
+        line.push('\n');
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())
+    }
+
+    // The vehicle's gaps overlapping [from_ms, to_ms], oldest first. Lines that don't
+    // parse, e.g. one cut short by a crash, are skipped.
+    pub fn query(&self, vehicle_id: &str, from_ms: i64, to_ms: i64) -> std::io::Result<Vec<Gap>> {
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error),
+        };
+        let mut gaps = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let Ok(gap) = serde_json::from_str::<Gap>(&line?) else {
+                continue;
+            };
+            if gap.vehicle_id.eq_ignore_ascii_case(vehicle_id)
+                && gap.from_unix_ms <= to_ms
+                && gap.to_unix_ms >= from_ms
+            {
+                gaps.push(gap);
+            }
+        }
+        gaps.sort_by_key(|gap| gap.from_unix_ms);
+        Ok(gaps)
+    }
+}
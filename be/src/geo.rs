@@ -0,0 +1,91 @@
+use crate::feed::BusPosition;
+
+// Spherical-earth distance and bearing between (lat, lon) pairs in degrees. Good to a few
+// metres at city scale, which is all the feed's GPS fixes are good for anyway.
+
+pub const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+
+pub fn haversine_meters(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lon1) = a;
+    let (lat2, lon2) = b;
+    let dlat = (lat2 - lat1).to_radians();
+    let dlon = (lon2 - lon1).to_radians();
+    let h = (dlat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.0).sin().powi(2);
+    EARTH_RADIUS_METERS * 2.0 * h.sqrt().min(1.0).asin()
+}
+
+// Initial great-circle bearing from `a` towards `b`, clockwise from north in [0, 360).
+pub fn bearing_deg(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (a.0.to_radians(), a.1.to_radians());
+    let (lat2, lon2) = (b.0.to_radians(), b.1.to_radians());
+    let dlon = lon2 - lon1;
+    let y = dlon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+// Point `t` of the way along the great circle from `a` to `b`, in (lat, lon) degrees, at
+// constant speed along the arc. `t` is clamped to [0, 1], so 0 and 1 return the ends
+// exactly; the antimeridian needs no special casing.
+pub fn slerp(a: (f64, f64), b: (f64, f64), t: f64) -> (f64, f64) {
+    if t <= 0.0 || t.is_nan() {
+        return a;
+    }
+    if t >= 1.0 {
+        return b;
+    }
+    let (from, to) = (unit_vector(a), unit_vector(b));
+    let dot = (from.0 * to.0 + from.1 * to.1 + from.2 * to.2).clamp(-1.0, 1.0);
+    let angle = dot.acos();
+    // Below a millimetre or so the two fixes are the same point for any map.
+    if angle < 1e-10 {
+        return a;
+    }
+    let (w_from, w_to) = (
+        ((1.0 - t) * angle).sin() / angle.sin(),
+        (t * angle).sin() / angle.sin(),
+    );
+    let (x, y, z) = (
+        w_from * from.0 + w_to * to.0,
+        w_from * from.1 + w_to * to.1,
+        w_from * from.2 + w_to * to.2,
+    );
+    (
+        z.atan2((x * x + y * y).sqrt()).to_degrees(),
+        y.atan2(x).to_degrees(),
+    )
+}
+
+fn unit_vector(point: (f64, f64)) -> (f64, f64, f64) {
+    let (lat, lon) = (point.0.to_radians(), point.1.to_radians());
+    (lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin())
+}
+
+// Where a bus is drawn between two fixes for a frame, as (lat, lon). `t` is the fraction
+// of the time between the fixes that has elapsed, (now - prev fix) / (next fix - prev
+// fix), which assumes the bus covered the distance at constant speed in a straight line.
+// Streets bend, so this is for animation only, never for ETAs or distances.
+pub fn interpolate(prev: &BusPosition, next: &BusPosition, t: f64) -> (f64, f64) {
+    slerp(
+        (prev.latitude, prev.longitude),
+        (next.latitude, next.longitude),
+        t,
+    )
+}
+
+// EPSG:3857 uses a sphere with the WGS84 semi-major axis, not the mean radius above.
+pub const WEB_MERCATOR_RADIUS_METERS: f64 = 6_378_137.0;
+// Latitude where the projection becomes a square; anything beyond is clamped to it.
+pub const WEB_MERCATOR_MAX_LATITUDE: f64 = 85.051_128_78;
+
+// Spherical (Web) Mercator x/y in metres for a (lat, lon) in degrees.
+pub fn web_mercator(point: (f64, f64)) -> (f64, f64) {
+    let lat = point
+        .0
+        .clamp(-WEB_MERCATOR_MAX_LATITUDE, WEB_MERCATOR_MAX_LATITUDE)
+        .to_radians();
+    let x = WEB_MERCATOR_RADIUS_METERS * point.1.to_radians();
+    let y = WEB_MERCATOR_RADIUS_METERS * (std::f64::consts::FRAC_PI_4 + lat / 2.0).tan().ln();
+    (x, y)
+}
@@ -0,0 +1,237 @@
+use crate::feed::{BusPosition, PositionSource};
+use futures_util::future::BoxFuture;
+use futures_util::{stream, Stream};
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("rapidbro.v1");
+}
+
+use proto::bus_feed_server::{BusFeed, BusFeedServer};
+use proto::{BusUpdate, Snapshot, SnapshotRequest, SubscribeRequest};
+
+pub const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0:50051";
+// Batches the fan-out channel holds for the per-client forwarders.
+pub const DEFAULT_BROADCAST_CAPACITY: usize = 64;
+// Updates a client may have buffered before it is considered too slow.
+pub const DEFAULT_CLIENT_BUFFER: usize = 1_024;
+
+// Loads the positions GetSnapshot answers with; the server decides where they live.
+pub type SnapshotLoader =
+    Arc<dyn Fn() -> BoxFuture<'static, Result<Vec<BusPosition>, String>> + Send + Sync>;
+
+impl From<&BusPosition> for BusUpdate {
+    fn from(bus: &BusPosition) -> Self {
+        Self {
+            bus_no: bus.bus_no.clone(),
+            route: bus.route.clone(),
+            latitude: bus.latitude,
+            longitude: bus.longitude,
+            speed: bus.speed,
+            angle: bus.angle,
+            dir: bus.dir.clone(),
+            dt_gps: bus.dt_gps.clone(),
+            dt_received: bus.dt_received.clone(),
+            provider: bus.provider.clone(),
+            trip_no: bus.trip_no.clone(),
+            busstop_id: bus.busstop_id.clone(),
+            route_color: bus.route_color.clone(),
+            delay_min: bus.delay_min,
+            trip_id: bus.trip_id.clone(),
+            route_id: bus.route_id.clone(),
+            current_stop_sequence: bus.current_stop_sequence,
+            source: match bus.source {
+                PositionSource::Websocket => "websocket",
+                PositionSource::Gtfs => "gtfs",
+            }
+            .to_string(),
+        }
+    }
+}
+
+// The ingestor's side of the gRPC API: every published batch fans out to the Subscribe
+// streams, each filtered to its client's routes before anything is queued for it.
+//
+// A client gets a buffer of `client_buffer` updates. One that lets it fill up is sent
+// RESOURCE_EXHAUSTED and dropped, so a slow reader never holds up the ingestor or the
+// other clients.
+#[derive(Clone)]
+pub struct GrpcFeed {
+    sender: broadcast::Sender<Arc<Vec<BusPosition>>>,
+    client_buffer: usize,
+    // Route codes are compared in this form, e.g. be::normalize_route_code.
+    route_key: fn(&str) -> String,
+}
+
+impl GrpcFeed {
+    pub fn new(client_buffer: usize, route_key: fn(&str) -> String) -> Self {
+        Self {
+            sender: broadcast::channel(DEFAULT_BROADCAST_CAPACITY).0,
+            // One slot is kept back for the status that ends a slow client's stream.
+            client_buffer: client_buffer.max(1) + 1,
+            route_key,
+        }
+    }
+
+    // Returns how many Subscribe streams are open.
+    pub fn publish(&self, buses: &[BusPosition]) -> usize {
+        if self.sender.receiver_count() == 0 || buses.is_empty() {
+            return 0;
+        }
+        self.sender.send(Arc::new(buses.to_vec())).unwrap_or(0)
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    pub fn service(&self, snapshot: SnapshotLoader) -> BusFeedServer<GrpcService> {
+        BusFeedServer::new(self.handler(snapshot))
+    }
+
+    // The BusFeed implementation without the tonic server around it.
+    pub fn handler(&self, snapshot: SnapshotLoader) -> GrpcService {
+        GrpcService {
+            feed: self.clone(),
+            snapshot,
+        }
+    }
+
+    fn route_filter(&self, routes: &[String]) -> RouteFilter {
+        RouteFilter {
+            routes: routes
+                .iter()
+                .map(|route| (self.route_key)(route))
+                .filter(|route| !route.is_empty())
+                .collect(),
+            route_key: self.route_key,
+        }
+    }
+}
+
+struct RouteFilter {
+    // Empty matches every route.
+    routes: HashSet<String>,
+    route_key: fn(&str) -> String,
+}
+
+impl RouteFilter {
+    fn matches(&self, bus: &BusPosition) -> bool {
+        self.routes.is_empty() || self.routes.contains(&(self.route_key)(&bus.route))
+    }
+}
+
+pub struct GrpcService {
+    feed: GrpcFeed,
+    snapshot: SnapshotLoader,
+}
+
+type UpdateStream = Pin<Box<dyn Stream<Item = Result<BusUpdate, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl BusFeed for GrpcService {
+    type SubscribeStream = UpdateStream;
+
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let peer = request
+            .remote_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let filter = self.feed.route_filter(&request.into_inner().routes);
+        let receiver = self.feed.sender.subscribe();
+        let (updates, stream) = mpsc::channel(self.feed.client_buffer);
+        println!(
+            "gRPC client {} subscribed to {}",
+            peer,
+            describe_routes(&filter.routes)
+        );
+        tokio::spawn(forward_updates(receiver, updates, filter, peer));
+
+        let stream = stream::unfold(stream, |mut stream| async move {
+            stream.recv().await.map(|update| (update, stream))
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn get_snapshot(
+        &self,
+        request: Request<SnapshotRequest>,
+    ) -> Result<Response<Snapshot>, Status> {
+        let filter = self.feed.route_filter(&request.into_inner().routes);
+        let buses = (self.snapshot)().await.map_err(Status::unavailable)?;
+        Ok(Response::new(Snapshot {
+            buses: buses
+                .iter()
+                .filter(|bus| filter.matches(bus))
+                .map(BusUpdate::from)
+                .collect(),
+        }))
+    }
+}
+
+// Copies one client's matching updates into its buffer until the client goes away or
+// falls behind.
+async fn forward_updates(
+    mut receiver: broadcast::Receiver<Arc<Vec<BusPosition>>>,
+    updates: mpsc::Sender<Result<BusUpdate, Status>>,
+    filter: RouteFilter,
+    peer: String,
+) {
+    loop {
+        let batch = tokio::select! {
+            batch = receiver.recv() => batch,
+            _ = updates.closed() => {
+                println!("gRPC client {} disconnected", peer);
+                return;
+            }
+        };
+        let batch = match batch {
+            Ok(batch) => batch,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                disconnect_slow_client(&updates, &peer, skipped);
+                return;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        for bus in batch.iter().filter(|bus| filter.matches(bus)) {
+            // The last slot is reserved for the disconnect status.
+            if updates.capacity() <= 1 {
+                disconnect_slow_client(&updates, &peer, 0);
+                return;
+            }
+            let _ = updates.try_send(Ok(BusUpdate::from(bus)));
+        }
+    }
+}
+
+fn disconnect_slow_client(
+    updates: &mpsc::Sender<Result<BusUpdate, Status>>,
+    peer: &str,
+    skipped_batches: u64,
+) {
+    println!(
+        "gRPC client {} fell behind ({} updates buffered, {} batches skipped); disconnecting",
+        peer,
+        updates.max_capacity() - updates.capacity(),
+        skipped_batches
+    );
+    let _ = updates.try_send(Err(Status::resource_exhausted(
+        "client fell too far behind the feed",
+    )));
+}
+
+fn describe_routes(routes: &HashSet<String>) -> String {
+    if routes.is_empty() {
+        return "all routes".to_string();
+    }
+    let mut routes: Vec<&str> = routes.iter().map(String::as_str).collect();
+    routes.sort_unstable();
+    format!("routes {}", routes.join(", "))
+}
@@ -0,0 +1,368 @@
+use crate::client::ClientEvent;
+use crate::direction::Direction;
+use crate::feed::{inflate, BusPosition, PositionSource, DEFAULT_MAX_DECOMPRESSED_BYTES};
+use crate::now_unix_ms;
+use crate::proxy::ProxyOptions;
+use crate::throttle::{throttle_wait, MAX_THROTTLE_WAIT};
+use crate::timestamp::{format_feed_timestamp, localize_timestamp, normalize_timestamp};
+use crate::tls::TlsOptions;
+use chrono::DateTime;
+use chrono_tz::Tz;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use gtfs_realtime::vehicle_position::OccupancyStatus;
+use gtfs_realtime::FeedMessage;
+use metrics::histogram;
+use prost::Message;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+pub const PRASARANA_VEHICLE_POSITIONS_URL: &str =
+    "https://api.data.gov.my/gtfs-realtime/vehicle-position/prasarana?category=rapid-bus-kl";
+
+// With a reused connection only the first poll includes the TCP/TLS setup, so a pooled
+// client shows up here as one slow sample followed by fast ones.
+pub const FETCH_SECONDS: &str = "rapidbro_gtfs_rt_fetch_seconds";
+
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const PROVIDER: &str = "RKL";
+
+// An owned, Option-light view of a GTFS-rt vehicle entity. Units match BusPosition.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Vehicle {
+    pub id: String,
+    pub route_id: Option<String>,
+    pub trip_id: Option<String>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub bearing: Option<f64>,
+    // km/h; the feed reports m/s.
+    pub speed: Option<f64>,
+    // Unix seconds of the fix.
+    pub timestamp: Option<i64>,
+    // GTFS-rt occupancy name, e.g. MANY_SEATS_AVAILABLE.
+    pub occupancy: Option<String>,
+    pub current_stop_sequence: Option<u32>,
+}
+
+// Entities without a vehicle or a position are skipped. The id prefers the vehicle id,
+// then its label, then the entity id.
+pub fn feed_to_vehicles(feed: &FeedMessage) -> Vec<Vehicle> {
+    feed.entity
+        .iter()
+        .filter_map(|entity| {
+            let vehicle = entity.vehicle.as_ref()?;
+            let position = vehicle.position.as_ref()?;
+            let descriptor = vehicle.vehicle.as_ref();
+            let id = descriptor
+                .and_then(|descriptor| descriptor.id.clone().or(descriptor.label.clone()))
+                .filter(|id| !id.is_empty())
+                .unwrap_or_else(|| entity.id.clone());
+            let trip = vehicle.trip.as_ref();
+
+            Some(Vehicle {
+                id,
+                route_id: trip.and_then(|trip| trip.route_id.clone()),
+                trip_id: trip.and_then(|trip| trip.trip_id.clone()),
+                latitude: position.latitude as f64,
+                longitude: position.longitude as f64,
+                bearing: position.bearing.map(f64::from),
+                speed: position.speed.map(|speed| speed as f64 * 3.6),
+                timestamp: vehicle.timestamp.map(|timestamp| timestamp as i64),
+                occupancy: vehicle
+                    .occupancy_status
+                    .and_then(|status| OccupancyStatus::try_from(status).ok())
+                    .map(|status| status.as_str_name().to_string()),
+                current_stop_sequence: vehicle.current_stop_sequence,
+            })
+        })
+        .collect()
+}
+
+// Lets GTFS-rt vehicles flow through the same pipeline as socket updates. dt_gps is
+// written the way the socket writes it, so consumers see one format whatever the source.
+impl From<&Vehicle> for BusPosition {
+    fn from(vehicle: &Vehicle) -> Self {
+        let timestamp = vehicle
+            .timestamp
+            .and_then(|seconds| DateTime::from_timestamp(seconds, 0));
+        BusPosition {
+            dt_received: None,
+            dt_gps: timestamp.map(format_feed_timestamp),
+            latitude: vehicle.latitude,
+            longitude: vehicle.longitude,
+            dir: None,
+            speed: vehicle.speed.unwrap_or(0.0),
+            angle: vehicle.bearing.unwrap_or(0.0),
+            route: vehicle.route_id.clone().unwrap_or_default(),
+            bus_no: vehicle.id.clone(),
+            trip_no: vehicle.trip_id.clone(),
+            captain_id: None,
+            trip_rev_kind: None,
+            engine_status: 0,
+            accessibility: 0,
+            busstop_id: None,
+            provider: PROVIDER.to_string(),
+            route_color: None,
+            route_text_color: None,
+            timestamp_rfc3339: timestamp.map(|timestamp| timestamp.to_rfc3339()),
+            local_time: None,
+            age_seconds: None,
+            timestamp_parse_error: false,
+            progress_m: None,
+            progress_pct: None,
+            off_route: false,
+            inactive: false,
+            direction: Direction::Unknown,
+            delay_min: None,
+            trip_id: vehicle.trip_id.clone(),
+            route_id: vehicle.route_id.clone(),
+            current_stop_sequence: vehicle.current_stop_sequence,
+            smoothed_speed_kmh: None,
+            source: PositionSource::Gtfs,
+            extra: HashMap::new(),
+        }
+    }
+}
+
+// A data.gov.my API key, sent as `Authorization: Token <key>` for higher rate limits.
+// Debug prints it redacted, and the header is marked sensitive so reqwest's own Debug
+// output redacts it too.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ApiToken(String);
+
+impl ApiToken {
+    pub fn new(token: impl Into<String>) -> Option<Self> {
+        let token = token.into().trim().to_string();
+        (!token.is_empty()).then_some(Self(token))
+    }
+
+    fn header_value(&self) -> Option<HeaderValue> {
+        let mut value = HeaderValue::from_str(&format!("Token {}", self.0)).ok()?;
+        value.set_sensitive(true);
+        Some(value)
+    }
+}
+
+impl fmt::Debug for ApiToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ApiToken(<redacted>)")
+    }
+}
+
+impl FromStr for ApiToken {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::new(value).ok_or_else(|| "the API token is empty".to_string())
+    }
+}
+
+// Idle connections outlive the poll interval, so every poll after the first rides the
+// same keep-alive connection; build this once and clone it (clones share the pool).
+// Fails rather than falling back to a default client, which would drop the TLS and
+// proxy settings and the token along with the pool tuning.
+pub fn build_http_client(
+    tls: &TlsOptions,
+    proxy: &ProxyOptions,
+    api_token: Option<&ApiToken>,
+) -> Result<reqwest::Client, String> {
+    let mut headers = HeaderMap::new();
+    if let Some(value) = api_token.and_then(ApiToken::header_value) {
+        headers.insert(AUTHORIZATION, value);
+    }
+    proxy
+        .apply(tls.apply(reqwest::Client::builder()))
+        .default_headers(headers)
+        .gzip(true)
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .tcp_keepalive(TCP_KEEPALIVE)
+        .build()
+        .map_err(|error| format!("failed to build the GTFS-realtime HTTP client: {}", error))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FetchError {
+    // HTTP 429 or 403; `wait` honours Retry-After when the server sends one.
+    Throttled { status: u16, wait: Duration },
+    Failed(String),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::Throttled { status, wait } => write!(
+                f,
+                "GTFS-rt is throttling us (HTTP {}), backing off for {}s",
+                status,
+                wait.as_secs()
+            ),
+            FetchError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+pub async fn fetch_feed(http: &reqwest::Client, url: &str) -> Result<FeedMessage, FetchError> {
+    let started = Instant::now();
+    let response = http
+        .get(url)
+        .send()
+        .await
+        .map_err(|error| FetchError::Failed(format!("GTFS-rt fetch failed: {}", error)))?;
+    let status = response.status().as_u16();
+    if let Some(wait) = throttle_wait(status, response.headers()) {
+        return Err(FetchError::Throttled { status, wait });
+    }
+    let body = response
+        .bytes()
+        .await
+        .map_err(|error| FetchError::Failed(format!("GTFS-rt body read failed: {}", error)))?;
+    histogram!(FETCH_SECONDS).record(started.elapsed().as_secs_f64());
+
+    decode_feed(&body).map_err(FetchError::Failed)
+}
+
+// One request, for `gtfs check-auth`: the HTTP status the feed answers with, which
+// tells whether the Authorization header (if any) was accepted.
+pub async fn check_auth(http: &reqwest::Client, url: &str) -> Result<u16, String> {
+    http.get(url)
+        .send()
+        .await
+        .map(|response| response.status().as_u16())
+        .map_err(|error| format!("GTFS-rt fetch failed: {}", error))
+}
+
+// Some responses arrive gzip-wrapped without a Content-Encoding header, so reqwest
+// hands over the compressed bytes untouched.
+pub fn decode_feed(body: &[u8]) -> Result<FeedMessage, String> {
+    decode_feed_within(body, DEFAULT_MAX_DECOMPRESSED_BYTES)
+}
+
+// Same, but a gzipped body may only inflate to `max_decompressed_bytes`, as with socket
+// payloads.
+pub fn decode_feed_within(
+    body: &[u8],
+    max_decompressed_bytes: usize,
+) -> Result<FeedMessage, String> {
+    let decode_error = match FeedMessage::decode(body) {
+        Ok(feed) => return Ok(feed),
+        Err(error) => error,
+    };
+
+    if !body.starts_with(&GZIP_MAGIC) {
+        return Err(format!("GTFS-rt decode failed: {}", decode_error));
+    }
+
+    let mut decompressed = Vec::new();
+    inflate(body, &mut decompressed, max_decompressed_bytes)
+        .map_err(|error| format!("GTFS-rt gunzip failed: {}", error))?;
+    FeedMessage::decode(decompressed.as_slice())
+        .map_err(|error| format!("GTFS-rt decode failed after gunzip: {}", error))
+}
+
+struct PollState {
+    http: reqwest::Client,
+    url: String,
+    base_interval: Duration,
+    // Stretched after a throttle and halved back towards base_interval on each success.
+    interval: Duration,
+    connected: bool,
+    polled: bool,
+    pending: VecDeque<ClientEvent>,
+}
+
+// Polls the feed every `interval` and reports it the way the socket client does
+// (Connected/Disconnected around Buses batches), so either source drives one pipeline.
+// With a `timezone`, positions carry local_time as socket positions do.
+pub fn poll_vehicle_positions(
+    http: reqwest::Client,
+    url: impl Into<String>,
+    interval: Duration,
+    timezone: Option<Tz>,
+) -> BoxStream<'static, ClientEvent> {
+    let state = PollState {
+        http,
+        url: url.into(),
+        base_interval: interval,
+        interval,
+        connected: false,
+        polled: false,
+        pending: VecDeque::new(),
+    };
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(event) = state.pending.pop_front() {
+                return Some((event, state));
+            }
+            if state.polled {
+                tokio::time::sleep(state.interval).await;
+            }
+            state.polled = true;
+
+            match fetch_feed(&state.http, &state.url).await {
+                Ok(feed) => {
+                    if state.interval > state.base_interval {
+                        state.interval = (state.interval / 2).max(state.base_interval);
+                        println!(
+                            "GTFS-rt fetch succeeded, polling every {}s",
+                            state.interval.as_secs()
+                        );
+                    }
+                    if !state.connected {
+                        state.connected = true;
+                        // There's no kiosk session here; a successful fetch stands in for one.
+                        state.pending.push_back(ClientEvent::SessionEstablished {
+                            route: String::new(),
+                        });
+                        state.pending.push_back(ClientEvent::Connected);
+                    }
+                    let received_at_unix_ms = now_unix_ms();
+                    let buses = feed_to_vehicles(&feed)
+                        .iter()
+                        .map(|vehicle| {
+                            let mut bus = BusPosition::from(vehicle);
+                            normalize_timestamp(&mut bus, received_at_unix_ms);
+                            if let Some(timezone) = timezone {
+                                localize_timestamp(&mut bus, timezone);
+                            }
+                            bus
+                        })
+                        .collect();
+                    state.pending.push_back(ClientEvent::Buses {
+                        buses,
+                        decode_failures: 0,
+                        received_at_unix_ms,
+                    });
+                }
+                Err(error) => {
+                    if let FetchError::Throttled { status, wait } = &error {
+                        state.interval = stretched_interval(state.interval, *wait);
+                        println!(
+                            "GTFS-rt is throttling us (HTTP {}); polling every {}s until it recovers",
+                            status,
+                            state.interval.as_secs()
+                        );
+                    }
+                    state.connected = false;
+                    state.pending.push_back(ClientEvent::Disconnected {
+                        reason: error.to_string(),
+                    });
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+// At least the server's wait and at least double the current interval, so repeated
+// throttles back off further even without a Retry-After.
+pub fn stretched_interval(current: Duration, wait: Duration) -> Duration {
+    wait.max(current * 2).min(MAX_THROTTLE_WAIT)
+}
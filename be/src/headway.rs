@@ -0,0 +1,148 @@
+use crate::feed::BusPosition;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+#[cfg(feature = "server")]
+use utoipa::ToSchema;
+
+// Below this a bus is treated as dwelling and its own speed says nothing about the gap.
+const MIN_MOVING_SPEED_KMH: f64 = 5.0;
+const ASSUMED_SPEED_KMH: f64 = 20.0;
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+pub struct Headway {
+    pub leading_bus: String,
+    pub following_bus: String,
+    pub distance_meters: f64,
+    pub seconds: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+pub struct HeadwaySummary {
+    pub route: String,
+    pub direction: u8,
+    pub headways: Vec<Headway>,
+}
+
+impl fmt::Display for HeadwaySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let gaps: Vec<String> = self
+            .headways
+            .iter()
+            .map(|headway| format!("{}m", (headway.seconds + 30) / 60))
+            .collect();
+        write!(
+            f,
+            "route {} dir {}: headways {}",
+            self.route,
+            self.direction,
+            gaps.join(", ")
+        )
+    }
+}
+
+// Orders buses by their progress along the route shape and measures the gap to the bus
+// ahead. Only buses with progress_m and a known direction take part; routes are grouped
+// by `route_key(route)` so callers can share their route normalization.
+pub fn compute_headways(
+    buses: &[BusPosition],
+    route_key: fn(&str) -> String,
+) -> Vec<HeadwaySummary> {
+    let mut groups: HashMap<(String, u8), Vec<(&BusPosition, f64)>> = HashMap::new();
+    let mut route_names: HashMap<String, String> = HashMap::new();
+
+    for bus in buses.iter().filter(|bus| !bus.bus_no.is_empty()) {
+        let (Some(progress_meters), Some(direction)) =
+            (bus.progress_m, bus.direction.direction_id())
+        else {
+            continue;
+        };
+        let key = route_key(&bus.route);
+        route_names
+            .entry(key.clone())
+            .or_insert_with(|| bus.route.clone());
+        groups
+            .entry((key, direction))
+            .or_default()
+            .push((bus, progress_meters));
+    }
+
+    let mut summaries: Vec<HeadwaySummary> = groups
+        .into_iter()
+        .filter(|(_, vehicles)| vehicles.len() >= 2)
+        .map(|((key, direction), mut vehicles)| {
+            // Leader first: furthest along the shape in the direction of travel.
+            vehicles.sort_by(|a, b| {
+                let ordering = b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal);
+                if direction == 0 {
+                    ordering
+                } else {
+                    ordering.reverse()
+                }
+            });
+
+            let headways = vehicles
+                .windows(2)
+                .map(|pair| {
+                    let (leading, leading_progress) = pair[0];
+                    let (following, following_progress) = pair[1];
+                    let distance_meters = (leading_progress - following_progress).abs();
+                    let speed_kmh = if following.speed >= MIN_MOVING_SPEED_KMH {
+                        following.speed
+                    } else {
+                        ASSUMED_SPEED_KMH
+                    };
+                    Headway {
+                        leading_bus: leading.bus_no.clone(),
+                        following_bus: following.bus_no.clone(),
+                        distance_meters,
+                        seconds: (distance_meters / (speed_kmh / 3.6)).round() as i64,
+                    }
+                })
+                .collect();
+
+            HeadwaySummary {
+                route: route_names.get(&key).cloned().unwrap_or(key),
+                direction,
+                headways,
+            }
+        })
+        .collect();
+
+    summaries.sort_by(|a, b| (&a.route, a.direction).cmp(&(&b.route, b.direction)));
+    summaries
+}
+
+// The latest headways of every route, keyed by `route_key`. Each socket client reports
+// only its own route, so a batch replaces the routes it carries and leaves the rest.
+#[derive(Debug, Default)]
+pub struct HeadwayBoard {
+    routes: HashMap<String, Vec<HeadwaySummary>>,
+}
+
+impl HeadwayBoard {
+    // Returns the batch's own summaries, for logging.
+    pub fn update(
+        &mut self,
+        buses: &[BusPosition],
+        route_key: fn(&str) -> String,
+    ) -> Vec<HeadwaySummary> {
+        let summaries = compute_headways(buses, route_key);
+        // A route in the batch with fewer than two placed buses has no headways any more.
+        for bus in buses {
+            self.routes.insert(route_key(&bus.route), Vec::new());
+        }
+        for summary in &summaries {
+            if let Some(route) = self.routes.get_mut(&route_key(&summary.route)) {
+                route.push(summary.clone());
+            }
+        }
+        summaries
+    }
+
+    pub fn summaries(&self) -> impl Iterator<Item = &HeadwaySummary> {
+        self.routes.values().flatten()
+    }
+}
@@ -0,0 +1,224 @@
+use crate::feed::BusPosition;
+use crate::queue::{BoundedQueue, OverflowPolicy};
+use crate::timestamp::parse_feed_timestamp;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use metrics::counter;
+use reqwest::header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::StatusCode;
+use std::io::Write;
+use std::time::Duration;
+
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+pub const DEFAULT_QUEUE_CAPACITY: usize = 50_000;
+const MAX_BATCH_LINES: usize = 5_000;
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MEASUREMENT: &str = "bus_position";
+
+pub const INFLUX_POINTS_WRITTEN_TOTAL: &str = "rapidbro_influx_points_written_total";
+pub const INFLUX_THROTTLED_TOTAL: &str = "rapidbro_influx_throttled_total";
+pub const INFLUX_DROPPED_TOTAL: &str = "rapidbro_influx_dropped_total";
+
+#[derive(Debug, Clone)]
+pub struct InfluxConfig {
+    pub url: String,
+    pub org: String,
+    pub bucket: String,
+    pub token: String,
+    pub flush_interval: Duration,
+    pub queue_capacity: usize,
+    pub overflow_policy: OverflowPolicy,
+}
+
+enum WriteError {
+    // 429, 5xx and network failures: keep the batch and try again later.
+    Retry {
+        after: Option<Duration>,
+        reason: String,
+    },
+    Rejected(String),
+}
+
+// Buffers positions as line-protocol points and writes them to the InfluxDB v2 write API
+// every flush interval. A throttled or failing flusher backs off while the queue keeps
+// only the newest points.
+#[derive(Debug, Clone)]
+pub struct InfluxSink {
+    config: InfluxConfig,
+    http: reqwest::Client,
+    queue: BoundedQueue<String>,
+}
+
+impl InfluxSink {
+    pub fn new(config: InfluxConfig) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+        let queue = BoundedQueue::new(
+            config.queue_capacity,
+            config.overflow_policy,
+            Duration::ZERO,
+        )
+        .named("influx");
+        Self {
+            config,
+            http,
+            queue,
+        }
+    }
+
+    pub async fn enqueue(&self, bus: &BusPosition, received_at_unix_ms: i64) {
+        let Some(line) = line_protocol(bus, received_at_unix_ms) else {
+            return;
+        };
+        if !self.queue.push(line).await {
+            counter!(INFLUX_DROPPED_TOTAL, "reason" => "queue_full").increment(1);
+        }
+    }
+
+    pub async fn run(&self) {
+        let mut pending: Vec<String> = Vec::new();
+        let mut backoff: Option<Duration> = None;
+        loop {
+            tokio::time::sleep(backoff.unwrap_or(self.config.flush_interval)).await;
+            pending.extend(
+                self.queue
+                    .drain(MAX_BATCH_LINES.saturating_sub(pending.len())),
+            );
+            if pending.is_empty() {
+                continue;
+            }
+
+            match self.write(&pending).await {
+                Ok(()) => {
+                    counter!(INFLUX_POINTS_WRITTEN_TOTAL).increment(pending.len() as u64);
+                    pending.clear();
+                    backoff = None;
+                }
+                Err(WriteError::Rejected(reason)) => {
+                    println!("InfluxDB rejected {} points: {}", pending.len(), reason);
+                    counter!(INFLUX_DROPPED_TOTAL, "reason" => "rejected")
+                        .increment(pending.len() as u64);
+                    pending.clear();
+                    backoff = None;
+                }
+                Err(WriteError::Retry { after, reason }) => {
+                    let next = after.unwrap_or_else(|| {
+                        backoff.map_or(self.config.flush_interval, |current| current * 2)
+                    });
+                    let next = next.min(MAX_BACKOFF);
+                    println!(
+                        "InfluxDB write of {} points failed ({}), retrying in {:?}",
+                        pending.len(),
+                        reason,
+                        next
+                    );
+                    backoff = Some(next);
+                }
+            }
+        }
+    }
+
+    async fn write(&self, lines: &[String]) -> Result<(), WriteError> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        for line in lines {
+            let _ = encoder.write_all(line.as_bytes());
+            let _ = encoder.write_all(b"\n");
+        }
+        let body = encoder
+            .finish()
+            .map_err(|error| WriteError::Rejected(format!("gzip failed: {}", error)))?;
+
+        let response = self
+            .http
+            .post(format!(
+                "{}/api/v2/write",
+                self.config.url.trim_end_matches('/')
+            ))
+            .query(&[
+                ("org", self.config.org.as_str()),
+                ("bucket", self.config.bucket.as_str()),
+                ("precision", "ns"),
+            ])
+            .header(AUTHORIZATION, format!("Token {}", self.config.token))
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .header(CONTENT_ENCODING, "gzip")
+            .body(body)
+            .send()
+            .await
+            .map_err(|error| WriteError::Retry {
+                after: None,
+                reason: error.to_string(),
+            })?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            counter!(INFLUX_THROTTLED_TOTAL).increment(1);
+            let after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .map(Duration::from_secs);
+            return Err(WriteError::Retry {
+                after,
+                reason: status.to_string(),
+            });
+        }
+        if status.is_server_error() {
+            return Err(WriteError::Retry {
+                after: None,
+                reason: status.to_string(),
+            });
+        }
+        let body = response.text().await.unwrap_or_default();
+        Err(WriteError::Rejected(format!("{} {}", status, body)))
+    }
+}
+
+// One point per position, timestamped with the GPS fix (falling back to the receive
+// time) in nanoseconds. Vehicles without an id aren't plottable and are skipped.
+pub fn line_protocol(bus: &BusPosition, received_at_unix_ms: i64) -> Option<String> {
+    if bus.bus_no.trim().is_empty() {
+        return None;
+    }
+    let timestamp_ns = [bus.dt_gps.as_deref(), bus.dt_received.as_deref()]
+        .into_iter()
+        .flatten()
+        .find_map(parse_feed_timestamp)
+        .and_then(|timestamp| timestamp.timestamp_nanos_opt())
+        .unwrap_or(received_at_unix_ms * 1_000_000);
+
+    let mut line = MEASUREMENT.to_string();
+    for (key, value) in [
+        ("provider", bus.provider.as_str()),
+        ("route", bus.route.as_str()),
+        ("vehicle", bus.bus_no.as_str()),
+    ] {
+        // Influx rejects empty tag values; leave the tag off instead.
+        if !value.trim().is_empty() {
+            line.push_str(&format!(",{}={}", key, escape_tag(value.trim())));
+        }
+    }
+    line.push_str(&format!(
+        " lat={},lon={},speed={},bearing={} {}",
+        bus.latitude, bus.longitude, bus.speed, bus.angle, timestamp_ns
+    ));
+    Some(line)
+}
+
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        if matches!(character, ',' | '=' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
@@ -0,0 +1,145 @@
+use crate::feed::BusPosition;
+use metrics::counter;
+use rdkafka::config::ClientConfig;
+use rdkafka::error::{KafkaError, KafkaResult, RDKafkaErrorCode};
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{BaseRecord, DeliveryResult, Producer, ProducerContext, ThreadedProducer};
+use rdkafka::ClientContext;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const DEFAULT_TOPIC: &str = "rapidbro.positions";
+pub const DEFAULT_QUEUE_CAPACITY: usize = 100_000;
+// How long shutdown waits for librdkafka to deliver what is still queued.
+pub const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const KAFKA_PRODUCED_TOTAL: &str = "rapidbro_kafka_produced_total";
+pub const KAFKA_ERRORS_TOTAL: &str = "rapidbro_kafka_errors_total";
+
+#[derive(Debug, Clone)]
+pub struct KafkaConfig {
+    pub brokers: String,
+    pub topic: String,
+    // Caps librdkafka's in-memory queue; updates beyond it are dropped, not waited on.
+    pub queue_capacity: usize,
+    pub security_protocol: Option<String>,
+    pub sasl_mechanism: Option<String>,
+    pub sasl_username: Option<String>,
+    pub sasl_password: Option<String>,
+    pub ssl_ca_location: Option<String>,
+}
+
+impl KafkaConfig {
+    pub fn new(brokers: impl Into<String>) -> Self {
+        Self {
+            brokers: brokers.into(),
+            topic: DEFAULT_TOPIC.to_string(),
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            security_protocol: None,
+            sasl_mechanism: None,
+            sasl_username: None,
+            sasl_password: None,
+            ssl_ca_location: None,
+        }
+    }
+}
+
+struct MetricsContext;
+
+impl ClientContext for MetricsContext {}
+
+impl ProducerContext for MetricsContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, delivery_result: &DeliveryResult<'_>, _: Self::DeliveryOpaque) {
+        match delivery_result {
+            Ok(_) => counter!(KAFKA_PRODUCED_TOTAL).increment(1),
+            Err((error, _)) => {
+                println!("Kafka delivery failed: {}", error);
+                counter!(KAFKA_ERRORS_TOTAL, "reason" => "delivery").increment(1);
+            }
+        }
+    }
+}
+
+// Fire-and-forget producer: send only enqueues into librdkafka, whose background thread
+// delivers and reports outcomes through metrics, so callers never wait on the brokers.
+// Clones share the one producer and its queue.
+#[derive(Clone)]
+pub struct KafkaSink {
+    producer: Arc<ThreadedProducer<MetricsContext>>,
+    topic: String,
+}
+
+impl KafkaSink {
+    pub fn new(config: &KafkaConfig) -> Result<Self, KafkaError> {
+        let mut client_config = ClientConfig::new();
+        client_config.set("bootstrap.servers", &config.brokers).set(
+            "queue.buffering.max.messages",
+            config.queue_capacity.max(1).to_string(),
+        );
+        let optional_settings = [
+            ("security.protocol", &config.security_protocol),
+            ("sasl.mechanism", &config.sasl_mechanism),
+            ("sasl.username", &config.sasl_username),
+            ("sasl.password", &config.sasl_password),
+            ("ssl.ca.location", &config.ssl_ca_location),
+        ];
+        for (key, value) in optional_settings {
+            if let Some(value) = value {
+                client_config.set(key, value);
+            }
+        }
+
+        Ok(Self {
+            producer: Arc::new(client_config.create_with_context(MetricsContext)?),
+            topic: config.topic.clone(),
+        })
+    }
+
+    // Keyed by vehicle so each bus's updates land on one partition, in order.
+    pub fn produce(&self, bus: &BusPosition) {
+        let payload = match serde_json::to_vec(bus) {
+            Ok(payload) => payload,
+            Err(_) => {
+                counter!(KAFKA_ERRORS_TOTAL, "reason" => "serialize").increment(1);
+                return;
+            }
+        };
+        let headers = OwnedHeaders::new()
+            .insert(Header {
+                key: "provider",
+                value: Some(&bus.provider),
+            })
+            .insert(Header {
+                key: "route",
+                value: Some(&bus.route),
+            });
+        let record = BaseRecord::to(&self.topic)
+            .key(&bus.bus_no)
+            .payload(&payload)
+            .headers(headers);
+
+        if let Err((error, _)) = self.producer.send(record) {
+            let reason = match error {
+                KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull) => "queue_full",
+                _ => "produce",
+            };
+            counter!(KAFKA_ERRORS_TOTAL, "reason" => reason).increment(1);
+        }
+    }
+
+    // Blocks until every queued update is delivered or `timeout` passes. Dropping the
+    // producer without this at shutdown loses whatever librdkafka hadn't sent yet.
+    pub fn flush(&self, timeout: Duration) -> KafkaResult<()> {
+        self.producer.flush(timeout)
+    }
+}
+
+impl std::fmt::Debug for KafkaSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KafkaSink")
+            .field("topic", &self.topic)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,215 @@
+use crate::feed::BusPosition;
+use crate::geo::haversine_meters;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+pub const DEFAULT_TERMINAL_RADIUS_METERS: f64 = 150.0;
+pub const DEFAULT_DWELL_MS: i64 = 120_000;
+const MAX_DWELL_SPEED_KMH: f64 = 3.0;
+// Leaving needs a wider margin than arriving so jitter at the stand isn't a departure.
+const DEPARTURE_RADIUS_FACTOR: f64 = 1.5;
+// A bus silent this long has no trustworthy departure time; it is reset instead.
+const DARK_AFTER_MS: i64 = 600_000;
+
+#[derive(Debug, Clone)]
+pub struct Terminal {
+    pub stop_id: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event")]
+pub enum TripEvent {
+    TripEnded {
+        bus_no: String,
+        route: String,
+        stop_id: String,
+        layover_started_unix_ms: i64,
+    },
+    TripStarted {
+        bus_no: String,
+        route: String,
+        stop_id: String,
+        layover_started_unix_ms: i64,
+        departed_unix_ms: i64,
+    },
+}
+
+impl fmt::Display for TripEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TripEvent::TripEnded {
+                bus_no,
+                route,
+                stop_id,
+                ..
+            } => write!(f, "{} route={} trip ended at {}", bus_no, route, stop_id),
+            TripEvent::TripStarted {
+                bus_no,
+                route,
+                stop_id,
+                layover_started_unix_ms,
+                departed_unix_ms,
+            } => write!(
+                f,
+                "{} route={} trip started from {} after {}m layover",
+                bus_no,
+                route,
+                stop_id,
+                (departed_unix_ms - layover_started_unix_ms) / 60_000
+            ),
+        }
+    }
+}
+
+enum LayoverState {
+    Moving,
+    Dwelling {
+        stop_id: String,
+        since_ms: i64,
+    },
+    Layover {
+        stop_id: String,
+        since_ms: i64,
+        lat: f64,
+        lon: f64,
+    },
+}
+
+struct TrackedVehicle {
+    state: LayoverState,
+    last_seen_ms: i64,
+}
+
+// Flags a trip as ended once a bus sits near-stationary within the radius of one of its
+// route's terminal stops for the dwell time, and as started again when it pulls away.
+pub struct LayoverDetector {
+    termini: HashMap<String, Vec<Terminal>>,
+    route_key: fn(&str) -> String,
+    radius_meters: f64,
+    dwell_ms: i64,
+    vehicles: HashMap<String, TrackedVehicle>,
+}
+
+impl LayoverDetector {
+    // `termini` is keyed by `route_key(route)` so callers can share their route normalization.
+    pub fn new(
+        termini: HashMap<String, Vec<Terminal>>,
+        route_key: fn(&str) -> String,
+        radius_meters: f64,
+        dwell_ms: i64,
+    ) -> Self {
+        Self {
+            termini,
+            route_key,
+            radius_meters,
+            dwell_ms,
+            vehicles: HashMap::new(),
+        }
+    }
+
+    pub fn observe(&mut self, bus: &BusPosition, now_ms: i64) -> Option<TripEvent> {
+        if bus.bus_no.is_empty() {
+            return None;
+        }
+        let termini = self.termini.get(&(self.route_key)(&bus.route))?;
+        let nearby = termini
+            .iter()
+            .map(|terminal| {
+                let distance =
+                    haversine_meters((bus.latitude, bus.longitude), (terminal.lat, terminal.lon));
+                (terminal, distance)
+            })
+            .filter(|(_, distance)| *distance <= self.radius_meters)
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(terminal, _)| terminal);
+        let is_dwelling = nearby.is_some() && bus.speed <= MAX_DWELL_SPEED_KMH;
+
+        let tracked = self
+            .vehicles
+            .entry(bus.bus_no.clone())
+            .or_insert(TrackedVehicle {
+                state: LayoverState::Moving,
+                last_seen_ms: now_ms,
+            });
+        if now_ms - tracked.last_seen_ms > DARK_AFTER_MS {
+            tracked.state = LayoverState::Moving;
+        }
+        tracked.last_seen_ms = now_ms;
+
+        let (next_state, event) = match std::mem::replace(&mut tracked.state, LayoverState::Moving)
+        {
+            LayoverState::Moving => match nearby.filter(|_| is_dwelling) {
+                Some(terminal) => (
+                    LayoverState::Dwelling {
+                        stop_id: terminal.stop_id.clone(),
+                        since_ms: now_ms,
+                    },
+                    None,
+                ),
+                None => (LayoverState::Moving, None),
+            },
+            LayoverState::Dwelling { stop_id, since_ms } if is_dwelling => {
+                if now_ms - since_ms >= self.dwell_ms {
+                    let event = TripEvent::TripEnded {
+                        bus_no: bus.bus_no.clone(),
+                        route: bus.route.clone(),
+                        stop_id: stop_id.clone(),
+                        layover_started_unix_ms: since_ms,
+                    };
+                    (
+                        LayoverState::Layover {
+                            stop_id,
+                            since_ms,
+                            lat: bus.latitude,
+                            lon: bus.longitude,
+                        },
+                        Some(event),
+                    )
+                } else {
+                    (LayoverState::Dwelling { stop_id, since_ms }, None)
+                }
+            }
+            LayoverState::Dwelling { .. } => (LayoverState::Moving, None),
+            LayoverState::Layover {
+                stop_id,
+                since_ms,
+                lat,
+                lon,
+            } => {
+                let departed = haversine_meters((bus.latitude, bus.longitude), (lat, lon))
+                    > self.radius_meters * DEPARTURE_RADIUS_FACTOR;
+                if departed {
+                    let event = TripEvent::TripStarted {
+                        bus_no: bus.bus_no.clone(),
+                        route: bus.route.clone(),
+                        stop_id,
+                        layover_started_unix_ms: since_ms,
+                        departed_unix_ms: now_ms,
+                    };
+                    (LayoverState::Moving, Some(event))
+                } else {
+                    (
+                        LayoverState::Layover {
+                            stop_id,
+                            since_ms,
+                            lat,
+                            lon,
+                        },
+                        None,
+                    )
+                }
+            }
+        };
+
+        tracked.state = next_state;
+        event
+    }
+
+    // A dwell at another route's terminal shouldn't end or start a trip on this one.
+    pub fn forget(&mut self, bus_no: &str) {
+        self.vehicles.remove(bus_no);
+    }
+}
@@ -0,0 +1,82 @@
+#[cfg(feature = "parquet")]
+pub mod archive;
+pub mod auth;
+pub mod backfill;
+pub mod backoff;
+pub mod bulk;
+pub mod channels;
+pub mod client;
+#[cfg(feature = "server")]
+pub mod config;
+pub mod connection;
+pub mod delay;
+pub mod diff;
+pub mod direction;
+pub mod enrich;
+#[cfg(feature = "parquet")]
+pub mod export;
+pub mod feed;
+pub mod field_map;
+#[cfg(feature = "server")]
+pub mod frame_format;
+pub mod gaps;
+pub mod geo;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod gtfs_rt;
+pub mod headway;
+pub mod influx;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod layover;
+#[cfg(feature = "nats")]
+pub mod nats;
+pub mod ordering;
+pub mod parse_failures;
+pub mod payload_log;
+pub mod pipeline;
+pub mod presence;
+pub mod progress;
+pub mod proxy;
+pub mod queue;
+pub mod rate_limit;
+pub mod reassign;
+pub mod reconcile;
+#[cfg(feature = "redis")]
+pub mod redis_pubsub;
+pub mod route_colors;
+pub mod route_versions;
+pub mod schema;
+pub mod session;
+pub mod session_store;
+pub mod sink;
+pub mod speed;
+pub mod stats;
+pub mod stop_index;
+pub mod subscriptions;
+pub mod throttle;
+pub mod timestamp;
+pub mod tls;
+pub mod updates;
+pub mod validate;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn now_unix_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+// The form routes are compared and keyed in across the server: case, surrounding spaces
+// and trailing zeros don't count. The route_key argument of the route-keyed modules.
+pub fn normalize_route_code(route: &str) -> String {
+    route
+        .trim()
+        .to_uppercase()
+        .trim_end_matches('0')
+        .to_string()
+}
@@ -1,46 +1,123 @@
+mod cli;
+mod tui;
+
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Path, Query, Request, State,
+    },
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Response,
+    },
     routing::get,
     Json, Router,
 };
-use base64::Engine;
-use flate2::read::GzDecoder;
-use futures_util::FutureExt;
-use prost::Message;
-use rust_socketio::{asynchronous::ClientBuilder, Payload, TransportType};
+use be::archive::ArchiveSink;
+use be::auth::{AllowedOrigins, ApiKeyCheck, ApiKeys, API_KEY_HEADER, API_KEY_QUERY};
+use be::backfill::{BackfillGuard, BACKFILL_POSITIONS_TOTAL, DEFAULT_BACKFILL_MIN_INTERVAL_MS};
+use be::bulk::{feature_collection, group_by_route, route_map, FieldSelection};
+use be::channels::{ChannelRegistry, DEFAULT_CHANNEL_CAPACITY, DEFAULT_IDLE_GRACE};
+use be::client::{
+    ClientEvent, RapidbroClient, DECODE_BATCH_SECONDS, DEFAULT_RELOAD_INTERVAL,
+    FIRST_PAYLOAD_SECONDS,
+};
+use be::config::FileConfig;
+use be::connection::ConnectionState;
+use be::delay::{
+    match_trip, parse_gtfs_time, MatchOutcome, ScheduledStop, ScheduledTrip,
+    DEFAULT_AMBIGUITY_MARGIN_SECS,
+};
+use be::diff::{DiffEngine, DEFAULT_LOST_AFTER_BATCHES};
+use be::direction::{DirectionTracker, DEFAULT_CONSISTENT_OBSERVATIONS};
+use be::enrich::{GtfsEnricher, DEFAULT_MATCH_TOLERANCE_METERS, DEFAULT_MAX_METADATA_AGE_MS};
+use be::export::{query_active, query_track_page, ActiveVehicle, TrackPoint, TrackQuery};
+use be::feed::BusPosition;
+use be::frame_format::{to_msgpack, FrameFormat};
+use be::gaps::{Gap, GapDetector, GapLog, DEFAULT_GAP_INTERVALS, VEHICLE_GAPS_TOTAL};
+use be::geo::{haversine_meters, web_mercator};
+#[cfg(feature = "grpc")]
+use be::grpc::{GrpcFeed, SnapshotLoader, DEFAULT_CLIENT_BUFFER};
+use be::gtfs_rt::{
+    fetch_feed, poll_vehicle_positions, FETCH_SECONDS, PRASARANA_VEHICLE_POSITIONS_URL,
+};
+use be::headway::{HeadwayBoard, HeadwaySummary};
+use be::influx::{
+    InfluxConfig, InfluxSink, DEFAULT_FLUSH_INTERVAL,
+    DEFAULT_QUEUE_CAPACITY as DEFAULT_INFLUX_QUEUE_CAPACITY,
+};
+#[cfg(feature = "kafka")]
+use be::kafka::{KafkaConfig, KafkaSink, DEFAULT_FLUSH_TIMEOUT};
+use be::layover::{LayoverDetector, Terminal, DEFAULT_DWELL_MS, DEFAULT_TERMINAL_RADIUS_METERS};
+use be::nats::NatsSink;
+use be::ordering::{OrderingGuard, DEFAULT_GRACE_MS};
+use be::parse_failures::{ParseFailureLog, RecordedFailure};
+use be::pipeline::emptied_route;
+use be::presence::{
+    PresenceEvent, PresenceTracker, DEFAULT_LOST_AFTER_CYCLES, DEFAULT_RECONNECT_GRACE_MS,
+};
+use be::progress::{RouteShape, RouteShapes, DEFAULT_OFF_ROUTE_METERS};
+use be::queue::{BoundedQueue, OverflowPolicy, DEFAULT_BLOCK_TIMEOUT, DEFAULT_QUEUE_CAPACITY};
+use be::rate_limit::{
+    ClientRateLimiter, EmitLimiter, TrustedProxies, DEFAULT_EMITS_PER_SECOND,
+    DEFAULT_HISTORY_REQUESTS_PER_MINUTE, DEFAULT_HISTORY_REQUEST_BURST,
+    DEFAULT_MAX_TRACKED_CLIENTS, DEFAULT_REQUESTS_PER_MINUTE, DEFAULT_REQUEST_BURST,
+    FORWARDED_FOR_HEADER, RATE_LIMITED_TOTAL,
+};
+use be::reassign::{
+    ReassignPolicy, RouteAssignments, DEFAULT_REASSIGN_GRACE_MS, ROUTE_REASSIGNMENTS_TOTAL,
+};
+use be::redis_pubsub::RedisPubSubSink;
+use be::route_colors::{fallback_route_colors, normalize_hex_color};
+use be::route_versions::{if_none_match, RouteVersions};
+use be::session::SessionInfo;
+use be::sink::{JsonLinesSink, SinkSet, SinkWorkers, StdoutSink};
+use be::speed::{SpeedSmoother, DEFAULT_WINDOW_SAMPLES};
+use be::stats::{ServiceStats, FEED_LATENCY_SECONDS};
+use be::stop_index::{StopIndex, StopLocation};
+use be::subscriptions::{RouteChange, RouteControl};
+use be::timestamp::{refresh_age, retain_recent};
+use be::updates::BusUpdate;
+use be::validate::{
+    validate_coordinates, BoundingBox, CoordinateCheck, COORDINATES_REJECTED_TOTAL,
+    COORDINATES_SWAPPED_TOTAL, MALAYSIA_BBOX,
+};
+use be::webhook::{WebhookConfig, WebhookEvent, WebhookSink};
+use be::{normalize_route_code, now_unix_ms};
+use chrono::{Datelike, Timelike, Utc};
+use chrono_tz::Asia::Kuala_Lumpur;
+use chrono_tz::Tz;
+use clap::Parser;
+use cli::{
+    is_valid_route_id, limit_connections, run_archive, run_compare, run_dry_run, run_export,
+    run_gtfs, run_inspect, spawn_route_client, spawn_route_clients, Cli, Command, HttpOptions,
+    LogFormat, NatsOptions, RedisOptions, Source,
+};
+use futures_util::stream::{self, BoxStream, SelectAll, StreamExt};
+use metrics::counter;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
 use std::env;
 use std::fs::File;
-use std::io::Read;
-use std::path::Path as StdPath;
-use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::{Notify, RwLock};
-use tokio::time::MissedTickBehavior;
-use tower_http::cors::{Any, CorsLayer};
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct BusPosition {
-    pub dt_received: Option<String>,
-    pub dt_gps: Option<String>,
-    pub latitude: f64,
-    pub longitude: f64,
-    pub dir: Option<String>,
-    pub speed: f64,
-    pub angle: f64,
-    pub route: String,
-    pub bus_no: String,
-    pub trip_no: Option<String>,
-    pub captain_id: Option<String>,
-    pub trip_rev_kind: Option<String>,
-    pub engine_status: i32,
-    pub accessibility: i32,
-    pub busstop_id: Option<String>,
-    pub provider: String,
-}
+use std::future::Future;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::SocketAddr;
+use std::path::{Path as StdPath, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(unix)]
+use tokio::signal::unix::SignalKind;
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio_util::sync::CancellationToken;
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
 // GTFS data structures
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -74,6 +151,26 @@ struct StopTime {
     stop_headsign: Option<String>,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+struct Calendar {
+    service_id: String,
+    monday: u8,
+    tuesday: u8,
+    wednesday: u8,
+    thursday: u8,
+    friday: u8,
+    saturday: u8,
+    sunday: u8,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Frequency {
+    trip_id: String,
+    start_time: String,
+    end_time: String,
+    headway_secs: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Stop {
     stop_id: String,
@@ -83,7 +180,7 @@ struct Stop {
     stop_lon: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 struct StopWithDetails {
     stop_id: String,
     stop_name: String,
@@ -93,7 +190,7 @@ struct StopWithDetails {
     sequence: u32,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 struct RouteStopsResponse {
     route_id: String,
     route_short_name: String,
@@ -109,27 +206,216 @@ struct ShapePoint {
     shape_pt_sequence: u32,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 struct RouteShapePoint {
     lat: f64,
     lon: f64,
     sequence: u32,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 struct RouteShapeResponse {
     route_id: String,
     shape_id: String,
     points: Vec<RouteShapePoint>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct StopsQuery {
+    // min_lon,min_lat,max_lon,max_lat; every stop when left out.
+    bbox: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct StreamQuery {
+    // json (the default) or msgpack; overrides the Accept header.
+    format: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct NearestStopQuery {
     lat: f64,
     lon: f64,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct NearestBusQuery {
+    lat: f64,
+    lon: f64,
+    limit: Option<usize>,
+    route: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct NearestBusResponse {
+    #[serde(flatten)]
+    bus: BusPosition,
+    distance_meters: f64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct TrackedBusResponse {
+    #[serde(flatten)]
+    bus: BusPosition,
+    stale: bool,
+    last_seen_age_seconds: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snapped_latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snapped_longitude: Option<f64>,
+    // EPSG:3857 metres, only with ?proj=mercator.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    y: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snapped_x: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snapped_y: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Projection {
+    #[default]
+    Wgs84,
+    Mercator,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RouteBusesQuery {
+    #[serde(default)]
+    snap: bool,
+    // wgs84 (default) or mercator, which adds EPSG:3857 x/y for canvas renderers.
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    proj: Projection,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BulkFormat {
+    #[default]
+    Json,
+    Geojson,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct BulkBusesQuery {
+    // min_lon,min_lat,max_lon,max_lat, applied across every route.
+    bbox: Option<String>,
+    // json (default), a map of route code to buses, or geojson, one FeatureCollection.
+    #[serde(default)]
+    #[param(value_type = Option<String>)]
+    format: BulkFormat,
+    // Comma-separated BusPosition fields to keep, e.g. lat,lon,route.
+    fields: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct RouteBusCountResponse {
+    route: String,
+    active: usize,
+    stale: usize,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RouteVehiclesQuery {
+    since_seq: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct RouteVehicle {
+    #[serde(flatten)]
+    bus: BusPosition,
+    seq: i64,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct VehicleHistoryQuery {
+    /// RFC 3339 start of the window
+    from: String,
+    /// RFC 3339 end of the window
+    to: String,
+    /// `next_cursor` from the previous page
+    cursor: Option<i64>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct VehicleHistoryResponse {
+    vehicle: String,
+    points: Vec<TrackPoint>,
+    // Point time (unix ms) to pass as `cursor` for the next page; None on the last page.
+    next_cursor: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct VehicleGapsQuery {
+    /// RFC 3339 start of the window; the whole log when unset
+    from: Option<String>,
+    /// RFC 3339 end of the window
+    to: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct VehicleGapsResponse {
+    vehicle: String,
+    gaps: Vec<Gap>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ActiveVehiclesQuery {
+    /// RFC 3339 moment to look at
+    at: String,
+    /// `next_cursor` from the previous page
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ActiveVehiclesResponse {
+    route: String,
+    at: String,
+    vehicles: Vec<ActiveVehicle>,
+    // Vehicle id to pass as `cursor` for the next page; None on the last page.
+    next_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct RouteChangeRequest {
+    #[serde(default)]
+    add: Vec<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct RouteSubscriptionsResponse {
+    // Empty while following every bus.
+    routes: Vec<String>,
+    added: Vec<String>,
+    removed: Vec<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct RouteVehiclesResponse {
+    route: String,
+    seq: i64,
+    data: Vec<RouteVehicle>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 struct NearestStopResponse {
     stop_id: String,
     stop_name: String,
@@ -140,25 +426,25 @@ struct NearestStopResponse {
     distance_meters: f64,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 struct StopRouteSummary {
     route_id: String,
     route_short_name: String,
     route_long_name: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct StopRoutesResponse {
     stop_id: String,
     routes: Vec<StopRouteSummary>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct ErrorResponse {
     error: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
 #[serde(rename_all = "lowercase")]
 enum StopResolutionSource {
     Live,
@@ -173,7 +459,7 @@ struct ResolvedCurrentStop {
     source: StopResolutionSource,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 struct BusEta {
     route_id: String,
     bus_no: String,
@@ -193,23 +479,127 @@ struct BusEta {
 struct AppState {
     redis_client: redis::Client,
     ingestor_status: Arc<RwLock<IngestorStatus>>,
+    emit_limiter: EmitLimiter,
+    live: Arc<RwLock<LiveSettings>>,
+    // --no-validate, which a config reload can't turn validation back on over.
+    no_validate: bool,
     bus_ttl_ms: i64,
     stale_after_ms: i64,
+    health_max_message_age_ms: i64,
+    vehicle_stale_after_ms: i64,
+    // --max-position-age-seconds; older positions aren't served at all.
+    max_position_age_seconds: Option<i64>,
+    metrics_handle: PrometheusHandle,
+    headways: Arc<RwLock<HeadwayBoard>>,
+    sink_queue: BoundedQueue<SinkBatch>,
+    delays: Arc<RwLock<HashMap<String, VehicleDelay>>>,
+    // Vehicles the presence tracker currently considers lost; marked inactive on read.
+    lost_vehicles: Arc<RwLock<HashSet<String>>>,
+    // Routes a reassigned vehicle is still listed on besides its current one, by vehicle.
+    previous_routes: Arc<RwLock<HashMap<String, Vec<String>>>>,
+    // Bumped whenever a route's Redis snapshot changes; see get_route_buses.
+    route_versions: RouteVersions,
+    route_shapes: Arc<RouteShapes>,
+    stats: Arc<RwLock<ServiceStats>>,
+    webhook: Option<WebhookSink>,
+    // Sinks fed every processed batch, each from its own queue: NATS and --jsonl-output.
+    sinks: SinkWorkers,
+    redis_pubsub: Option<RedisPubSubSink>,
+    influx: Option<InfluxSink>,
+    archive: Option<ArchiveSink>,
+    #[cfg(feature = "kafka")]
+    kafka: Option<KafkaSink>,
+    // Set when GRPC_BIND is; see grpc_server_from_env.
+    #[cfg(feature = "grpc")]
+    grpc: Option<GrpcFeed>,
+    channels: ChannelRegistry,
+    gtfs_http: reqwest::Client,
+    // --timezone, for GTFS-rt positions; socket clients get it through HttpOptions.
+    timezone: Option<Tz>,
+    routes: RouteControl,
+    // Socket clients by route ("" for all buses); empty with --source gtfs.
+    socket_clients: Arc<RwLock<HashMap<String, RapidbroClient>>>,
+    // Shared with every socket client through HttpOptions.
+    parse_failures: ParseFailureLog,
+    // stops.txt, indexed at startup; None when the static GTFS couldn't be read.
+    stops: Option<Arc<StopIndex>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Settings a SIGHUP config reload can change while running.
+#[derive(Debug, Clone, Copy)]
+struct LiveSettings {
+    coordinate_bounds: Option<BoundingBox>,
+    headway_log_seconds: u64,
+}
+
+#[derive(Debug)]
+struct SinkBatch {
+    buses: Vec<BusPosition>,
+    received_at_unix_ms: i64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RedisFlush {
+    interval: Duration,
+    max_rows: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 struct IngestorStatus {
     connected: bool,
+    session_established: bool,
     reconnect_count: u64,
     messages_processed: u64,
     buses_written: u64,
     decode_failures: u64,
     redis_write_failures: u64,
+    throttled_emits: u64,
+    sink_dropped_batches: u64,
+    coordinates_swapped: u64,
+    coordinates_rejected_zero: u64,
+    coordinates_rejected_out_of_bounds: u64,
     last_message_unix_ms: Option<i64>,
+    // The last batch that decoded, even an empty one.
+    last_data_unix_ms: Option<i64>,
+    // The last batch that decoded to no buses at all, e.g. off-hours.
+    last_valid_empty_unix_ms: Option<i64>,
+    last_error: Option<String>,
+    // --source hybrid is polling GTFS-rt because the socket has been down too long.
+    gtfs_fallback_active: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct HealthReport {
+    healthy: bool,
+    ready: bool,
+    connected: bool,
+    session_established: bool,
+    seconds_since_last_message: Option<i64>,
+    seconds_since_last_data: Option<i64>,
+    reconnect_count: u64,
+    route_vehicle_counts: BTreeMap<String, usize>,
+    // Socket client state by route ("all" for the all-buses client).
+    connection_states: BTreeMap<String, ConnectionState>,
     last_error: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
+struct VehicleDelay {
+    bus_no: String,
+    route: String,
+    #[serde(flatten)]
+    outcome: MatchOutcome,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ReadinessReport {
+    ready: bool,
+    connected: bool,
+    last_data_unix_ms: Option<i64>,
+    seconds_since_last_data: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 struct GetAllMeta {
     source: &'static str,
     last_ingest_at_unix_ms: Option<i64>,
@@ -217,13 +607,13 @@ struct GetAllMeta {
     active_bus_count: usize,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct GetAllResponse {
     data: Vec<BusPosition>,
     meta: GetAllMeta,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 struct RouteBusPositionResponse {
     #[serde(flatten)]
     bus: BusPosition,
@@ -233,8 +623,9 @@ struct RouteBusPositionResponse {
     stop_resolution_source: Option<StopResolutionSource>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct StopIncomingMeta {
+    #[schema(value_type = String)]
     source: &'static str,
     generated_at_unix_ms: i64,
     last_ingest_at_unix_ms: Option<i64>,
@@ -244,7 +635,7 @@ struct StopIncomingMeta {
     has_incoming_buses: bool,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct StopIncomingResponse {
     stop_id: String,
     stop_name: String,
@@ -264,6 +655,7 @@ struct BusMotionState {
 struct RedisBusSnapshot {
     buses: Vec<BusPosition>,
     motion_states: HashMap<String, BusMotionState>,
+    last_seen_unix_ms: HashMap<String, i64>,
     active_bus_count: usize,
     last_ingest_at_unix_ms: Option<i64>,
 }
@@ -275,24 +667,183 @@ struct GtfsContext {
     stops_map: HashMap<String, Stop>,
 }
 
-const SOCKET_URL: &str = "https://rapidbus-socketio-avl.prasarana.com.my";
 const GTFS_DATA_PATH: &str = "../rapid_kl_data";
 const REDIS_BUSES_LATEST_KEY: &str = "rapidbro:buses:latest";
 const REDIS_BUSES_LAST_SEEN_KEY: &str = "rapidbro:buses:last_seen";
 const REDIS_BUSES_MOTION_KEY: &str = "rapidbro:buses:motion";
 const REDIS_INGEST_LAST_KEY: &str = "rapidbro:ingestor:last_ingest_at";
+const REDIS_BUSES_SEQ_KEY: &str = "rapidbro:buses:seq";
+const REDIS_ROUTES_SEQ_KEY: &str = "rapidbro:routes:seq";
 const DEFAULT_REDIS_URL: &str = "redis://127.0.0.1:6379/";
-const DEFAULT_BUS_TTL_SECONDS: i64 = 120;
+const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0:3030";
+const DEFAULT_BUS_TTL_SECONDS: i64 = 300;
 const DEFAULT_STALE_AFTER_SECONDS: i64 = 20;
+const DEFAULT_HEALTH_MAX_MESSAGE_AGE_SECONDS: i64 = 60;
+const DEFAULT_VEHICLE_STALE_AFTER_SECONDS: i64 = 60;
+const STATUS_FILE_INTERVAL: Duration = Duration::from_secs(5);
+const METRICS_UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_HEADWAY_LOG_SECONDS: u64 = 60;
+const DEFAULT_GTFS_FALLBACK_AFTER_SECONDS: i64 = 30;
+const SINK_DROPPED_TOTAL: &str = "rapidbro_sink_dropped_total";
+const REDIS_BATCHES_DROPPED_TOTAL: &str = "rapidbro_redis_batches_dropped_total";
+const DEFAULT_REDIS_FLUSH_INTERVAL_MS: u64 = 500;
+const DEFAULT_REDIS_FLUSH_MAX_ROWS: usize = 100;
+const REDIS_WRITE_ATTEMPTS: u32 = 3;
+const REDIS_RETRY_DELAY: Duration = Duration::from_millis(200);
+const OUT_OF_ORDER_REJECTED_TOTAL: &str = "rapidbro_out_of_order_rejected_total";
 const MAX_DERIVED_STOP_DISTANCE_KM: f64 = 0.75;
 const STATIONARY_SPEED_THRESHOLD_KMH: f64 = 1.0;
 const STATIONARY_DISTANCE_THRESHOLD_KM: f64 = 0.03;
 const STATIONARY_WINDOW_MS: i64 = 60_000;
 const PANTAI_HILLPARK_PHASE_5_STOP_ID: &str = "1008485";
+const DEFAULT_NEAREST_BUS_LIMIT: usize = 5;
+const DEFAULT_HISTORY_PAGE_SIZE: usize = 500;
+const MAX_HISTORY_PAGE_SIZE: usize = 5_000;
 
 #[tokio::main]
 async fn main() {
-    let redis_url = env::var("REDIS_URL").unwrap_or_else(|_| DEFAULT_REDIS_URL.to_string());
+    let mut cli = Cli::parse();
+    if let Err(error) = cli.http.load_tls() {
+        eprintln!("{}", error);
+        std::process::exit(2);
+    }
+    if let Err(error) = cli.http.load_proxy() {
+        eprintln!("{}", error);
+        std::process::exit(2);
+    }
+    cli.http.load_session_store();
+    let _payload_log_guard = cli.http.load_payload_log().unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(2);
+    });
+    if cli.dry_run {
+        if let Some(path) = &cli.config {
+            if let Err(error) = FileConfig::load(path) {
+                eprintln!("{}", error);
+                std::process::exit(2);
+            }
+            println!("{:<12} ok {}", "config", path.display());
+        }
+        let route = cli
+            .subscriptions
+            .resolve()
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+        std::process::exit(run_dry_run(route, &cli.http, cli.source).await);
+    }
+    match cli.command {
+        Some(Command::Inspect { route }) => std::process::exit(run_inspect(route, &cli.http).await),
+        Some(Command::Archive { command }) => std::process::exit(run_archive(command)),
+        Some(Command::Export { command }) => std::process::exit(run_export(command)),
+        Some(Command::Gtfs { command }) => std::process::exit(run_gtfs(command, &cli.http).await),
+        Some(Command::Compare {
+            route,
+            report_seconds,
+            stale_seconds,
+        }) => std::process::exit(
+            run_compare(
+                route,
+                &cli.http,
+                Duration::from_secs(report_seconds),
+                Duration::from_secs(stale_seconds),
+            )
+            .await,
+        ),
+        Some(Command::Openapi) => match ApiDoc::openapi().to_pretty_json() {
+            Ok(spec) => println!("{}", spec),
+            Err(error) => {
+                eprintln!("Failed to serialize the OpenAPI spec: {}", error);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Tui { subscriptions }) => {
+            let routes = subscriptions.resolve();
+            // The clients must outlive the dashboard: dropping the last one stops it.
+            let (_clients, events): (Vec<_>, Vec<_>) =
+                match spawn_route_clients(&routes, &cli.http, &EmitLimiter::default()).await {
+                    Ok(spawned) => spawned.into_iter().unzip(),
+                    Err(error) => {
+                        eprintln!("{}", error);
+                        std::process::exit(2);
+                    }
+                };
+            std::process::exit(tui::run_tui(routes, stream::select_all(events).boxed()).await)
+        }
+        None => {
+            serve(
+                cli.http,
+                cli.subscriptions.resolve(),
+                cli.subscriptions.max_connections,
+                cli.source,
+                cli.format,
+                cli.stats_file,
+                cli.status_file,
+                cli.jsonl_output,
+                cli.nats,
+                cli.redis,
+                cli.config,
+                cli.debug_endpoints,
+                cli.no_validate,
+                cli.max_position_age_seconds,
+            )
+            .await
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn serve(
+    mut http: HttpOptions,
+    routes: Vec<String>,
+    max_connections: Option<usize>,
+    source: Source,
+    format: LogFormat,
+    stats_file: Option<String>,
+    status_file: Option<String>,
+    jsonl_output: Option<PathBuf>,
+    nats: NatsOptions,
+    redis: RedisOptions,
+    config_path: Option<PathBuf>,
+    debug_endpoints: bool,
+    no_validate: bool,
+    max_position_age_seconds: Option<u64>,
+) {
+    let file_config = match &config_path {
+        Some(path) => FileConfig::load(path).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }),
+        None => FileConfig::default(),
+    };
+    // Validated by FileConfig::load; only read at startup.
+    http.field_map = file_config.field_map().unwrap_or_default();
+    if no_validate {
+        println!("Coordinate validation is off (--no-validate); serving the raw feed's fixes");
+    }
+    // Cancelled on ctrl-c or SIGTERM. The socket clients get it through HttpOptions, and
+    // every task spawned below stops with it.
+    let shutdown = CancellationToken::new();
+    http.cancellation = Some(shutdown.clone());
+    let signal_shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        signal_shutdown.cancel();
+    });
+    // --route on the command line wins over the config file's list.
+    let routes = if routes.is_empty() {
+        let mut routes = config_routes(&file_config);
+        limit_connections(&mut routes, max_connections);
+        routes
+    } else {
+        routes
+    };
+    let redis_url = redis
+        .redis_url
+        .clone()
+        .or_else(|| file_config.redis_url.clone())
+        .or_else(|| env::var("REDIS_URL").ok())
+        .unwrap_or_else(|| DEFAULT_REDIS_URL.to_string());
     let bus_ttl_seconds = env::var("BUS_TTL_SECONDS")
         .ok()
         .and_then(|value| value.parse::<i64>().ok())
@@ -301,11 +852,155 @@ async fn main() {
         .ok()
         .and_then(|value| value.parse::<i64>().ok())
         .unwrap_or(DEFAULT_STALE_AFTER_SECONDS);
+    let health_max_message_age_seconds = env::var("HEALTH_MAX_MESSAGE_AGE_SECONDS")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_HEALTH_MAX_MESSAGE_AGE_SECONDS);
+    let vehicle_stale_after_seconds = env::var("VEHICLE_STALE_AFTER_SECONDS")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_VEHICLE_STALE_AFTER_SECONDS);
+    let emits_per_second = env::var("EMITS_PER_SECOND")
+        .ok()
+        .and_then(|value| value.parse::<u32>().ok())
+        .unwrap_or(DEFAULT_EMITS_PER_SECOND);
+    let sink_queue_capacity = env::var("SINK_QUEUE_CAPACITY")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_QUEUE_CAPACITY);
+    // SINK_OVERFLOW_POLICY=drop-oldest|drop-newest|block decides what gives when Redis falls behind.
+    let sink_overflow_policy = overflow_policy_from_env("SINK_OVERFLOW_POLICY");
+    // Positions arriving within one flush window (or up to the row cap) share a Redis transaction.
+    let redis_flush = RedisFlush {
+        interval: Duration::from_millis(
+            env::var("REDIS_FLUSH_INTERVAL_MS")
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(DEFAULT_REDIS_FLUSH_INTERVAL_MS),
+        ),
+        max_rows: env::var("REDIS_FLUSH_MAX_ROWS")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_REDIS_FLUSH_MAX_ROWS)
+            .max(1),
+    };
+    // WEBHOOK_URL enables POSTing position and trip events to an external receiver.
+    let webhook = env::var("WEBHOOK_URL")
+        .ok()
+        .filter(|url| !url.is_empty())
+        .map(|url| {
+            let mut config = WebhookConfig::new(url);
+            config.secret = env::var("WEBHOOK_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty());
+            if let Some(max_batch_size) = env::var("WEBHOOK_BATCH_SIZE")
+                .ok()
+                .and_then(|value| value.parse::<usize>().ok())
+            {
+                config.max_batch_size = max_batch_size;
+            }
+            if let Some(max_delay_ms) = env::var("WEBHOOK_MAX_DELAY_MS")
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+            {
+                config.max_delay = Duration::from_millis(max_delay_ms);
+            }
+            if let Some(queue_capacity) = env::var("WEBHOOK_QUEUE_CAPACITY")
+                .ok()
+                .and_then(|value| value.parse::<usize>().ok())
+            {
+                config.queue_capacity = queue_capacity;
+            }
+            if let Some(max_retries) = env::var("WEBHOOK_MAX_RETRIES")
+                .ok()
+                .and_then(|value| value.parse::<u32>().ok())
+            {
+                config.max_retries = max_retries;
+            }
+            config.overflow_policy = overflow_policy_from_env("WEBHOOK_OVERFLOW_POLICY");
+            // WEBHOOK_PRESENCE_EVENTS=true adds VehicleLost/VehicleReturned to the stream.
+            config.presence_events = env::var("WEBHOOK_PRESENCE_EVENTS")
+                .ok()
+                .and_then(|value| value.parse::<bool>().ok())
+                .unwrap_or(false);
+            WebhookSink::new(config)
+        });
 
+    // CORS_ORIGINS / API_KEYS are comma-separated; the config file's lists win over them.
+    let allowed_origins = match &file_config.cors_origins {
+        Some(origins) => AllowedOrigins::new(origins.clone()),
+        None => env::var("CORS_ORIGINS")
+            .ok()
+            .filter(|origins| !origins.is_empty())
+            .map(|origins| AllowedOrigins::parse(&origins))
+            .unwrap_or(Ok(AllowedOrigins::Any)),
+    }
+    .unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(2);
+    });
     let cors = CorsLayer::new()
-        .allow_origin(Any)
+        .allow_origin(match &allowed_origins {
+            AllowedOrigins::Any => AllowOrigin::any(),
+            AllowedOrigins::List(origins) => {
+                println!("Allowing CORS requests from {}", origins.join(", "));
+                AllowOrigin::list(
+                    origins
+                        .iter()
+                        .filter_map(|origin| origin.parse::<HeaderValue>().ok()),
+                )
+            }
+        })
         .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers([header::ETAG]);
+    let api_keys = file_config
+        .api_keys
+        .clone()
+        .map(ApiKeys::new)
+        .or_else(|| env::var("API_KEYS").ok().map(|keys| ApiKeys::parse(&keys)))
+        .unwrap_or_default();
+    if !api_keys.is_empty() {
+        println!(
+            "Requiring {} on every endpoint but {} ({} keys)",
+            API_KEY_HEADER,
+            AUTH_EXEMPT_PATHS.join(", "),
+            api_keys.len()
+        );
+    }
+    // Behind a reverse proxy every request comes from the proxy, so TRUSTED_PROXIES lists
+    // the addresses whose X-Forwarded-For names the real client.
+    let max_tracked_clients = env::var("RATE_LIMIT_MAX_CLIENTS")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_MAX_TRACKED_CLIENTS);
+    let rate_limits = HttpRateLimits {
+        api: rate_limiter_from_env(
+            "API",
+            "RATE_LIMIT",
+            DEFAULT_REQUESTS_PER_MINUTE,
+            DEFAULT_REQUEST_BURST,
+            max_tracked_clients,
+        ),
+        history: rate_limiter_from_env(
+            "history",
+            "RATE_LIMIT_HISTORY",
+            DEFAULT_HISTORY_REQUESTS_PER_MINUTE,
+            DEFAULT_HISTORY_REQUEST_BURST,
+            max_tracked_clients,
+        ),
+        trusted_proxies: TrustedProxies::parse(&env::var("TRUSTED_PROXIES").unwrap_or_default())
+            .unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                std::process::exit(2);
+            }),
+    };
+    if !rate_limits.trusted_proxies.is_empty() {
+        println!(
+            "Reading client addresses from {} sent by TRUSTED_PROXIES",
+            FORWARDED_FOR_HEADER
+        );
+    }
 
     let redis_client = redis::Client::open(redis_url.clone()).unwrap_or_else(|error| {
         panic!(
@@ -324,31 +1019,246 @@ async fn main() {
         .await
         .unwrap_or_else(|error| panic!("Failed to ping Redis '{}': {}", redis_url, error));
 
+    let mut sinks = SinkSet::default();
+    if let Some(url) = nats.nats_url.as_deref() {
+        let nats = NatsSink::connect(url, nats.mode())
+            .await
+            .unwrap_or_else(|error| panic!("{}", error));
+        sinks.push_queued(
+            Box::new(nats),
+            DEFAULT_QUEUE_CAPACITY,
+            overflow_policy_from_env("NATS_OVERFLOW_POLICY"),
+        );
+    }
+    match jsonl_output.as_deref() {
+        Some(path) if path == StdPath::new("-") => sinks.push(Box::new(StdoutSink)),
+        Some(path) => match JsonLinesSink::open(path).await {
+            Ok(file) => sinks.push(Box::new(file)),
+            Err(error) => {
+                eprintln!("Failed to open '{}': {}", path.display(), error);
+                std::process::exit(2);
+            }
+        },
+        None => {}
+    }
+
+    let gtfs_http = http.gtfs_http_client().unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(2);
+    });
+
+    let redis_pubsub = redis.redis_pubsub.then(|| {
+        RedisPubSubSink::new(
+            redis_client.clone(),
+            redis.redis_snapshot_ttl.map(Duration::from_secs),
+            overflow_policy_from_env("REDIS_PUBSUB_OVERFLOW_POLICY"),
+        )
+    });
+
+    #[cfg(feature = "grpc")]
+    let grpc_bind = grpc_bind_from_env();
+
+    let stops = load_stop_index();
+
+    let (route_control, route_changes) = RouteControl::new(&routes, max_connections);
     let app_state = AppState {
         redis_client: redis_client.clone(),
         ingestor_status: Arc::new(RwLock::new(IngestorStatus {
             connected: false,
+            session_established: false,
             reconnect_count: 0,
             messages_processed: 0,
             buses_written: 0,
             decode_failures: 0,
             redis_write_failures: 0,
+            throttled_emits: 0,
+            sink_dropped_batches: 0,
+            coordinates_swapped: 0,
+            coordinates_rejected_zero: 0,
+            coordinates_rejected_out_of_bounds: 0,
             last_message_unix_ms: None,
+            last_data_unix_ms: None,
+            last_valid_empty_unix_ms: None,
             last_error: None,
+            gtfs_fallback_active: false,
         })),
+        emit_limiter: EmitLimiter::per_second(emits_per_second),
+        live: Arc::new(RwLock::new(live_settings(&file_config, no_validate))),
+        no_validate,
         bus_ttl_ms: bus_ttl_seconds * 1_000,
         stale_after_ms: stale_after_seconds * 1_000,
+        health_max_message_age_ms: health_max_message_age_seconds * 1_000,
+        vehicle_stale_after_ms: vehicle_stale_after_seconds * 1_000,
+        max_position_age_seconds: max_position_age_seconds.map(|seconds| seconds as i64),
+        metrics_handle: install_metrics_recorder(),
+        headways: Arc::new(RwLock::new(HeadwayBoard::default())),
+        delays: Arc::new(RwLock::new(HashMap::new())),
+        lost_vehicles: Arc::new(RwLock::new(HashSet::new())),
+        previous_routes: Arc::new(RwLock::new(HashMap::new())),
+        route_versions: RouteVersions::new(now_unix_ms() as u64, normalize_route_code),
+        route_shapes: Arc::new(RouteShapes::new(
+            load_route_shapes(),
+            normalize_route_code,
+            env::var("OFF_ROUTE_METERS")
+                .ok()
+                .and_then(|value| value.parse::<f64>().ok())
+                .unwrap_or(DEFAULT_OFF_ROUTE_METERS),
+        )),
+        sink_queue: BoundedQueue::new(
+            sink_queue_capacity,
+            sink_overflow_policy,
+            DEFAULT_BLOCK_TIMEOUT,
+        )
+        .named("redis"),
+        stats: Arc::new(RwLock::new(ServiceStats::new(vehicle_stale_after_seconds))),
+        webhook,
+        sinks: sinks.spawn(),
+        redis_pubsub,
+        influx: influx_sink_from_env(),
+        // ARCHIVE_DIR enables hourly Parquet files partitioned by year/month/day.
+        archive: file_config
+            .archive_dir
+            .clone()
+            .or_else(|| env::var("ARCHIVE_DIR").ok())
+            .filter(|path| !path.is_empty())
+            .map(|root| {
+                ArchiveSink::new(root, overflow_policy_from_env("ARCHIVE_OVERFLOW_POLICY"))
+            }),
+        #[cfg(feature = "kafka")]
+        kafka: kafka_sink_from_env(),
+        #[cfg(feature = "grpc")]
+        grpc: grpc_bind.map(|_| {
+            GrpcFeed::new(
+                env::var("GRPC_CLIENT_BUFFER")
+                    .ok()
+                    .and_then(|value| value.parse::<usize>().ok())
+                    .unwrap_or(DEFAULT_CLIENT_BUFFER),
+                normalize_route_code,
+            )
+        }),
+        gtfs_http,
+        timezone: http.timezone,
+        routes: route_control,
+        socket_clients: Arc::new(RwLock::new(HashMap::new())),
+        parse_failures: http.parse_failures.clone(),
+        stops,
+        channels: ChannelRegistry::new(
+            env::var("STREAM_CHANNEL_CAPACITY")
+                .ok()
+                .and_then(|value| value.parse::<usize>().ok())
+                .unwrap_or(DEFAULT_CHANNEL_CAPACITY),
+            env::var("STREAM_IDLE_GRACE_SECONDS")
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_IDLE_GRACE),
+        ),
     };
 
     let ingestor_state = app_state.clone();
-    tokio::spawn(async move {
-        run_bus_ingestor(ingestor_state).await;
+    spawn_until_shutdown(&shutdown, async move {
+        run_bus_ingestor(ingestor_state, http, route_changes, source, format).await;
+    });
+
+    let sink_state = app_state.clone();
+    spawn_until_shutdown(&shutdown, async move {
+        run_redis_sink(sink_state, redis_flush).await;
+    });
+
+    let channels = app_state.channels.clone();
+    spawn_until_shutdown(&shutdown, async move {
+        channels.run_cleanup().await;
+    });
+
+    // Not tied to the shutdown token: the archive is stopped and drained in order below.
+    let archive_task = app_state
+        .archive
+        .clone()
+        .map(|archive| tokio::spawn(async move { archive.run().await }));
+
+    if let Some(influx) = app_state.influx.clone() {
+        spawn_until_shutdown(&shutdown, async move {
+            influx.run().await;
+        });
+    }
+
+    if let Some(redis_pubsub) = app_state.redis_pubsub.clone() {
+        spawn_until_shutdown(&shutdown, async move {
+            redis_pubsub.run().await;
+        });
+    }
+
+    if let Some(webhook) = app_state.webhook.clone() {
+        spawn_until_shutdown(&shutdown, async move {
+            webhook.run().await;
+        });
+    }
+
+    #[cfg(feature = "grpc")]
+    if let (Some(bind), Some(feed)) = (grpc_bind, app_state.grpc.clone()) {
+        tokio::spawn(run_grpc_server(
+            app_state.clone(),
+            feed,
+            bind,
+            shutdown.clone(),
+        ));
+    }
+
+    let upkeep_handle = app_state.metrics_handle.clone();
+    spawn_until_shutdown(&shutdown, async move {
+        let mut upkeep_interval = tokio::time::interval(METRICS_UPKEEP_INTERVAL);
+        loop {
+            upkeep_interval.tick().await;
+            upkeep_handle.run_upkeep();
+        }
     });
 
+    if let Some(status_file) = status_file.filter(|path| !path.is_empty()) {
+        let status_state = app_state.clone();
+        spawn_until_shutdown(&shutdown, async move {
+            run_status_file_writer(status_state, status_file).await;
+        });
+    }
+
+    // STATS_INTERVAL_MINUTES=0 (the default) only reports statistics at shutdown.
+    let stats_interval_minutes = env::var("STATS_INTERVAL_MINUTES")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(0);
+    if stats_interval_minutes > 0 {
+        let stats_state = app_state.clone();
+        let stats_file = stats_file.clone();
+        spawn_until_shutdown(&shutdown, async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(stats_interval_minutes * 60));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                report_service_stats(&stats_state, stats_file.as_deref()).await;
+            }
+        });
+    }
+    let shutdown_state = app_state.clone();
+
+    // Sids are short-lived, so they are shown as-is; the flag keeps them off by default.
+    let debug_routes = if debug_endpoints {
+        Router::new()
+            .route("/debug/sessions", get(get_debug_sessions))
+            .route("/debug/parse-failures", get(get_debug_parse_failures))
+    } else {
+        Router::new()
+    };
     let app = Router::new()
         .route("/gtfs", get(prasarana_gtfs_data))
         .route("/get-all", get(fetch_all_buses))
         .route("/ingestor/status", get(get_ingestor_status))
+        .route("/metrics", get(get_metrics))
+        .route("/openapi.json", get(get_openapi))
+        .route("/docs", get(get_docs))
+        .route("/health", get(get_health))
+        .route("/ready", get(get_ready))
+        .route("/healthz", get(get_healthz))
+        .route("/readyz", get(get_readyz))
         .route("/get-route-t789", get(get_route_t789))
         .route("/get-t789-eta", get(get_t789_eta))
         .route(
@@ -360,16 +1270,148 @@ async fn main() {
         .route("/stops/{stop_id}/routes", get(get_stop_routes))
         .route("/route/{route_id}/stops", get(get_route_stops))
         .route("/route/{route_id}/shape", get(get_route_shape))
+        .route("/stops", get(get_stops))
         .route("/stops/nearest", get(get_nearest_stop))
+        .route("/buses", get(get_all_route_buses))
+        .route("/buses/nearest", get(get_nearest_buses))
+        .route("/buses/{route_id}", get(get_route_buses))
+        .route("/buses/{route_id}/count", get(get_route_bus_count))
+        .route("/routes/{route_id}/vehicles", get(get_route_vehicles))
+        .route("/routes/{route_id}/headways", get(get_route_headways))
+        .route("/routes/{route_id}/delays", get(get_route_delays))
+        .route("/routes/{route_id}/stream", get(get_route_stream))
+        .route("/routes/{route_id}/ws", get(get_route_ws))
+        .route(
+            "/control/routes",
+            get(get_control_routes).post(post_control_routes),
+        )
+        .route("/history/vehicles/{vehicle_id}", get(get_vehicle_history))
+        .route("/vehicles/{vehicle_id}/gaps", get(get_vehicle_gaps))
+        .route(
+            "/history/routes/{route_id}/active",
+            get(get_active_vehicles),
+        )
+        .merge(debug_routes)
+        .layer(middleware::from_fn_with_state(api_keys, require_api_key))
+        .layer(middleware::from_fn_with_state(
+            rate_limits,
+            limit_request_rate,
+        ))
         .layer(cors)
+        // gzip or deflate, whichever the client accepts. Unlike the default predicate this
+        // covers SSE too: the encoder flushes whenever the stream has nothing more ready, so
+        // each event and keep-alive still goes out as it's sent. /routes/{route_id}/ws is
+        // an upgraded connection the layer never sees, and axum's WebSocket doesn't offer
+        // permessage-deflate, so its frames go out uncompressed.
+        .layer(
+            CompressionLayer::new().compress_when(
+                SizeAbove::default()
+                    .and(NotForContentType::GRPC)
+                    .and(NotForContentType::IMAGES),
+            ),
+        )
         .with_state(app_state);
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3030").await.unwrap();
+    let bind = file_config
+        .bind
+        .clone()
+        .unwrap_or_else(|| DEFAULT_BIND_ADDRESS.to_string());
+    #[cfg(unix)]
+    if let Some(path) = config_path {
+        let reload_state = shutdown_state.clone();
+        spawn_until_shutdown(&shutdown, async move {
+            reload_config_on_sighup(reload_state, path, file_config).await;
+        });
+    }
+    let listener = tokio::net::TcpListener::bind(&bind).await.unwrap();
+
+    println!("Server is running on http://{}", bind);
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.cancelled_owned())
+    .await
+    .unwrap();
+    report_service_stats(&shutdown_state, stats_file.as_deref()).await;
+    if let Some(archive) = &shutdown_state.archive {
+        archive.stop();
+        if let Some(task) = archive_task {
+            let _ = task.await;
+        }
+        archive.flush().await;
+    }
+    shutdown_state.sinks.shutdown().await;
+    #[cfg(feature = "kafka")]
+    if let Some(kafka) = shutdown_state.kafka.clone() {
+        let flushed = tokio::task::spawn_blocking(move || kafka.flush(DEFAULT_FLUSH_TIMEOUT)).await;
+        if let Ok(Err(error)) = flushed {
+            println!("Failed to flush Kafka producer: {}", error);
+        }
+    }
+    for client in shutdown_state.socket_clients.read().await.values() {
+        client.save_session_state();
+    }
+}
+
+// Resolves on ctrl-c or, on unix, SIGTERM, which is what docker stop and Kubernetes send.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(SignalKind::terminate()) {
+            Ok(mut terminations) => {
+                terminations.recv().await;
+            }
+            Err(error) => {
+                println!("Cannot listen for SIGTERM: {}", error);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+// Runs `task` until it finishes or `shutdown` is cancelled, whichever comes first.
+fn spawn_until_shutdown(
+    shutdown: &CancellationToken,
+    task: impl Future<Output = ()> + Send + 'static,
+) {
+    let shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = task => {}
+            _ = shutdown.cancelled() => {}
+        }
+    });
+}
+
+// Prints one stats line per route and, with --stats-file, writes the same summary as JSON.
+async fn report_service_stats(state: &AppState, stats_file: Option<&str>) {
+    let summary = state.stats.read().await.summary();
+    println!("Service statistics for {} routes", summary.len());
+    for (route, route_stats) in &summary {
+        println!("  route {}: {}", route, route_stats);
+    }
 
-    println!("Server is running on http://localhost:3030");
-    axum::serve(listener, app).await.unwrap();
+    let Some(path) = stats_file else {
+        return;
+    };
+    match serde_json::to_string_pretty(&summary) {
+        Ok(serialized) => write_file_atomically(path, serialized).await,
+        Err(error) => println!("Failed to serialize service statistics: {}", error),
+    }
 }
 
+#[utoipa::path(
+    get, path = "/get-all", tag = "buses",
+    responses((status = 200, description = "All active buses", body = GetAllResponse), (status = 500, description = "Redis or GTFS data unavailable", body = ErrorResponse))
+)]
 async fn fetch_all_buses(
     State(state): State<AppState>,
 ) -> Result<Json<GetAllResponse>, (StatusCode, Json<ErrorResponse>)> {
@@ -426,6 +1468,11 @@ async fn load_active_bus_snapshot(
             .arg(REDIS_BUSES_MOTION_KEY)
             .arg(&stale_bus_ids)
             .ignore();
+        delete_pipe
+            .cmd("HDEL")
+            .arg(REDIS_BUSES_SEQ_KEY)
+            .arg(&stale_bus_ids)
+            .ignore();
         delete_pipe
             .cmd("ZREMRANGEBYSCORE")
             .arg(REDIS_BUSES_LAST_SEEN_KEY)
@@ -436,15 +1483,26 @@ async fn load_active_bus_snapshot(
             .query_async::<()>(&mut redis_conn)
             .await
             .map_err(internal_error)?;
+        // Which routes the expired vehicles were on isn't known without reading them first.
+        state.route_versions.bump_all();
     }
 
-    let active_bus_ids: Vec<String> = redis::cmd("ZRANGEBYSCORE")
+    let active_bus_scores: Vec<(String, f64)> = redis::cmd("ZRANGEBYSCORE")
         .arg(REDIS_BUSES_LAST_SEEN_KEY)
         .arg(cutoff_ms + 1)
         .arg("+inf")
+        .arg("WITHSCORES")
         .query_async(&mut redis_conn)
         .await
         .map_err(internal_error)?;
+    let last_seen_unix_ms: HashMap<String, i64> = active_bus_scores
+        .iter()
+        .map(|(bus_no, score)| (bus_no.clone(), *score as i64))
+        .collect();
+    let active_bus_ids: Vec<String> = active_bus_scores
+        .into_iter()
+        .map(|(bus_no, _)| bus_no)
+        .collect();
 
     let buses: Vec<BusPosition> = if active_bus_ids.is_empty() {
         Vec::new()
@@ -456,11 +1514,21 @@ async fn load_active_bus_snapshot(
             .await
             .map_err(internal_error)?;
 
-        raw_buses
+        let lost_vehicles = state.lost_vehicles.read().await;
+        let mut buses: Vec<BusPosition> = raw_buses
             .into_iter()
             .flatten()
             .filter_map(|entry| serde_json::from_str::<BusPosition>(&entry).ok())
-            .collect()
+            .map(|mut bus| {
+                refresh_age(&mut bus, now_ms);
+                bus.inactive = lost_vehicles.contains(&bus.bus_no);
+                bus
+            })
+            .collect();
+        if let Some(max_age_seconds) = state.max_position_age_seconds {
+            retain_recent(&mut buses, max_age_seconds, now_ms);
+        }
+        buses
     };
 
     let motion_states: HashMap<String, BusMotionState> = if active_bus_ids.is_empty() {
@@ -476,7 +1544,7 @@ async fn load_active_bus_snapshot(
         active_bus_ids
             .iter()
             .cloned()
-            .zip(raw_states.into_iter())
+            .zip(raw_states)
             .filter_map(|(bus_no, raw_state)| {
                 raw_state.and_then(|value| {
                     serde_json::from_str::<BusMotionState>(&value)
@@ -497,184 +1565,1975 @@ async fn load_active_bus_snapshot(
         buses,
         motion_states,
         active_bus_count: active_bus_ids.len(),
+        last_seen_unix_ms,
         last_ingest_at_unix_ms,
     })
 }
 
+#[utoipa::path(
+    get, path = "/ingestor/status", tag = "health",
+    responses((status = 200, description = "Socket ingestor counters", body = IngestorStatus))
+)]
 async fn get_ingestor_status(State(state): State<AppState>) -> Json<IngestorStatus> {
-    Json(state.ingestor_status.read().await.clone())
+    let mut status = state.ingestor_status.read().await.clone();
+    status.throttled_emits = state.emit_limiter.throttled_count();
+    status.sink_dropped_batches = state.sink_queue.dropped_count();
+    Json(status)
 }
 
-async fn run_bus_ingestor(state: AppState) {
-    let mut backoff_seconds: u64 = 1;
+// Liveness and readiness probes can't be expected to carry a key.
+const AUTH_EXEMPT_PATHS: &[&str] = &["/healthz", "/readyz", "/health", "/ready"];
+
+// Checks X-Api-Key (or ?api_key= for EventSource, which can't set headers) when API keys
+// are configured. Runs before the handler, so the SSE stream is refused at connection time.
+async fn require_api_key(
+    State(api_keys): State<ApiKeys>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if api_keys.is_empty() || AUTH_EXEMPT_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let presented = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| {
+            Query::<HashMap<String, String>>::try_from_uri(request.uri())
+                .ok()
+                .and_then(|Query(mut params)| params.remove(API_KEY_QUERY))
+        });
+    match api_keys.check(presented.as_deref()) {
+        ApiKeyCheck::Allowed => next.run(request).await,
+        ApiKeyCheck::Missing => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "ApiKey header=\"X-Api-Key\"")],
+            Json(ErrorResponse {
+                error: "Missing API key; send it in the X-Api-Key header".to_string(),
+            }),
+        )
+            .into_response(),
+        ApiKeyCheck::Rejected => (
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse {
+                error: "API key not accepted".to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
 
-    loop {
-        let redis_conn = match state.redis_client.get_multiplexed_async_connection().await {
-            Ok(connection) => connection,
-            Err(error) => {
-                record_ingestor_error(
-                    &state,
-                    format!("Redis connection failed before socket connect: {}", error),
-                    true,
-                )
-                .await;
-                tokio::time::sleep(Duration::from_secs(backoff_seconds)).await;
-                backoff_seconds = (backoff_seconds * 2).min(30);
+// Per-IP limits for the API; None turns a group's limit off.
+#[derive(Debug, Clone)]
+struct HttpRateLimits {
+    api: Option<ClientRateLimiter>,
+    // /history/*, which reads the Parquet archive instead of the Redis snapshot.
+    history: Option<ClientRateLimiter>,
+    trusted_proxies: TrustedProxies,
+}
+
+// Probes poll these too often to share a bucket with real clients.
+const RATE_LIMIT_EXEMPT_PATHS: &[&str] = &["/healthz", "/readyz", "/health", "/ready"];
+
+// Runs outside the API key check, so guessing keys is rate limited too.
+async fn limit_request_rate(
+    State(limits): State<HttpRateLimits>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    let (group, limiter) = if path.starts_with("/history/") {
+        ("history", &limits.history)
+    } else {
+        ("api", &limits.api)
+    };
+    let Some(limiter) = limiter
+        .as_ref()
+        .filter(|_| !RATE_LIMIT_EXEMPT_PATHS.contains(&path))
+    else {
+        return next.run(request).await;
+    };
+    let forwarded_for = request
+        .headers()
+        .get(FORWARDED_FOR_HEADER)
+        .and_then(|value| value.to_str().ok());
+    let client = limits.trusted_proxies.client_ip(peer.ip(), forwarded_for);
+    let Err(wait) = limiter.check(client) else {
+        return next.run(request).await;
+    };
+    counter!(RATE_LIMITED_TOTAL, "group" => group).increment(1);
+    // Whole seconds, rounded up so a client that waits as told gets through.
+    let retry_after_seconds = wait.as_secs_f64().ceil().max(1.0) as u64;
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after_seconds.to_string())],
+        Json(ErrorResponse {
+            error: format!("Too many requests; retry in {}s", retry_after_seconds),
+        }),
+    )
+        .into_response()
+}
+
+// {prefix}_PER_MINUTE and {prefix}_BURST; a rate of 0 turns the limit off.
+fn rate_limiter_from_env(
+    group: &str,
+    prefix: &str,
+    default_per_minute: u32,
+    default_burst: u32,
+    max_clients: usize,
+) -> Option<ClientRateLimiter> {
+    let per_minute = env::var(format!("{}_PER_MINUTE", prefix))
+        .ok()
+        .and_then(|value| value.parse::<u32>().ok())
+        .unwrap_or(default_per_minute);
+    let burst = env::var(format!("{}_BURST", prefix))
+        .ok()
+        .and_then(|value| value.parse::<u32>().ok())
+        .unwrap_or(default_burst);
+    if per_minute == 0 {
+        println!("Not rate limiting {} requests", group);
+        return None;
+    }
+    println!(
+        "Rate limiting {} requests to {}/minute per client, burst {}",
+        group, per_minute, burst
+    );
+    Some(ClientRateLimiter::per_minute(
+        per_minute,
+        burst,
+        max_clients,
+    ))
+}
+
+#[utoipa::path(
+    get, path = "/metrics", tag = "health",
+    responses((status = 200, description = "Prometheus metrics", body = String, content_type = "text/plain"))
+)]
+async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics_handle.render(),
+    )
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "rapidbro",
+        description = "Rapid KL live bus positions, ETAs and service health"
+    ),
+    paths(
+        fetch_all_buses,
+        get_nearest_buses,
+        get_all_route_buses,
+        get_route_buses,
+        get_route_bus_count,
+        get_route_vehicles,
+        get_route_stops,
+        get_route_shape,
+        get_route_headways,
+        get_route_delays,
+        get_route_stream,
+        get_route_ws,
+        get_vehicle_history,
+        get_vehicle_gaps,
+        get_active_vehicles,
+        get_control_routes,
+        post_control_routes,
+        get_stops,
+        get_nearest_stop,
+        get_stop_routes,
+        get_route_eta,
+        get_stop_eta,
+        get_health,
+        get_ready,
+        get_healthz,
+        get_readyz,
+        get_ingestor_status,
+        get_metrics,
+        prasarana_gtfs_data,
+        get_route_t789,
+        get_t789_eta,
+        get_pantai_hillpark_phase_5_eta,
+        get_debug_sessions,
+        get_debug_parse_failures,
+    )
+)]
+struct ApiDoc;
+
+async fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+// Swagger UI from the CDN, pointed at /openapi.json; nothing to bundle into the binary.
+async fn get_docs() -> Html<&'static str> {
+    Html(SWAGGER_UI_HTML)
+}
+
+const SWAGGER_UI_HTML: &str = r##"<!doctype html>
+<html>
+  <head>
+    <title>rapidbro API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+  </head>
+  <body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+    <script>
+      SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+    </script>
+  </body>
+</html>
+"##;
+
+// Buckets are in seconds: the handshake and GTFS-rt fetch cross the network, decode does not.
+fn install_metrics_recorder() -> PrometheusHandle {
+    let network_buckets = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+    let decode_buckets = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25];
+    // From a fix being taken to it arriving here: seconds on a good day, minutes when the
+    // AVL unit buffers through a dead zone.
+    let latency_buckets = [1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(FIRST_PAYLOAD_SECONDS.to_string()),
+            &network_buckets,
+        )
+        .and_then(|builder| {
+            builder
+                .set_buckets_for_metric(Matcher::Full(FETCH_SECONDS.to_string()), &network_buckets)
+        })
+        .and_then(|builder| {
+            builder.set_buckets_for_metric(
+                Matcher::Full(DECODE_BATCH_SECONDS.to_string()),
+                &decode_buckets,
+            )
+        })
+        .and_then(|builder| {
+            builder.set_buckets_for_metric(
+                Matcher::Full(FEED_LATENCY_SECONDS.to_string()),
+                &latency_buckets,
+            )
+        })
+        .and_then(|builder| builder.install_recorder())
+        .unwrap_or_else(|error| panic!("Failed to install metrics recorder: {}", error))
+}
+
+async fn build_health_report(state: &AppState) -> HealthReport {
+    let status = state.ingestor_status.read().await.clone();
+    let now_ms = now_unix_ms();
+    let seconds_since_last_message = status
+        .last_message_unix_ms
+        .map(|last_message_ms| (now_ms - last_message_ms) / 1_000);
+    let seconds_since_last_data = status
+        .last_data_unix_ms
+        .map(|last_data_ms| (now_ms - last_data_ms) / 1_000);
+    let message_is_recent = status
+        .last_message_unix_ms
+        .is_some_and(|last_message_ms| now_ms - last_message_ms <= state.health_max_message_age_ms);
+
+    // Vehicle counts are informational; a Redis hiccup shouldn't fail the probe on its own.
+    let mut route_vehicle_counts = BTreeMap::new();
+    if let Ok(snapshot) = load_active_bus_snapshot(state).await {
+        for bus in &snapshot.buses {
+            *route_vehicle_counts.entry(bus.route.clone()).or_insert(0) += 1;
+        }
+    }
+
+    let connection_states = state
+        .socket_clients
+        .read()
+        .await
+        .iter()
+        .map(|(route, client)| {
+            let route = if route.is_empty() {
+                "all"
+            } else {
+                route.as_str()
+            };
+            (route.to_string(), client.state())
+        })
+        .collect();
+
+    // Ready once a batch has decoded, even an empty one: off-hours, a route with no buses
+    // running is still a working feed. While the first sessions are still being retried
+    // at startup this stays false.
+    let healthy = status.connected && message_is_recent;
+    HealthReport {
+        healthy,
+        ready: healthy && status.session_established && status.last_data_unix_ms.is_some(),
+        connected: status.connected,
+        session_established: status.session_established,
+        seconds_since_last_message,
+        seconds_since_last_data,
+        reconnect_count: status.reconnect_count,
+        route_vehicle_counts,
+        connection_states,
+        last_error: status.last_error,
+    }
+}
+
+#[utoipa::path(
+    get, path = "/healthz", tag = "health",
+    responses((status = 200, description = "Healthy", body = HealthReport), (status = 503, description = "Unhealthy", body = HealthReport))
+)]
+async fn get_healthz(State(state): State<AppState>) -> (StatusCode, Json<HealthReport>) {
+    let report = build_health_report(&state).await;
+    let status = if report.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+#[utoipa::path(
+    get, path = "/readyz", tag = "health",
+    responses((status = 200, description = "Ready", body = HealthReport), (status = 503, description = "Not ready", body = HealthReport))
+)]
+async fn get_readyz(State(state): State<AppState>) -> (StatusCode, Json<HealthReport>) {
+    let report = build_health_report(&state).await;
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+// Liveness only: answers as long as the process is serving requests, so a feed outage
+// never gets a working pod restarted.
+#[utoipa::path(
+    get, path = "/health", tag = "health",
+    responses((status = 200, description = "Process is serving requests", example = json!({"status": "ok"})))
+)]
+async fn get_health() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+// Ready once the socket is connected and at least one batch has decoded, even an empty
+// one: off-hours, a route with no buses running is still a working feed. While the first
+// sessions are still being retried at startup this stays 503.
+#[utoipa::path(
+    get, path = "/ready", tag = "health",
+    responses((status = 200, description = "Connected with data", body = ReadinessReport), (status = 503, description = "No data yet", body = ReadinessReport))
+)]
+async fn get_ready(State(state): State<AppState>) -> (StatusCode, Json<ReadinessReport>) {
+    let status = state.ingestor_status.read().await.clone();
+    let report = ReadinessReport {
+        ready: status.connected && status.last_data_unix_ms.is_some(),
+        connected: status.connected,
+        last_data_unix_ms: status.last_data_unix_ms,
+        seconds_since_last_data: status
+            .last_data_unix_ms
+            .map(|last_data_ms| (now_unix_ms() - last_data_ms) / 1_000),
+    };
+    let code = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(report))
+}
+
+// Mirrors the health report to disk for supervisors that can't probe HTTP.
+async fn run_status_file_writer(state: AppState, path: String) {
+    let mut interval = tokio::time::interval(STATUS_FILE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let report = build_health_report(&state).await;
+        let Ok(serialized) = serde_json::to_string_pretty(&report) else {
+            continue;
+        };
+        write_file_atomically(&path, serialized).await;
+    }
+}
+
+// Write then rename so readers never see a half-written file.
+async fn write_file_atomically(path: &str, contents: String) {
+    let temp_path = format!("{}.tmp", path);
+    if let Err(error) = tokio::fs::write(&temp_path, contents).await {
+        println!("Failed to write '{}': {}", temp_path, error);
+        return;
+    }
+    if let Err(error) = tokio::fs::rename(&temp_path, path).await {
+        println!("Failed to replace '{}': {}", path, error);
+    }
+}
+
+async fn run_bus_ingestor(
+    state: AppState,
+    http: HttpOptions,
+    mut route_changes: mpsc::UnboundedReceiver<RouteChange>,
+    source: Source,
+    format: LogFormat,
+) {
+    let routes = state.routes.routes();
+    if !routes.is_empty() {
+        println!(
+            "Subscribing to {} routes: {}",
+            routes.len(),
+            routes.join(",")
+        );
+    }
+    // Socket clients by route ("" for the all-buses client), so they can be stopped.
+    let mut route_clients: HashMap<String, RapidbroClient> = HashMap::new();
+    // Never run: it only carries the derived events (trips, presence) published below. A
+    // route client of its own would stop publishing when that route is unsubscribed.
+    let publisher = match http.apply(RapidbroClient::builder()).build() {
+        Ok(publisher) => publisher,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }
+    };
+    let mut events = match source {
+        Source::Websocket | Source::Hybrid => {
+            let spawned = match spawn_route_clients(&routes, &http, &state.emit_limiter).await {
+                Ok(spawned) => spawned,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(2);
+                }
+            };
+            let keys = if routes.is_empty() {
+                vec![String::new()]
+            } else {
+                routes.clone()
+            };
+            let mut events = SelectAll::new();
+            for (route, (client, client_events)) in keys.into_iter().zip(spawned) {
+                events.push(route_events(route.clone(), client_events));
+                route_clients.insert(route, client);
+            }
+            *state.socket_clients.write().await = route_clients.clone();
+            events
+        }
+        Source::Gtfs => stream::select_all(vec![route_events(
+            String::new(),
+            gtfs_position_stream(
+                state.gtfs_http.clone(),
+                state.routes.clone(),
+                state.timezone,
+            ),
+        )]),
+    };
+    events.push(route_events(String::new(), publisher.subscribe().await));
+
+    let route_colors = load_route_colors();
+    // --format diff prints one line per material vehicle change instead of nothing.
+    let mut diff_engine = (format == LogFormat::Diff).then(|| {
+        DiffEngine::new(
+            env::var("DIFF_LOST_AFTER_BATCHES")
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(DEFAULT_LOST_AFTER_BATCHES),
+        )
+    });
+    let schedules = load_route_schedules(&state.route_shapes);
+    let active_services = load_active_services();
+    let mut layover_detector = LayoverDetector::new(
+        load_route_termini(),
+        normalize_route_code,
+        env::var("LAYOVER_RADIUS_METERS")
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .unwrap_or(DEFAULT_TERMINAL_RADIUS_METERS),
+        env::var("LAYOVER_DWELL_SECONDS")
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .map(|seconds| seconds * 1_000)
+            .unwrap_or(DEFAULT_DWELL_MS),
+    );
+    let mut direction_tracker = DirectionTracker::new(
+        env::var("DIRECTION_OBSERVATIONS")
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(DEFAULT_CONSISTENT_OBSERVATIONS),
+    );
+    let mut last_headway_log_ms: i64 = 0;
+    let mut speed_smoother = SpeedSmoother::new(
+        env::var("SPEED_WINDOW_SAMPLES")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_WINDOW_SAMPLES),
+        state.vehicle_stale_after_ms,
+    );
+    // Routes whose socket last said no buses are running; cleared once per quiet spell.
+    let mut emptied_routes: HashSet<String> = HashSet::new();
+    let mut ordering_guard = OrderingGuard::new(
+        env::var("OUT_OF_ORDER_GRACE_MS")
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .unwrap_or(DEFAULT_GRACE_MS),
+    );
+    // A vehicle missing for VEHICLE_LOST_AFTER_CYCLES reload intervals is reported lost.
+    let mut presence_tracker = PresenceTracker::new(
+        env::var("VEHICLE_LOST_AFTER_CYCLES")
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(DEFAULT_LOST_AFTER_CYCLES),
+        env::var("VEHICLE_LOST_GRACE_SECONDS")
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .map(|seconds| seconds * 1_000)
+            .unwrap_or(DEFAULT_RECONNECT_GRACE_MS),
+    );
+    // A vehicle whose next fix arrives more than GAP_THRESHOLD_SECONDS after its last one
+    // has a gap, logged next to the archive when ARCHIVE_DIR is set.
+    let mut gap_detector = GapDetector::new(
+        env::var("GAP_THRESHOLD_SECONDS")
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .map(|seconds| seconds * 1_000)
+            .unwrap_or((DEFAULT_RELOAD_INTERVAL * DEFAULT_GAP_INTERVALS).as_millis() as i64),
+    );
+    let gap_log = state
+        .archive
+        .as_ref()
+        .map(|archive| GapLog::new(archive.root()));
+    let mut presence_cycle = tokio::time::interval(DEFAULT_RELOAD_INTERVAL);
+    presence_cycle.reset();
+    // ROUTE_REASSIGN_POLICY=keep-both lists a reassigned vehicle on its old route as well;
+    // the default moves it once ROUTE_REASSIGN_GRACE_SECONDS have passed.
+    let mut route_assignments = RouteAssignments::new(
+        env::var("ROUTE_REASSIGN_POLICY")
+            .ok()
+            .and_then(|value| value.parse::<ReassignPolicy>().ok())
+            .unwrap_or_default(),
+        env::var("ROUTE_REASSIGN_GRACE_SECONDS")
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .map(|seconds| seconds * 1_000)
+            .unwrap_or(DEFAULT_REASSIGN_GRACE_MS),
+        normalize_route_code,
+    );
+
+    // GTFS_ENRICH=true polls GTFS-rt alongside the socket to add trip_id, route_id and
+    // current_stop_sequence to socket updates; GTFS-rt positions already carry them.
+    let mut gtfs_enricher = (source != Source::Gtfs
+        && env::var("GTFS_ENRICH")
+            .ok()
+            .and_then(|value| value.parse::<bool>().ok())
+            .unwrap_or(false))
+    .then(|| {
+        GtfsEnricher::new(
+            env::var("GTFS_ENRICH_MAX_AGE_SECONDS")
+                .ok()
+                .and_then(|value| value.parse::<i64>().ok())
+                .map(|seconds| seconds * 1_000)
+                .unwrap_or(DEFAULT_MAX_METADATA_AGE_MS),
+            env::var("GTFS_ENRICH_MATCH_METERS")
+                .ok()
+                .and_then(|value| value.parse::<f64>().ok())
+                .unwrap_or(DEFAULT_MATCH_TOLERANCE_METERS),
+        )
+    });
+    let mut enrich_positions = gtfs_enricher.is_some().then(|| {
+        gtfs_position_stream(
+            state.gtfs_http.clone(),
+            state.routes.clone(),
+            state.timezone,
+        )
+    });
+
+    // Hybrid mode: each route's socket counts as down from startup until it first connects,
+    // and GTFS-rt only stands in for the routes whose socket has been down too long.
+    let fallback_after_ms = env::var("GTFS_FALLBACK_AFTER_SECONDS")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_GTFS_FALLBACK_AFTER_SECONDS)
+        * 1_000;
+    let mut socket_down_since: HashMap<String, i64> = match source {
+        Source::Hybrid => route_clients
+            .keys()
+            .map(|route| (route.clone(), now_unix_ms()))
+            .collect(),
+        _ => HashMap::new(),
+    };
+    let fallback_routes: Arc<Mutex<HashSet<String>>> = Arc::default();
+    let mut gtfs_fallback: Option<BoxStream<'static, ClientEvent>> = None;
+    let mut fallback_check = tokio::time::interval(Duration::from_secs(1));
+
+    // GTFS_BACKFILL=false leaves the gap after a websocket outage until the socket sends
+    // fresh positions; otherwise one GTFS-rt snapshot fills it on reconnect.
+    let backfill_enabled = source != Source::Gtfs
+        && env::var("GTFS_BACKFILL")
+            .ok()
+            .and_then(|value| value.parse::<bool>().ok())
+            .unwrap_or(true);
+    let mut backfill_guard = BackfillGuard::new(DEFAULT_BACKFILL_MIN_INTERVAL_MS);
+    let mut backfill: Option<BoxStream<'static, ClientEvent>> = None;
+    let mut socket_outage = false;
+
+    loop {
+        // `link` is the route of the socket the event came from ("" for the all-buses
+        // client and for GTFS-rt), so a disconnect only pauses that route's vehicles.
+        let (link, event, from_fallback) = tokio::select! {
+            event = events.next(), if !events.is_empty() => match event {
+                Some((link, event)) => (link, event, false),
+                None => break,
+            },
+            event = async {
+                match gtfs_fallback.as_mut() {
+                    Some(positions) => positions.next().await,
+                    None => std::future::pending().await,
+                }
+            }, if gtfs_fallback.is_some() => match event {
+                // Only positions pass through; the poller's connection events would
+                // otherwise overwrite the socket's status.
+                Some(event @ ClientEvent::Buses { .. }) => (String::new(), event, true),
+                Some(ClientEvent::Disconnected { reason }) => {
+                    println!("GTFS-rt fallback poll failed: {}", reason);
+                    continue;
+                }
+                Some(_) => continue,
+                None => {
+                    gtfs_fallback = None;
+                    continue;
+                }
+            },
+            event = async {
+                match backfill.as_mut() {
+                    Some(snapshot) => snapshot.next().await,
+                    None => std::future::pending().await,
+                }
+            }, if backfill.is_some() => match event {
+                Some(ClientEvent::Buses { mut buses, decode_failures, received_at_unix_ms }) => {
+                    backfill = None;
+                    let superseded = backfill_guard.finish(&mut buses);
+                    counter!(BACKFILL_POSITIONS_TOTAL, "outcome" => "applied")
+                        .increment(buses.len() as u64);
+                    counter!(BACKFILL_POSITIONS_TOTAL, "outcome" => "superseded")
+                        .increment(superseded as u64);
+                    println!(
+                        "Backfilled {} positions from GTFS-rt after the websocket reconnected ({} already fresh from the socket)",
+                        buses.len(),
+                        superseded
+                    );
+                    (String::new(), ClientEvent::Buses { buses, decode_failures, received_at_unix_ms }, true)
+                }
+                Some(ClientEvent::Disconnected { reason }) => {
+                    backfill = None;
+                    backfill_guard.cancel();
+                    println!("GTFS-rt backfill failed: {}", reason);
+                    continue;
+                }
+                Some(_) => continue,
+                None => {
+                    backfill = None;
+                    backfill_guard.cancel();
+                    continue;
+                }
+            },
+            event = async {
+                match enrich_positions.as_mut() {
+                    Some(positions) => positions.next().await,
+                    None => std::future::pending().await,
+                }
+            }, if enrich_positions.is_some() => {
+                match (event, gtfs_enricher.as_mut()) {
+                    (Some(ClientEvent::Buses { buses, received_at_unix_ms, .. }), Some(enricher)) => {
+                        enricher.update(&buses, received_at_unix_ms);
+                    }
+                    (None, _) => enrich_positions = None,
+                    _ => {}
+                }
                 continue;
             }
+            _ = fallback_check.tick(), if source == Source::Hybrid => {
+                let now = now_unix_ms();
+                let polling = {
+                    let mut polled = fallback_routes
+                        .lock()
+                        .unwrap_or_else(|error| error.into_inner());
+                    for (route, since) in &socket_down_since {
+                        if now - since >= fallback_after_ms && polled.insert(route.clone()) {
+                            println!(
+                                "Websocket for route {} down for over {}s, polling GTFS-rt until it recovers",
+                                if route.is_empty() { "all" } else { route.as_str() },
+                                fallback_after_ms / 1_000
+                            );
+                        }
+                    }
+                    !polled.is_empty()
+                };
+                if gtfs_fallback.is_none() && polling {
+                    gtfs_fallback = Some(only_polled_routes(
+                        gtfs_position_stream(
+                            state.gtfs_http.clone(),
+                            state.routes.clone(),
+                            state.timezone,
+                        ),
+                        fallback_routes.clone(),
+                    ));
+                    state.ingestor_status.write().await.gtfs_fallback_active = true;
+                }
+                continue;
+            }
+            _ = presence_cycle.tick(), if !events.is_empty() || gtfs_fallback.is_some() => {
+                for presence_event in presence_tracker.end_cycle(now_unix_ms()) {
+                    publisher.publish(ClientEvent::Presence(presence_event));
+                }
+                continue;
+            }
+            Some(change) = route_changes.recv() => {
+                if source != Source::Gtfs {
+                    let known: HashSet<String> = route_clients.keys().cloned().collect();
+                    apply_route_change(&state, &http, &change, &mut route_clients, &mut events).await;
+                    *state.socket_clients.write().await = route_clients.clone();
+                    if source == Source::Hybrid {
+                        // A new socket is down until it connects; a stopped one needs no cover.
+                        socket_down_since.retain(|route, _| route_clients.contains_key(route));
+                        let added = route_clients.keys().filter(|route| !known.contains(*route));
+                        for route in added {
+                            socket_down_since.insert(route.clone(), now_unix_ms());
+                        }
+                        let polling = {
+                            let mut polled = fallback_routes
+                                .lock()
+                                .unwrap_or_else(|error| error.into_inner());
+                            polled.retain(|route| route_clients.contains_key(route));
+                            !polled.is_empty()
+                        };
+                        if !polling && gtfs_fallback.take().is_some() {
+                            state.ingestor_status.write().await.gtfs_fallback_active = false;
+                        }
+                    }
+                }
+                if let RouteChange::Remove(route) = change {
+                    match untrack_route_vehicles(&state, &route).await {
+                        Ok(count) => println!("Unsubscribed from route {}; untracked {} vehicles", route, count),
+                        Err(error) => println!("Unsubscribed from route {}; failed to untrack vehicles: {}", route, error),
+                    }
+                }
+                continue;
+            }
+            else => break,
         };
+        if backfill_enabled && !from_fallback {
+            match &event {
+                ClientEvent::Disconnected { .. } | ClientEvent::SessionFailed { .. } => {
+                    socket_outage = true;
+                }
+                // Not while the hybrid fallback is running; it has kept the map current.
+                ClientEvent::Connected if socket_outage => {
+                    socket_outage = false;
+                    let polled = fallback_routes
+                        .lock()
+                        .unwrap_or_else(|error| error.into_inner())
+                        .contains(&link);
+                    if !polled && backfill_guard.start(now_unix_ms()) {
+                        backfill = Some(gtfs_position_stream(
+                            state.gtfs_http.clone(),
+                            state.routes.clone(),
+                            state.timezone,
+                        ));
+                    }
+                }
+                ClientEvent::Buses { buses, .. } => backfill_guard.observe_live(buses),
+                _ => {}
+            }
+        }
+        if source == Source::Hybrid && !from_fallback {
+            match &event {
+                ClientEvent::Disconnected { .. } | ClientEvent::SessionFailed { .. } => {
+                    socket_down_since
+                        .entry(link.clone())
+                        .or_insert_with(now_unix_ms);
+                }
+                ClientEvent::Connected | ClientEvent::Buses { .. } => {
+                    socket_down_since.remove(&link);
+                    let (recovered, polling) = {
+                        let mut polled = fallback_routes
+                            .lock()
+                            .unwrap_or_else(|error| error.into_inner());
+                        (polled.remove(&link), !polled.is_empty())
+                    };
+                    if recovered {
+                        println!(
+                            "Websocket for route {} recovered, stopped polling GTFS-rt for it",
+                            if link.is_empty() {
+                                "all"
+                            } else {
+                                link.as_str()
+                            }
+                        );
+                    }
+                    if !polling && gtfs_fallback.take().is_some() {
+                        state.ingestor_status.write().await.gtfs_fallback_active = false;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !from_fallback {
+            match &event {
+                ClientEvent::Disconnected { .. } => {
+                    presence_tracker.on_disconnected(&link);
+                    gap_detector.on_disconnected(&link, now_unix_ms());
+                }
+                ClientEvent::SessionFailed { .. } => {
+                    gap_detector.on_disconnected(&link, now_unix_ms())
+                }
+                ClientEvent::Connected => {
+                    presence_tracker.on_connected(&link, now_unix_ms());
+                    gap_detector.on_connected(&link, now_unix_ms());
+                }
+                _ => {}
+            }
+        }
+        match event {
+            ClientEvent::SessionEstablished { .. } => {
+                state.ingestor_status.write().await.session_established = true;
+            }
+            ClientEvent::SessionFailed { reason } => {
+                state.ingestor_status.write().await.session_established = false;
+                record_ingestor_error(&state, reason, true).await;
+            }
+            ClientEvent::StartupFailed {
+                route,
+                attempts,
+                reason,
+            } => {
+                let message = format!(
+                    "Route {}: no session after {} startup attempts, giving up: {}",
+                    if route.is_empty() { "all" } else { &route },
+                    attempts,
+                    reason
+                );
+                // Nothing has been served yet, so exit and leave restarting to the supervisor
+                // rather than sit unready. A route added or failing later only loses its
+                // client, and hybrid keeps going on GTFS-rt.
+                let never_served = state
+                    .ingestor_status
+                    .read()
+                    .await
+                    .last_data_unix_ms
+                    .is_none();
+                if never_served && source != Source::Hybrid {
+                    eprintln!("{}", message);
+                    std::process::exit(1);
+                }
+                println!("{}", message);
+                record_ingestor_error(&state, message, false).await;
+            }
+            // Read per client through RapidbroClient::state for /healthz.
+            ClientEvent::RawPayloads { .. } | ClientEvent::Status(_) => {}
+            ClientEvent::Trip(trip_event) => {
+                println!("{}", trip_event);
+                if let Some(webhook) = &state.webhook {
+                    webhook.enqueue(WebhookEvent::Trip(trip_event)).await;
+                }
+            }
+            ClientEvent::Presence(presence_event) => {
+                println!("{}", presence_event);
+                {
+                    let mut lost_vehicles = state.lost_vehicles.write().await;
+                    match &presence_event {
+                        PresenceEvent::VehicleLost { vehicle_id, .. } => {
+                            lost_vehicles.insert(vehicle_id.clone());
+                        }
+                        PresenceEvent::VehicleReturned { vehicle_id, .. } => {
+                            lost_vehicles.remove(vehicle_id);
+                        }
+                    }
+                }
+                // The vehicle's inactive flag just changed in its route's snapshot.
+                state.route_versions.bump(presence_event.route());
+                if let Some(webhook) = &state.webhook {
+                    webhook
+                        .enqueue(WebhookEvent::Presence(presence_event))
+                        .await;
+                }
+            }
+            ClientEvent::Connected => {
+                let mut status = state.ingestor_status.write().await;
+                status.connected = true;
+                status.last_error = None;
+                state.stats.write().await.on_reconnect();
+            }
+            ClientEvent::Disconnected { reason } => {
+                record_ingestor_error(&state, reason, true).await;
+            }
+            ClientEvent::Buses {
+                mut buses,
+                decode_failures,
+                received_at_unix_ms,
+            } => {
+                let live = *state.live.read().await;
+                // An empty list that decoded cleanly means no buses are running, not that
+                // the feed is broken.
+                let valid_empty = buses.is_empty() && decode_failures == 0;
+                let valid_batch = !buses.is_empty() || decode_failures == 0;
+                let emptied = emptied_route(&link, buses.len(), decode_failures);
+                let checks = match live.coordinate_bounds {
+                    Some(bounds) => filter_valid_coordinates(&mut buses, &bounds),
+                    None => Vec::new(),
+                };
+                let out_of_order = ordering_guard.retain_in_order(&mut buses);
+                if out_of_order > 0 {
+                    counter!(OUT_OF_ORDER_REJECTED_TOTAL).increment(out_of_order as u64);
+                }
+
+                {
+                    let mut status = state.ingestor_status.write().await;
+                    status.messages_processed += 1;
+                    status.last_message_unix_ms = Some(received_at_unix_ms);
+                    if valid_batch {
+                        status.last_data_unix_ms = Some(received_at_unix_ms);
+                    }
+                    if valid_empty {
+                        status.last_valid_empty_unix_ms = Some(received_at_unix_ms);
+                    }
+                    status.decode_failures += decode_failures;
+                    for check in checks {
+                        match check {
+                            CoordinateCheck::Valid => {}
+                            CoordinateCheck::Swapped => {
+                                status.coordinates_swapped += 1;
+                                counter!(COORDINATES_SWAPPED_TOTAL).increment(1);
+                            }
+                            CoordinateCheck::ZeroFix => {
+                                status.coordinates_rejected_zero += 1;
+                                counter!(COORDINATES_REJECTED_TOTAL, "reason" => "zero_fix")
+                                    .increment(1);
+                            }
+                            CoordinateCheck::OutOfBounds => {
+                                status.coordinates_rejected_out_of_bounds += 1;
+                                counter!(COORDINATES_REJECTED_TOTAL, "reason" => "out_of_bounds")
+                                    .increment(1);
+                            }
+                        }
+                    }
+                }
+
+                if let Some(diff_engine) = diff_engine.as_mut() {
+                    for change in diff_engine.apply(&buses) {
+                        println!("{}", change);
+                    }
+                }
+
+                if buses.is_empty() {
+                    // Off-hours the route's last positions would otherwise linger until
+                    // BUS_TTL_SECONDS ran out.
+                    if let Some(route) =
+                        emptied.filter(|route| emptied_routes.insert(route.to_string()))
+                    {
+                        match untrack_route_vehicles(&state, route).await {
+                            Ok(count) => println!(
+                                "Route {} has no buses running; untracked {} vehicles",
+                                route, count
+                            ),
+                            Err(error) => {
+                                println!(
+                                    "Route {} has no buses running; failed to untrack vehicles: {}",
+                                    route, error
+                                );
+                                // Try again on the next empty batch.
+                                emptied_routes.remove(route);
+                            }
+                        }
+                        let route = normalize_route_code(route);
+                        if let Some(redis_pubsub) = &state.redis_pubsub {
+                            redis_pubsub.clear(route.clone()).await;
+                        }
+                        if state.channels.has_subscribers(&route) {
+                            state.channels.publish(&route, Vec::new());
+                        }
+                        state.route_versions.bump(&route);
+                    }
+                    continue;
+                }
+                emptied_routes.remove(&link);
+
+                if let Some(enricher) = &gtfs_enricher {
+                    for bus in &mut buses {
+                        enricher.enrich(bus, received_at_unix_ms);
+                    }
+                }
+                let mut reassignments = Vec::new();
+                let mut gaps = Vec::new();
+                for bus in &mut buses {
+                    state.route_shapes.annotate(bus);
+                    if let Some(reassignment) = route_assignments.observe(bus, received_at_unix_ms)
+                    {
+                        direction_tracker.forget(&bus.bus_no);
+                        layover_detector.forget(&bus.bus_no);
+                        reassignments.push(reassignment);
+                    }
+                    direction_tracker.annotate(bus);
+                    speed_smoother.annotate(bus, received_at_unix_ms);
+                    if let Some(trip_event) = layover_detector.observe(bus, received_at_unix_ms) {
+                        publisher.publish(ClientEvent::Trip(trip_event));
+                    }
+                    // A GTFS-rt position stands in for the socket following its route.
+                    let bus_link = if from_fallback {
+                        route_link(&route_clients, &bus.route)
+                    } else {
+                        link.as_str()
+                    };
+                    if let Some(presence_event) =
+                        presence_tracker.observe(bus, bus_link, received_at_unix_ms)
+                    {
+                        publisher.publish(ClientEvent::Presence(presence_event));
+                    }
+                    gaps.extend(gap_detector.observe(bus, bus_link, received_at_unix_ms));
+                }
+                for gap in &gaps {
+                    println!("{}", gap);
+                    counter!(VEHICLE_GAPS_TOTAL, "cause" => gap.cause.as_str()).increment(1);
+                }
+                if let Some(gap_log) = gap_log.as_ref().filter(|_| !gaps.is_empty()) {
+                    let gap_log = gap_log.clone();
+                    tokio::task::spawn_blocking(move || {
+                        for gap in &gaps {
+                            if let Err(error) = gap_log.append(gap) {
+                                println!("Failed to log gap for {}: {}", gap.vehicle_id, error);
+                            }
+                        }
+                    });
+                }
+                {
+                    let mut stats = state.stats.write().await;
+                    for reassignment in &reassignments {
+                        stats.on_reassigned(&reassignment.vehicle_id);
+                    }
+                    for bus in &buses {
+                        stats.record(bus, received_at_unix_ms);
+                    }
+                }
+                let released = route_assignments.release(received_at_unix_ms);
+                if !reassignments.is_empty() || !released.is_empty() {
+                    counter!(ROUTE_REASSIGNMENTS_TOTAL).increment(reassignments.len() as u64);
+                    let mut previous_routes = state.previous_routes.write().await;
+                    for reassignment in &reassignments {
+                        println!("{}", reassignment);
+                    }
+                    for (vehicle_id, route) in &released {
+                        println!("{} no longer listed on route {}", vehicle_id, route);
+                    }
+                    let changes = reassignments
+                        .iter()
+                        .map(|reassignment| (&reassignment.vehicle_id, &reassignment.from_route))
+                        .chain(
+                            released
+                                .iter()
+                                .map(|(vehicle_id, route)| (vehicle_id, route)),
+                        );
+                    for (vehicle_id, route) in changes {
+                        let routes = route_assignments.previous_routes(vehicle_id);
+                        if routes.is_empty() {
+                            previous_routes.remove(vehicle_id);
+                        } else {
+                            previous_routes.insert(vehicle_id.clone(), routes);
+                        }
+                        // The vehicle was added to or dropped from the route's listing.
+                        state.route_versions.bump(route);
+                    }
+                }
+                apply_schedule_delays(&state, &mut buses, &schedules, &active_services).await;
+                {
+                    let mut headways = state.headways.write().await;
+                    headways.update(&buses, normalize_route_code);
+                    if live.headway_log_seconds > 0
+                        && received_at_unix_ms - last_headway_log_ms
+                            >= (live.headway_log_seconds * 1_000) as i64
+                    {
+                        last_headway_log_ms = received_at_unix_ms;
+                        for summary in sorted_headways(headways.summaries()) {
+                            println!("{}", summary);
+                        }
+                    }
+                }
+
+                for bus in &mut buses {
+                    apply_route_colors(bus, &route_colors);
+                }
+                #[cfg(feature = "kafka")]
+                if let Some(kafka) = &state.kafka {
+                    for bus in &buses {
+                        kafka.produce(bus);
+                    }
+                }
+                #[cfg(feature = "grpc")]
+                if let Some(grpc) = &state.grpc {
+                    grpc.publish(&buses);
+                }
+                if let Some(archive) = &state.archive {
+                    for bus in &buses {
+                        archive.enqueue(bus, received_at_unix_ms).await;
+                    }
+                }
+                if let Some(influx) = &state.influx {
+                    for bus in &buses {
+                        influx.enqueue(bus, received_at_unix_ms).await;
+                    }
+                }
+                if !state.sinks.is_empty() {
+                    let batch = buses.iter().cloned().map(BusUpdate::Position).collect();
+                    state.sinks.send(batch).await;
+                }
+                if let Some(webhook) = &state.webhook {
+                    for bus in &buses {
+                        webhook
+                            .enqueue(WebhookEvent::Position(Box::new(bus.clone())))
+                            .await;
+                    }
+                }
+                let mut route_frames: HashMap<String, Vec<BusPosition>> = HashMap::new();
+                for bus in &buses {
+                    let route = normalize_route_code(&bus.route);
+                    if state.redis_pubsub.is_some() || state.channels.has_subscribers(&route) {
+                        route_frames.entry(route).or_default().push(bus.clone());
+                    }
+                }
+                for (route, frame) in route_frames {
+                    if let Some(redis_pubsub) = &state.redis_pubsub {
+                        redis_pubsub.enqueue(route.clone(), frame.clone()).await;
+                    }
+                    if state.channels.has_subscribers(&route) {
+                        state.channels.publish(&route, frame);
+                    }
+                }
+
+                let batch = SinkBatch {
+                    buses,
+                    received_at_unix_ms,
+                };
+                if !state.sink_queue.push(batch).await {
+                    counter!(SINK_DROPPED_TOTAL).increment(1);
+                }
+            }
+        }
+    }
+}
+
+// Config file values win over the environment, which wins over the defaults.
+fn live_settings(config: &FileConfig, no_validate: bool) -> LiveSettings {
+    // --no-validate or VALIDATE_COORDINATES=false passes the raw feed through for debugging.
+    let validate_coordinates = !no_validate
+        && config.validate_coordinates.unwrap_or_else(|| {
+            env::var("VALIDATE_COORDINATES")
+                .ok()
+                .and_then(|value| value.parse::<bool>().ok())
+                .unwrap_or(true)
+        });
+    let coordinate_bounds = config
+        .coordinate_bounds()
+        .or_else(|| {
+            env::var("COORDINATE_BBOX")
+                .ok()
+                .and_then(|value| BoundingBox::parse(&value))
+        })
+        .unwrap_or(MALAYSIA_BBOX);
+    LiveSettings {
+        coordinate_bounds: validate_coordinates.then_some(coordinate_bounds),
+        // HEADWAY_LOG_SECONDS=0 keeps the summaries on the API only.
+        headway_log_seconds: config.headway_log_seconds.unwrap_or_else(|| {
+            env::var("HEADWAY_LOG_SECONDS")
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(DEFAULT_HEADWAY_LOG_SECONDS)
+        }),
+    }
+}
+
+fn config_routes(config: &FileConfig) -> Vec<String> {
+    let mut routes: Vec<String> = Vec::new();
+    for route in config.routes.iter().flatten() {
+        let route = route.trim().to_uppercase();
+        if !is_valid_route_id(&route) {
+            println!("Skipping invalid route id `{}` in config", route);
+        } else if !routes.contains(&route) {
+            routes.push(route);
+        }
+    }
+    routes
+}
+
+// Re-reads --config on SIGHUP and applies what can change live. An unreadable or
+// invalid file is reported and the running configuration kept.
+#[cfg(unix)]
+async fn reload_config_on_sighup(state: AppState, path: PathBuf, mut current: FileConfig) {
+    let mut hangups = match tokio::signal::unix::signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(error) => {
+            println!(
+                "Config reload disabled: cannot listen for SIGHUP: {}",
+                error
+            );
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        let reloaded = match FileConfig::load(&path) {
+            Ok(reloaded) => reloaded,
+            Err(error) => {
+                println!(
+                    "Config reload failed, keeping current configuration: {}",
+                    error
+                );
+                continue;
+            }
+        };
+        let diff = current.diff(&reloaded);
+        if diff.is_empty() {
+            println!("Config reloaded: no changes");
+            continue;
+        }
+
+        // A config without `routes` leaves the current subscriptions alone.
+        if diff.hot.contains(&"routes") && reloaded.routes.is_some() {
+            let wanted = config_routes(&reloaded);
+            for route in state.routes.routes() {
+                if !wanted.contains(&route) {
+                    state.routes.remove(&route);
+                }
+            }
+            for route in &wanted {
+                if !state.routes.add(route) && !state.routes.routes().contains(route) {
+                    println!("Skipping route {} past --max-connections", route);
+                }
+            }
+        }
+        *state.live.write().await = live_settings(&reloaded, state.no_validate);
+
+        if !diff.hot.is_empty() {
+            println!("Config reloaded; applied {}", diff.hot.join(", "));
+        }
+        if !diff.restart.is_empty() {
+            println!(
+                "Config reloaded; {} only take effect after a restart",
+                diff.restart.join(", ")
+            );
+        }
+        // Keep the values actually in use for settings that need a restart.
+        current = FileConfig {
+            bind: current.bind,
+            redis_url: current.redis_url,
+            archive_dir: current.archive_dir,
+            field_map: current.field_map,
+            cors_origins: current.cors_origins,
+            api_keys: current.api_keys,
+            ..reloaded
+        };
+    }
+}
+
+// Opens or closes a route's socket after a /control/routes change. The all-buses client
+// only runs while the set is empty, matching serve without --route.
+async fn apply_route_change(
+    state: &AppState,
+    http: &HttpOptions,
+    change: &RouteChange,
+    route_clients: &mut HashMap<String, RapidbroClient>,
+    events: &mut SelectAll<BoxStream<'static, (String, ClientEvent)>>,
+) {
+    match change {
+        RouteChange::Add(route) => {
+            if let Some(all_buses) = route_clients.remove("") {
+                all_buses.stop();
+            }
+            if !route_clients.contains_key(route) {
+                match spawn_route_client(route.clone(), http, &state.emit_limiter, None).await {
+                    Ok((client, client_events)) => {
+                        events.push(route_events(route.clone(), client_events));
+                        route_clients.insert(route.clone(), client);
+                        println!("Subscribed to route {}", route);
+                    }
+                    Err(error) => println!("Failed to subscribe to route {}: {}", route, error),
+                }
+            }
+        }
+        RouteChange::Remove(route) => {
+            if let Some(client) = route_clients.remove(route) {
+                client.stop();
+            }
+            if state.routes.is_empty() && route_clients.is_empty() {
+                match spawn_route_client(String::new(), http, &state.emit_limiter, None).await {
+                    Ok((client, client_events)) => {
+                        events.push(route_events(String::new(), client_events));
+                        route_clients.insert(String::new(), client);
+                        println!("No routes left; following every bus");
+                    }
+                    Err(error) => println!("Failed to follow every bus: {}", error),
+                }
+            }
+        }
+    }
+}
+
+// Tags a stream's events with the route whose socket produced them.
+fn route_events(
+    route: String,
+    events: BoxStream<'static, ClientEvent>,
+) -> BoxStream<'static, (String, ClientEvent)> {
+    events.map(move |event| (route.clone(), event)).boxed()
+}
+
+// The route client a bus belongs to, or "" when none follows its route.
+fn route_link<'a>(route_clients: &'a HashMap<String, RapidbroClient>, bus_route: &str) -> &'a str {
+    route_clients
+        .keys()
+        .find(|route| !route.is_empty() && is_bus_on_route(bus_route, route))
+        .map(String::as_str)
+        .unwrap_or("")
+}
+
+// Drops a route's vehicles from the live snapshot so they stop being served as tracked.
+async fn untrack_route_vehicles(state: &AppState, route: &str) -> Result<usize, String> {
+    let mut redis_conn = state
+        .redis_client
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|error| error.to_string())?;
+    let latest: HashMap<String, String> = redis::cmd("HGETALL")
+        .arg(REDIS_BUSES_LATEST_KEY)
+        .query_async(&mut redis_conn)
+        .await
+        .map_err(|error| error.to_string())?;
+    let bus_ids: Vec<String> = latest
+        .into_iter()
+        .filter(|(_, entry)| {
+            serde_json::from_str::<BusPosition>(entry)
+                .is_ok_and(|bus| is_bus_on_route(&bus.route, route))
+        })
+        .map(|(bus_no, _)| bus_no)
+        .collect();
+    if bus_ids.is_empty() {
+        return Ok(0);
+    }
 
-        let disconnect_notify = Arc::new(Notify::new());
-        let on_any_state = state.clone();
-        let on_any_conn = redis_conn.clone();
+    let mut pipe = redis::pipe();
+    for key in [
+        REDIS_BUSES_LATEST_KEY,
+        REDIS_BUSES_MOTION_KEY,
+        REDIS_BUSES_SEQ_KEY,
+    ] {
+        pipe.cmd("HDEL").arg(key).arg(&bus_ids).ignore();
+    }
+    pipe.cmd("ZREM")
+        .arg(REDIS_BUSES_LAST_SEEN_KEY)
+        .arg(&bus_ids)
+        .ignore();
+    pipe.query_async::<()>(&mut redis_conn)
+        .await
+        .map_err(|error| error.to_string())?;
+    Ok(bus_ids.len())
+}
 
-        let on_any = move |_event: rust_socketio::Event,
-                           payload: Payload,
-                           _socket: rust_socketio::asynchronous::Client| {
-            let state = on_any_state.clone();
-            let mut redis_conn = on_any_conn.clone();
-            async move {
-                let now_ms = now_unix_ms();
-                let (buses, decode_failures) = parse_bus_positions_from_payload(payload);
+// KAFKA_BROKERS enables the producer; security settings map onto librdkafka's.
+#[cfg(feature = "kafka")]
+fn kafka_sink_from_env() -> Option<KafkaSink> {
+    let brokers = env::var("KAFKA_BROKERS")
+        .ok()
+        .filter(|brokers| !brokers.is_empty())?;
+    let mut config = KafkaConfig::new(brokers);
+    if let Ok(topic) = env::var("KAFKA_TOPIC") {
+        config.topic = topic;
+    }
+    if let Some(queue_capacity) = env::var("KAFKA_QUEUE_CAPACITY")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+    {
+        config.queue_capacity = queue_capacity;
+    }
+    config.security_protocol = env::var("KAFKA_SECURITY_PROTOCOL").ok();
+    config.sasl_mechanism = env::var("KAFKA_SASL_MECHANISM").ok();
+    config.sasl_username = env::var("KAFKA_SASL_USERNAME").ok();
+    config.sasl_password = env::var("KAFKA_SASL_PASSWORD").ok();
+    config.ssl_ca_location = env::var("KAFKA_SSL_CA_LOCATION").ok();
+
+    Some(
+        KafkaSink::new(&config)
+            .unwrap_or_else(|error| panic!("Failed to create Kafka producer: {}", error)),
+    )
+}
 
-                {
-                    let mut status = state.ingestor_status.write().await;
-                    status.messages_processed += 1;
-                    status.last_message_unix_ms = Some(now_ms);
-                    status.decode_failures += decode_failures;
-                }
+// GRPC_BIND (e.g. 0.0.0.0:50051) enables the gRPC server alongside the HTTP one.
+#[cfg(feature = "grpc")]
+fn grpc_bind_from_env() -> Option<std::net::SocketAddr> {
+    let bind = env::var("GRPC_BIND").ok().filter(|bind| !bind.is_empty())?;
+    Some(
+        bind.parse()
+            .unwrap_or_else(|error| panic!("Invalid GRPC_BIND '{}': {}", bind, error)),
+    )
+}
 
-                if buses.is_empty() {
-                    return;
-                }
+// Serves BusFeed until `shutdown` is cancelled. GetSnapshot answers from the same Redis
+// snapshot as /get-all.
+#[cfg(feature = "grpc")]
+async fn run_grpc_server(
+    state: AppState,
+    feed: GrpcFeed,
+    bind: std::net::SocketAddr,
+    shutdown: CancellationToken,
+) {
+    let snapshot: SnapshotLoader = Arc::new(move || {
+        let state = state.clone();
+        Box::pin(async move {
+            load_active_bus_snapshot(&state)
+                .await
+                .map(|snapshot| snapshot.buses)
+                .map_err(|(_, Json(error))| error.error)
+        })
+    });
+    println!("gRPC server is running on {}", bind);
+    let served = tonic::transport::Server::builder()
+        .add_service(feed.service(snapshot))
+        .serve_with_shutdown(bind, shutdown.cancelled_owned())
+        .await;
+    if let Err(error) = served {
+        println!("gRPC server stopped: {}", error);
+    }
+}
 
-                match write_buses_to_redis(&mut redis_conn, &buses, now_ms).await {
-                    Ok(written_count) => {
-                        let mut status = state.ingestor_status.write().await;
-                        status.buses_written += written_count as u64;
-                        status.last_error = None;
-                    }
-                    Err(error) => {
-                        let mut status = state.ingestor_status.write().await;
-                        status.redis_write_failures += 1;
-                        status.last_error = Some(format!("Redis write failed: {}", error));
-                    }
-                }
+// INFLUX_URL enables the sink; org, bucket and token are required alongside it.
+fn influx_sink_from_env() -> Option<InfluxSink> {
+    let url = env::var("INFLUX_URL").ok().filter(|url| !url.is_empty())?;
+    let required = |name: &str| {
+        env::var(name).unwrap_or_else(|_| panic!("{} is required when INFLUX_URL is set", name))
+    };
+    Some(InfluxSink::new(InfluxConfig {
+        url,
+        org: required("INFLUX_ORG"),
+        bucket: required("INFLUX_BUCKET"),
+        token: required("INFLUX_TOKEN"),
+        flush_interval: env::var("INFLUX_FLUSH_SECONDS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_FLUSH_INTERVAL),
+        queue_capacity: env::var("INFLUX_QUEUE_CAPACITY")
+            .ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_INFLUX_QUEUE_CAPACITY),
+        overflow_policy: overflow_policy_from_env("INFLUX_OVERFLOW_POLICY"),
+    }))
+}
+
+// Each sink reads its own <NAME>_OVERFLOW_POLICY; unset means drop-oldest.
+fn overflow_policy_from_env(name: &str) -> OverflowPolicy {
+    env::var(name)
+        .ok()
+        .map(|value| {
+            value
+                .parse::<OverflowPolicy>()
+                .unwrap_or_else(|error| panic!("Invalid {}: {}", name, error))
+        })
+        .unwrap_or(OverflowPolicy::DropOldest)
+}
+
+// Polls the GTFS-rt vehicle positions, keeping only subscribed routes when there are any.
+// Takes the shared client so each hybrid fallback reuses the pooled connections instead
+// of paying for a new TLS handshake.
+fn gtfs_position_stream(
+    http: reqwest::Client,
+    wanted_routes: RouteControl,
+    timezone: Option<Tz>,
+) -> BoxStream<'static, ClientEvent> {
+    poll_vehicle_positions(
+        http,
+        PRASARANA_VEHICLE_POSITIONS_URL,
+        DEFAULT_RELOAD_INTERVAL,
+        timezone,
+    )
+    .map(move |event| match event {
+        ClientEvent::Buses {
+            mut buses,
+            decode_failures,
+            received_at_unix_ms,
+        } if !wanted_routes.is_empty() => {
+            let wanted_routes = wanted_routes.routes();
+            buses.retain(|bus| {
+                wanted_routes
+                    .iter()
+                    .any(|route| is_bus_on_route(&bus.route, route))
+            });
+            ClientEvent::Buses {
+                buses,
+                decode_failures,
+                received_at_unix_ms,
             }
-            .boxed()
-        };
+        }
+        event => event,
+    })
+    .boxed()
+}
 
-        let disconnect_state = state.clone();
-        let disconnect_signal = disconnect_notify.clone();
-        let disconnect_state_for_error = state.clone();
-        let disconnect_signal_for_error = disconnect_notify.clone();
-
-        let socket = ClientBuilder::new(SOCKET_URL)
-            .transport_type(TransportType::Websocket)
-            .on_any(on_any)
-            .on("disconnect", move |_, _| {
-                let state = disconnect_state.clone();
-                let notify = disconnect_signal.clone();
-                async move {
-                    {
-                        let mut status = state.ingestor_status.write().await;
-                        status.connected = false;
-                        status.last_error = Some("Socket disconnected".to_string());
-                        status.reconnect_count += 1;
-                    }
-                    notify.notify_one();
-                }
-                .boxed()
-            })
-            .on("error", move |_, _| {
-                let state = disconnect_state_for_error.clone();
-                let notify = disconnect_signal_for_error.clone();
-                async move {
-                    {
-                        let mut status = state.ingestor_status.write().await;
-                        status.connected = false;
-                        status.last_error = Some("Socket error event".to_string());
-                        status.reconnect_count += 1;
-                    }
-                    notify.notify_one();
+// Keeps the hybrid fallback to the routes whose socket is down. "" is the all-buses client,
+// whose outage leaves every route uncovered.
+fn only_polled_routes(
+    positions: BoxStream<'static, ClientEvent>,
+    polled: Arc<Mutex<HashSet<String>>>,
+) -> BoxStream<'static, ClientEvent> {
+    positions
+        .map(move |event| match event {
+            ClientEvent::Buses {
+                mut buses,
+                decode_failures,
+                received_at_unix_ms,
+            } => {
+                let polled = polled.lock().unwrap_or_else(|error| error.into_inner());
+                if !polled.contains("") {
+                    buses.retain(|bus| {
+                        polled
+                            .iter()
+                            .any(|route| is_bus_on_route(&bus.route, route))
+                    });
                 }
-                .boxed()
-            })
-            .connect()
-            .await;
-
-        match socket {
-            Ok(socket) => {
-                let payload = json!({
-                    "sid": "",
-                    "uid": "",
-                    "provider": "RKL",
-                    "route": ""
-                });
-                if let Err(error) = socket.emit("onFts-reload", payload).await {
-                    record_ingestor_error(
-                        &state,
-                        format!("Socket subscribe emit failed: {}", error),
-                        true,
-                    )
-                    .await;
-                    tokio::time::sleep(Duration::from_secs(backoff_seconds)).await;
-                    backoff_seconds = (backoff_seconds * 2).min(30);
-                    continue;
+                ClientEvent::Buses {
+                    buses,
+                    decode_failures,
+                    received_at_unix_ms,
                 }
+            }
+            event => event,
+        })
+        .boxed()
+}
 
-                {
-                    let mut status = state.ingestor_status.write().await;
-                    status.connected = true;
-                    status.last_error = None;
-                }
+// Drains the sink queue into Redis so a slow Redis never stalls the socket consumer.
+// Batches that arrive within one flush window are written as a single transaction.
+async fn run_redis_sink(state: AppState, flush: RedisFlush) {
+    let mut redis_conn: Option<redis::aio::MultiplexedConnection> = None;
 
-                backoff_seconds = 1;
-                let mut reload_interval = tokio::time::interval(Duration::from_secs(20));
-                reload_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
-                // Consume immediate first tick so the first periodic reload happens after the interval.
-                reload_interval.tick().await;
+    loop {
+        let SinkBatch {
+            buses,
+            received_at_unix_ms,
+        } = collect_sink_batches(&state.sink_queue, flush).await;
+
+        let mut attempt = 1;
+        let mut route_sequences = None;
+        let result = loop {
+            let connection = match redis_conn.as_mut() {
+                Some(connection) => Ok(connection),
+                None => state
+                    .redis_client
+                    .get_multiplexed_async_connection()
+                    .await
+                    .map(|connection| redis_conn.insert(connection))
+                    .map_err(|error| format!("Redis connection failed: {}", error)),
+            };
+            let result = match connection {
+                Ok(connection) => write_batch_to_redis(
+                    connection,
+                    &buses,
+                    &mut route_sequences,
+                    received_at_unix_ms,
+                )
+                .await
+                .map_err(|error| {
+                    // Drop the connection so the retry reconnects instead of reusing a broken one.
+                    redis_conn = None;
+                    format!("Redis write failed: {}", error)
+                }),
+                Err(error) => Err(error),
+            };
+            if result.is_ok() || attempt >= REDIS_WRITE_ATTEMPTS {
+                break result;
+            }
+            attempt += 1;
+            tokio::time::sleep(REDIS_RETRY_DELAY).await;
+        };
 
-                loop {
-                    tokio::select! {
-                        _ = disconnect_notify.notified() => {
-                            break;
-                        }
-                        _ = reload_interval.tick() => {
-                            let payload = json!({
-                                "sid": "",
-                                "uid": "",
-                                "provider": "RKL",
-                                "route": ""
-                            });
-
-                            if let Err(error) = socket.emit("onFts-reload", payload).await {
-                                record_ingestor_error(
-                                    &state,
-                                    format!("Periodic socket reload emit failed: {}", error),
-                                    true,
-                                )
-                                .await;
-                                break;
-                            }
-                        }
-                    }
+        match result {
+            Ok(written_count) => {
+                let routes: HashSet<&str> = buses.iter().map(|bus| bus.route.as_str()).collect();
+                for route in routes {
+                    state.route_versions.bump(route);
                 }
-                drop(socket);
+                let mut status = state.ingestor_status.write().await;
+                status.buses_written += written_count as u64;
+                status.last_error = None;
             }
             Err(error) => {
-                record_ingestor_error(&state, format!("Socket connection failed: {}", error), true)
-                    .await;
-                tokio::time::sleep(Duration::from_secs(backoff_seconds)).await;
-                backoff_seconds = (backoff_seconds * 2).min(30);
+                println!(
+                    "Dropping {} positions after {} attempts: {}",
+                    buses.len(),
+                    REDIS_WRITE_ATTEMPTS,
+                    error
+                );
+                counter!(REDIS_BATCHES_DROPPED_TOTAL).increment(1);
+                let mut status = state.ingestor_status.write().await;
+                status.redis_write_failures += 1;
+                status.last_error = Some(error);
+            }
+        }
+    }
+}
+
+// Waits for one batch, then keeps taking batches until the flush window closes or the row cap
+// is reached. A vehicle reported more than once keeps only its latest position.
+async fn collect_sink_batches(queue: &BoundedQueue<SinkBatch>, flush: RedisFlush) -> SinkBatch {
+    let first = queue.pop().await;
+    let deadline = tokio::time::Instant::now() + flush.interval;
+    let mut received_at_unix_ms = first.received_at_unix_ms;
+    let mut buses = first.buses;
+
+    while buses.len() < flush.max_rows {
+        let Ok(batch) = tokio::time::timeout_at(deadline, queue.pop()).await else {
+            break;
+        };
+        received_at_unix_ms = received_at_unix_ms.max(batch.received_at_unix_ms);
+        buses.extend(batch.buses);
+    }
+
+    let mut latest_index: HashMap<String, usize> = HashMap::new();
+    let mut merged: Vec<BusPosition> = Vec::with_capacity(buses.len());
+    for bus in buses {
+        match latest_index.get(&bus.bus_no) {
+            Some(&index) if !bus.bus_no.is_empty() => merged[index] = bus,
+            _ => {
+                latest_index.insert(bus.bus_no.clone(), merged.len());
+                merged.push(bus);
+            }
+        }
+    }
+
+    SinkBatch {
+        buses: merged,
+        received_at_unix_ms,
+    }
+}
+
+// Shape of each route's first trip, keyed like load_route_colors. Empty without static GTFS.
+fn load_route_shapes() -> HashMap<String, RouteShape> {
+    let (routes, trips_by_route, shapes_by_id) = match (load_routes(), load_trips(), load_shapes())
+    {
+        (Ok(routes), Ok(trips), Ok(shapes)) => (routes, trips, shapes),
+        _ => {
+            println!("Route shapes unavailable, progress and headways disabled");
+            return HashMap::new();
+        }
+    };
+
+    let mut route_shapes = HashMap::new();
+    for route in routes {
+        let Ok(shape) = get_shape_by_route(&route.route_id, &trips_by_route, &shapes_by_id) else {
+            continue;
+        };
+        let points = shape
+            .points
+            .iter()
+            .map(|point| (point.lat, point.lon))
+            .collect();
+        let Some(route_shape) = RouteShape::new(points) else {
+            continue;
+        };
+
+        route_shapes
+            .entry(normalize_route_code(&route.route_short_name))
+            .or_insert_with(|| route_shape.clone());
+        route_shapes.insert(normalize_route_code(&route.route_id), route_shape);
+    }
+    route_shapes
+}
+
+// Estimates delay_min for every bus with a known position along its route shape.
+async fn apply_schedule_delays(
+    state: &AppState,
+    buses: &mut [BusPosition],
+    schedules: &HashMap<String, Vec<ScheduledTrip>>,
+    active_services: &HashMap<String, [bool; 7]>,
+) {
+    if schedules.is_empty() {
+        return;
+    }
+
+    let now_local = Utc::now().with_timezone(&Kuala_Lumpur);
+    let weekday = now_local.weekday().num_days_from_monday() as usize;
+    let now_secs = now_local.num_seconds_from_midnight() as i64;
+    let mut delays = state.delays.write().await;
+
+    for bus in buses.iter_mut() {
+        let (Some(progress_m), Some(trips)) = (
+            bus.progress_m,
+            schedules.get(&normalize_route_code(&bus.route)),
+        ) else {
+            bus.delay_min = None;
+            delays.remove(&bus.bus_no);
+            continue;
+        };
+
+        let running_today = trips.iter().filter(|trip| {
+            active_services
+                .get(&trip.service_id)
+                .is_none_or(|days| days[weekday])
+        });
+        let outcome = match_trip(
+            running_today,
+            bus.direction.direction_id(),
+            progress_m,
+            now_secs,
+            DEFAULT_AMBIGUITY_MARGIN_SECS,
+        );
+        bus.delay_min = match &outcome {
+            MatchOutcome::Matched { estimate } => Some(estimate.delay_min),
+            _ => None,
+        };
+        delays.insert(
+            bus.bus_no.clone(),
+            VehicleDelay {
+                bus_no: bus.bus_no.clone(),
+                route: bus.route.clone(),
+                outcome,
+            },
+        );
+    }
+}
+
+// Stop patterns and start times for every trip, with stops placed along the route shape.
+// Keyed like load_route_shapes; routes without a shape get no schedule.
+fn load_route_schedules(route_shapes: &RouteShapes) -> HashMap<String, Vec<ScheduledTrip>> {
+    let (trips_by_route, stop_times_by_trip, stops_map) =
+        match (load_trips(), load_stop_times(), load_stops()) {
+            (Ok(trips), Ok(stop_times), Ok(stops)) => (trips, stop_times, stops),
+            _ => {
+                println!("Schedules unavailable, delay estimation disabled");
+                return HashMap::new();
+            }
+        };
+    let frequencies_by_trip = load_frequencies().unwrap_or_default();
+
+    let mut schedules: HashMap<String, Vec<ScheduledTrip>> = HashMap::new();
+    for (route_id, trips) in &trips_by_route {
+        let route_key = normalize_route_code(route_id);
+        let Some(shape) = route_shapes.get(route_id) else {
+            continue;
+        };
+
+        for trip in trips {
+            let Some(stop_times) = stop_times_by_trip.get(&trip.trip_id) else {
+                continue;
+            };
+            let mut sorted_stop_times: Vec<&StopTime> = stop_times.iter().collect();
+            sorted_stop_times.sort_by_key(|stop_time| stop_time.stop_sequence);
+            let Some(first_secs) = sorted_stop_times
+                .first()
+                .and_then(|stop_time| parse_gtfs_time(&stop_time.arrival_time))
+            else {
+                continue;
+            };
+
+            let stops: Vec<ScheduledStop> = sorted_stop_times
+                .iter()
+                .filter_map(|stop_time| {
+                    let stop = stops_map.get(&stop_time.stop_id)?;
+                    Some(ScheduledStop {
+                        stop_id: stop.stop_id.clone(),
+                        progress_m: shape.project(stop.stop_lat, stop.stop_lon).progress_meters,
+                        offset_secs: parse_gtfs_time(&stop_time.arrival_time)? - first_secs,
+                    })
+                })
+                .collect();
+
+            // Frequency-based trips are a template repeated every headway_secs.
+            let start_times: Vec<i64> = match frequencies_by_trip.get(&trip.trip_id) {
+                Some(frequencies) => frequencies
+                    .iter()
+                    .filter_map(|frequency| {
+                        let start = parse_gtfs_time(&frequency.start_time)?;
+                        let end = parse_gtfs_time(&frequency.end_time)?;
+                        let step = frequency.headway_secs.max(60) as usize;
+                        Some((start..end).step_by(step))
+                    })
+                    .flatten()
+                    .collect(),
+                None => vec![first_secs],
+            };
+
+            schedules
+                .entry(route_key.clone())
+                .or_default()
+                .push(ScheduledTrip {
+                    trip_id: trip.trip_id.clone(),
+                    service_id: trip.service_id.clone(),
+                    direction_id: trip.direction_id.map(|direction| direction as u8),
+                    stops,
+                    start_times,
+                });
+        }
+    }
+    schedules
+}
+
+fn load_frequencies() -> Result<HashMap<String, Vec<Frequency>>, Box<dyn std::error::Error>> {
+    let path = StdPath::new(GTFS_DATA_PATH).join("frequencies.txt");
+    let file = File::open(path)?;
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(file);
+    let mut frequencies_by_trip: HashMap<String, Vec<Frequency>> = HashMap::new();
+    for result in rdr.deserialize() {
+        let frequency: Frequency = result?;
+        frequencies_by_trip
+            .entry(frequency.trip_id.clone())
+            .or_default()
+            .push(frequency);
+    }
+    Ok(frequencies_by_trip)
+}
+
+// Service days per service_id, Monday first. The published calendar's date range has
+// lapsed while the timetable is still in use, so only the weekday flags are honoured.
+fn load_active_services() -> HashMap<String, [bool; 7]> {
+    let path = StdPath::new(GTFS_DATA_PATH).join("calendar.txt");
+    let Ok(file) = File::open(path) else {
+        return HashMap::new();
+    };
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(file);
+    rdr.deserialize::<Calendar>()
+        .filter_map(Result::ok)
+        .map(|calendar| {
+            let days = [
+                calendar.monday,
+                calendar.tuesday,
+                calendar.wednesday,
+                calendar.thursday,
+                calendar.friday,
+                calendar.saturday,
+                calendar.sunday,
+            ]
+            .map(|flag| flag == 1);
+            (calendar.service_id, days)
+        })
+        .collect()
+}
+
+// First and last stop of each route's first trip, keyed like load_route_colors.
+fn load_route_termini() -> HashMap<String, Vec<Terminal>> {
+    let (routes, trips_by_route, stop_times_by_trip, stops_map) =
+        match (load_routes(), load_trips(), load_stop_times(), load_stops()) {
+            (Ok(routes), Ok(trips), Ok(stop_times), Ok(stops)) => {
+                (routes, trips, stop_times, stops)
+            }
+            _ => {
+                println!("Route termini unavailable, layover detection disabled");
+                return HashMap::new();
             }
+        };
+
+    let mut route_termini = HashMap::new();
+    for route in &routes {
+        let Ok(route_stops) = get_stops_by_route(
+            &route.route_id,
+            &routes,
+            &trips_by_route,
+            &stop_times_by_trip,
+            &stops_map,
+        ) else {
+            continue;
+        };
+        let termini: Vec<Terminal> = [route_stops.stops.first(), route_stops.stops.last()]
+            .into_iter()
+            .flatten()
+            .map(|stop| Terminal {
+                stop_id: stop.stop_id.clone(),
+                lat: stop.stop_lat,
+                lon: stop.stop_lon,
+            })
+            .collect();
+        if termini.is_empty() {
+            continue;
+        }
+
+        route_termini
+            .entry(normalize_route_code(&route.route_short_name))
+            .or_insert_with(|| termini.clone());
+        route_termini.insert(normalize_route_code(&route.route_id), termini);
+    }
+    route_termini
+}
+
+// Colors keyed by normalized route id and short name. Empty when static GTFS is missing,
+// in which case every route falls back to a hashed color.
+fn load_route_colors() -> HashMap<String, (String, String)> {
+    let routes = match load_routes() {
+        Ok(routes) => routes,
+        Err(error) => {
+            println!("Route colors unavailable, using fallback colors: {}", error);
+            return HashMap::new();
         }
+    };
+
+    let mut route_colors = HashMap::new();
+    for route in routes {
+        let (Some(color), Some(text_color)) = (
+            normalize_hex_color(&route.route_color),
+            normalize_hex_color(&route.route_text_color),
+        ) else {
+            continue;
+        };
+
+        route_colors
+            .entry(normalize_route_code(&route.route_short_name))
+            .or_insert_with(|| (color.clone(), text_color.clone()));
+        route_colors.insert(normalize_route_code(&route.route_id), (color, text_color));
     }
+    route_colors
+}
+
+fn apply_route_colors(bus: &mut BusPosition, route_colors: &HashMap<String, (String, String)>) {
+    let (color, text_color) = route_colors
+        .get(&normalize_route_code(&bus.route))
+        .cloned()
+        .unwrap_or_else(|| fallback_route_colors(&bus.route));
+    bus.route_color = Some(color);
+    bus.route_text_color = Some(text_color);
+}
+
+// Repairs swapped fixes, drops the rest of the bad ones, and reports every non-valid check.
+fn filter_valid_coordinates(
+    buses: &mut Vec<BusPosition>,
+    bounds: &BoundingBox,
+) -> Vec<CoordinateCheck> {
+    let mut checks = Vec::new();
+    buses.retain_mut(|bus| {
+        let check = validate_coordinates(bus, bounds);
+        match check {
+            CoordinateCheck::Valid => {}
+            CoordinateCheck::Swapped => {
+                println!(
+                    "Swapped latitude/longitude for bus {} on route {}",
+                    bus.bus_no, bus.route
+                );
+                checks.push(check);
+            }
+            _ => checks.push(check),
+        }
+        check.is_accepted()
+    });
+    checks
+}
+
+// Route sequences are allocated once per batch and kept across retries, so a write that
+// fails after the bump doesn't bump the routes again when it is retried.
+async fn write_batch_to_redis(
+    redis_conn: &mut redis::aio::MultiplexedConnection,
+    buses: &[BusPosition],
+    route_sequences: &mut Option<HashMap<String, i64>>,
+    now_ms: i64,
+) -> Result<usize, String> {
+    let sequences = match route_sequences.take() {
+        Some(sequences) => sequences,
+        None => allocate_route_sequences(redis_conn, buses).await?,
+    };
+    let written = write_buses_to_redis(redis_conn, buses, &sequences, now_ms).await;
+    *route_sequences = Some(sequences);
+    written
 }
 
 async fn write_buses_to_redis(
     redis_conn: &mut redis::aio::MultiplexedConnection,
     buses: &[BusPosition],
+    route_sequences: &HashMap<String, i64>,
     now_ms: i64,
 ) -> Result<usize, String> {
     let mut serialized_entries: Vec<(String, String)> = Vec::new();
@@ -698,7 +3557,7 @@ async fn write_buses_to_redis(
         bus_ids
             .iter()
             .cloned()
-            .zip(raw_states.into_iter())
+            .zip(raw_states)
             .filter_map(|(bus_no, raw_state)| {
                 raw_state.and_then(|value| {
                     serde_json::from_str::<BusMotionState>(&value)
@@ -724,6 +3583,7 @@ async fn write_buses_to_redis(
     }
 
     let mut pipe = redis::pipe();
+    pipe.atomic();
     for (bus_no, bus_json) in &serialized_entries {
         let Some(bus) = valid_buses.get(bus_no) else {
             continue;
@@ -740,6 +3600,13 @@ async fn write_buses_to_redis(
             .arg(bus_no)
             .arg(serde_json::to_string(&motion_state).map_err(|error| error.to_string())?)
             .ignore();
+        if let Some(sequence) = route_sequences.get(&normalize_route_code(&bus.route)) {
+            pipe.cmd("HSET")
+                .arg(REDIS_BUSES_SEQ_KEY)
+                .arg(bus_no)
+                .arg(sequence)
+                .ignore();
+        }
         pipe.cmd("ZADD")
             .arg(REDIS_BUSES_LAST_SEEN_KEY)
             .arg(now_ms)
@@ -759,55 +3626,40 @@ async fn write_buses_to_redis(
     Ok(serialized_entries.len())
 }
 
-fn parse_bus_positions_from_payload(payload: Payload) -> (Vec<BusPosition>, u64) {
-    let mut buses = Vec::new();
-    let mut decode_failures = 0;
-
-    if let Payload::Text(values) = payload {
-        for value in values {
-            let Some(encoded_str) = value.as_str() else {
-                continue;
-            };
-
-            let Some(decoded) = decode_bus_data(encoded_str) else {
-                decode_failures += 1;
-                continue;
-            };
-
-            match parse_bus_positions_from_json(&decoded) {
-                Some(mut parsed_buses) => buses.append(&mut parsed_buses),
-                None => decode_failures += 1,
-            }
-        }
-    }
-
-    (buses, decode_failures)
-}
+// Bumps the sequence of every route present in the batch once, so clients can ask for
+// vehicles updated after the last sequence they saw. The bumps apply together or not at
+// all, so a failed call can simply be retried.
+async fn allocate_route_sequences(
+    redis_conn: &mut redis::aio::MultiplexedConnection,
+    buses: &[BusPosition],
+) -> Result<HashMap<String, i64>, String> {
+    let routes: Vec<String> = buses
+        .iter()
+        .filter(|bus| !bus.bus_no.is_empty())
+        .map(|bus| normalize_route_code(&bus.route))
+        .filter(|route| !route.is_empty())
+        .collect::<HashSet<String>>()
+        .into_iter()
+        .collect();
 
-fn parse_bus_positions_from_json(decoded: &str) -> Option<Vec<BusPosition>> {
-    if let Ok(single_bus) = serde_json::from_str::<BusPosition>(decoded) {
-        return Some(vec![single_bus]);
+    if routes.is_empty() {
+        return Ok(HashMap::new());
     }
 
-    if let Ok(bus_list) = serde_json::from_str::<Vec<BusPosition>>(decoded) {
-        return Some(bus_list);
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for route in &routes {
+        pipe.cmd("HINCRBY")
+            .arg(REDIS_ROUTES_SEQ_KEY)
+            .arg(route)
+            .arg(1);
     }
+    let sequences: Vec<i64> = pipe
+        .query_async(redis_conn)
+        .await
+        .map_err(|error| error.to_string())?;
 
-    let value = serde_json::from_str::<serde_json::Value>(decoded).ok()?;
-    if let serde_json::Value::Array(entries) = value {
-        let buses: Vec<BusPosition> = entries
-            .into_iter()
-            .filter_map(|entry| serde_json::from_value::<BusPosition>(entry).ok())
-            .collect();
-
-        if buses.is_empty() {
-            None
-        } else {
-            Some(buses)
-        }
-    } else {
-        None
-    }
+    Ok(routes.into_iter().zip(sequences).collect())
 }
 
 async fn record_ingestor_error(state: &AppState, message: String, count_reconnect: bool) {
@@ -828,6 +3680,49 @@ fn internal_error(error: impl std::fmt::Display) -> (StatusCode, Json<ErrorRespo
     )
 }
 
+fn bad_request(message: String) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse { error: message }),
+    )
+}
+
+fn parse_bbox_param(
+    bbox: Option<&str>,
+) -> Result<Option<BoundingBox>, (StatusCode, Json<ErrorResponse>)> {
+    bbox.map(|bbox| {
+        BoundingBox::parse(bbox).ok_or_else(|| {
+            bad_request(format!(
+                "bbox `{}` is not min_lon,min_lat,max_lon,max_lat",
+                bbox
+            ))
+        })
+    })
+    .transpose()
+}
+
+fn parse_rfc3339_param(name: &str, value: &str) -> Result<i64, (StatusCode, Json<ErrorResponse>)> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.timestamp_millis())
+        .map_err(|error| bad_request(format!("`{}` is not RFC 3339: {}", name, error)))
+}
+
+// History reads the Parquet archive, so it is only available with ARCHIVE_DIR set.
+fn history_root(state: &AppState) -> Result<std::path::PathBuf, (StatusCode, Json<ErrorResponse>)> {
+    state
+        .archive
+        .as_ref()
+        .map(|archive| archive.root().to_path_buf())
+        .ok_or_else(|| {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ErrorResponse {
+                    error: "History is unavailable: ARCHIVE_DIR is not set".to_string(),
+                }),
+            )
+        })
+}
+
 fn is_t789_route(route: &str) -> bool {
     normalize_route_code(route) == "T789"
 }
@@ -838,22 +3733,23 @@ fn is_bus_on_route(bus_route: &str, route_id: &str) -> bool {
     !bus_base.is_empty() && bus_base == route_base
 }
 
-fn normalize_route_code(route: &str) -> String {
-    route
-        .trim()
-        .to_uppercase()
-        .trim_end_matches('0')
-        .to_string()
-}
-
-fn now_unix_ms() -> i64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|duration| duration.as_millis() as i64)
-        .unwrap_or(0)
+// Also true on a route a reassigned vehicle is still listed on; see RouteAssignments.
+fn is_listed_on_route(
+    bus: &BusPosition,
+    route_id: &str,
+    previous_routes: &HashMap<String, Vec<String>>,
+) -> bool {
+    is_bus_on_route(&bus.route, route_id)
+        || previous_routes
+            .get(&bus.bus_no)
+            .is_some_and(|routes| routes.iter().any(|route| is_bus_on_route(route, route_id)))
 }
 
 // Get buses for route T789 specifically from Redis snapshot
+#[utoipa::path(
+    get, path = "/get-route-t789", tag = "buses",
+    responses((status = 200, description = "Active T789 buses with their resolved stop; a single bus is returned as an object, not an array", body = Vec<RouteBusPositionResponse>), (status = 500, description = "Redis or GTFS data unavailable", body = ErrorResponse))
+)]
 async fn get_route_t789(
     State(state): State<AppState>,
 ) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
@@ -898,6 +3794,10 @@ async fn get_route_t789(
 }
 
 // Calculate ETA for T789 buses from Redis snapshot to reach stop 1000838 (KL1397 FLAT PKNS KERINCHI/KL GATEWAY)
+#[utoipa::path(
+    get, path = "/get-t789-eta", tag = "eta",
+    responses((status = 200, description = "ETA of each T789 bus to stop 1000838", body = Vec<BusEta>), (status = 500, description = "Redis or GTFS data unavailable", body = ErrorResponse))
+)]
 async fn get_t789_eta(
     State(state): State<AppState>,
 ) -> Result<Json<Vec<BusEta>>, (StatusCode, Json<ErrorResponse>)> {
@@ -911,6 +3811,10 @@ async fn get_t789_eta(
 }
 
 // Calculate ETA for all incoming buses to Pantai Hillpark Phase 5 (stop 1008485).
+#[utoipa::path(
+    get, path = "/get-pantai-hillpark-phase-5-eta", tag = "eta",
+    responses((status = 200, description = "ETA of every bus incoming to stop 1008485", body = StopIncomingResponse), (status = 500, description = "Redis or GTFS data unavailable", body = ErrorResponse))
+)]
 async fn get_pantai_hillpark_phase_5_eta(
     State(state): State<AppState>,
 ) -> Result<Json<StopIncomingResponse>, (StatusCode, Json<ErrorResponse>)> {
@@ -961,6 +3865,11 @@ async fn get_pantai_hillpark_phase_5_eta(
 }
 
 // Calculate ETA for buses in route/{route_id} to reach stop/{stop_id}, based on Redis snapshot.
+#[utoipa::path(
+    get, path = "/route/{route_id}/eta/{stop_id}", tag = "eta",
+    params(("route_id" = String, Path, description = "Route code, e.g. T789"), ("stop_id" = String, Path, description = "GTFS stop_id")),
+    responses((status = 200, description = "ETA of each bus on the route to the stop", body = Vec<BusEta>), (status = 404, description = "Unknown route or stop", body = ErrorResponse), (status = 500, description = "Redis or GTFS data unavailable", body = ErrorResponse))
+)]
 async fn get_route_eta(
     Path((route_id, stop_id)): Path<(String, String)>,
     State(state): State<AppState>,
@@ -976,6 +3885,11 @@ async fn get_route_eta(
 }
 
 // Calculate ETA for all routes incoming to /stops/{stop_id}
+#[utoipa::path(
+    get, path = "/stops/{stop_id}/eta", tag = "eta",
+    params(("stop_id" = String, Path, description = "GTFS stop_id")),
+    responses((status = 200, description = "ETA of every incoming bus", body = Vec<BusEta>), (status = 500, description = "Redis or GTFS data unavailable", body = ErrorResponse))
+)]
 async fn get_stop_eta(
     Path(stop_id): Path<String>,
     State(state): State<AppState>,
@@ -992,6 +3906,11 @@ async fn get_stop_eta(
     Ok(Json(all_eta_results))
 }
 
+#[utoipa::path(
+    get, path = "/stops/{stop_id}/routes", tag = "stops",
+    params(("stop_id" = String, Path, description = "GTFS stop_id")),
+    responses((status = 200, description = "Routes serving the stop", body = StopRoutesResponse), (status = 404, description = "Unknown stop", body = ErrorResponse), (status = 500, description = "Redis or GTFS data unavailable", body = ErrorResponse))
+)]
 async fn get_stop_routes(
     Path(stop_id): Path<String>,
 ) -> Result<Json<StopRoutesResponse>, (StatusCode, Json<ErrorResponse>)> {
@@ -1382,44 +4301,28 @@ fn get_routes_for_stop(
         ));
     }
 
-    Ok(stop_routes)
-}
-
-// Decode base64 + gzip compressed data from the websocket
-fn decode_bus_data(encoded: &str) -> Option<String> {
-    let decoded = base64::engine::general_purpose::STANDARD
-        .decode(encoded)
-        .ok()?;
-
-    let mut decoder = GzDecoder::new(&decoded[..]);
-    let mut decompressed = String::new();
-    decoder.read_to_string(&mut decompressed).ok()?;
-
-    Some(decompressed)
+    Ok(stop_routes)
 }
 
 // Calculate haversine distance between two GPS coordinates (returns km)
 fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
-    let r = 6371.0; // Earth radius in km
-    let dlat = (lat2 - lat1).to_radians();
-    let dlon = (lon2 - lon1).to_radians();
-    let a = (dlat / 2.0).sin().powi(2)
-        + lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.0).sin().powi(2);
-    let c = 2.0 * a.sqrt().asin();
-    r * c
+    haversine_meters((lat1, lon1), (lat2, lon2)) / 1_000.0
 }
 
 // Data OpenDOSM Prasarana - uses protobuf (alternative data source)
-#[allow(dead_code)]
-async fn prasarana_gtfs_data() -> Json<gtfs_realtime::FeedMessage> {
-    let endpoint =
-        "https://api.data.gov.my/gtfs-realtime/vehicle-position/prasarana?category=rapid-bus-kl";
-    let response = reqwest::get(endpoint).await.unwrap();
-    let body = response.bytes().await.unwrap();
-    let feed = gtfs_realtime::FeedMessage::decode(body).unwrap();
+#[utoipa::path(
+    get, path = "/gtfs", tag = "buses",
+    responses((status = 200, description = "The Prasarana GTFS-realtime FeedMessage, converted to JSON", content_type = "application/json"), (status = 500, description = "Feed unavailable", body = ErrorResponse))
+)]
+async fn prasarana_gtfs_data(
+    State(state): State<AppState>,
+) -> Result<Json<gtfs_realtime::FeedMessage>, (StatusCode, Json<ErrorResponse>)> {
+    let feed = fetch_feed(&state.gtfs_http, PRASARANA_VEHICLE_POSITIONS_URL)
+        .await
+        .map_err(internal_error)?;
 
     println!("Calling prasarana_gtfs_data");
-    Json(feed)
+    Ok(Json(feed))
 }
 
 // GTFS data loading functions
@@ -1605,6 +4508,11 @@ fn get_shape_by_route(
 }
 
 // Axum handler for /route/:route_id/stops
+#[utoipa::path(
+    get, path = "/route/{route_id}/stops", tag = "routes",
+    params(("route_id" = String, Path, description = "Route code, e.g. T789")),
+    responses((status = 200, description = "Stops in sequence", body = RouteStopsResponse), (status = 404, description = "Unknown route", body = ErrorResponse), (status = 500, description = "Redis or GTFS data unavailable", body = ErrorResponse))
+)]
 async fn get_route_stops(
     Path(route_id): Path<String>,
 ) -> Result<Json<RouteStopsResponse>, (StatusCode, Json<ErrorResponse>)> {
@@ -1672,6 +4580,11 @@ async fn get_route_stops(
     }
 }
 
+#[utoipa::path(
+    get, path = "/route/{route_id}/shape", tag = "routes",
+    params(("route_id" = String, Path, description = "Route code, e.g. T789")),
+    responses((status = 200, description = "Route polyline", body = RouteShapeResponse), (status = 404, description = "Unknown route", body = ErrorResponse), (status = 500, description = "Redis or GTFS data unavailable", body = ErrorResponse))
+)]
 async fn get_route_shape(
     Path(route_id): Path<String>,
 ) -> Result<Json<RouteShapeResponse>, (StatusCode, Json<ErrorResponse>)> {
@@ -1708,7 +4621,70 @@ async fn get_route_shape(
     }
 }
 
+// Set to "unavailable" on /stops when no static GTFS is loaded and the list is empty
+// for that reason rather than because the bbox holds no stops.
+const GTFS_STATUS_HEADER: &str = "x-gtfs-status";
+
+fn load_stop_index() -> Option<Arc<StopIndex>> {
+    match load_stops() {
+        Ok(stops_map) => {
+            let index = StopIndex::new(
+                stops_map
+                    .into_values()
+                    .map(|stop| StopLocation {
+                        stop_id: stop.stop_id,
+                        name: stop.stop_name,
+                        lat: stop.stop_lat,
+                        lon: stop.stop_lon,
+                    })
+                    .collect(),
+            );
+            println!("Indexed {} GTFS stops for /stops", index.len());
+            Some(Arc::new(index))
+        }
+        Err(error) => {
+            println!("GTFS stops unavailable, /stops will be empty: {}", error);
+            None
+        }
+    }
+}
+
+// Axum handler for /stops?bbox={min_lon,min_lat,max_lon,max_lat}
+#[utoipa::path(
+    get, path = "/stops", tag = "stops",
+    params(StopsQuery),
+    responses((status = 200, description = "Stops inside the bbox, ordered by stop_id; empty with x-gtfs-status: unavailable when no static GTFS is loaded", body = Vec<StopLocation>), (status = 400, description = "Invalid bbox", body = ErrorResponse))
+)]
+async fn get_stops(
+    Query(query): Query<StopsQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let bbox = parse_bbox_param(query.bbox.as_deref())?;
+    let Some(index) = &state.stops else {
+        return Ok((
+            [(GTFS_STATUS_HEADER, "unavailable")],
+            Json(Vec::<StopLocation>::new()),
+        )
+            .into_response());
+    };
+    let stops: Vec<&StopLocation> = match &bbox {
+        Some(bbox) => index.within(bbox),
+        None => index.all().iter().collect(),
+    };
+    println!(
+        "Calling get_stops for bbox={:?}: {} stops",
+        query.bbox,
+        stops.len()
+    );
+    Ok(Json(stops).into_response())
+}
+
 // Axum handler for /stops/nearest?lat={lat}&lon={lon}
+#[utoipa::path(
+    get, path = "/stops/nearest", tag = "stops",
+    params(NearestStopQuery),
+    responses((status = 200, description = "Closest stop", body = NearestStopResponse), (status = 400, description = "Invalid coordinates", body = ErrorResponse), (status = 404, description = "No stops loaded", body = ErrorResponse))
+)]
 async fn get_nearest_stop(
     Query(query): Query<NearestStopQuery>,
 ) -> Result<Json<NearestStopResponse>, (StatusCode, Json<ErrorResponse>)> {
@@ -1768,3 +4744,760 @@ async fn get_nearest_stop(
     );
     Ok(Json(response))
 }
+
+// Axum handler for /buses/nearest?lat={lat}&lon={lon}&limit={limit}&route={route}
+#[utoipa::path(
+    get, path = "/buses/nearest", tag = "buses",
+    params(NearestBusQuery),
+    responses((status = 200, description = "Buses ordered by distance", body = Vec<NearestBusResponse>), (status = 400, description = "Invalid coordinates", body = ErrorResponse), (status = 500, description = "Redis or GTFS data unavailable", body = ErrorResponse))
+)]
+async fn get_nearest_buses(
+    Query(query): Query<NearestBusQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<NearestBusResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    if !(-90.0..=90.0).contains(&query.lat) || !(-180.0..=180.0).contains(&query.lon) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Invalid latitude/longitude values".to_string(),
+            }),
+        ));
+    }
+
+    let snapshot = load_active_bus_snapshot(&state).await?;
+    let route = query
+        .route
+        .as_deref()
+        .filter(|route| !route.trim().is_empty());
+    let limit = query.limit.unwrap_or(DEFAULT_NEAREST_BUS_LIMIT);
+
+    let mut nearest_buses: Vec<NearestBusResponse> = snapshot
+        .buses
+        .into_iter()
+        .filter(|bus| route.is_none_or(|route| is_bus_on_route(&bus.route, route)))
+        .map(|bus| {
+            let distance_km = haversine_distance(query.lat, query.lon, bus.latitude, bus.longitude);
+            NearestBusResponse {
+                bus,
+                distance_meters: (distance_km * 1000.0 * 10.0).round() / 10.0,
+            }
+        })
+        .collect();
+
+    nearest_buses.sort_by(|a, b| {
+        a.distance_meters
+            .partial_cmp(&b.distance_meters)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    nearest_buses.truncate(limit);
+
+    println!(
+        "Calling get_nearest_buses for lat={}, lon={}, route={:?}: {} buses",
+        query.lat,
+        query.lon,
+        route,
+        nearest_buses.len()
+    );
+    Ok(Json(nearest_buses))
+}
+
+// Axum handler for /routes/{route_id}/vehicles?since_seq={seq}, answering 304 when the
+// client's If-None-Match still matches the route snapshot.
+#[utoipa::path(
+    get, path = "/routes/{route_id}/vehicles", tag = "buses",
+    params(("route_id" = String, Path, description = "Route code, e.g. T789"), RouteVehiclesQuery),
+    responses((status = 200, description = "Vehicles changed since `since_seq`", body = RouteVehiclesResponse), (status = 304, description = "If-None-Match still matches"), (status = 500, description = "Redis or GTFS data unavailable", body = ErrorResponse))
+)]
+async fn get_route_vehicles(
+    Path(route_id): Path<String>,
+    Query(query): Query<RouteVehiclesQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let snapshot = load_active_bus_snapshot(&state).await?;
+    let previous_routes = state.previous_routes.read().await.clone();
+    let route_buses: Vec<BusPosition> = snapshot
+        .buses
+        .into_iter()
+        .filter(|bus| is_listed_on_route(bus, &route_id, &previous_routes))
+        .collect();
+    let bus_ids: Vec<&str> = route_buses.iter().map(|bus| bus.bus_no.as_str()).collect();
+
+    let mut redis_conn = state
+        .redis_client
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(internal_error)?;
+    let route_seq: Option<i64> = redis::cmd("HGET")
+        .arg(REDIS_ROUTES_SEQ_KEY)
+        .arg(normalize_route_code(&route_id))
+        .query_async(&mut redis_conn)
+        .await
+        .map_err(internal_error)?;
+    let bus_seqs: Vec<Option<i64>> = if bus_ids.is_empty() {
+        Vec::new()
+    } else {
+        redis::cmd("HMGET")
+            .arg(REDIS_BUSES_SEQ_KEY)
+            .arg(&bus_ids)
+            .query_async(&mut redis_conn)
+            .await
+            .map_err(internal_error)?
+    };
+
+    let since_seq = query.since_seq.unwrap_or(0);
+    let mut vehicles: Vec<RouteVehicle> = route_buses
+        .into_iter()
+        .zip(bus_seqs)
+        .map(|(bus, seq)| RouteVehicle {
+            bus,
+            seq: seq.unwrap_or(0),
+        })
+        .filter(|vehicle| vehicle.seq > since_seq)
+        .collect();
+    vehicles.sort_by(|a, b| a.seq.cmp(&b.seq).then(a.bus.bus_no.cmp(&b.bus.bus_no)));
+
+    // Tagged like /buses/{route_id}: the route's version, plus what identifies this page of
+    // the snapshot rather than the body, since age_seconds changes on every request even
+    // when no vehicle has moved.
+    let mut hasher = DefaultHasher::new();
+    since_seq.hash(&mut hasher);
+    for vehicle in &vehicles {
+        vehicle.bus.bus_no.hash(&mut hasher);
+        vehicle.seq.hash(&mut hasher);
+    }
+    let etag = state.route_versions.etag(&route_id, hasher.finish());
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| if_none_match(value, &etag))
+    {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    println!(
+        "Calling get_route_vehicles for route_id={}, since_seq={}: {} vehicles",
+        route_id,
+        since_seq,
+        vehicles.len()
+    );
+    Ok((
+        [(header::ETAG, etag)],
+        Json(RouteVehiclesResponse {
+            route: route_id,
+            seq: route_seq.unwrap_or(0),
+            data: vehicles,
+        }),
+    )
+        .into_response())
+}
+
+// Axum handler for /buses/{route_id}. Vehicles stay listed (flagged stale) until
+// BUS_TTL_SECONDS evicts them, so the frontend can fade them out; ?snap=true adds
+// road-aligned coordinates for buses within the off-route tolerance of the route shape,
+// and ?proj=mercator adds EPSG:3857 x/y (metres) next to lat/lon.
+#[utoipa::path(
+    get, path = "/buses/{route_id}", tag = "buses",
+    params(("route_id" = String, Path, description = "Route code, e.g. T789"), RouteBusesQuery),
+    responses((status = 200, description = "Buses on the route, stale ones flagged", body = Vec<TrackedBusResponse>), (status = 304, description = "If-None-Match still matches"), (status = 500, description = "Redis or GTFS data unavailable", body = ErrorResponse))
+)]
+async fn get_route_buses(
+    Path(route_id): Path<String>,
+    Query(query): Query<RouteBusesQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let snapshot = load_active_bus_snapshot(&state).await?;
+    let now_ms = now_unix_ms();
+    let previous_routes = state.previous_routes.read().await.clone();
+    let buses: Vec<TrackedBusResponse> = snapshot
+        .buses
+        .into_iter()
+        .filter(|bus| is_listed_on_route(bus, &route_id, &previous_routes))
+        .map(|bus| {
+            let last_seen_ms = snapshot
+                .last_seen_unix_ms
+                .get(&bus.bus_no)
+                .copied()
+                .unwrap_or(now_ms);
+            let age_ms = (now_ms - last_seen_ms).max(0);
+            let snapped = query.snap.then(|| state.route_shapes.snap(&bus)).flatten();
+            let mercator = query.proj == Projection::Mercator;
+            let projected = mercator.then(|| web_mercator((bus.latitude, bus.longitude)));
+            let snapped_projected = snapped.filter(|_| mercator).map(web_mercator);
+            TrackedBusResponse {
+                stale: age_ms > state.vehicle_stale_after_ms,
+                last_seen_age_seconds: age_ms / 1_000,
+                snapped_latitude: snapped.map(|(lat, _)| lat),
+                snapped_longitude: snapped.map(|(_, lon)| lon),
+                x: projected.map(|(x, _)| x),
+                y: projected.map(|(_, y)| y),
+                snapped_x: snapped_projected.map(|(x, _)| x),
+                snapped_y: snapped_projected.map(|(_, y)| y),
+                bus,
+            }
+        })
+        .collect();
+
+    // The version only moves when positions are written, so which vehicles are listed and
+    // which have gone stale since goes into the tag as well; ages alone don't.
+    let mut hasher = DefaultHasher::new();
+    query.snap.hash(&mut hasher);
+    (query.proj == Projection::Mercator).hash(&mut hasher);
+    for bus in &buses {
+        bus.bus.bus_no.hash(&mut hasher);
+        bus.stale.hash(&mut hasher);
+    }
+    let etag = state.route_versions.etag(&route_id, hasher.finish());
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| if_none_match(value, &etag))
+    {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    println!(
+        "Calling get_route_buses for route_id={}: {} buses",
+        route_id,
+        buses.len()
+    );
+    Ok(([(header::ETAG, etag)], Json(buses)).into_response())
+}
+
+// Axum handler for /buses: every tracked route's buses in one response, so a city-wide
+// dashboard doesn't poll each route. Unlike /buses/{route_id} there are no stale flags or
+// snapping; ?fields= keeps the response small.
+#[utoipa::path(
+    get, path = "/buses", tag = "buses",
+    params(BulkBusesQuery),
+    responses((status = 200, description = "Buses by route code, or one FeatureCollection (application/geo+json) with format=geojson", body = BTreeMap<String, Vec<BusPosition>>), (status = 400, description = "Invalid bbox or fields", body = ErrorResponse), (status = 500, description = "Redis or GTFS data unavailable", body = ErrorResponse))
+)]
+async fn get_all_route_buses(
+    Query(query): Query<BulkBusesQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let bbox = parse_bbox_param(query.bbox.as_deref())?;
+    let fields = query
+        .fields
+        .as_deref()
+        .map(FieldSelection::parse)
+        .transpose()
+        .map_err(|error| bad_request(format!("Invalid fields: {}", error)))?;
+
+    let snapshot = load_active_bus_snapshot(&state).await?;
+    let previous_routes = state.previous_routes.read().await.clone();
+    let groups = group_by_route(
+        snapshot.buses,
+        &state.routes.routes(),
+        &previous_routes,
+        bbox.as_ref(),
+        normalize_route_code,
+    );
+
+    println!(
+        "Calling get_all_route_buses for format={:?}: {} routes, {} buses",
+        query.format,
+        groups.len(),
+        groups.values().map(Vec::len).sum::<usize>()
+    );
+    Ok(match query.format {
+        BulkFormat::Json => Json(route_map(&groups, fields.as_ref())).into_response(),
+        BulkFormat::Geojson => (
+            [(header::CONTENT_TYPE, "application/geo+json")],
+            Json(feature_collection(&groups, fields.as_ref())),
+        )
+            .into_response(),
+    })
+}
+
+// Axum handler for /buses/{route_id}/count. Unknown routes report zero counts, not 404.
+#[utoipa::path(
+    get, path = "/buses/{route_id}/count", tag = "buses",
+    params(("route_id" = String, Path, description = "Route code, e.g. T789")),
+    responses((status = 200, description = "Active and stale bus counts", body = RouteBusCountResponse), (status = 500, description = "Redis or GTFS data unavailable", body = ErrorResponse))
+)]
+async fn get_route_bus_count(
+    Path(route_id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let snapshot = load_active_bus_snapshot(&state).await?;
+    let now_ms = now_unix_ms();
+    let previous_routes = state.previous_routes.read().await.clone();
+    let (stale, active): (Vec<&BusPosition>, Vec<&BusPosition>) = snapshot
+        .buses
+        .iter()
+        .filter(|bus| is_listed_on_route(bus, &route_id, &previous_routes))
+        .partition(|bus| {
+            let last_seen_ms = snapshot
+                .last_seen_unix_ms
+                .get(&bus.bus_no)
+                .copied()
+                .unwrap_or(now_ms);
+            now_ms - last_seen_ms > state.vehicle_stale_after_ms
+        });
+
+    println!(
+        "Calling get_route_bus_count for route_id={}: {} active, {} stale",
+        route_id,
+        active.len(),
+        stale.len()
+    );
+    Ok((
+        [(header::CACHE_CONTROL, "public, max-age=5")],
+        Json(RouteBusCountResponse {
+            route: route_id,
+            active: active.len(),
+            stale: stale.len(),
+        }),
+    )
+        .into_response())
+}
+
+fn sorted_headways<'a>(summaries: impl Iterator<Item = &'a HeadwaySummary>) -> Vec<HeadwaySummary> {
+    let mut summaries: Vec<HeadwaySummary> = summaries.cloned().collect();
+    summaries.sort_by(|a, b| (&a.route, a.direction).cmp(&(&b.route, b.direction)));
+    summaries
+}
+
+// Axum handler for /routes/{route_id}/headways
+#[utoipa::path(
+    get, path = "/routes/{route_id}/headways", tag = "routes",
+    params(("route_id" = String, Path, description = "Route code, e.g. T789")),
+    responses((status = 200, description = "Gaps between consecutive buses per direction", body = Vec<HeadwaySummary>))
+)]
+async fn get_route_headways(
+    Path(route_id): Path<String>,
+    State(state): State<AppState>,
+) -> Json<Vec<HeadwaySummary>> {
+    let headways = sorted_headways(
+        state
+            .headways
+            .read()
+            .await
+            .summaries()
+            .filter(|summary| is_bus_on_route(&summary.route, &route_id)),
+    );
+
+    println!(
+        "Calling get_route_headways for route_id={}: {} directions",
+        route_id,
+        headways.len()
+    );
+    Json(headways)
+}
+
+// Axum handler for /routes/{route_id}/delays
+#[utoipa::path(
+    get, path = "/routes/{route_id}/delays", tag = "routes",
+    params(("route_id" = String, Path, description = "Route code, e.g. T789")),
+    responses((status = 200, description = "Schedule delay per bus", body = Vec<VehicleDelay>))
+)]
+async fn get_route_delays(
+    Path(route_id): Path<String>,
+    State(state): State<AppState>,
+) -> Json<Vec<VehicleDelay>> {
+    let mut delays: Vec<VehicleDelay> = state
+        .delays
+        .read()
+        .await
+        .values()
+        .filter(|delay| is_bus_on_route(&delay.route, &route_id))
+        .cloned()
+        .collect();
+    delays.sort_by(|a, b| a.bus_no.cmp(&b.bus_no));
+
+    println!(
+        "Calling get_route_delays for route_id={}: {} buses",
+        route_id,
+        delays.len()
+    );
+    Json(delays)
+}
+
+// Axum handler for /debug/sessions (only with --debug-endpoints)
+#[utoipa::path(
+    get, path = "/debug/sessions", tag = "debug",
+    responses((status = 200, description = "Kiosk session of each socket client; only served with --debug-endpoints", body = Vec<SessionInfo>))
+)]
+async fn get_debug_sessions(State(state): State<AppState>) -> Json<Vec<SessionInfo>> {
+    let mut sessions: Vec<SessionInfo> = state
+        .socket_clients
+        .read()
+        .await
+        .values()
+        .map(RapidbroClient::session_info)
+        .collect();
+    sessions.sort_by(|a, b| a.route.cmp(&b.route));
+    Json(sessions)
+}
+
+// Axum handler for /debug/parse-failures (only with --debug-endpoints)
+#[utoipa::path(
+    get, path = "/debug/parse-failures", tag = "debug",
+    responses((status = 200, description = "Recent payloads that failed to decode, newest first; only served with --debug-endpoints", body = Vec<RecordedFailure>))
+)]
+async fn get_debug_parse_failures(State(state): State<AppState>) -> Json<Vec<RecordedFailure>> {
+    Json(state.parse_failures.recent())
+}
+
+// Axum handler for GET /control/routes
+#[utoipa::path(
+    get, path = "/control/routes", tag = "control",
+    responses((status = 200, description = "Current route subscriptions", body = RouteSubscriptionsResponse))
+)]
+async fn get_control_routes(State(state): State<AppState>) -> Json<RouteSubscriptionsResponse> {
+    Json(RouteSubscriptionsResponse {
+        routes: state.routes.routes(),
+        added: Vec::new(),
+        removed: Vec::new(),
+    })
+}
+
+// Axum handler for POST /control/routes: adds and removes subscriptions without a
+// restart. Nothing is applied if any route id is invalid or the result would need more
+// than --max-connections connections.
+#[utoipa::path(
+    post, path = "/control/routes", tag = "control",
+    request_body = RouteChangeRequest,
+    responses((status = 200, description = "Subscriptions after the change", body = RouteSubscriptionsResponse), (status = 400, description = "Invalid route id, or over --max-connections", body = ErrorResponse))
+)]
+async fn post_control_routes(
+    State(state): State<AppState>,
+    Json(request): Json<RouteChangeRequest>,
+) -> Result<Json<RouteSubscriptionsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let normalize = |routes: Vec<String>| -> Vec<String> {
+        routes
+            .into_iter()
+            .map(|route| route.trim().to_uppercase())
+            .collect()
+    };
+    let (add, remove) = (normalize(request.add), normalize(request.remove));
+    let invalid: Vec<&str> = add
+        .iter()
+        .chain(&remove)
+        .filter(|route| !is_valid_route_id(route))
+        .map(String::as_str)
+        .collect();
+    if !invalid.is_empty() {
+        return Err(bad_request(format!(
+            "Invalid route ids: {}",
+            invalid.join(",")
+        )));
+    }
+
+    if let Some(max) = state.routes.max_routes() {
+        let mut after: HashSet<String> = state.routes.routes().into_iter().collect();
+        for route in &remove {
+            after.remove(route);
+        }
+        after.extend(add.iter().cloned());
+        if after.len() > max {
+            return Err(bad_request(format!(
+                "The change would subscribe to {} routes; --max-connections is {}",
+                after.len(),
+                max
+            )));
+        }
+    }
+
+    let removed: Vec<String> = remove
+        .into_iter()
+        .filter(|route| state.routes.remove(route))
+        .collect();
+    let added: Vec<String> = add
+        .into_iter()
+        .filter(|route| state.routes.add(route))
+        .collect();
+
+    println!(
+        "Calling post_control_routes: added [{}], removed [{}]",
+        added.join(","),
+        removed.join(",")
+    );
+    Ok(Json(RouteSubscriptionsResponse {
+        routes: state.routes.routes(),
+        added,
+        removed,
+    }))
+}
+
+// Axum handler for /history/vehicles/{vehicle_id}?from=&to=&cursor=&limit=, paging
+// through the archived track in time order. Archive reads run on the blocking pool.
+#[utoipa::path(
+    get, path = "/history/vehicles/{vehicle_id}", tag = "history",
+    params(("vehicle_id" = String, Path, description = "Vehicle registration, e.g. WXX1234"), VehicleHistoryQuery),
+    responses((status = 200, description = "One page of the vehicle's stored track", body = VehicleHistoryResponse), (status = 400, description = "Invalid timestamps", body = ErrorResponse), (status = 503, description = "ARCHIVE_DIR not set", body = ErrorResponse))
+)]
+async fn get_vehicle_history(
+    Path(vehicle_id): Path<String>,
+    Query(query): Query<VehicleHistoryQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<VehicleHistoryResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let root = history_root(&state)?;
+    let from_ms = parse_rfc3339_param("from", &query.from)?;
+    let to_ms = parse_rfc3339_param("to", &query.to)?;
+    if from_ms > to_ms {
+        return Err(bad_request("`from` is after `to`".to_string()));
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_HISTORY_PAGE_SIZE)
+        .clamp(1, MAX_HISTORY_PAGE_SIZE);
+
+    let track_query = TrackQuery {
+        vehicle: vehicle_id.to_uppercase(),
+        from_ms: query
+            .cursor
+            .map_or(from_ms, |cursor| from_ms.max(cursor + 1)),
+        to_ms,
+        include_invalid: false,
+    };
+    let (points, more) =
+        tokio::task::spawn_blocking(move || query_track_page(&root, &track_query, limit))
+            .await
+            .map_err(internal_error)?
+            .map_err(internal_error)?;
+    let next_cursor = more.then(|| points[limit - 1].time_ms);
+
+    println!(
+        "Calling get_vehicle_history for vehicle_id={}: {} points",
+        vehicle_id,
+        points.len()
+    );
+    Ok(Json(VehicleHistoryResponse {
+        vehicle: vehicle_id.to_uppercase(),
+        points,
+        next_cursor,
+    }))
+}
+
+// Axum handler for /vehicles/{vehicle_id}/gaps?from=&to=: the vehicle's logged tracking
+// gaps, oldest first. The gap log sits in ARCHIVE_DIR, so this needs it set as history does.
+#[utoipa::path(
+    get, path = "/vehicles/{vehicle_id}/gaps", tag = "history",
+    params(("vehicle_id" = String, Path, description = "Vehicle registration, e.g. WXX1234"), VehicleGapsQuery),
+    responses((status = 200, description = "Gaps in the vehicle's tracking", body = VehicleGapsResponse), (status = 400, description = "Invalid timestamps", body = ErrorResponse), (status = 503, description = "ARCHIVE_DIR not set", body = ErrorResponse))
+)]
+async fn get_vehicle_gaps(
+    Path(vehicle_id): Path<String>,
+    Query(query): Query<VehicleGapsQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<VehicleGapsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let gap_log = GapLog::new(&history_root(&state)?);
+    let from_ms = match &query.from {
+        Some(from) => parse_rfc3339_param("from", from)?,
+        None => i64::MIN,
+    };
+    let to_ms = match &query.to {
+        Some(to) => parse_rfc3339_param("to", to)?,
+        None => i64::MAX,
+    };
+    if from_ms > to_ms {
+        return Err(bad_request("`from` is after `to`".to_string()));
+    }
+
+    let vehicle = vehicle_id.to_uppercase();
+    let query_vehicle = vehicle.clone();
+    let gaps = tokio::task::spawn_blocking(move || gap_log.query(&query_vehicle, from_ms, to_ms))
+        .await
+        .map_err(internal_error)?
+        .map_err(internal_error)?;
+
+    println!(
+        "Calling get_vehicle_gaps for vehicle_id={}: {} gaps",
+        vehicle_id,
+        gaps.len()
+    );
+    Ok(Json(VehicleGapsResponse { vehicle, gaps }))
+}
+
+// Axum handler for /history/routes/{route_id}/active?at=&cursor=&limit=: vehicles with
+// a stored point within VEHICLE_STALE_AFTER_SECONDS before `at`, paged by vehicle id.
+#[utoipa::path(
+    get, path = "/history/routes/{route_id}/active", tag = "history",
+    params(("route_id" = String, Path, description = "Route code, e.g. T789"), ActiveVehiclesQuery),
+    responses((status = 200, description = "One page of vehicles active at `at`", body = ActiveVehiclesResponse), (status = 400, description = "Invalid timestamp", body = ErrorResponse), (status = 503, description = "ARCHIVE_DIR not set", body = ErrorResponse))
+)]
+async fn get_active_vehicles(
+    Path(route_id): Path<String>,
+    Query(query): Query<ActiveVehiclesQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<ActiveVehiclesResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let root = history_root(&state)?;
+    let at_ms = parse_rfc3339_param("at", &query.at)?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_HISTORY_PAGE_SIZE)
+        .clamp(1, MAX_HISTORY_PAGE_SIZE);
+
+    let route = route_id.clone();
+    let window_ms = state.vehicle_stale_after_ms;
+    let mut vehicles = tokio::task::spawn_blocking(move || {
+        query_active(&root, &route, at_ms, window_ms, normalize_route_code)
+    })
+    .await
+    .map_err(internal_error)?
+    .map_err(internal_error)?;
+    if let Some(cursor) = &query.cursor {
+        vehicles.retain(|vehicle| vehicle.vehicle_id.as_str() > cursor.as_str());
+    }
+    let next_cursor = (vehicles.len() > limit).then(|| vehicles[limit - 1].vehicle_id.clone());
+    vehicles.truncate(limit);
+
+    println!(
+        "Calling get_active_vehicles for route_id={} at={}: {} vehicles",
+        route_id,
+        query.at,
+        vehicles.len()
+    );
+    Ok(Json(ActiveVehiclesResponse {
+        route: route_id,
+        at: query.at,
+        vehicles,
+        next_cursor,
+    }))
+}
+
+// Axum handler for /routes/{route_id}/stream: server-sent `buses` events carrying each
+// batch of updates for this route only. A subscriber that falls behind skips the frames
+// it missed rather than being disconnected. With --redis-snapshot-ttl the route's Redis
+// snapshot goes out first, so a freshly started instance isn't silent until its first batch.
+// Events are text, so MessagePack is only offered on /routes/{route_id}/ws.
+#[utoipa::path(
+    get, path = "/routes/{route_id}/stream", tag = "routes",
+    params(("route_id" = String, Path, description = "Route code, e.g. T789"), StreamQuery),
+    responses((status = 200, description = "text/event-stream of `buses` events, each a JSON array", body = Vec<BusPosition>, content_type = "text/event-stream"), (status = 400, description = "format=msgpack, which SSE can't carry", body = ErrorResponse))
+)]
+async fn get_route_stream(
+    Path(route_id): Path<String>,
+    Query(query): Query<StreamQuery>,
+    State(state): State<AppState>,
+) -> Result<
+    Sse<impl futures_util::Stream<Item = Result<Event, Infallible>>>,
+    (StatusCode, Json<ErrorResponse>),
+> {
+    // An EventSource always sends Accept: text/event-stream, so only ?format= counts here.
+    if FrameFormat::negotiate(query.format.as_deref(), None).map_err(bad_request)?
+        == FrameFormat::MessagePack
+    {
+        return Err(bad_request(format!(
+            "server-sent events are text; use /routes/{}/ws for MessagePack frames",
+            route_id
+        )));
+    }
+    println!("Calling get_route_stream for route_id={}", route_id);
+    let batches = route_batches(state, route_id).await;
+    let events = batches.map(|buses| Ok(buses_event(&buses)));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+// Axum handler for /routes/{route_id}/ws: the same batches as /routes/{route_id}/stream
+// over a WebSocket, one message per batch. JSON arrays go out as text messages; with
+// `Accept: application/msgpack` or ?format=msgpack each batch is a binary MessagePack
+// array of the same positions instead, about 12% smaller than the JSON.
+#[utoipa::path(
+    get, path = "/routes/{route_id}/ws", tag = "routes",
+    params(("route_id" = String, Path, description = "Route code, e.g. T789"), StreamQuery),
+    responses((status = 101, description = "WebSocket of position batches: JSON text messages, or binary application/msgpack ones", body = Vec<BusPosition>), (status = 400, description = "Unknown format", body = ErrorResponse))
+)]
+async fn get_route_ws(
+    Path(route_id): Path<String>,
+    Query(query): Query<StreamQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok());
+    let format = FrameFormat::negotiate(query.format.as_deref(), accept).map_err(bad_request)?;
+    println!(
+        "Calling get_route_ws for route_id={} format={:?}",
+        route_id, format
+    );
+    let batches = route_batches(state, route_id).await;
+    Ok(upgrade.on_upgrade(move |socket| send_route_batches(socket, batches, format)))
+}
+
+async fn send_route_batches(
+    mut socket: WebSocket,
+    batches: impl futures_util::Stream<Item = Vec<BusPosition>>,
+    format: FrameFormat,
+) {
+    let mut batches = std::pin::pin!(batches);
+    while let Some(buses) = batches.next().await {
+        let message = match format {
+            FrameFormat::Json => serde_json::to_string(&buses)
+                .map(|json| Message::Text(json.into()))
+                .map_err(|error| error.to_string()),
+            FrameFormat::MessagePack => to_msgpack(&buses)
+                .map(|bytes| Message::Binary(bytes.into()))
+                .map_err(|error| error.to_string()),
+        };
+        let message = match message {
+            Ok(message) => message,
+            Err(error) => {
+                println!("Skipping a batch the WebSocket couldn't encode: {}", error);
+                continue;
+            }
+        };
+        // The client went away.
+        if socket.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+// The route's Redis snapshot (with --redis-snapshot-ttl), then every batch published for
+// it, dropping positions past --max-position-age. Lagging subscribers skip what they missed.
+async fn route_batches(
+    state: AppState,
+    route_id: String,
+) -> impl futures_util::Stream<Item = Vec<BusPosition>> {
+    let route = normalize_route_code(&route_id);
+    let receiver = state.channels.subscribe(&route);
+    let snapshot = match &state.redis_pubsub {
+        Some(redis_pubsub) => redis_pubsub
+            .read_snapshot(&route)
+            .await
+            .unwrap_or_else(|error| {
+                println!("Skipping snapshot for route {}: {}", route, error);
+                None
+            }),
+        None => None,
+    };
+    let max_age_seconds = state.max_position_age_seconds;
+    let frames = stream::unfold(receiver, move |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(mut buses) => {
+                    if let Some(max_age_seconds) = max_age_seconds {
+                        if retain_recent(&mut buses, max_age_seconds, now_unix_ms()) > 0
+                            && buses.is_empty()
+                        {
+                            continue;
+                        }
+                    }
+                    return Some((buses, receiver));
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    let snapshot = snapshot.map(|mut buses| {
+        if let Some(max_age_seconds) = max_age_seconds {
+            retain_recent(&mut buses, max_age_seconds, now_unix_ms());
+        }
+        buses
+    });
+    stream::iter(snapshot).chain(frames)
+}
+
+fn buses_event(buses: &[BusPosition]) -> Event {
+    Event::default()
+        .event("buses")
+        .json_data(buses)
+        .unwrap_or_else(|_| Event::default().event("buses").data("[]"))
+}
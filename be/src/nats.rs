@@ -0,0 +1,163 @@
+use crate::feed::BusPosition;
+use crate::sink::{Sink, SinkError};
+use crate::updates::BusUpdate;
+use async_nats::jetstream;
+use async_trait::async_trait;
+use metrics::counter;
+use std::time::Duration;
+
+pub const SUBJECT_PREFIX: &str = "rapidbro";
+pub const DEFAULT_STREAM: &str = "RAPIDBRO";
+const JETSTREAM_PUBLISH_ATTEMPTS: u32 = 3;
+const JETSTREAM_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+pub const NATS_PUBLISHED_TOTAL: &str = "rapidbro_nats_published_total";
+pub const NATS_ERRORS_TOTAL: &str = "rapidbro_nats_errors_total";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NatsMode {
+    // Fire-and-forget; subscribers that aren't connected miss the update.
+    Core,
+    // Waits for the stream's ack, so each update is persisted at least once.
+    JetStream,
+}
+
+#[derive(Debug, Clone)]
+enum Publisher {
+    Core(async_nats::Client),
+    JetStream(jetstream::Context),
+}
+
+// Publishes each update to rapidbro.{provider}.{route}.{bus_no}. The client reconnects on
+// its own; meanwhile batches wait in the sink's queue (see SinkSet::push_queued).
+#[derive(Debug, Clone)]
+pub struct NatsSink {
+    publisher: Publisher,
+}
+
+impl NatsSink {
+    // With JetStream, the DEFAULT_STREAM stream is created over rapidbro.> if missing.
+    pub async fn connect(url: &str, mode: NatsMode) -> Result<Self, String> {
+        let client = async_nats::connect(url)
+            .await
+            .map_err(|error| format!("failed to connect to NATS '{}': {}", url, error))?;
+
+        let publisher = match mode {
+            NatsMode::Core => Publisher::Core(client),
+            NatsMode::JetStream => {
+                let context = jetstream::new(client);
+                context
+                    .get_or_create_stream(jetstream::stream::Config {
+                        name: DEFAULT_STREAM.to_string(),
+                        subjects: vec![format!("{}.>", SUBJECT_PREFIX)],
+                        ..Default::default()
+                    })
+                    .await
+                    .map_err(|error| {
+                        format!("failed to set up stream {}: {}", DEFAULT_STREAM, error)
+                    })?;
+                Publisher::JetStream(context)
+            }
+        };
+
+        Ok(Self { publisher })
+    }
+
+    pub async fn publish(&self, bus: &BusPosition) -> Result<(), String> {
+        let payload = serde_json::to_vec(bus).map_err(|error| error.to_string())?;
+        let subject = subject_for(bus);
+
+        let result = match &self.publisher {
+            Publisher::Core(client) => client
+                .publish(subject, payload.into())
+                .await
+                .map_err(|error| error.to_string()),
+            Publisher::JetStream(context) => publish_acknowledged(context, subject, payload).await,
+        };
+
+        match &result {
+            Ok(()) => counter!(NATS_PUBLISHED_TOTAL).increment(1),
+            Err(_) => counter!(NATS_ERRORS_TOTAL, "reason" => "publish").increment(1),
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl Sink for NatsSink {
+    fn name(&self) -> &str {
+        "nats"
+    }
+
+    // Publishes every position in the batch; one failure doesn't stop the rest.
+    async fn handle(&self, batch: &[BusUpdate]) -> Result<(), SinkError> {
+        let mut failed = 0;
+        let mut last_error = String::new();
+        for bus in batch.iter().filter_map(BusUpdate::position) {
+            if let Err(error) = self.publish(bus).await {
+                failed += 1;
+                last_error = format!("{}: {}", bus.bus_no, error);
+            }
+        }
+        if failed > 0 {
+            return Err(SinkError::Other(format!(
+                "{} publishes failed, last for {}",
+                failed, last_error
+            )));
+        }
+        Ok(())
+    }
+}
+
+async fn publish_acknowledged(
+    context: &jetstream::Context,
+    subject: String,
+    payload: Vec<u8>,
+) -> Result<(), String> {
+    let mut last_error = String::new();
+    for attempt in 1..=JETSTREAM_PUBLISH_ATTEMPTS {
+        let acknowledged = match context
+            .publish(subject.clone(), payload.clone().into())
+            .await
+        {
+            Ok(ack) => ack.await.map(|_| ()),
+            Err(error) => Err(error),
+        };
+        match acknowledged {
+            Ok(()) => return Ok(()),
+            Err(error) => last_error = error.to_string(),
+        }
+        if attempt < JETSTREAM_PUBLISH_ATTEMPTS {
+            tokio::time::sleep(JETSTREAM_RETRY_DELAY).await;
+        }
+    }
+    Err(last_error)
+}
+
+pub fn subject_for(bus: &BusPosition) -> String {
+    format!(
+        "{}.{}.{}.{}",
+        SUBJECT_PREFIX,
+        subject_token(&bus.provider),
+        subject_token(&bus.route),
+        subject_token(&bus.bus_no)
+    )
+}
+
+// Subject tokens can't be empty or contain separators and wildcards.
+fn subject_token(value: &str) -> String {
+    let token: String = value
+        .trim()
+        .chars()
+        .map(|character| match character {
+            '.' | '*' | '>' => '_',
+            character if character.is_whitespace() => '_',
+            character => character,
+        })
+        .collect();
+    if token.is_empty() {
+        "_".to_string()
+    } else {
+        token
+    }
+}
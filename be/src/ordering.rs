@@ -0,0 +1,54 @@
+use crate::feed::BusPosition;
+use chrono::DateTime;
+use std::collections::HashMap;
+
+pub const DEFAULT_GRACE_MS: i64 = 5_000;
+
+// Rejects positions whose fix time is older than the newest one already accepted for
+// the vehicle, as happens when reconnects and reload emits replay older state. Fixes up
+// to `grace_ms` older still pass so small clock skew between sources isn't rejected.
+#[derive(Debug)]
+pub struct OrderingGuard {
+    newest_fix_ms: HashMap<String, i64>,
+    grace_ms: i64,
+}
+
+impl OrderingGuard {
+    pub fn new(grace_ms: i64) -> Self {
+        Self {
+            newest_fix_ms: HashMap::new(),
+            grace_ms,
+        }
+    }
+
+    // Vehicles seen for the first time and fixes without a parseable timestamp always pass.
+    pub fn accept(&mut self, bus: &BusPosition) -> bool {
+        let Some(fix_ms) = bus
+            .timestamp_rfc3339
+            .as_deref()
+            .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+            .map(|timestamp| timestamp.timestamp_millis())
+        else {
+            return true;
+        };
+
+        match self.newest_fix_ms.get_mut(&bus.bus_no) {
+            Some(newest_ms) if fix_ms < *newest_ms - self.grace_ms => false,
+            Some(newest_ms) => {
+                *newest_ms = (*newest_ms).max(fix_ms);
+                true
+            }
+            None => {
+                self.newest_fix_ms.insert(bus.bus_no.clone(), fix_ms);
+                true
+            }
+        }
+    }
+
+    // Drops every vehicle that fails `accept`; returns how many were dropped.
+    pub fn retain_in_order(&mut self, buses: &mut Vec<BusPosition>) -> usize {
+        let before = buses.len();
+        buses.retain(|bus| self.accept(bus));
+        before - buses.len()
+    }
+}
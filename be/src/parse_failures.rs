@@ -0,0 +1,86 @@
+use crate::feed::ParseFailure;
+use metrics::counter;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "server")]
+use utoipa::ToSchema;
+
+pub const PARSE_FAILURES_TOTAL: &str = "rapidbro_parse_failures_total";
+pub const DEFAULT_RECENT_FAILURES: usize = 20;
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+pub struct RecordedFailure {
+    pub route: String,
+    pub event: String,
+    pub received_at_unix_ms: i64,
+    #[serde(flatten)]
+    pub failure: ParseFailure,
+}
+
+// Keeps the most recent payloads that failed to decode, so a feed format change can be
+// diagnosed from /debug/parse-failures instead of a bare failure count. Every failure is
+// also logged and counted per route in PARSE_FAILURES_TOTAL; clones share one buffer.
+#[derive(Debug, Clone)]
+pub struct ParseFailureLog {
+    recent: Arc<Mutex<VecDeque<RecordedFailure>>>,
+    capacity: usize,
+}
+
+impl ParseFailureLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            recent: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn record(
+        &self,
+        route: &str,
+        event: &str,
+        received_at_unix_ms: i64,
+        failure: ParseFailure,
+    ) {
+        let route_label = if route.is_empty() { "all" } else { route };
+        println!(
+            "warning: failed to decode {} payload on route {} ({} bytes): {}",
+            event, route_label, failure.payload_bytes, failure.error
+        );
+        counter!(PARSE_FAILURES_TOTAL, "route" => route_label.to_string()).increment(1);
+        if self.capacity == 0 {
+            return;
+        }
+        let mut recent = self
+            .recent
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if recent.len() == self.capacity {
+            recent.pop_front();
+        }
+        recent.push_back(RecordedFailure {
+            route: route.to_string(),
+            event: event.to_string(),
+            received_at_unix_ms,
+            failure,
+        });
+    }
+
+    // Newest first.
+    pub fn recent(&self) -> Vec<RecordedFailure> {
+        self.recent
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .iter()
+            .rev()
+            .cloned()
+            .collect()
+    }
+}
+
+impl Default for ParseFailureLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_RECENT_FAILURES)
+    }
+}
@@ -0,0 +1,136 @@
+use crate::feed::RawPayload;
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use tracing_appender::non_blocking::{NonBlocking, NonBlockingBuilder, WorkerGuard};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+
+pub const DEFAULT_MAX_FILES: usize = 48;
+// Lines waiting for the writer thread; past this, new lines are dropped rather than
+// making the decoder wait on the disk.
+const BUFFERED_LINES: usize = 16_384;
+const FILE_PREFIX: &str = "payloads";
+const FILE_SUFFIX: &str = "jsonl";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RotationPeriod {
+    Hourly,
+    #[default]
+    Daily,
+}
+
+impl FromStr for RotationPeriod {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "hourly" => Ok(RotationPeriod::Hourly),
+            "daily" => Ok(RotationPeriod::Daily),
+            other => Err(format!(
+                "unknown rotation `{}`; expected hourly or daily",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RotationPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RotationPeriod::Hourly => write!(f, "hourly"),
+            RotationPeriod::Daily => write!(f, "daily"),
+        }
+    }
+}
+
+// Writes every decompressed socket payload as one JSON line to payloads.<period>.jsonl
+// under a directory of its own, rotated hourly or daily with only the newest `max_files`
+// kept, so a debugging capture can run for days without filling the disk.
+//
+// Lines go to a background writer thread. When it falls behind they are dropped and
+// counted instead, so logging never holds up decoding.
+#[derive(Clone)]
+pub struct PayloadLog {
+    writer: NonBlocking,
+}
+
+impl PayloadLog {
+    // Keep the guard alive for as long as payloads are logged; dropping it flushes the
+    // buffered lines and stops the writer thread.
+    pub fn open(
+        dir: &Path,
+        rotation: RotationPeriod,
+        max_files: usize,
+    ) -> Result<(Self, WorkerGuard), String> {
+        std::fs::create_dir_all(dir).map_err(|error| {
+            format!(
+                "failed to create payload log dir '{}': {}",
+                dir.display(),
+                error
+            )
+        })?;
+        let appender = RollingFileAppender::builder()
+            .rotation(match rotation {
+                RotationPeriod::Hourly => Rotation::HOURLY,
+                RotationPeriod::Daily => Rotation::DAILY,
+            })
+            .filename_prefix(FILE_PREFIX)
+            .filename_suffix(FILE_SUFFIX)
+            .max_log_files(max_files.max(1))
+            .build(dir)
+            .map_err(|error| {
+                format!(
+                    "failed to open payload log in '{}': {}",
+                    dir.display(),
+                    error
+                )
+            })?;
+        let (writer, guard) = NonBlockingBuilder::default()
+            .lossy(true)
+            .buffered_lines_limit(BUFFERED_LINES)
+            .thread_name("rapidbro-payload-log")
+            .finish(appender);
+        Ok((Self { writer }, guard))
+    }
+
+    pub fn write(&self, event: &str, received_at_unix_ms: i64, payload: &RawPayload) {
+        let line = payload_line(event, received_at_unix_ms, payload);
+        // Never blocks: a lossy NonBlocking only queues the line, or drops it when full.
+        let _ = self.writer.clone().write_all(line.as_bytes());
+    }
+
+    // Lines dropped because the writer thread couldn't keep up.
+    pub fn dropped_lines(&self) -> usize {
+        self.writer.error_counter().dropped_lines()
+    }
+}
+
+impl fmt::Debug for PayloadLog {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("PayloadLog")
+            .field("dropped_lines", &self.dropped_lines())
+            .finish_non_exhaustive()
+    }
+}
+
+// One line per payload. The JSON is embedded as-is when it parses and as a string when it
+// doesn't, since a payload that fails to parse is usually the one worth looking at.
+pub fn payload_line(event: &str, received_at_unix_ms: i64, payload: &RawPayload) -> String {
+    let json = serde_json::from_str::<serde_json::Value>(&payload.json)
+        .unwrap_or_else(|_| serde_json::Value::String(payload.json.clone()));
+    let received_at = DateTime::<Utc>::from_timestamp_millis(received_at_unix_ms)
+        .map(|timestamp| timestamp.to_rfc3339());
+    let mut line = serde_json::json!({
+        "received_at": received_at,
+        "event": event,
+        "base64_bytes": payload.base64_len,
+        "gzip_bytes": payload.gzip_len,
+        "payload": json,
+    })
+    .to_string();
+    line.push('\n');
+    line
+}
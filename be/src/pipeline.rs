@@ -0,0 +1,220 @@
+use crate::client::{ClientEvent, DECODE_BATCH_SECONDS};
+use crate::feed::{
+    decode_gzip_within, decode_raw_payload_within, DecodeContext, RawPayload,
+    DEFAULT_MAX_DECOMPRESSED_BYTES,
+};
+use crate::field_map::FieldMap;
+use crate::parse_failures::ParseFailureLog;
+use crate::payload_log::PayloadLog;
+use crate::queue::BoundedQueue;
+use crate::schema::SchemaMonitor;
+use crate::session::SessionInfo;
+use crate::timestamp::{localize_timestamp, normalize_timestamp};
+use chrono_tz::Tz;
+use metrics::histogram;
+use rust_socketio::Payload;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::broadcast;
+
+pub const DEFAULT_FRAME_QUEUE_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    // Also publish ClientEvent::RawPayloads with the decompressed JSON.
+    pub raw_payloads: bool,
+    // Keep unrecognized feed keys in BusPosition::extra.
+    pub capture_extra: bool,
+    // Renames the provider's keys before parsing; the default is the Rapid KL layout.
+    pub field_map: Arc<FieldMap>,
+    // Every decompressed payload is also written here; see --payload-log-dir.
+    pub payload_log: Option<PayloadLog>,
+    // The client's route, labelling decode failures.
+    pub route: String,
+    // Payloads that fail to decode are logged, counted and kept here.
+    pub parse_failures: ParseFailureLog,
+    // Fills BusPosition::local_time in this zone.
+    pub timezone: Option<Tz>,
+    // Checks each payload's shape before it's parsed; see SchemaMonitor.
+    pub schema: Option<SchemaMonitor>,
+    // The client's session info, where frames that decode to a valid batch are recorded
+    // for its no-data watchdog. Frames that fail to decode leave it alone.
+    pub session_info: Option<Arc<Mutex<SessionInfo>>>,
+    // Payload values that inflate past this many bytes fail with DecodeError::TooLarge.
+    pub max_decompressed_bytes: usize,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            raw_payloads: false,
+            capture_extra: false,
+            field_map: Arc::default(),
+            payload_log: None,
+            route: String::new(),
+            parse_failures: ParseFailureLog::default(),
+            timezone: None,
+            schema: None,
+            session_info: None,
+            max_decompressed_bytes: DEFAULT_MAX_DECOMPRESSED_BYTES,
+        }
+    }
+}
+
+impl DecodeOptions {
+    fn context(&self) -> DecodeContext {
+        DecodeContext::with_schema(self.schema.clone())
+            .max_decompressed_bytes(self.max_decompressed_bytes)
+    }
+}
+
+// A socket frame exactly as the callback received it.
+#[derive(Debug)]
+pub struct RawFrame {
+    pub event: String,
+    pub payload: Payload,
+    pub received_at_unix_ms: i64,
+}
+
+const MAX_FRAMES_PER_DECODE: usize = 32;
+
+// Decodes frames the socket callback queued and publishes the results, so the callback
+// only has to enqueue and a slow consumer never holds up the socket. Decompression and
+// parsing run on the blocking pool, a few queued frames at a time. Runs until aborted.
+pub async fn run_decode_pipeline(
+    frames: BoundedQueue<RawFrame>,
+    events: broadcast::Sender<ClientEvent>,
+    options: DecodeOptions,
+) {
+    // Moves in and out of the blocking task so its buffers survive between batches.
+    let mut context = options.context();
+    loop {
+        let mut batch = vec![frames.pop().await];
+        batch.extend(frames.drain(MAX_FRAMES_PER_DECODE - 1));
+        let options = options.clone();
+        let decoded = tokio::task::spawn_blocking(move || {
+            let decoded = batch
+                .into_iter()
+                .flat_map(|frame| decode_frame_events(frame, &options, &mut context))
+                .collect::<Vec<ClientEvent>>();
+            (decoded, context)
+        })
+        .await;
+        match decoded {
+            Ok((decoded, returned)) => {
+                context = returned;
+                for event in decoded {
+                    let _ = events.send(event);
+                }
+            }
+            Err(error) => {
+                println!("Payload decode task failed: {}", error);
+                context = options.context();
+            }
+        }
+    }
+}
+
+pub fn decode_frame(
+    frame: RawFrame,
+    events: &broadcast::Sender<ClientEvent>,
+    options: &DecodeOptions,
+) {
+    let mut context = options.context();
+    for event in decode_frame_events(frame, options, &mut context) {
+        let _ = events.send(event);
+    }
+}
+
+// CPU-bound: call from a blocking context when the frame may be large.
+pub fn decode_frame_events(
+    frame: RawFrame,
+    options: &DecodeOptions,
+    context: &mut DecodeContext,
+) -> Vec<ClientEvent> {
+    let mut decoded = Vec::new();
+    if options.raw_payloads || options.payload_log.is_some() {
+        let payloads: Vec<RawPayload> = match &frame.payload {
+            Payload::Text(values) => values
+                .iter()
+                .filter_map(|value| value.as_str())
+                .filter_map(|value| {
+                    decode_raw_payload_within(value, options.max_decompressed_bytes)
+                })
+                .collect(),
+            Payload::Binary(bytes) => decode_gzip_within(bytes, options.max_decompressed_bytes)
+                .ok()
+                .map(|json| RawPayload {
+                    base64_len: 0,
+                    gzip_len: bytes.len(),
+                    json,
+                })
+                .into_iter()
+                .collect(),
+            _ => Vec::new(),
+        };
+        if let Some(payload_log) = &options.payload_log {
+            for payload in &payloads {
+                payload_log.write(&frame.event, frame.received_at_unix_ms, payload);
+            }
+        }
+        if options.raw_payloads {
+            decoded.push(ClientEvent::RawPayloads {
+                event: frame.event,
+                payloads,
+            });
+        }
+    }
+    // A frame without a single payload value has nothing to decode, valid or not.
+    let carries_values = match &frame.payload {
+        Payload::Text(values) => values.iter().any(serde_json::Value::is_string),
+        Payload::Binary(_) => true,
+        _ => false,
+    };
+    let decode_started = Instant::now();
+    let (mut buses, decode_failures) =
+        context.parse_mapped(frame.payload, options.capture_extra, &options.field_map);
+    histogram!(DECODE_BATCH_SECONDS).record(decode_started.elapsed().as_secs_f64());
+    for failure in context.take_failures() {
+        options.parse_failures.record(
+            &options.route,
+            &frame.event,
+            frame.received_at_unix_ms,
+            failure,
+        );
+    }
+    if !carries_values {
+        return decoded;
+    }
+    // An empty list that decoded cleanly is the feed saying no buses are running, which
+    // is as much a sign of a working connection as a full one.
+    let valid_batch = !buses.is_empty() || decode_failures == 0;
+    if let Some(session_info) = options.session_info.as_ref().filter(|_| valid_batch) {
+        let mut info = session_info
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        info.last_data_unix_ms = Some(frame.received_at_unix_ms);
+        if buses.is_empty() {
+            info.last_valid_empty_unix_ms = Some(frame.received_at_unix_ms);
+        }
+    }
+    for bus in &mut buses {
+        normalize_timestamp(bus, frame.received_at_unix_ms);
+        if let Some(timezone) = options.timezone {
+            localize_timestamp(bus, timezone);
+        }
+    }
+    decoded.push(ClientEvent::Buses {
+        buses,
+        decode_failures,
+        received_at_unix_ms: frame.received_at_unix_ms,
+    });
+    decoded
+}
+
+// The route a batch says has no buses running. Only a route's own socket can vouch for
+// that route; the all-buses socket and GTFS-rt have an empty link, and a batch that
+// failed to decode says nothing either way.
+pub fn emptied_route(link: &str, bus_count: usize, decode_failures: u64) -> Option<&str> {
+    (bus_count == 0 && decode_failures == 0 && !link.is_empty()).then_some(link)
+}
@@ -0,0 +1,198 @@
+use crate::feed::BusPosition;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+pub const DEFAULT_LOST_AFTER_CYCLES: u32 = 3;
+// After a reconnect the feed needs a cycle or two to resend every vehicle; until then a
+// missing bus says more about the socket than about the bus.
+pub const DEFAULT_RECONNECT_GRACE_MS: i64 = 60_000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event")]
+pub enum PresenceEvent {
+    VehicleLost {
+        vehicle_id: String,
+        route: String,
+        last_position: Box<BusPosition>,
+        last_seen_unix_ms: i64,
+    },
+    VehicleReturned {
+        vehicle_id: String,
+        route: String,
+        position: Box<BusPosition>,
+        lost_for_ms: i64,
+    },
+}
+
+impl PresenceEvent {
+    pub fn vehicle_id(&self) -> &str {
+        match self {
+            PresenceEvent::VehicleLost { vehicle_id, .. }
+            | PresenceEvent::VehicleReturned { vehicle_id, .. } => vehicle_id,
+        }
+    }
+
+    pub fn route(&self) -> &str {
+        match self {
+            PresenceEvent::VehicleLost { route, .. }
+            | PresenceEvent::VehicleReturned { route, .. } => route,
+        }
+    }
+}
+
+impl fmt::Display for PresenceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PresenceEvent::VehicleLost {
+                vehicle_id, route, ..
+            } => write!(f, "{} route={} lost", vehicle_id, route),
+            PresenceEvent::VehicleReturned {
+                vehicle_id,
+                route,
+                lost_for_ms,
+                ..
+            } => write!(
+                f,
+                "{} route={} returned after {}s",
+                vehicle_id,
+                route,
+                lost_for_ms / 1_000
+            ),
+        }
+    }
+}
+
+struct TrackedVehicle {
+    last_position: BusPosition,
+    last_seen_ms: i64,
+    // The socket the vehicle was last seen on; its outages pause the vehicle's count.
+    link: String,
+    seen_this_cycle: bool,
+    missed_cycles: u32,
+    lost: bool,
+}
+
+struct LinkState {
+    connected: bool,
+    suppressed_until_ms: i64,
+}
+
+// Flags a vehicle as lost once it has been missing from the feed for `lost_after_cycles`
+// consecutive reload cycles, and as returned when it shows up again. The caller ends each
+// cycle with `end_cycle`; `observe` is fed every position in between.
+//
+// Each socket ("link", normally the route it follows) reports its own disconnects and
+// reconnects. Nothing is counted as missed for a vehicle while its link is down or within
+// the grace period after it reconnects, so one dropped connection doesn't turn that route's
+// fleet into lost vehicles, and doesn't hide a vehicle vanishing from a healthy route.
+pub struct PresenceTracker {
+    vehicles: HashMap<String, TrackedVehicle>,
+    lost_after_cycles: u32,
+    grace_ms: i64,
+    // Links never reported are taken to be connected.
+    links: HashMap<String, LinkState>,
+}
+
+impl PresenceTracker {
+    pub fn new(lost_after_cycles: u32, grace_ms: i64) -> Self {
+        Self {
+            vehicles: HashMap::new(),
+            lost_after_cycles: lost_after_cycles.max(1),
+            grace_ms,
+            links: HashMap::new(),
+        }
+    }
+
+    pub fn observe(&mut self, bus: &BusPosition, link: &str, now_ms: i64) -> Option<PresenceEvent> {
+        if bus.bus_no.is_empty() {
+            return None;
+        }
+        let Some(tracked) = self.vehicles.get_mut(&bus.bus_no) else {
+            self.vehicles.insert(
+                bus.bus_no.clone(),
+                TrackedVehicle {
+                    last_position: bus.clone(),
+                    last_seen_ms: now_ms,
+                    link: link.to_string(),
+                    seen_this_cycle: true,
+                    missed_cycles: 0,
+                    lost: false,
+                },
+            );
+            return None;
+        };
+
+        let event = tracked.lost.then(|| PresenceEvent::VehicleReturned {
+            vehicle_id: bus.bus_no.clone(),
+            route: bus.route.clone(),
+            position: Box::new(bus.clone()),
+            lost_for_ms: now_ms - tracked.last_seen_ms,
+        });
+        tracked.last_position = bus.clone();
+        tracked.last_seen_ms = now_ms;
+        if tracked.link != link {
+            tracked.link = link.to_string();
+        }
+        tracked.seen_this_cycle = true;
+        tracked.missed_cycles = 0;
+        tracked.lost = false;
+        event
+    }
+
+    // Closes one reload cycle and reports the vehicles that just crossed the threshold.
+    pub fn end_cycle(&mut self, now_ms: i64) -> Vec<PresenceEvent> {
+        let mut events = Vec::new();
+        for (bus_no, tracked) in &mut self.vehicles {
+            let suppressed = self
+                .links
+                .get(&tracked.link)
+                .is_some_and(|link| !link.connected || now_ms < link.suppressed_until_ms);
+            let seen = std::mem::take(&mut tracked.seen_this_cycle);
+            if seen || suppressed {
+                tracked.missed_cycles = 0;
+                continue;
+            }
+            if tracked.lost {
+                continue;
+            }
+            tracked.missed_cycles += 1;
+            if tracked.missed_cycles >= self.lost_after_cycles {
+                tracked.lost = true;
+                events.push(PresenceEvent::VehicleLost {
+                    vehicle_id: bus_no.clone(),
+                    route: tracked.last_position.route.clone(),
+                    last_position: Box::new(tracked.last_position.clone()),
+                    last_seen_unix_ms: tracked.last_seen_ms,
+                });
+            }
+        }
+        events
+    }
+
+    pub fn on_disconnected(&mut self, link: &str) {
+        self.links
+            .entry(link.to_string())
+            .or_insert(LinkState {
+                connected: true,
+                suppressed_until_ms: i64::MIN,
+            })
+            .connected = false;
+    }
+
+    pub fn on_connected(&mut self, link: &str, now_ms: i64) {
+        self.links.insert(
+            link.to_string(),
+            LinkState {
+                connected: true,
+                suppressed_until_ms: now_ms.saturating_add(self.grace_ms),
+            },
+        );
+    }
+
+    pub fn is_lost(&self, bus_no: &str) -> bool {
+        self.vehicles
+            .get(bus_no)
+            .is_some_and(|tracked| tracked.lost)
+    }
+}
@@ -0,0 +1,287 @@
+use crate::feed::BusPosition;
+use std::collections::HashMap;
+
+const METERS_PER_DEGREE: f64 = 111_320.0;
+// Grid cells of about 550m for the segment index.
+const CELL_DEGREES: f64 = 0.005;
+// Further than this from the shape a fix is flagged off-route instead of snapped.
+pub const DEFAULT_OFF_ROUTE_METERS: f64 = 300.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Projection {
+    pub lat: f64,
+    pub lon: f64,
+    pub progress_meters: f64,
+    pub distance_meters: f64,
+}
+
+// A route polyline with cumulative distances, per-segment bounding boxes and a grid
+// index of the segments, so a projection only measures the segments near the position
+// rather than every segment of a long shape.
+#[derive(Debug, Clone)]
+pub struct RouteShape {
+    points: Vec<(f64, f64)>,
+    cumulative_meters: Vec<f64>,
+    // (min_lat, min_lon, max_lat, max_lon) per segment.
+    segment_bounds: Vec<(f64, f64, f64, f64)>,
+    // Segments by every grid cell their bounding box touches.
+    cells: HashMap<(i64, i64), Vec<usize>>,
+    // (min_row, min_col, max_row, max_col) of the cells in use.
+    cell_extent: (i64, i64, i64, i64),
+}
+
+impl RouteShape {
+    // Points are (lat, lon) in shape_pt_sequence order.
+    pub fn new(points: Vec<(f64, f64)>) -> Option<Self> {
+        if points.len() < 2 {
+            return None;
+        }
+
+        let mut cumulative_meters = Vec::with_capacity(points.len());
+        let mut total = 0.0;
+        cumulative_meters.push(total);
+        for pair in points.windows(2) {
+            let (east, north) = local_offset_meters(pair[0], pair[1]);
+            total += east.hypot(north);
+            cumulative_meters.push(total);
+        }
+
+        let segment_bounds: Vec<(f64, f64, f64, f64)> = points
+            .windows(2)
+            .map(|pair| {
+                (
+                    pair[0].0.min(pair[1].0),
+                    pair[0].1.min(pair[1].1),
+                    pair[0].0.max(pair[1].0),
+                    pair[0].1.max(pair[1].1),
+                )
+            })
+            .collect();
+
+        let mut cells: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+        let mut cell_extent = (i64::MAX, i64::MAX, i64::MIN, i64::MIN);
+        for (index, &(min_lat, min_lon, max_lat, max_lon)) in segment_bounds.iter().enumerate() {
+            let (low_row, low_col) = cell_of(min_lat, min_lon);
+            let (high_row, high_col) = cell_of(max_lat, max_lon);
+            for row in low_row..=high_row {
+                for col in low_col..=high_col {
+                    cells.entry((row, col)).or_default().push(index);
+                }
+            }
+            cell_extent = (
+                cell_extent.0.min(low_row),
+                cell_extent.1.min(low_col),
+                cell_extent.2.max(high_row),
+                cell_extent.3.max(high_col),
+            );
+        }
+
+        Some(Self {
+            points,
+            cumulative_meters,
+            segment_bounds,
+            cells,
+            cell_extent,
+        })
+    }
+
+    pub fn length_meters(&self) -> f64 {
+        self.cumulative_meters.last().copied().unwrap_or(0.0)
+    }
+
+    // Closest point on the nearest segment: how far along the shape it is and how far
+    // the position is from it. Grid cells are searched in rings around the position until
+    // no unvisited cell could hold a closer segment.
+    pub fn project(&self, lat: f64, lon: f64) -> Projection {
+        let mut best = Projection {
+            lat,
+            lon,
+            progress_meters: 0.0,
+            distance_meters: f64::MAX,
+        };
+        if !lat.is_finite() || !lon.is_finite() {
+            return best;
+        }
+        let (row, col) = cell_of(lat, lon);
+        let (min_row, min_col, max_row, max_col) = self.cell_extent;
+        // Offsets are scaled at the mean latitude of their two ends, so degrees of
+        // longitude are priced at the latitude furthest from the equator to keep box and
+        // ring distances lower bounds.
+        let widest_lat = (min_row as f64 * CELL_DEGREES)
+            .abs()
+            .max(((max_row + 1) as f64 * CELL_DEGREES).abs())
+            .max(lat.abs());
+        let lon_scale = METERS_PER_DEGREE * widest_lat.to_radians().cos();
+        // A ring further out is at least this much further away.
+        let cell_meters = CELL_DEGREES * lon_scale;
+        let first_ring = (min_row - row)
+            .max(row - max_row)
+            .max(min_col - col)
+            .max(col - max_col)
+            .max(0);
+        let last_ring = (row - min_row)
+            .max(max_row - row)
+            .max(col - min_col)
+            .max(max_col - col);
+
+        let mut visited = vec![false; self.segment_bounds.len()];
+        for ring in first_ring..=last_ring {
+            for cell in ring_cells((row, col), ring, self.cell_extent) {
+                for &index in self.cells.get(&cell).into_iter().flatten() {
+                    if !std::mem::replace(&mut visited[index], true) {
+                        self.project_onto(index, (lat, lon), lon_scale, &mut best);
+                    }
+                }
+            }
+            // Every segment not visited yet lies wholly outside this ring.
+            if best.distance_meters <= ring as f64 * cell_meters {
+                break;
+            }
+        }
+        best
+    }
+
+    fn project_onto(
+        &self,
+        index: usize,
+        (lat, lon): (f64, f64),
+        lon_scale: f64,
+        best: &mut Projection,
+    ) {
+        // The box distance never exceeds the true distance, so a box further away than
+        // the best match so far can't contain a better one.
+        let (min_lat, min_lon, max_lat, max_lon) = self.segment_bounds[index];
+        let box_north = (min_lat - lat).max(lat - max_lat).max(0.0) * METERS_PER_DEGREE;
+        let box_east = (min_lon - lon).max(lon - max_lon).max(0.0) * lon_scale;
+        if box_north.hypot(box_east) >= best.distance_meters {
+            return;
+        }
+
+        let (start, end) = (self.points[index], self.points[index + 1]);
+        let segment = local_offset_meters(start, end);
+        let to_bus = local_offset_meters(start, (lat, lon));
+        let length_sq = segment.0 * segment.0 + segment.1 * segment.1;
+        let t = if length_sq > 0.0 {
+            ((to_bus.0 * segment.0 + to_bus.1 * segment.1) / length_sq).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let distance_meters = (to_bus.0 - segment.0 * t).hypot(to_bus.1 - segment.1 * t);
+        if distance_meters < best.distance_meters {
+            *best = Projection {
+                lat: start.0 + (end.0 - start.0) * t,
+                lon: start.1 + (end.1 - start.1) * t,
+                progress_meters: self.cumulative_meters[index] + t * length_sq.sqrt(),
+                distance_meters,
+            };
+        }
+    }
+}
+
+fn cell_of(lat: f64, lon: f64) -> (i64, i64) {
+    (
+        (lat / CELL_DEGREES).floor() as i64,
+        (lon / CELL_DEGREES).floor() as i64,
+    )
+}
+
+// The cells exactly `ring` cells (Chebyshev distance) from `center` that fall within
+// `extent`, so a position far from the shape doesn't walk empty cells.
+fn ring_cells(
+    (row, col): (i64, i64),
+    ring: i64,
+    (min_row, min_col, max_row, max_col): (i64, i64, i64, i64),
+) -> Vec<(i64, i64)> {
+    let cols = (col - ring).max(min_col)..=(col + ring).min(max_col);
+    let rows = (row - ring + 1).max(min_row)..=(row + ring - 1).min(max_row);
+    let mut cells = Vec::new();
+    for edge_row in [row - ring, row + ring] {
+        if (min_row..=max_row).contains(&edge_row) {
+            cells.extend(cols.clone().map(|col| (edge_row, col)));
+        }
+        if ring == 0 {
+            return cells;
+        }
+    }
+    for edge_col in [col - ring, col + ring] {
+        if (min_col..=max_col).contains(&edge_col) {
+            cells.extend(rows.clone().map(|row| (row, edge_col)));
+        }
+    }
+    cells
+}
+
+// Equirectangular (east, north) offset in meters; accurate enough at route scale.
+fn local_offset_meters(from: (f64, f64), to: (f64, f64)) -> (f64, f64) {
+    let mean_lat = ((from.0 + to.0) / 2.0).to_radians();
+    (
+        (to.1 - from.1) * METERS_PER_DEGREE * mean_lat.cos(),
+        (to.0 - from.0) * METERS_PER_DEGREE,
+    )
+}
+
+// Projects the position onto the nearest segment of the shape; returns the snapped
+// (lat, lon) together with the projection for distance-along-route.
+pub fn snap_to_shape(pos: &BusPosition, shape: &RouteShape) -> Option<((f64, f64), Projection)> {
+    let projection = shape.project(pos.latitude, pos.longitude);
+    projection
+        .distance_meters
+        .is_finite()
+        .then_some(((projection.lat, projection.lon), projection))
+}
+
+// Shapes keyed by `route_key(route)` so callers can share their route normalization.
+#[derive(Debug)]
+pub struct RouteShapes {
+    shapes: HashMap<String, RouteShape>,
+    route_key: fn(&str) -> String,
+    off_route_meters: f64,
+}
+
+impl RouteShapes {
+    pub fn new(
+        shapes: HashMap<String, RouteShape>,
+        route_key: fn(&str) -> String,
+        off_route_meters: f64,
+    ) -> Self {
+        Self {
+            shapes,
+            route_key,
+            off_route_meters,
+        }
+    }
+
+    pub fn get(&self, route: &str) -> Option<&RouteShape> {
+        self.shapes.get(&(self.route_key)(route))
+    }
+
+    // Snapped (lat, lon) on the route shape, or None for unknown routes and off-route fixes.
+    pub fn snap(&self, bus: &BusPosition) -> Option<(f64, f64)> {
+        snap_to_shape(bus, self.get(&bus.route)?)
+            .filter(|(_, projection)| projection.distance_meters <= self.off_route_meters)
+            .map(|(snapped, _)| snapped)
+    }
+
+    // Fills progress_m/progress_pct, or flags off_route when the fix is too far from the
+    // shape. Routes without a shape are left untouched.
+    pub fn annotate(&self, bus: &mut BusPosition) {
+        bus.progress_m = None;
+        bus.progress_pct = None;
+        bus.off_route = false;
+
+        let Some(shape) = self.get(&bus.route) else {
+            return;
+        };
+        let projection = shape.project(bus.latitude, bus.longitude);
+        if projection.distance_meters > self.off_route_meters {
+            bus.off_route = true;
+            return;
+        }
+
+        bus.progress_m = Some(projection.progress_meters);
+        let length_meters = shape.length_meters();
+        if length_meters > 0.0 {
+            bus.progress_pct = Some(projection.progress_meters / length_meters * 100.0);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::env;
+
+const PROXY_ENV_VARS: [&str; 2] = ["HTTPS_PROXY", "https_proxy"];
+
+// The HTTP(S) proxy in front of the Prasarana endpoints: --proxy, else HTTPS_PROXY.
+// The default goes direct, as before. It is set on each client that talks to Prasarana,
+// never exported, so clients for our own services (webhooks, InfluxDB) stay direct.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyOptions {
+    url: Option<String>,
+    // Whether HTTPS_PROXY already names this proxy; see covers_socket.
+    in_env: bool,
+}
+
+impl ProxyOptions {
+    // Validates the URL up front, so a typo fails at startup instead of on the first fetch.
+    pub fn load(explicit: Option<&str>) -> Result<Self, String> {
+        let from_env = PROXY_ENV_VARS.iter().find_map(|name| env::var(name).ok());
+        let url = explicit
+            .map(str::to_string)
+            .or_else(|| from_env.clone())
+            .filter(|url| !url.trim().is_empty());
+        if let Some(url) = &url {
+            reqwest::Proxy::all(url.as_str())
+                .map_err(|error| format!("invalid proxy '{}': {}", url, error))?;
+        }
+        Ok(Self {
+            in_env: url.is_some() && url == from_env,
+            url,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.url.is_some()
+    }
+
+    // The Socket.IO polling transport builds its own reqwest client, which only picks a
+    // proxy up from HTTPS_PROXY. A --proxy that the environment doesn't repeat can't reach
+    // it, and the socket connects direct.
+    pub fn covers_socket(&self) -> bool {
+        self.in_env
+    }
+
+    pub fn apply(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        if let Some(proxy) = self
+            .url
+            .as_deref()
+            .and_then(|url| reqwest::Proxy::all(url).ok())
+        {
+            builder = builder.proxy(proxy);
+        }
+        builder
+    }
+}
@@ -0,0 +1,173 @@
+use metrics::counter;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+pub const DEFAULT_QUEUE_CAPACITY: usize = 64;
+pub const DEFAULT_BLOCK_TIMEOUT: Duration = Duration::from_millis(100);
+pub const QUEUE_DROPPED_TOTAL: &str = "rapidbro_queue_dropped_total";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    DropOldest,
+    DropNewest,
+    // Waits up to the block timeout for space, then sheds the oldest item.
+    Block,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "drop-newest" => Ok(OverflowPolicy::DropNewest),
+            "block" => Ok(OverflowPolicy::Block),
+            other => Err(format!(
+                "unknown overflow policy `{}` (expected drop-oldest, drop-newest or block)",
+                other
+            )),
+        }
+    }
+}
+
+// Bounded hand-off between the socket and slower sinks. Unlike tokio's mpsc the
+// producer can evict the oldest item, so a stalled sink always sees the freshest data.
+#[derive(Debug)]
+pub struct BoundedQueue<T> {
+    items: Arc<Mutex<VecDeque<T>>>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    block_timeout: Duration,
+    item_ready: Arc<Notify>,
+    space_ready: Arc<Notify>,
+    dropped: Arc<AtomicU64>,
+    // Label for QUEUE_DROPPED_TOTAL; unnamed queues only keep the local count.
+    name: Option<&'static str>,
+}
+
+// Clones share the same queue; derive would needlessly require T: Clone.
+impl<T> Clone for BoundedQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            items: self.items.clone(),
+            capacity: self.capacity,
+            policy: self.policy,
+            block_timeout: self.block_timeout,
+            item_ready: self.item_ready.clone(),
+            space_ready: self.space_ready.clone(),
+            dropped: self.dropped.clone(),
+            name: self.name,
+        }
+    }
+}
+
+impl<T> BoundedQueue<T> {
+    pub fn new(capacity: usize, policy: OverflowPolicy, block_timeout: Duration) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            items: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            policy,
+            block_timeout,
+            item_ready: Arc::new(Notify::new()),
+            space_ready: Arc::new(Notify::new()),
+            dropped: Arc::new(AtomicU64::new(0)),
+            name: None,
+        }
+    }
+
+    pub fn named(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    // Returns false when an item (this one or an older one) was dropped to make room.
+    pub async fn push(&self, item: T) -> bool {
+        if self.policy == OverflowPolicy::Block {
+            let deadline = tokio::time::Instant::now() + self.block_timeout;
+            loop {
+                let space_ready = self.space_ready.notified();
+                if self.len() < self.capacity {
+                    break;
+                }
+                if tokio::time::timeout_at(deadline, space_ready)
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        }
+
+        let accepted = {
+            let mut items = self.items.lock().unwrap_or_else(|error| error.into_inner());
+            if items.len() < self.capacity {
+                items.push_back(item);
+                true
+            } else if self.policy == OverflowPolicy::DropNewest {
+                false
+            } else {
+                items.pop_front();
+                items.push_back(item);
+                false
+            }
+        };
+
+        if !accepted {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            if let Some(name) = self.name {
+                counter!(QUEUE_DROPPED_TOTAL, "queue" => name).increment(1);
+            }
+        }
+        self.item_ready.notify_one();
+        accepted
+    }
+
+    pub async fn pop(&self) -> T {
+        loop {
+            let item_ready = self.item_ready.notified();
+            let item = self
+                .items
+                .lock()
+                .unwrap_or_else(|error| error.into_inner())
+                .pop_front();
+            if let Some(item) = item {
+                self.space_ready.notify_one();
+                return item;
+            }
+            item_ready.await;
+        }
+    }
+
+    // Takes up to `max` items without waiting.
+    pub fn drain(&self, max: usize) -> Vec<T> {
+        let drained: Vec<T> = {
+            let mut items = self.items.lock().unwrap_or_else(|error| error.into_inner());
+            let count = items.len().min(max);
+            items.drain(..count).collect()
+        };
+        if !drained.is_empty() {
+            self.space_ready.notify_waiters();
+        }
+        drained
+    }
+
+    pub fn len(&self) -> usize {
+        self.items
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
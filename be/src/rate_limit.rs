@@ -0,0 +1,324 @@
+use governor::clock::{Clock, DefaultClock};
+use governor::middleware::NoOpMiddleware;
+use governor::state::{InMemoryState, NotKeyed};
+use governor::{Quota, RateLimiter};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const DEFAULT_EMITS_PER_SECOND: u32 = 2;
+// Per client IP on the HTTP API. History queries scan Parquet files, so they get less.
+pub const DEFAULT_REQUESTS_PER_MINUTE: u32 = 60;
+pub const DEFAULT_REQUEST_BURST: u32 = 20;
+pub const DEFAULT_HISTORY_REQUESTS_PER_MINUTE: u32 = 10;
+pub const DEFAULT_HISTORY_REQUEST_BURST: u32 = 5;
+pub const DEFAULT_MAX_TRACKED_CLIENTS: usize = 10_000;
+pub const RATE_LIMITED_TOTAL: &str = "rapidbro_http_rate_limited_total";
+pub const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+// Periodic reloads land anywhere within ±10% of the reload interval.
+pub const RELOAD_JITTER: f64 = 0.1;
+
+type DirectLimiter<C> =
+    RateLimiter<NotKeyed, InMemoryState, C, NoOpMiddleware<<C as Clock>::Instant>>;
+
+// Token bucket shared by every client that emits to the upstream socket. Clones share
+// the same bucket, so handing one limiter to several clients caps them together.
+#[derive(Clone)]
+pub struct EmitLimiter<C: Clock = DefaultClock> {
+    limiter: Arc<DirectLimiter<C>>,
+    clock: C,
+    throttled: Arc<AtomicU64>,
+}
+
+impl EmitLimiter {
+    pub fn per_second(emits_per_second: u32) -> Self {
+        Self::with_clock(emits_per_second, DefaultClock::default())
+    }
+
+    // Waits for a token instead of dropping the emit when the bucket is empty, so a
+    // throttled emit goes out as soon as the bucket allows.
+    pub async fn acquire(&self) {
+        let Err(mut wait) = self.try_acquire() else {
+            return;
+        };
+        loop {
+            tokio::time::sleep(wait).await;
+            match self.limiter.check() {
+                Ok(()) => return,
+                Err(not_until) => wait = not_until.wait_time_from(self.clock.now()),
+            }
+        }
+    }
+}
+
+impl<C: Clock> EmitLimiter<C> {
+    pub fn with_clock(emits_per_second: u32, clock: C) -> Self {
+        let rate = NonZeroU32::new(emits_per_second).unwrap_or(NonZeroU32::MIN);
+        Self {
+            limiter: Arc::new(RateLimiter::direct_with_clock(
+                Quota::per_second(rate),
+                clock.clone(),
+            )),
+            clock,
+            throttled: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    // Takes a token, or counts the emit as throttled and says how long until one is free.
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        self.limiter.check().map_err(|not_until| {
+            self.throttled.fetch_add(1, Ordering::Relaxed);
+            not_until.wait_time_from(self.clock.now())
+        })
+    }
+
+    pub fn throttled_count(&self) -> u64 {
+        self.throttled.load(Ordering::Relaxed)
+    }
+}
+
+// Written out because the derive can't see that the limiter's middleware is Debug.
+impl<C: Clock> fmt::Debug for EmitLimiter<C> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("EmitLimiter")
+            .field("throttled", &self.throttled_count())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for EmitLimiter {
+    fn default() -> Self {
+        Self::per_second(DEFAULT_EMITS_PER_SECOND)
+    }
+}
+
+// A token bucket per client IP: `per_minute` requests a minute on average, up to `burst`
+// at once. Clones share the buckets.
+//
+// At most `max_clients` buckets are kept; past that the least recently seen client is
+// forgotten, so a scan from many addresses can't grow memory without bound. A forgotten
+// client starts again with a full bucket.
+#[derive(Clone)]
+pub struct ClientRateLimiter<C: Clock = DefaultClock> {
+    quota: Quota,
+    clock: C,
+    max_clients: usize,
+    clients: Arc<Mutex<RecentClients<C>>>,
+}
+
+struct RecentClients<C: Clock> {
+    // Each client's bucket and when it was last seen, as a position in `recency`.
+    buckets: HashMap<IpAddr, (Arc<DirectLimiter<C>>, u64)>,
+    // Least recently seen first.
+    recency: BTreeMap<u64, IpAddr>,
+    next_use: u64,
+}
+
+impl ClientRateLimiter {
+    pub fn per_minute(per_minute: u32, burst: u32, max_clients: usize) -> Self {
+        Self::with_clock(per_minute, burst, max_clients, DefaultClock::default())
+    }
+}
+
+impl<C: Clock> ClientRateLimiter<C> {
+    pub fn with_clock(per_minute: u32, burst: u32, max_clients: usize, clock: C) -> Self {
+        let rate = NonZeroU32::new(per_minute).unwrap_or(NonZeroU32::MIN);
+        let burst = NonZeroU32::new(burst).unwrap_or(NonZeroU32::MIN);
+        Self {
+            quota: Quota::per_minute(rate).allow_burst(burst),
+            clock,
+            max_clients: max_clients.max(1),
+            clients: Arc::new(Mutex::new(RecentClients {
+                buckets: HashMap::new(),
+                recency: BTreeMap::new(),
+                next_use: 0,
+            })),
+        }
+    }
+
+    // Takes a token from the client's bucket, or says how long until one is free.
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        let bucket = {
+            let mut clients = self
+                .clients
+                .lock()
+                .unwrap_or_else(|error| error.into_inner());
+            let clients = &mut *clients;
+            let used = clients.next_use;
+            clients.next_use += 1;
+            let bucket = match clients.buckets.get_mut(&client) {
+                Some((bucket, last_used)) => {
+                    clients.recency.remove(last_used);
+                    *last_used = used;
+                    bucket.clone()
+                }
+                None => {
+                    if clients.buckets.len() >= self.max_clients {
+                        if let Some((_, evicted)) = clients.recency.pop_first() {
+                            clients.buckets.remove(&evicted);
+                        }
+                    }
+                    let bucket = Arc::new(RateLimiter::direct_with_clock(
+                        self.quota,
+                        self.clock.clone(),
+                    ));
+                    clients.buckets.insert(client, (bucket.clone(), used));
+                    bucket
+                }
+            };
+            clients.recency.insert(used, client);
+            bucket
+        };
+        bucket
+            .check()
+            .map_err(|not_until| not_until.wait_time_from(self.clock.now()))
+    }
+
+    pub fn tracked_clients(&self) -> usize {
+        self.clients
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .buckets
+            .len()
+    }
+}
+
+impl<C: Clock> fmt::Debug for ClientRateLimiter<C> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("ClientRateLimiter")
+            .field("quota", &self.quota)
+            .field("tracked_clients", &self.tracked_clients())
+            .finish_non_exhaustive()
+    }
+}
+
+// Proxies whose X-Forwarded-For is believed, as addresses or CIDR ranges. With none, the
+// header is ignored and clients are told apart by the connecting address alone, since
+// anyone can send X-Forwarded-For.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies {
+    ranges: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    // Comma-separated, as in TRUSTED_PROXIES, e.g. `10.0.0.0/8,127.0.0.1`.
+    pub fn parse(proxies: &str) -> Result<Self, String> {
+        let mut ranges = Vec::new();
+        for proxy in proxies
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+        {
+            let (address, prefix) = proxy.split_once('/').unwrap_or((proxy, ""));
+            let address: IpAddr = address
+                .parse()
+                .map_err(|_| format!("trusted proxy `{}` is not an IP address or range", proxy))?;
+            let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+            let prefix = if prefix.is_empty() {
+                max_prefix
+            } else {
+                prefix
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|prefix| *prefix <= max_prefix)
+                    .ok_or_else(|| format!("trusted proxy `{}` has an invalid prefix", proxy))?
+            };
+            ranges.push((address, prefix));
+        }
+        Ok(Self { ranges })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let address = canonical(address);
+        self.ranges
+            .iter()
+            .any(|(range, prefix)| match (canonical(*range), address) {
+                (IpAddr::V4(range), IpAddr::V4(address)) => prefix_matches(
+                    u32::from(range).into(),
+                    u32::from(address).into(),
+                    *prefix,
+                    32,
+                ),
+                (IpAddr::V6(range), IpAddr::V6(address)) => {
+                    prefix_matches(u128::from(range), u128::from(address), *prefix, 128)
+                }
+                _ => false,
+            })
+    }
+
+    // The address to rate limit a request by. A trusted peer's X-Forwarded-For is read
+    // from the right, skipping further trusted proxies; the first address that isn't one
+    // is the client. Anything unparseable ends the walk at the last address trusted.
+    pub fn client_ip(&self, peer: IpAddr, forwarded_for: Option<&str>) -> IpAddr {
+        let mut client = canonical(peer);
+        if !self.contains(client) {
+            return client;
+        }
+        let hops = forwarded_for.unwrap_or("").rsplit(',').map(str::trim);
+        for hop in hops {
+            let Ok(hop) = hop.parse::<IpAddr>() else {
+                break;
+            };
+            client = canonical(hop);
+            if !self.contains(client) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+// IPv4-mapped IPv6 addresses (as seen on a dual-stack listener) compare as IPv4.
+fn canonical(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => v6
+            .to_ipv4_mapped()
+            .map(IpAddr::V4)
+            .unwrap_or(IpAddr::V6(v6)),
+        v4 => v4,
+    }
+}
+
+fn prefix_matches(range: u128, address: u128, prefix: u8, bits: u32) -> bool {
+    let shift = bits - u32::from(prefix);
+    shift >= bits || (range ^ address) >> shift == 0
+}
+
+// Scales `interval` by a factor in [1 - fraction, 1 + fraction]; `unit` in [0, 1]
+// picks where, so 0.5 leaves it unchanged.
+pub fn jittered(interval: Duration, fraction: f64, unit: f64) -> Duration {
+    let fraction = fraction.clamp(0.0, 1.0);
+    interval.mul_f64(1.0 + fraction * (2.0 * unit.clamp(0.0, 1.0) - 1.0))
+}
+
+// Where the `index`th of `count` clients sends its first reload, so their reloads are
+// spaced evenly across one interval instead of landing together.
+pub fn spread_offset(interval: Duration, index: usize, count: usize) -> Duration {
+    let count = count.max(1);
+    interval.mul_f64((index % count + 1) as f64 / count as f64)
+}
+
+// Uniform in [0, 1). Good enough to keep instances from lining up; not for anything
+// that needs real randomness.
+pub fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0),
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,181 @@
+use crate::feed::BusPosition;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+
+pub const ROUTE_REASSIGNMENTS_TOTAL: &str = "rapidbro_route_reassignments_total";
+// Long enough that a feed flipping a vehicle to another route for a reload or two doesn't
+// make it vanish from the route it is actually serving.
+pub const DEFAULT_REASSIGN_GRACE_MS: i64 = 120_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReassignPolicy {
+    // The vehicle stays listed on every route it has been reported on.
+    KeepBoth,
+    // The vehicle is listed on its previous route until the grace period runs out.
+    #[default]
+    Move,
+}
+
+impl FromStr for ReassignPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "keep-both" | "keep_both" | "keep" => Ok(ReassignPolicy::KeepBoth),
+            "move" => Ok(ReassignPolicy::Move),
+            other => Err(format!(
+                "unknown reassignment policy `{}`; expected keep-both or move",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteReassignment {
+    pub vehicle_id: String,
+    pub from_route: String,
+    pub to_route: String,
+    pub at_unix_ms: i64,
+    // Since the vehicle was last reported on `from_route`.
+    pub gap_ms: i64,
+}
+
+impl fmt::Display for RouteReassignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} reassigned from route {} to {} after {}s",
+            self.vehicle_id,
+            self.from_route,
+            self.to_route,
+            self.gap_ms / 1_000
+        )
+    }
+}
+
+struct Assignment {
+    route: String,
+    last_seen_ms: i64,
+    // Routes the vehicle is still listed on, with when it left each.
+    previous: Vec<(String, i64)>,
+}
+
+// Notices a vehicle turning up on a different route than it was last reported on, which
+// the feed does when a bus is put on another service mid-shift (or briefly, by mistake).
+// Per-vehicle state built for the old route is stale from then on, so callers reset it.
+//
+// Under ReassignPolicy::Move the vehicle stays listed on the old route until `release`
+// reports it after the grace period; under KeepBoth it is never released.
+pub struct RouteAssignments {
+    vehicles: HashMap<String, Assignment>,
+    // (due, vehicle, route); in due order, since every release waits the same grace period.
+    releases: VecDeque<(i64, String, String)>,
+    policy: ReassignPolicy,
+    grace_ms: i64,
+    route_key: fn(&str) -> String,
+}
+
+impl RouteAssignments {
+    pub fn new(policy: ReassignPolicy, grace_ms: i64, route_key: fn(&str) -> String) -> Self {
+        Self {
+            vehicles: HashMap::new(),
+            releases: VecDeque::new(),
+            policy,
+            grace_ms: grace_ms.max(0),
+            route_key,
+        }
+    }
+
+    pub fn observe(&mut self, bus: &BusPosition, now_ms: i64) -> Option<RouteReassignment> {
+        let route_key = (self.route_key)(&bus.route);
+        if bus.bus_no.is_empty() || route_key.is_empty() {
+            return None;
+        }
+        let Some(assignment) = self.vehicles.get_mut(&bus.bus_no) else {
+            self.vehicles.insert(
+                bus.bus_no.clone(),
+                Assignment {
+                    route: bus.route.clone(),
+                    last_seen_ms: now_ms,
+                    previous: Vec::new(),
+                },
+            );
+            return None;
+        };
+        if (self.route_key)(&assignment.route) == route_key {
+            assignment.last_seen_ms = now_ms;
+            return None;
+        }
+
+        let from_route = std::mem::replace(&mut assignment.route, bus.route.clone());
+        let gap_ms = now_ms - assignment.last_seen_ms;
+        assignment.last_seen_ms = now_ms;
+        // Back on a route it was still listed on: that one is current again.
+        let route_key_fn = self.route_key;
+        assignment
+            .previous
+            .retain(|(route, _)| route_key_fn(route) != route_key);
+        assignment.previous.push((from_route.clone(), now_ms));
+        if self.policy == ReassignPolicy::Move {
+            self.releases.push_back((
+                now_ms + self.grace_ms,
+                bus.bus_no.clone(),
+                from_route.clone(),
+            ));
+        }
+        Some(RouteReassignment {
+            vehicle_id: bus.bus_no.clone(),
+            from_route,
+            to_route: bus.route.clone(),
+            at_unix_ms: now_ms,
+            gap_ms,
+        })
+    }
+
+    // Routes the vehicle is still listed on besides its current one.
+    pub fn previous_routes(&self, vehicle_id: &str) -> Vec<String> {
+        self.vehicles
+            .get(vehicle_id)
+            .map(|assignment| {
+                assignment
+                    .previous
+                    .iter()
+                    .map(|(route, _)| route.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Drops vehicles from the routes they left more than the grace period ago and returns
+    // them as (vehicle, route). Always empty under KeepBoth.
+    pub fn release(&mut self, now_ms: i64) -> Vec<(String, String)> {
+        let mut released = Vec::new();
+        while self
+            .releases
+            .front()
+            .is_some_and(|(due, _, _)| *due <= now_ms)
+        {
+            let Some((_, vehicle_id, route)) = self.releases.pop_front() else {
+                break;
+            };
+            let Some(assignment) = self.vehicles.get_mut(&vehicle_id) else {
+                continue;
+            };
+            let route_key = (self.route_key)(&route);
+            let grace_ms = self.grace_ms;
+            let route_key_fn = self.route_key;
+            // A vehicle that came back and left again since is released by a later entry.
+            let before = assignment.previous.len();
+            assignment.previous.retain(|(previous, left_ms)| {
+                route_key_fn(previous) != route_key || left_ms + grace_ms > now_ms
+            });
+            if assignment.previous.len() < before {
+                released.push((vehicle_id, route));
+            }
+        }
+        released
+    }
+}
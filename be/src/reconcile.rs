@@ -0,0 +1,145 @@
+use crate::feed::BusPosition;
+use crate::geo::haversine_meters;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchedVehicle {
+    // As the websocket spells it.
+    pub vehicle_id: String,
+    pub divergence_meters: f64,
+}
+
+// Which vehicles the websocket and GTFS-rt feeds agree are in service, and how far apart
+// they place the ones they share. Ids are sorted, matches by divergence, largest first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reconciliation {
+    pub websocket_count: usize,
+    pub gtfs_count: usize,
+    pub only_websocket: Vec<String>,
+    pub only_gtfs: Vec<String>,
+    pub matched: Vec<MatchedVehicle>,
+}
+
+impl Reconciliation {
+    pub fn median_divergence_meters(&self) -> Option<f64> {
+        let mut divergences: Vec<f64> = self
+            .matched
+            .iter()
+            .map(|matched| matched.divergence_meters)
+            .collect();
+        if divergences.is_empty() {
+            return None;
+        }
+        divergences.sort_by(|a, b| a.total_cmp(b));
+        let middle = divergences.len() / 2;
+        Some(if divergences.len() % 2 == 0 {
+            (divergences[middle - 1] + divergences[middle]) / 2.0
+        } else {
+            divergences[middle]
+        })
+    }
+
+    pub fn max_divergence_meters(&self) -> Option<f64> {
+        self.matched
+            .first()
+            .map(|matched| matched.divergence_meters)
+    }
+}
+
+impl fmt::Display for Reconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "websocket={} gtfs-rt={} matched={}",
+            self.websocket_count,
+            self.gtfs_count,
+            self.matched.len()
+        )?;
+        if let (Some(median), Some(max)) = (
+            self.median_divergence_meters(),
+            self.max_divergence_meters(),
+        ) {
+            write!(f, " divergence median={:.0}m max={:.0}m", median, max)?;
+        }
+        if !self.only_websocket.is_empty() {
+            write!(
+                f,
+                "\n  only websocket ({}): {}",
+                self.only_websocket.len(),
+                self.only_websocket.join(", ")
+            )?;
+        }
+        if !self.only_gtfs.is_empty() {
+            write!(
+                f,
+                "\n  only gtfs-rt ({}): {}",
+                self.only_gtfs.len(),
+                self.only_gtfs.join(", ")
+            )?;
+        }
+        for matched in &self.matched {
+            write!(
+                f,
+                "\n  {:<10} {:>6.0}m",
+                matched.vehicle_id, matched.divergence_meters
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// The two feeds spell registrations differently ("WXX1234", "wxx 1234", "WXX-1234"), so
+// ids are compared uppercased with anything but letters and digits removed.
+pub fn normalize_vehicle_id(id: &str) -> String {
+    id.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+// Pairs the vehicles of two snapshots by normalized id. A vehicle listed more than once
+// in a snapshot counts by its last entry; entries without a usable id are ignored.
+pub fn reconcile(websocket: &[BusPosition], gtfs: &[BusPosition]) -> Reconciliation {
+    let websocket = by_normalized_id(websocket);
+    let gtfs = by_normalized_id(gtfs);
+
+    let mut reconciliation = Reconciliation {
+        websocket_count: websocket.len(),
+        gtfs_count: gtfs.len(),
+        ..Reconciliation::default()
+    };
+    for (id, socket_bus) in &websocket {
+        match gtfs.get(id) {
+            Some(gtfs_bus) => reconciliation.matched.push(MatchedVehicle {
+                vehicle_id: socket_bus.bus_no.clone(),
+                divergence_meters: haversine_meters(
+                    (socket_bus.latitude, socket_bus.longitude),
+                    (gtfs_bus.latitude, gtfs_bus.longitude),
+                ),
+            }),
+            None => reconciliation
+                .only_websocket
+                .push(socket_bus.bus_no.clone()),
+        }
+    }
+    reconciliation.only_gtfs = gtfs
+        .iter()
+        .filter(|(id, _)| !websocket.contains_key(*id))
+        .map(|(_, bus)| bus.bus_no.clone())
+        .collect();
+    reconciliation.matched.sort_by(|a, b| {
+        b.divergence_meters
+            .total_cmp(&a.divergence_meters)
+            .then_with(|| a.vehicle_id.cmp(&b.vehicle_id))
+    });
+    reconciliation
+}
+
+fn by_normalized_id(buses: &[BusPosition]) -> BTreeMap<String, &BusPosition> {
+    buses
+        .iter()
+        .map(|bus| (normalize_vehicle_id(&bus.bus_no), bus))
+        .filter(|(id, _)| !id.is_empty())
+        .collect()
+}
@@ -0,0 +1,188 @@
+use crate::feed::BusPosition;
+use crate::queue::{BoundedQueue, OverflowPolicy};
+use metrics::counter;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub const CHANNEL_PREFIX: &str = "rapidbro:buses";
+pub const SNAPSHOT_PREFIX: &str = "rapidbro:snapshot";
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1_000;
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+pub const REDIS_PUBSUB_PUBLISHED_TOTAL: &str = "rapidbro_redis_pubsub_published_total";
+pub const REDIS_PUBSUB_ERRORS_TOTAL: &str = "rapidbro_redis_pubsub_errors_total";
+
+#[derive(Debug)]
+struct RouteBatch {
+    route: String,
+    buses: Vec<BusPosition>,
+    // The route has no buses running, so its snapshot starts over.
+    clear: bool,
+}
+
+// Publishes each route's batch as a JSON array to rapidbro:buses:{route}. With a snapshot
+// TTL it also keeps rapidbro:snapshot:{route} set to every bus seen on the route within
+// the TTL, so a new instance can serve something before its first batch arrives.
+//
+// Runs on its own task behind a bounded queue: while Redis is down, batches are dropped
+// (the queue sheds the oldest first) and the connection is retried with backoff, without
+// holding up the socket.
+#[derive(Debug, Clone)]
+pub struct RedisPubSubSink {
+    client: redis::Client,
+    snapshot_ttl: Option<Duration>,
+    queue: BoundedQueue<RouteBatch>,
+}
+
+impl RedisPubSubSink {
+    pub fn new(
+        client: redis::Client,
+        snapshot_ttl: Option<Duration>,
+        overflow_policy: OverflowPolicy,
+    ) -> Self {
+        Self {
+            client,
+            snapshot_ttl: snapshot_ttl.filter(|ttl| !ttl.is_zero()),
+            queue: BoundedQueue::new(DEFAULT_QUEUE_CAPACITY, overflow_policy, Duration::ZERO)
+                .named("redis_pubsub"),
+        }
+    }
+
+    pub async fn enqueue(&self, route: String, buses: Vec<BusPosition>) {
+        self.push(RouteBatch {
+            route,
+            buses,
+            clear: false,
+        })
+        .await;
+    }
+
+    // Publishes an empty batch and empties the route's snapshot, rather than leaving its
+    // last buses there for the rest of the TTL.
+    pub async fn clear(&self, route: String) {
+        self.push(RouteBatch {
+            route,
+            buses: Vec::new(),
+            clear: true,
+        })
+        .await;
+    }
+
+    async fn push(&self, batch: RouteBatch) {
+        if !self.queue.push(batch).await {
+            counter!(REDIS_PUBSUB_ERRORS_TOTAL, "reason" => "queue_full").increment(1);
+        }
+    }
+
+    pub async fn run(&self) {
+        let mut connection: Option<redis::aio::MultiplexedConnection> = None;
+        let mut snapshots: HashMap<String, HashMap<String, (Instant, BusPosition)>> =
+            HashMap::new();
+        let mut reconnect_delay = MIN_RECONNECT_DELAY;
+
+        loop {
+            let batch = self.queue.pop().await;
+            let snapshot: Option<Vec<&BusPosition>> = match self.snapshot_ttl {
+                Some(ttl) => {
+                    let route_snapshot = snapshots.entry(batch.route.clone()).or_default();
+                    if batch.clear {
+                        route_snapshot.clear();
+                    }
+                    let now = Instant::now();
+                    route_snapshot.retain(|_, (seen_at, _)| now.duration_since(*seen_at) < ttl);
+                    for bus in batch.buses.iter().filter(|bus| !bus.bus_no.is_empty()) {
+                        route_snapshot.insert(bus.bus_no.clone(), (now, bus.clone()));
+                    }
+                    Some(route_snapshot.values().map(|(_, bus)| bus).collect())
+                }
+                None => None,
+            };
+
+            let conn = match connection.as_mut() {
+                Some(conn) => Ok(conn),
+                None => self
+                    .client
+                    .get_multiplexed_async_connection()
+                    .await
+                    .map(|conn| connection.insert(conn)),
+            };
+            let result = match conn {
+                Ok(conn) => self.publish(conn, &batch, snapshot.as_deref()).await,
+                Err(error) => Err(error),
+            };
+
+            match result {
+                Ok(()) => {
+                    counter!(REDIS_PUBSUB_PUBLISHED_TOTAL).increment(1);
+                    reconnect_delay = MIN_RECONNECT_DELAY;
+                }
+                Err(error) => {
+                    counter!(REDIS_PUBSUB_ERRORS_TOTAL, "reason" => "publish").increment(1);
+                    println!(
+                        "Redis publish for route {} failed, reconnecting in {}ms: {}",
+                        batch.route,
+                        reconnect_delay.as_millis(),
+                        error
+                    );
+                    // Drop the connection so the next batch reconnects instead of reusing it.
+                    connection = None;
+                    tokio::time::sleep(reconnect_delay).await;
+                    reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            }
+        }
+    }
+
+    async fn publish(
+        &self,
+        connection: &mut redis::aio::MultiplexedConnection,
+        batch: &RouteBatch,
+        snapshot: Option<&[&BusPosition]>,
+    ) -> redis::RedisResult<()> {
+        let payload = serde_json::to_string(&batch.buses).unwrap_or_else(|_| "[]".to_string());
+        let mut pipe = redis::pipe();
+        // Snapshot first, so a subscriber reacting to the message already finds it.
+        if let (Some(snapshot), Some(ttl)) = (snapshot, self.snapshot_ttl) {
+            let snapshot = serde_json::to_string(snapshot).unwrap_or_else(|_| "[]".to_string());
+            pipe.cmd("SET")
+                .arg(snapshot_key_for(&batch.route))
+                .arg(snapshot)
+                .arg("PX")
+                .arg(ttl.as_millis() as u64)
+                .ignore();
+        }
+        pipe.cmd("PUBLISH")
+            .arg(channel_for(&batch.route))
+            .arg(payload)
+            .ignore();
+        pipe.query_async::<()>(connection).await
+    }
+
+    // The route's last snapshot, if one was written within the TTL.
+    pub async fn read_snapshot(&self, route: &str) -> Result<Option<Vec<BusPosition>>, String> {
+        let mut connection = self
+            .client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|error| format!("Redis connection failed: {}", error))?;
+        let raw: Option<String> = redis::cmd("GET")
+            .arg(snapshot_key_for(route))
+            .query_async(&mut connection)
+            .await
+            .map_err(|error| format!("Redis snapshot read failed: {}", error))?;
+        raw.map(|raw| {
+            serde_json::from_str(&raw)
+                .map_err(|error| format!("invalid snapshot for route {}: {}", route, error))
+        })
+        .transpose()
+    }
+}
+
+pub fn channel_for(route: &str) -> String {
+    format!("{}:{}", CHANNEL_PREFIX, route)
+}
+
+pub fn snapshot_key_for(route: &str) -> String {
+    format!("{}:{}", SNAPSHOT_PREFIX, route)
+}
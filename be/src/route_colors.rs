@@ -0,0 +1,61 @@
+// Accepts "RRGGBB", "#RRGGBB" or the short "RGB" form and returns "#RRGGBB".
+pub fn normalize_hex_color(value: &str) -> Option<String> {
+    let hex = value.trim().trim_start_matches('#');
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    match hex.len() {
+        6 => Some(format!("#{}", hex.to_uppercase())),
+        3 => Some(format!(
+            "#{}",
+            hex.chars()
+                .flat_map(|c| [c, c])
+                .collect::<String>()
+                .to_uppercase()
+        )),
+        _ => None,
+    }
+}
+
+// Stable per-route color for routes missing from static GTFS. FNV-1a keeps the hash
+// identical across builds, so a route keeps its color between deploys.
+pub fn fallback_route_colors(route: &str) -> (String, String) {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in route.trim().to_uppercase().bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+
+    let hue = (hash % 360) as f64;
+    let (r, g, b) = hsl_to_rgb(hue, 0.65, 0.45);
+    let luminance = 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+    let text_color = if luminance > 150.0 {
+        "#000000"
+    } else {
+        "#FFFFFF"
+    };
+
+    (
+        format!("#{:02X}{:02X}{:02X}", r, g, b),
+        text_color.to_string(),
+    )
+}
+
+fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> (u8, u8, u8) {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let sector = hue / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    let channel = |value: f64| ((value + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+
+    (channel(r), channel(g), channel(b))
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+// A counter per route that goes up whenever the route's snapshot changes, for ETags on
+// polled endpoints. Reading never blocks a writer for long: the map is only locked to find
+// (or, the first time a route is bumped, add) the route's counter, which is then updated
+// on its own. Unknown routes are at version 0.
+//
+// Counters start again from 0 when the process restarts, so `epoch` (e.g. the start time)
+// goes into every ETag to keep one run's tags from matching another's.
+#[derive(Debug, Clone)]
+pub struct RouteVersions {
+    versions: Arc<RwLock<HashMap<String, Arc<AtomicU64>>>>,
+    epoch: u64,
+    route_key: fn(&str) -> String,
+}
+
+impl RouteVersions {
+    pub fn new(epoch: u64, route_key: fn(&str) -> String) -> Self {
+        Self {
+            versions: Arc::new(RwLock::new(HashMap::new())),
+            epoch,
+            route_key,
+        }
+    }
+
+    pub fn version(&self, route: &str) -> u64 {
+        self.versions
+            .read()
+            .unwrap_or_else(|error| error.into_inner())
+            .get(&(self.route_key)(route))
+            .map(|version| version.load(Ordering::Acquire))
+            .unwrap_or(0)
+    }
+
+    // Returns the new version.
+    pub fn bump(&self, route: &str) -> u64 {
+        let key = (self.route_key)(route);
+        let existing = self
+            .versions
+            .read()
+            .unwrap_or_else(|error| error.into_inner())
+            .get(&key)
+            .cloned();
+        let version = existing.unwrap_or_else(|| {
+            self.versions
+                .write()
+                .unwrap_or_else(|error| error.into_inner())
+                .entry(key)
+                .or_default()
+                .clone()
+        });
+        version.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    // For changes that can't be pinned to one route, e.g. expired vehicles.
+    pub fn bump_all(&self) {
+        for version in self
+            .versions
+            .read()
+            .unwrap_or_else(|error| error.into_inner())
+            .values()
+        {
+            version.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    // A weak ETag for the route's current version. `variant` tells apart representations
+    // of the same snapshot, e.g. different query parameters.
+    pub fn etag(&self, route: &str, variant: u64) -> String {
+        format!(
+            "W/\"{:x}-{}-{:x}\"",
+            self.epoch,
+            self.version(route),
+            variant
+        )
+    }
+}
+
+// Whether an If-None-Match header value matches `etag`, comparing weakly as RFC 9110
+// requires for If-None-Match.
+pub fn if_none_match(header: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    header.trim() == "*"
+        || header
+            .split(',')
+            .any(|candidate| opaque(candidate) == opaque(etag))
+}
@@ -0,0 +1,253 @@
+use crate::field_map::{FieldMap, RAPID_KL_FIELDS};
+use metrics::counter;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+pub const UNKNOWN_FIELDS_TOTAL: &str = "rapidbro_unknown_fields_total";
+pub const SCHEMA_VIOLATIONS_TOTAL: &str = "rapidbro_schema_violations_total";
+
+// What a position can't be placed without: the coordinates and the vehicle.
+const REQUIRED_FIELDS: &[(&str, JsonType)] = &[
+    ("latitude", JsonType::Number),
+    ("longitude", JsonType::Number),
+    ("bus_no", JsonType::String),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JsonType {
+    Number,
+    String,
+}
+
+impl JsonType {
+    fn matches(&self, value: &Value) -> bool {
+        match self {
+            JsonType::Number => value.is_number(),
+            JsonType::String => value.is_string(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            JsonType::Number => "number",
+            JsonType::String => "string",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SchemaViolation {
+    // A key the feed didn't send before; often the first sign of a payload change.
+    UnknownField(String),
+    MissingField(&'static str),
+    WrongType {
+        field: &'static str,
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+impl SchemaViolation {
+    fn kind(&self) -> &'static str {
+        match self {
+            SchemaViolation::UnknownField(_) => "unknown",
+            SchemaViolation::MissingField(_) => "missing",
+            SchemaViolation::WrongType { .. } => "wrong_type",
+        }
+    }
+
+    fn field(&self) -> &str {
+        match self {
+            SchemaViolation::UnknownField(field) => field,
+            SchemaViolation::MissingField(field) => field,
+            SchemaViolation::WrongType { field, .. } => field,
+        }
+    }
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaViolation::UnknownField(field) => {
+                write!(f, "unknown field `{}` observed in payload", field)
+            }
+            SchemaViolation::MissingField(field) => {
+                write!(f, "expected field `{}` missing from payload", field)
+            }
+            SchemaViolation::WrongType {
+                field,
+                expected,
+                found,
+            } => write!(
+                f,
+                "field `{}` is a {} in payload, expected a {}",
+                field, found, expected
+            ),
+        }
+    }
+}
+
+// Checks one position object against the kiosk's layout, already renamed through the
+// field map. Anything that isn't an object is left to the decoder to reject.
+pub fn check_position(entry: &Value) -> Vec<SchemaViolation> {
+    let Value::Object(object) = entry else {
+        return Vec::new();
+    };
+    let mut violations: Vec<SchemaViolation> = object
+        .keys()
+        .filter(|key| !RAPID_KL_FIELDS.contains(&key.as_str()))
+        .map(|key| SchemaViolation::UnknownField(key.clone()))
+        .collect();
+    for (field, expected) in REQUIRED_FIELDS {
+        match object.get(*field) {
+            None => violations.push(SchemaViolation::MissingField(*field)),
+            Some(value) if !expected.matches(value) => {
+                violations.push(SchemaViolation::WrongType {
+                    field: *field,
+                    expected: expected.name(),
+                    found: json_type_name(value),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    violations
+}
+
+fn json_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[derive(Debug, Default)]
+struct SchemaCounts {
+    // Every violation seen, with how many times; the first of each is logged.
+    seen: HashMap<SchemaViolation, u64>,
+}
+
+// Watches decoded payloads for changes to the feed's shape, since Prasarana changes it
+// without notice and a renamed field otherwise only shows up as positions going missing.
+// Each distinct violation is logged once and every occurrence is counted, per field, in
+// UNKNOWN_FIELDS_TOTAL or SCHEMA_VIOLATIONS_TOTAL. A strict monitor also fails the
+// payload (see --strict-schema), for running against recorded fixtures in CI. Clones
+// share their counts.
+#[derive(Debug, Clone, Default)]
+pub struct SchemaMonitor {
+    counts: Arc<Mutex<SchemaCounts>>,
+    strict: bool,
+}
+
+impl SchemaMonitor {
+    pub fn new(strict: bool) -> Self {
+        Self {
+            counts: Arc::default(),
+            strict,
+        }
+    }
+
+    // The same counts, with violations failing the payload or not.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    // `json` is one decompressed payload: a position object or an array of them. Fails
+    // with the payload's distinct violations in strict mode; invalid JSON is left to the
+    // decoder to report.
+    pub fn check(&self, json: &[u8], field_map: &FieldMap) -> Result<(), String> {
+        let Ok(value) = serde_json::from_slice::<Value>(json) else {
+            return Ok(());
+        };
+        let violations = match field_map.apply(value) {
+            Value::Array(entries) => entries.iter().flat_map(check_position).collect(),
+            single => check_position(&single),
+        };
+        if violations.is_empty() {
+            return Ok(());
+        }
+        self.record(&violations);
+        if !self.strict {
+            return Ok(());
+        }
+        let mut distinct: Vec<String> = Vec::new();
+        for violation in &violations {
+            let message = violation.to_string();
+            if !distinct.contains(&message) {
+                distinct.push(message);
+            }
+        }
+        Err(distinct.join("; "))
+    }
+
+    fn record(&self, violations: &[SchemaViolation]) {
+        let mut counts = self
+            .counts
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        for violation in violations {
+            let seen = counts.seen.entry(violation.clone()).or_insert(0);
+            if *seen == 0 {
+                println!("warning: {}", violation);
+            }
+            *seen += 1;
+            let field = violation.field().to_string();
+            match violation {
+                SchemaViolation::UnknownField(_) => {
+                    counter!(UNKNOWN_FIELDS_TOTAL, "field" => field).increment(1)
+                }
+                _ => counter!(
+                    SCHEMA_VIOLATIONS_TOTAL,
+                    "field" => field,
+                    "kind" => violation.kind()
+                )
+                .increment(1),
+            }
+        }
+    }
+
+    // Unknown field names with how often each was seen, most frequent first.
+    pub fn unknown_fields(&self) -> Vec<(String, u64)> {
+        let mut unknown: Vec<(String, u64)> = self
+            .counts
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .seen
+            .iter()
+            .filter_map(|(violation, count)| match violation {
+                SchemaViolation::UnknownField(field) => Some((field.clone(), *count)),
+                _ => None,
+            })
+            .collect();
+        unknown.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        unknown
+    }
+
+    // Every distinct violation with its count, most frequent first.
+    pub fn violations(&self) -> Vec<(SchemaViolation, u64)> {
+        let mut violations: Vec<(SchemaViolation, u64)> = self
+            .counts
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .seen
+            .iter()
+            .map(|(violation, count)| (violation.clone(), *count))
+            .collect();
+        violations.sort_by(|a, b| {
+            b.1.cmp(&a.1)
+                .then_with(|| a.0.to_string().cmp(&b.0.to_string()))
+        });
+        violations
+    }
+}
@@ -0,0 +1,368 @@
+use crate::throttle::throttle_wait;
+use regex::Regex;
+use serde::Serialize;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "server")]
+use utoipa::ToSchema;
+
+pub const DEFAULT_KIOSK_URL: &str = "https://myrapidbus.prasarana.com.my/kiosk";
+pub const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Safari/605.1.15";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub sid: String,
+    pub prm: String,
+    pub route: String,
+}
+
+// What a client is currently using and when it last heard anything, for diagnosing
+// routes that have gone quiet. Times are unix ms.
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+pub struct SessionInfo {
+    pub route: String,
+    pub sid: Option<String>,
+    pub prm: Option<String>,
+    pub last_fetch_unix_ms: Option<i64>,
+    pub connected: bool,
+    // The last frame that decoded to a valid batch, empty or not; failed frames don't count.
+    pub last_data_unix_ms: Option<i64>,
+    // The last frame that decoded to an empty list: the feed is up but no buses are
+    // running on the route, e.g. off-hours.
+    pub last_valid_empty_unix_ms: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ExtractError {
+    MissingVariable(&'static str),
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtractError::MissingVariable(name) => {
+                write!(f, "could not find `{}` in kiosk page", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {}
+
+// Block pages are usually transient rate limiting, so one retry is worth it.
+const BLOCKED_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+const CAPTCHA_MARKERS: &[&str] = &[
+    "captcha",
+    "cf-challenge",
+    "challenge-platform",
+    "cf-turnstile",
+];
+const DENIED_MARKERS: &[&str] = &["access denied", "request blocked", "attention required"];
+
+#[derive(Debug)]
+pub enum RapidbroError {
+    Fetch(String),
+    // The kiosk answered with something other than the kiosk page.
+    Blocked { status: u16, reason: &'static str },
+    // HTTP 429 or 403; `wait` honours Retry-After when the kiosk sends one.
+    Throttled { status: u16, wait: Duration },
+    Extract(ExtractError),
+}
+
+impl RapidbroError {
+    // How long to hold off before fetching again, when the kiosk told us to.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            RapidbroError::Throttled { wait, .. } => Some(*wait),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RapidbroError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RapidbroError::Fetch(message) => write!(f, "Kiosk fetch failed: {}", message),
+            RapidbroError::Blocked { status, reason } => {
+                write!(f, "Kiosk page blocked (HTTP {}): {}", status, reason)
+            }
+            RapidbroError::Throttled { status, wait } => write!(
+                f,
+                "Kiosk is throttling us (HTTP {}), backing off for {}s",
+                status,
+                wait.as_secs()
+            ),
+            RapidbroError::Extract(error) => {
+                write!(f, "Kiosk session extraction failed: {}", error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RapidbroError {}
+
+// Called when the page has no `sid`; names what was served instead.
+pub fn classify_block_page(status: u16, html: &str) -> &'static str {
+    let lower = html.to_lowercase();
+    if CAPTCHA_MARKERS.iter().any(|marker| lower.contains(marker)) {
+        "captcha challenge"
+    } else if DENIED_MARKERS.iter().any(|marker| lower.contains(marker)) || status == 403 {
+        "access denied"
+    } else if status == 429 {
+        "rate limited"
+    } else if status >= 500 {
+        "server error page"
+    } else {
+        "unexpected page without session marker"
+    }
+}
+
+// Pulls the session variables out of a kiosk page. SessionExtractors tries several in
+// turn, so a change to the page can be handled with --sid-pattern and friends rather than
+// a new release.
+pub trait SessionExtractor: fmt::Debug + Send + Sync {
+    // Logged when this extractor is the one that read the session.
+    fn name(&self) -> &str;
+
+    fn extract(&self, html: &str) -> Result<Session, ExtractError>;
+}
+
+// One pattern per variable, each with a capture group around the value; the first group
+// that matched is used. The default reads the variables the kiosk's inline script declares.
+#[derive(Debug, Clone)]
+pub struct RegexExtractor {
+    name: String,
+    sid: Regex,
+    prm: Regex,
+    route: Regex,
+}
+
+impl Default for RegexExtractor {
+    fn default() -> Self {
+        Self {
+            name: "script-variables".to_string(),
+            sid: js_string_pattern("sid"),
+            prm: js_string_pattern("prm"),
+            route: js_string_pattern("no_route"),
+        }
+    }
+}
+
+impl RegexExtractor {
+    // Operator-supplied patterns; a variable without one keeps the default pattern.
+    pub fn custom(sid: Option<Regex>, prm: Option<Regex>, route: Option<Regex>) -> Self {
+        let default = Self::default();
+        Self {
+            name: "custom-patterns".to_string(),
+            sid: sid.unwrap_or(default.sid),
+            prm: prm.unwrap_or(default.prm),
+            route: route.unwrap_or(default.route),
+        }
+    }
+}
+
+impl SessionExtractor for RegexExtractor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn extract(&self, html: &str) -> Result<Session, ExtractError> {
+        Ok(Session {
+            sid: capture_value(&self.sid, html, "sid")?,
+            prm: capture_value(&self.prm, html, "prm")?,
+            route: capture_value(&self.route, html, "no_route")?,
+        })
+    }
+}
+
+// Looser than the default: also finds the variables as object properties, JSON keys,
+// data attributes or query parameters, e.g. `sid: "..."`, `"sid":"..."`,
+// `data-sid="..."` or `?sid=...`, for when they move out of the inline script.
+#[derive(Debug, Clone)]
+pub struct FallbackExtractor {
+    sid: Regex,
+    prm: Regex,
+    route: Regex,
+}
+
+impl Default for FallbackExtractor {
+    fn default() -> Self {
+        Self {
+            sid: key_value_pattern("sid"),
+            prm: key_value_pattern("prm"),
+            route: key_value_pattern("no_route"),
+        }
+    }
+}
+
+impl SessionExtractor for FallbackExtractor {
+    fn name(&self) -> &str {
+        "fallback"
+    }
+
+    fn extract(&self, html: &str) -> Result<Session, ExtractError> {
+        Ok(Session {
+            sid: capture_value(&self.sid, html, "sid")?,
+            prm: capture_value(&self.prm, html, "prm")?,
+            route: capture_value(&self.route, html, "no_route")?,
+        })
+    }
+}
+
+// The extractors a client tries, in order, until one finds a non-empty sid. By default
+// the script-variable patterns and then the fallback.
+#[derive(Debug, Clone)]
+pub struct SessionExtractors {
+    extractors: Vec<Arc<dyn SessionExtractor>>,
+}
+
+impl Default for SessionExtractors {
+    fn default() -> Self {
+        Self {
+            extractors: vec![
+                Arc::new(RegexExtractor::default()),
+                Arc::new(FallbackExtractor::default()),
+            ],
+        }
+    }
+}
+
+impl SessionExtractors {
+    pub fn new(extractors: Vec<Arc<dyn SessionExtractor>>) -> Self {
+        Self { extractors }
+    }
+
+    // Tries `extractor` before the ones already registered.
+    pub fn try_first(mut self, extractor: Arc<dyn SessionExtractor>) -> Self {
+        self.extractors.insert(0, extractor);
+        self
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.extractors
+            .iter()
+            .map(|extractor| extractor.name())
+            .collect()
+    }
+
+    // When none finds a non-empty sid, the first other outcome stands: a session with an
+    // empty sid, or the variable an extractor that did find the sid was missing.
+    // MissingVariable("sid") means no extractor recognised the page at all.
+    pub fn extract(&self, html: &str) -> Result<Session, ExtractError> {
+        let mut outcome = None;
+        for (index, extractor) in self.extractors.iter().enumerate() {
+            match extractor.extract(html) {
+                Ok(session) if !session.sid.is_empty() => {
+                    if index > 0 {
+                        println!(
+                            "Kiosk session read by the {} extractor; the page layout may have changed",
+                            extractor.name()
+                        );
+                    }
+                    return Ok(session);
+                }
+                Err(ExtractError::MissingVariable("sid")) => {}
+                other => {
+                    outcome.get_or_insert(other);
+                }
+            }
+        }
+        outcome.unwrap_or(Err(ExtractError::MissingVariable("sid")))
+    }
+}
+
+// Pull the session variables the kiosk page embeds in its inline script.
+pub fn extract_session(html: &str) -> Result<Session, ExtractError> {
+    SessionExtractors::default().extract(html)
+}
+
+fn capture_value(pattern: &Regex, html: &str, name: &'static str) -> Result<String, ExtractError> {
+    pattern
+        .captures(html)
+        .and_then(|captures| captures.iter().skip(1).flatten().next())
+        .map(|value| value.as_str().to_string())
+        .ok_or(ExtractError::MissingVariable(name))
+}
+
+// Matches `var name = "value"` with any declaration keyword or quote style, and
+// the `,name="value"` form minifiers produce when they merge declarations.
+fn js_string_pattern(name: &str) -> Regex {
+    Regex::new(&format!(
+        r#"(?:\b(?:var|let|const)\s+|,\s*){}\s*=\s*(?:"([^"]*)"|'([^']*)')"#,
+        regex::escape(name)
+    ))
+    .expect("session pattern is valid")
+}
+
+// `name` as a key, optionally quoted, followed by `:` or `=` and a quoted or bare value.
+fn key_value_pattern(name: &str) -> Regex {
+    Regex::new(&format!(
+        r#"(?:\b|["'])(?:data-)?{}["']?\s*[:=]\s*(?:"([^"]*)"|'([^']*)'|([^"'\s&,;<>]+))"#,
+        regex::escape(name)
+    ))
+    .expect("session pattern is valid")
+}
+
+pub async fn fetch_session(
+    http: &reqwest::Client,
+    kiosk_url: &str,
+    route: &str,
+    extractors: &SessionExtractors,
+) -> Result<Session, RapidbroError> {
+    match fetch_session_once(http, kiosk_url, route, extractors).await {
+        Err(RapidbroError::Blocked { status, reason }) => {
+            println!(
+                "Kiosk page blocked (HTTP {}, {}); retrying in {}s",
+                status,
+                reason,
+                BLOCKED_RETRY_DELAY.as_secs()
+            );
+            tokio::time::sleep(BLOCKED_RETRY_DELAY).await;
+            fetch_session_once(http, kiosk_url, route, extractors).await
+        }
+        Err(RapidbroError::Throttled { status, wait }) => {
+            // Left to the caller to wait out, since a Retry-After can run to minutes.
+            println!(
+                "Kiosk is throttling us (HTTP {}); waiting {}s before the next fetch",
+                status,
+                wait.as_secs()
+            );
+            Err(RapidbroError::Throttled { status, wait })
+        }
+        result => result,
+    }
+}
+
+async fn fetch_session_once(
+    http: &reqwest::Client,
+    kiosk_url: &str,
+    route: &str,
+    extractors: &SessionExtractors,
+) -> Result<Session, RapidbroError> {
+    let response = http
+        .get(kiosk_url)
+        .query(&[("route", route)])
+        .send()
+        .await
+        .map_err(|error| RapidbroError::Fetch(error.to_string()))?;
+    let status = response.status().as_u16();
+    if let Some(wait) = throttle_wait(status, response.headers()) {
+        return Err(RapidbroError::Throttled { status, wait });
+    }
+    let html = response
+        .text()
+        .await
+        .map_err(|error| RapidbroError::Fetch(format!("body read failed: {}", error)))?;
+
+    match extractors.extract(&html) {
+        Err(ExtractError::MissingVariable("sid")) => Err(RapidbroError::Blocked {
+            status,
+            reason: classify_block_page(status, &html),
+        }),
+        result => result.map_err(RapidbroError::Extract),
+    }
+}
@@ -0,0 +1,131 @@
+use crate::now_unix_ms;
+use crate::session::Session;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// A kiosk session as it was when it last produced data, plus the kiosk cookies that
+// went with it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedSession {
+    pub sid: String,
+    pub prm: String,
+    // Route code as the kiosk spelled it, which is what the subscribe emit sends.
+    pub route: String,
+    // Cookie header value for the kiosk URL, "name=value; name=value".
+    pub cookies: String,
+    pub saved_at_unix_ms: i64,
+}
+
+// Sessions by route, persisted to one JSON file shared by every client. The file holds
+// live session ids and cookies, so it's written owner-only.
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    path: PathBuf,
+    sessions: Arc<Mutex<BTreeMap<String, CachedSession>>>,
+}
+
+impl CachedSession {
+    pub fn session(&self) -> Session {
+        Session {
+            sid: self.sid.clone(),
+            prm: self.prm.clone(),
+            route: self.route.clone(),
+        }
+    }
+}
+
+impl SessionStore {
+    // A missing file starts empty; an unreadable one is reported and ignored rather than
+    // keeping the service from starting.
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let sessions = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|error| {
+                println!(
+                    "Ignoring unreadable session state '{}': {}",
+                    path.display(),
+                    error
+                );
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Self {
+            path,
+            sessions: Arc::new(Mutex::new(sessions)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, route: &str) -> Option<CachedSession> {
+        self.lock().get(route).cloned()
+    }
+
+    pub fn save(&self, route: &str, session: &Session, cookies: &str) {
+        let mut sessions = self.lock();
+        sessions.insert(
+            route.to_string(),
+            CachedSession {
+                sid: session.sid.clone(),
+                prm: session.prm.clone(),
+                route: session.route.clone(),
+                cookies: cookies.to_string(),
+                saved_at_unix_ms: now_unix_ms(),
+            },
+        );
+        self.persist(&sessions);
+    }
+
+    pub fn forget(&self, route: &str) {
+        let mut sessions = self.lock();
+        if sessions.remove(route).is_some() {
+            self.persist(&sessions);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, CachedSession>> {
+        self.sessions
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+
+    // Written under the lock so concurrent clients can't interleave partial files.
+    fn persist(&self, sessions: &BTreeMap<String, CachedSession>) {
+        if let Err(error) = write_private(&self.path, sessions) {
+            println!(
+                "Failed to write session state '{}': {}",
+                self.path.display(),
+                error
+            );
+        }
+    }
+}
+
+fn write_private(path: &Path, sessions: &BTreeMap<String, CachedSession>) -> Result<(), String> {
+    let serialized = serde_json::to_vec_pretty(sessions).map_err(|error| error.to_string())?;
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(&temp_path)
+        .map_err(|error| error.to_string())?;
+    file.write_all(&serialized)
+        .map_err(|error| error.to_string())?;
+    file.sync_all().map_err(|error| error.to_string())?;
+    std::fs::rename(&temp_path, path).map_err(|error| error.to_string())
+}
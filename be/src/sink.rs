@@ -0,0 +1,286 @@
+use crate::queue::{BoundedQueue, OverflowPolicy, DEFAULT_BLOCK_TIMEOUT, DEFAULT_QUEUE_CAPACITY};
+use crate::updates::BusUpdate;
+use async_trait::async_trait;
+use metrics::counter;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+pub const SINK_ERRORS_TOTAL: &str = "rapidbro_sink_errors_total";
+pub const SINK_BATCHES_DROPPED_TOTAL: &str = "rapidbro_sink_batches_dropped_total";
+
+#[derive(Debug)]
+pub enum SinkError {
+    Io(std::io::Error),
+    Encode(serde_json::Error),
+    // Anything else a sink wants to report, e.g. a rejected request.
+    Other(String),
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkError::Io(error) => write!(f, "I/O error: {}", error),
+            SinkError::Encode(error) => write!(f, "encoding failed: {}", error),
+            SinkError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SinkError {}
+
+impl From<std::io::Error> for SinkError {
+    fn from(error: std::io::Error) -> Self {
+        SinkError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for SinkError {
+    fn from(error: serde_json::Error) -> Self {
+        SinkError::Encode(error)
+    }
+}
+
+// Somewhere a client's updates go, registered with RapidbroClientBuilder::sink. Each batch
+// is what the client decoded from one socket message, or a Gap if the sink fell behind.
+#[async_trait]
+pub trait Sink: Send + Sync {
+    // Labels the sink's error count and log lines.
+    fn name(&self) -> &str;
+
+    async fn handle(&self, batch: &[BusUpdate]) -> Result<(), SinkError>;
+
+    async fn flush(&self) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    // Called once when the client stops; nothing is handled after it.
+    async fn shutdown(&self) -> Result<(), SinkError> {
+        self.flush().await
+    }
+}
+
+struct Registered {
+    sink: Box<dyn Sink>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    errors: AtomicU64,
+}
+
+impl Registered {
+    fn report(&self, step: &str, result: Result<(), SinkError>) {
+        if let Err(error) = result {
+            let name = self.sink.name().to_string();
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            counter!(SINK_ERRORS_TOTAL, "sink" => name.clone()).increment(1);
+            println!("Sink {} {} failed: {}", name, step, error);
+        }
+    }
+}
+
+// The sinks a client (or the server) fans each batch out to. Each runs in its own task
+// behind its own queue, so a slow sink only falls behind itself, and an error from one is
+// counted and logged without holding up or stopping the others.
+#[derive(Clone, Default)]
+pub struct SinkSet {
+    sinks: Vec<Arc<Registered>>,
+}
+
+impl fmt::Debug for SinkSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.sinks.iter().map(|registered| registered.sink.name()))
+            .finish()
+    }
+}
+
+impl SinkSet {
+    // Queues up to DEFAULT_QUEUE_CAPACITY batches for the sink, dropping the oldest past it.
+    pub fn push(&mut self, sink: Box<dyn Sink>) {
+        self.push_queued(sink, DEFAULT_QUEUE_CAPACITY, OverflowPolicy::DropOldest);
+    }
+
+    pub fn push_queued(&mut self, sink: Box<dyn Sink>, capacity: usize, policy: OverflowPolicy) {
+        self.sinks.push(Arc::new(Registered {
+            sink,
+            capacity,
+            policy,
+            errors: AtomicU64::new(0),
+        }));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    // Starts one task per sink; feed them with SinkWorkers::send.
+    pub fn spawn(&self) -> SinkWorkers {
+        let workers = self
+            .sinks
+            .iter()
+            .map(|registered| {
+                let queue = BoundedQueue::new(
+                    registered.capacity,
+                    registered.policy,
+                    DEFAULT_BLOCK_TIMEOUT,
+                );
+                let done = CancellationToken::new();
+                tokio::spawn(run_sink(registered.clone(), queue.clone(), done.clone()));
+                Worker {
+                    registered: registered.clone(),
+                    queue,
+                    done,
+                }
+            })
+            .collect();
+        SinkWorkers {
+            workers: Arc::new(workers),
+        }
+    }
+
+    // (name, errors so far) for every sink, in registration order.
+    pub fn error_counts(&self) -> Vec<(String, u64)> {
+        self.sinks
+            .iter()
+            .map(|registered| {
+                (
+                    registered.sink.name().to_string(),
+                    registered.errors.load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+}
+
+// None asks the task to shut its sink down once the batches ahead of it are handled.
+type SinkQueue = BoundedQueue<Option<Arc<[BusUpdate]>>>;
+
+struct Worker {
+    registered: Arc<Registered>,
+    queue: SinkQueue,
+    // Cancelled once the sink has shut down.
+    done: CancellationToken,
+}
+
+// The running tasks of a SinkSet. Clones feed the same tasks.
+#[derive(Clone)]
+pub struct SinkWorkers {
+    workers: Arc<Vec<Worker>>,
+}
+
+impl SinkWorkers {
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+
+    // Queues the batch for every sink without waiting for any of them to handle it.
+    pub async fn send(&self, batch: Vec<BusUpdate>) {
+        let batch: Arc<[BusUpdate]> = batch.into();
+        for worker in self.workers.iter() {
+            if !worker.queue.push(Some(batch.clone())).await {
+                let name = worker.registered.sink.name().to_string();
+                counter!(SINK_BATCHES_DROPPED_TOTAL, "sink" => name).increment(1);
+            }
+        }
+    }
+
+    // Lets each sink handle what is already queued, then shuts it down and waits for that.
+    pub async fn shutdown(&self) {
+        for worker in self.workers.iter() {
+            worker.queue.push(None).await;
+        }
+        for worker in self.workers.iter() {
+            worker.done.cancelled().await;
+        }
+    }
+}
+
+async fn run_sink(registered: Arc<Registered>, queue: SinkQueue, done: CancellationToken) {
+    let _done = done.drop_guard();
+    while let Some(batch) = queue.pop().await {
+        registered.report("handle", registered.sink.handle(&batch).await);
+    }
+    registered.report("shutdown", registered.sink.shutdown().await);
+}
+
+// Writes each position as a line of JSON to stdout.
+pub struct StdoutSink;
+
+#[async_trait]
+impl Sink for StdoutSink {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+
+    async fn handle(&self, batch: &[BusUpdate]) -> Result<(), SinkError> {
+        let mut lines = Vec::new();
+        for update in batch {
+            write_json_line(&mut lines, update)?;
+        }
+        let mut stdout = tokio::io::stdout();
+        stdout.write_all(&lines).await?;
+        stdout.flush().await?;
+        Ok(())
+    }
+}
+
+// Appends each position as a line of JSON to a file, e.g. for replaying a session later.
+// Writes are buffered until `flush`.
+pub struct JsonLinesSink {
+    path: PathBuf,
+    file: Mutex<BufWriter<File>>,
+}
+
+impl JsonLinesSink {
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, SinkError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        Ok(Self {
+            path,
+            file: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[async_trait]
+impl Sink for JsonLinesSink {
+    fn name(&self) -> &str {
+        "json-lines"
+    }
+
+    async fn handle(&self, batch: &[BusUpdate]) -> Result<(), SinkError> {
+        let mut lines = Vec::new();
+        for update in batch {
+            write_json_line(&mut lines, update)?;
+        }
+        self.file.lock().await.write_all(&lines).await?;
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), SinkError> {
+        self.file.lock().await.flush().await?;
+        Ok(())
+    }
+}
+
+// Gaps are left out: a line per position is what a reader of the file expects.
+fn write_json_line(lines: &mut Vec<u8>, update: &BusUpdate) -> Result<(), SinkError> {
+    if let BusUpdate::Position(bus) = update {
+        serde_json::to_writer(&mut *lines, bus)?;
+        lines.push(b'\n');
+    }
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use crate::feed::BusPosition;
+use chrono::DateTime;
+use std::collections::{HashMap, VecDeque};
+
+pub const DEFAULT_WINDOW_SAMPLES: usize = 3;
+
+struct SpeedWindow {
+    samples: VecDeque<f64>,
+    // Fix time (unix ms) of the newest sample.
+    last_fix_ms: i64,
+}
+
+// Simple moving average of the feed's per-frame speed over the last few fixes of each
+// vehicle. A vehicle silent for longer than `reset_after_ms` starts a fresh window, so
+// a bus leaving a depot doesn't average against its speed from an hour ago.
+pub struct SpeedSmoother {
+    vehicles: HashMap<String, SpeedWindow>,
+    window_samples: usize,
+    reset_after_ms: i64,
+}
+
+impl SpeedSmoother {
+    pub fn new(window_samples: usize, reset_after_ms: i64) -> Self {
+        Self {
+            vehicles: HashMap::new(),
+            window_samples: window_samples.max(1),
+            reset_after_ms,
+        }
+    }
+
+    // Sets smoothed_speed_kmh; a repeated fix reports the current average without
+    // adding a sample.
+    pub fn annotate(&mut self, bus: &mut BusPosition, received_at_unix_ms: i64) {
+        if bus.bus_no.is_empty() || !bus.speed.is_finite() || bus.speed < 0.0 {
+            return;
+        }
+        let fix_ms = bus
+            .timestamp_rfc3339
+            .as_deref()
+            .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+            .map(|timestamp| timestamp.timestamp_millis())
+            .unwrap_or(received_at_unix_ms);
+
+        let window = self
+            .vehicles
+            .entry(bus.bus_no.clone())
+            .or_insert_with(|| SpeedWindow {
+                samples: VecDeque::new(),
+                last_fix_ms: fix_ms,
+            });
+        if fix_ms - window.last_fix_ms > self.reset_after_ms {
+            window.samples.clear();
+        }
+        if window.samples.is_empty() || fix_ms > window.last_fix_ms {
+            window.samples.push_back(bus.speed);
+            while window.samples.len() > self.window_samples {
+                window.samples.pop_front();
+            }
+            window.last_fix_ms = fix_ms;
+        }
+
+        let average = window.samples.iter().sum::<f64>() / window.samples.len() as f64;
+        bus.smoothed_speed_kmh = Some((average * 10.0).round() / 10.0);
+    }
+}
@@ -0,0 +1,245 @@
+use crate::feed::BusPosition;
+use crate::geo::haversine_meters;
+use chrono::DateTime;
+use metrics::{counter, histogram};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+
+// Receive time minus GPS fix time, for fixes that aren't ahead of our clock.
+pub const FEED_LATENCY_SECONDS: &str = "rapidbro_feed_latency_seconds";
+// Fixes stamped later than they were received: the AVL unit's clock is ahead of ours.
+pub const CLOCK_SKEW_RECORDS_TOTAL: &str = "rapidbro_clock_skew_records_total";
+
+// Speeds above this between two fixes are GPS jumps, not driving.
+const MAX_PLAUSIBLE_SPEED_KMH: f64 = 150.0;
+// How many recent latencies (and skews) per route the percentiles are taken over.
+const LATENCY_WINDOW: usize = 512;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RouteStats {
+    pub unique_vehicles: usize,
+    pub total_updates: u64,
+    pub avg_update_interval_seconds: Option<f64>,
+    pub avg_speed_kmh: Option<f64>,
+    pub max_speed_kmh: Option<f64>,
+    pub stale_records: u64,
+    pub duplicate_records: u64,
+    pub off_route_records: u64,
+    // Over the route's last LATENCY_WINDOW fixes that weren't ahead of our clock.
+    pub latency_p50_seconds: Option<f64>,
+    pub latency_p95_seconds: Option<f64>,
+    // Fixes timestamped after they arrived, and the median of how far ahead they were.
+    pub skewed_records: u64,
+    pub clock_skew_seconds: Option<f64>,
+}
+
+impl fmt::Display for RouteStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let optional = |value: Option<f64>, unit: &str| {
+            value
+                .map(|value| format!("{:.1}{}", value, unit))
+                .unwrap_or_else(|| "-".to_string())
+        };
+        write!(
+            f,
+            "vehicles={} updates={} avg_interval={} avg_speed={} max_speed={} stale={} duplicate={} off_route={} latency_p50={} latency_p95={} skewed={} clock_skew={}",
+            self.unique_vehicles,
+            self.total_updates,
+            optional(self.avg_update_interval_seconds, "s"),
+            optional(self.avg_speed_kmh, "km/h"),
+            optional(self.max_speed_kmh, "km/h"),
+            self.stale_records,
+            self.duplicate_records,
+            self.off_route_records,
+            optional(self.latency_p50_seconds, "s"),
+            optional(self.latency_p95_seconds, "s"),
+            self.skewed_records,
+            optional(self.clock_skew_seconds, "s")
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct RouteAccumulator {
+    vehicles: HashSet<String>,
+    total_updates: u64,
+    interval_seconds_sum: f64,
+    interval_count: u64,
+    speed_kmh_sum: f64,
+    speed_count: u64,
+    max_speed_kmh: Option<f64>,
+    stale_records: u64,
+    duplicate_records: u64,
+    off_route_records: u64,
+    latencies_ms: VecDeque<i64>,
+    skewed_records: u64,
+    skews_ms: VecDeque<i64>,
+}
+
+impl RouteAccumulator {
+    // A negative latency is clock skew, not a fast feed: it's counted and kept apart
+    // instead of being clamped to zero and dragging the percentiles down.
+    fn observe_latency(&mut self, latency_ms: i64) {
+        if latency_ms < 0 {
+            self.skewed_records += 1;
+            counter!(CLOCK_SKEW_RECORDS_TOTAL).increment(1);
+            push_rolling(&mut self.skews_ms, -latency_ms);
+        } else {
+            histogram!(FEED_LATENCY_SECONDS).record(latency_ms as f64 / 1_000.0);
+            push_rolling(&mut self.latencies_ms, latency_ms);
+        }
+    }
+}
+
+fn push_rolling(window: &mut VecDeque<i64>, value: i64) {
+    if window.len() == LATENCY_WINDOW {
+        window.pop_front();
+    }
+    window.push_back(value);
+}
+
+// Nearest-rank percentile, in seconds.
+fn percentile_seconds(window: &VecDeque<i64>, percentile: f64) -> Option<f64> {
+    if window.is_empty() {
+        return None;
+    }
+    let mut sorted: Vec<i64> = window.iter().copied().collect();
+    sorted.sort_unstable();
+    let rank = ((percentile / 100.0 * sorted.len() as f64).ceil() as usize).max(1);
+    Some(sorted[rank - 1] as f64 / 1_000.0)
+}
+
+#[derive(Debug, Default)]
+struct VehicleTrack {
+    last_fix: Option<String>,
+    // (lat, lon, fix time) of the last accepted update; cleared on reconnect.
+    last_position: Option<(f64, f64, i64)>,
+}
+
+// Per-route service statistics accumulated from the pipeline. Records already seen
+// (same vehicle and fix time) count as duplicates, so a reconnect that replays the
+// current state doesn't inflate the update counts.
+#[derive(Debug)]
+pub struct ServiceStats {
+    routes: HashMap<String, RouteAccumulator>,
+    vehicles: HashMap<String, VehicleTrack>,
+    stale_after_seconds: i64,
+}
+
+impl ServiceStats {
+    pub fn new(stale_after_seconds: i64) -> Self {
+        Self {
+            routes: HashMap::new(),
+            vehicles: HashMap::new(),
+            stale_after_seconds,
+        }
+    }
+
+    // `received_at_unix_ms` is when the batch carrying `bus` arrived, for its feed latency.
+    pub fn record(&mut self, bus: &BusPosition, received_at_unix_ms: i64) {
+        if bus.bus_no.is_empty() {
+            return;
+        }
+        let route = self.routes.entry(bus.route.clone()).or_default();
+        let vehicle = self.vehicles.entry(bus.bus_no.clone()).or_default();
+
+        let fix = bus.timestamp_rfc3339.clone().or_else(|| bus.dt_gps.clone());
+        if fix.is_some() && fix == vehicle.last_fix {
+            route.duplicate_records += 1;
+            return;
+        }
+        vehicle.last_fix = fix;
+
+        let fix_ms = bus
+            .timestamp_rfc3339
+            .as_deref()
+            .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+            .map(|timestamp| timestamp.timestamp_millis());
+        // Stale and off-route fixes still say how late the feed is.
+        if let Some(fix_ms) = fix_ms {
+            route.observe_latency(received_at_unix_ms - fix_ms);
+        }
+
+        if bus
+            .age_seconds
+            .is_some_and(|age| age > self.stale_after_seconds)
+        {
+            route.stale_records += 1;
+            return;
+        }
+        if bus.off_route {
+            route.off_route_records += 1;
+            return;
+        }
+
+        route.total_updates += 1;
+        route.vehicles.insert(bus.bus_no.clone());
+
+        let Some(fix_ms) = fix_ms else {
+            return;
+        };
+
+        if let Some((last_lat, last_lon, last_ms)) = vehicle.last_position {
+            let elapsed_seconds = (fix_ms - last_ms) as f64 / 1_000.0;
+            if elapsed_seconds > 0.0 {
+                route.interval_seconds_sum += elapsed_seconds;
+                route.interval_count += 1;
+
+                let speed_kmh =
+                    haversine_meters((last_lat, last_lon), (bus.latitude, bus.longitude))
+                        / elapsed_seconds
+                        * 3.6;
+                if speed_kmh <= MAX_PLAUSIBLE_SPEED_KMH {
+                    route.speed_kmh_sum += speed_kmh;
+                    route.speed_count += 1;
+                    route.max_speed_kmh = Some(route.max_speed_kmh.unwrap_or(0.0).max(speed_kmh));
+                }
+            }
+        }
+        vehicle.last_position = Some((bus.latitude, bus.longitude, fix_ms));
+    }
+
+    // The gap while disconnected isn't an update interval; keep last fixes for dedupe.
+    pub fn on_reconnect(&mut self) {
+        for vehicle in self.vehicles.values_mut() {
+            vehicle.last_position = None;
+        }
+    }
+
+    // The jump from the last fix on the old route isn't an interval on the new one.
+    pub fn on_reassigned(&mut self, bus_no: &str) {
+        if let Some(vehicle) = self.vehicles.get_mut(bus_no) {
+            vehicle.last_position = None;
+        }
+    }
+
+    pub fn summary(&self) -> BTreeMap<String, RouteStats> {
+        self.routes
+            .iter()
+            .map(|(route, accumulator)| {
+                let average = |sum: f64, count: u64| (count > 0).then(|| sum / count as f64);
+                (
+                    route.clone(),
+                    RouteStats {
+                        unique_vehicles: accumulator.vehicles.len(),
+                        total_updates: accumulator.total_updates,
+                        avg_update_interval_seconds: average(
+                            accumulator.interval_seconds_sum,
+                            accumulator.interval_count,
+                        ),
+                        avg_speed_kmh: average(accumulator.speed_kmh_sum, accumulator.speed_count),
+                        max_speed_kmh: accumulator.max_speed_kmh,
+                        stale_records: accumulator.stale_records,
+                        duplicate_records: accumulator.duplicate_records,
+                        off_route_records: accumulator.off_route_records,
+                        latency_p50_seconds: percentile_seconds(&accumulator.latencies_ms, 50.0),
+                        latency_p95_seconds: percentile_seconds(&accumulator.latencies_ms, 95.0),
+                        skewed_records: accumulator.skewed_records,
+                        clock_skew_seconds: percentile_seconds(&accumulator.skews_ms, 50.0),
+                    },
+                )
+            })
+            .collect()
+    }
+}
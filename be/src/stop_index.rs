@@ -0,0 +1,99 @@
+use crate::validate::BoundingBox;
+use serde::Serialize;
+use std::collections::HashMap;
+#[cfg(feature = "server")]
+use utoipa::ToSchema;
+
+// About a kilometre at Klang Valley latitudes: a map viewport spans a few dozen cells, and
+// a cell holds a handful of stops.
+pub const DEFAULT_CELL_DEGREES: f64 = 0.01;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "server", derive(ToSchema))]
+pub struct StopLocation {
+    pub stop_id: String,
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+// The static GTFS stops bucketed into a lat/lon grid once at startup, so a bbox query
+// only looks at the cells it overlaps instead of every stop.
+#[derive(Debug, Clone)]
+pub struct StopIndex {
+    stops: Vec<StopLocation>,
+    cell_degrees: f64,
+    // Cell -> indexes into `stops`.
+    cells: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl StopIndex {
+    pub fn new(stops: Vec<StopLocation>) -> Self {
+        Self::with_cell_size(stops, DEFAULT_CELL_DEGREES)
+    }
+
+    pub fn with_cell_size(mut stops: Vec<StopLocation>, cell_degrees: f64) -> Self {
+        // Stops without a usable position can't be placed on the map.
+        stops.retain(|stop| stop.lat.is_finite() && stop.lon.is_finite());
+        stops.sort_by(|a, b| a.stop_id.cmp(&b.stop_id));
+        let mut cells: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+        for (index, stop) in stops.iter().enumerate() {
+            cells
+                .entry(cell_of(stop.lat, stop.lon, cell_degrees))
+                .or_default()
+                .push(index);
+        }
+        Self {
+            stops,
+            cell_degrees,
+            cells,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.stops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stops.is_empty()
+    }
+
+    // Every stop, ordered by stop_id.
+    pub fn all(&self) -> &[StopLocation] {
+        &self.stops
+    }
+
+    // The stops inside `bbox` (edges included), ordered by stop_id.
+    pub fn within(&self, bbox: &BoundingBox) -> Vec<&StopLocation> {
+        let (min_lat_cell, min_lon_cell) = cell_of(bbox.min_lat, bbox.min_lon, self.cell_degrees);
+        let (max_lat_cell, max_lon_cell) = cell_of(bbox.max_lat, bbox.max_lon, self.cell_degrees);
+        let cell_count =
+            (max_lat_cell - min_lat_cell + 1).saturating_mul(max_lon_cell - min_lon_cell + 1);
+        // A bbox covering most of the map is cheaper to answer by scanning.
+        let mut indexes: Vec<usize> = if cell_count as usize >= self.cells.len() {
+            (0..self.stops.len()).collect()
+        } else {
+            (min_lat_cell..=max_lat_cell)
+                .flat_map(|lat_cell| {
+                    (min_lon_cell..=max_lon_cell)
+                        .filter_map(move |lon_cell| self.cells.get(&(lat_cell, lon_cell)))
+                })
+                .flatten()
+                .copied()
+                .collect()
+        };
+        indexes.sort_unstable();
+        indexes
+            .into_iter()
+            .map(|index| &self.stops[index])
+            .filter(|stop| bbox.contains(stop.lat, stop.lon))
+            .collect()
+    }
+}
+
+fn cell_of(lat: f64, lon: f64, cell_degrees: f64) -> (i64, i64) {
+    (
+        (lat / cell_degrees).floor() as i64,
+        (lon / cell_degrees).floor() as i64,
+    )
+}
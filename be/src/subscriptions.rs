@@ -0,0 +1,90 @@
+use metrics::gauge;
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc;
+
+pub const ROUTE_SUBSCRIBED: &str = "rapidbro_route_subscribed";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteChange {
+    Add(String),
+    Remove(String),
+}
+
+// The live route subscription set. Changes are recorded here at once, so readers see
+// the new set immediately, and queued for the ingestor to open or close connections.
+// An empty set follows every bus, as when serve starts without --route. Each route is
+// one feed connection, so `max_routes` is --max-connections.
+#[derive(Debug, Clone)]
+pub struct RouteControl {
+    routes: Arc<RwLock<BTreeSet<String>>>,
+    changes: mpsc::UnboundedSender<RouteChange>,
+    max_routes: Option<usize>,
+}
+
+impl RouteControl {
+    pub fn new(
+        initial: &[String],
+        max_routes: Option<usize>,
+    ) -> (Self, mpsc::UnboundedReceiver<RouteChange>) {
+        let (changes, receiver) = mpsc::unbounded_channel();
+        for route in initial {
+            gauge!(ROUTE_SUBSCRIBED, "route" => route.clone()).set(1.0);
+        }
+        let control = Self {
+            routes: Arc::new(RwLock::new(initial.iter().cloned().collect())),
+            changes,
+            max_routes,
+        };
+        (control, receiver)
+    }
+
+    pub fn routes(&self) -> Vec<String> {
+        self.read().iter().cloned().collect()
+    }
+
+    pub fn max_routes(&self) -> Option<usize> {
+        self.max_routes
+    }
+
+    // Returns false if the route was already subscribed or the set is at max_routes.
+    pub fn add(&self, route: &str) -> bool {
+        let added = {
+            let mut routes = self
+                .routes
+                .write()
+                .unwrap_or_else(|error| error.into_inner());
+            let full = self.max_routes.is_some_and(|max| routes.len() >= max);
+            !full && routes.insert(route.to_string())
+        };
+        if added {
+            gauge!(ROUTE_SUBSCRIBED, "route" => route.to_string()).set(1.0);
+            let _ = self.changes.send(RouteChange::Add(route.to_string()));
+        }
+        added
+    }
+
+    // Returns false if the route wasn't subscribed.
+    pub fn remove(&self, route: &str) -> bool {
+        let removed = self
+            .routes
+            .write()
+            .unwrap_or_else(|error| error.into_inner())
+            .remove(route);
+        if removed {
+            gauge!(ROUTE_SUBSCRIBED, "route" => route.to_string()).set(0.0);
+            let _ = self.changes.send(RouteChange::Remove(route.to_string()));
+        }
+        removed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, BTreeSet<String>> {
+        self.routes
+            .read()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
@@ -0,0 +1,37 @@
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use std::time::Duration;
+
+// Used when a 429 or 403 comes without a usable Retry-After.
+pub const DEFAULT_RATE_LIMITED_WAIT: Duration = Duration::from_secs(30);
+pub const DEFAULT_FORBIDDEN_WAIT: Duration = Duration::from_secs(60);
+// A Retry-After far in the future is more likely a misconfigured server than a real ban.
+pub const MAX_THROTTLE_WAIT: Duration = Duration::from_secs(15 * 60);
+
+// How long to stay away after this response, or None when it isn't a throttle.
+pub fn throttle_wait(status: u16, headers: &HeaderMap) -> Option<Duration> {
+    let retry_after = headers
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_retry_after(value, Utc::now()));
+    let default = match status {
+        429 => DEFAULT_RATE_LIMITED_WAIT,
+        403 => DEFAULT_FORBIDDEN_WAIT,
+        _ => return None,
+    };
+    Some(retry_after.unwrap_or(default).min(MAX_THROTTLE_WAIT))
+}
+
+// Retry-After is either delta-seconds or an HTTP date; a date in the past means now.
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (at.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
@@ -0,0 +1,105 @@
+use crate::feed::BusPosition;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Asia::Kuala_Lumpur;
+use chrono_tz::Tz;
+
+// Where operators read times; see localize_timestamp.
+pub const DEFAULT_TIMEZONE: Tz = Kuala_Lumpur;
+
+// How the socket feed writes dt_gps/dt_received, and so how every source's dt_gps reads.
+pub const FEED_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// Layouts seen in dt_gps/dt_received. The feed drops the seconds on some records.
+const LOCAL_TIMESTAMP_FORMATS: [&str; 5] = [
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+];
+
+// Feed timestamps carry no offset; they are wall-clock time in Kuala Lumpur.
+pub fn parse_feed_timestamp(raw: &str) -> Option<DateTime<Utc>> {
+    let raw = raw.trim();
+    if let Ok(with_offset) = DateTime::parse_from_rfc3339(raw) {
+        return Some(with_offset.with_timezone(&Utc));
+    }
+
+    LOCAL_TIMESTAMP_FORMATS.iter().find_map(|format| {
+        let naive = NaiveDateTime::parse_from_str(raw, format).ok()?;
+        Kuala_Lumpur
+            .from_local_datetime(&naive)
+            .single()
+            .map(|local| local.with_timezone(&Utc))
+    })
+}
+
+// The inverse of parse_feed_timestamp: Kuala Lumpur wall-clock time without an offset,
+// e.g. 2024-05-01 16:30:00 for 08:30 UTC.
+pub fn format_feed_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp
+        .with_timezone(&Kuala_Lumpur)
+        .format(FEED_TIMESTAMP_FORMAT)
+        .to_string()
+}
+
+// Fills timestamp_rfc3339 from the GPS fix time (falling back to the receive time).
+// Unparseable values are kept as-is and flagged instead of dropping the record.
+pub fn normalize_timestamp(bus: &mut BusPosition, now_ms: i64) {
+    let raw = bus
+        .dt_gps
+        .as_deref()
+        .filter(|value| !value.trim().is_empty())
+        .or(bus.dt_received.as_deref())
+        .filter(|value| !value.trim().is_empty());
+
+    let Some(raw) = raw else {
+        bus.timestamp_rfc3339 = None;
+        bus.timestamp_parse_error = true;
+        bus.age_seconds = None;
+        return;
+    };
+
+    match parse_feed_timestamp(raw) {
+        Some(timestamp) => {
+            bus.timestamp_rfc3339 = Some(timestamp.to_rfc3339());
+            bus.timestamp_parse_error = false;
+        }
+        None => {
+            bus.timestamp_rfc3339 = None;
+            bus.timestamp_parse_error = true;
+        }
+    }
+    refresh_age(bus, now_ms);
+}
+
+// Renders timestamp_rfc3339 in `timezone` as local_time, e.g. 2024-05-01T16:30:00+08:00
+// for 08:30 UTC in Kuala Lumpur. Cleared when there is no UTC timestamp to render.
+pub fn localize_timestamp(bus: &mut BusPosition, timezone: Tz) {
+    bus.local_time = bus
+        .timestamp_rfc3339
+        .as_deref()
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .map(|timestamp| timestamp.with_timezone(&timezone).to_rfc3339());
+}
+
+// Refreshes age_seconds and drops the positions whose fix is more than `max_age_seconds`
+// old; returns how many were dropped. A position with no parseable fix time is kept, since
+// its age isn't known (timestamp_parse_error flags it).
+pub fn retain_recent(buses: &mut Vec<BusPosition>, max_age_seconds: i64, now_ms: i64) -> usize {
+    let before = buses.len();
+    buses.retain_mut(|bus| {
+        refresh_age(bus, now_ms);
+        bus.age_seconds
+            .is_none_or(|age_seconds| age_seconds <= max_age_seconds)
+    });
+    before - buses.len()
+}
+
+pub fn refresh_age(bus: &mut BusPosition, now_ms: i64) {
+    bus.age_seconds = bus
+        .timestamp_rfc3339
+        .as_deref()
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .map(|timestamp| (now_ms - timestamp.timestamp_millis()) / 1_000);
+}
@@ -0,0 +1,98 @@
+use native_tls::TlsConnector;
+use std::path::Path;
+
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+// Extra trust for the Prasarana endpoints: CA certificates added on top of the system
+// roots, and an opt-in to skip verification for a local mock. The default trusts the
+// system roots only, as before.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    // One PEM certificate per entry.
+    ca_certs: Vec<String>,
+    accept_invalid_certs: bool,
+}
+
+impl TlsOptions {
+    // Reads every certificate in each PEM file up front, so a bad path or file fails
+    // at startup instead of on the first connection.
+    pub fn load(
+        ca_cert_paths: &[impl AsRef<Path>],
+        accept_invalid_certs: bool,
+    ) -> Result<Self, String> {
+        let mut ca_certs = Vec::new();
+        for path in ca_cert_paths {
+            let path = path.as_ref();
+            let pem = std::fs::read_to_string(path)
+                .map_err(|error| format!("failed to read '{}': {}", path.display(), error))?;
+            let blocks = pem_blocks(&pem);
+            if blocks.is_empty() {
+                return Err(format!("no PEM certificates in '{}'", path.display()));
+            }
+            for block in blocks {
+                reqwest::Certificate::from_pem(block.as_bytes()).map_err(|error| {
+                    format!("invalid certificate in '{}': {}", path.display(), error)
+                })?;
+                native_tls::Certificate::from_pem(block.as_bytes()).map_err(|error| {
+                    format!("invalid certificate in '{}': {}", path.display(), error)
+                })?;
+                ca_certs.push(block);
+            }
+        }
+        Ok(Self {
+            ca_certs,
+            accept_invalid_certs,
+        })
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.ca_certs.is_empty() && !self.accept_invalid_certs
+    }
+
+    pub fn apply(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        for pem in &self.ca_certs {
+            if let Ok(certificate) = reqwest::Certificate::from_pem(pem.as_bytes()) {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if self.accept_invalid_certs {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        builder
+    }
+
+    // The Socket.IO client takes a native-tls connector; None keeps its default.
+    pub fn connector(&self) -> Result<Option<TlsConnector>, String> {
+        if self.is_default() {
+            return Ok(None);
+        }
+        let mut builder = TlsConnector::builder();
+        for pem in &self.ca_certs {
+            let certificate = native_tls::Certificate::from_pem(pem.as_bytes())
+                .map_err(|error| format!("invalid certificate: {}", error))?;
+            builder.add_root_certificate(certificate);
+        }
+        if self.accept_invalid_certs {
+            builder.danger_accept_invalid_certs(true);
+        }
+        builder
+            .build()
+            .map(Some)
+            .map_err(|error| format!("failed to build TLS connector: {}", error))
+    }
+}
+
+fn pem_blocks(pem: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut rest = pem;
+    while let Some(start) = rest.find(PEM_BEGIN) {
+        let Some(end) = rest[start..].find(PEM_END) else {
+            break;
+        };
+        let end = start + end + PEM_END.len();
+        blocks.push(format!("{}\n", &rest[start..end]));
+        rest = &rest[end..];
+    }
+    blocks
+}
@@ -0,0 +1,427 @@
+use crate::{haversine_distance, is_bus_on_route, load_stops, Stop};
+use be::client::ClientEvent;
+use be::feed::BusPosition;
+use be::now_unix_ms;
+use be::route_colors::fallback_route_colors;
+use be::timestamp::refresh_age;
+use be::validate::{validate_coordinates, MALAYSIA_BBOX};
+use futures_util::stream::{BoxStream, StreamExt};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::symbols::Marker;
+use ratatui::widgets::canvas::{Canvas, Points};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, Tabs};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::time::Duration;
+
+const STALE_FIX_SECONDS: i64 = 60;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+// Keeps a single bus (or a tight cluster) from zooming the map to street level.
+const MIN_MAP_SPAN_DEGREES: f64 = 0.01;
+const MAP_PADDING_RATIO: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortColumn {
+    Vehicle,
+    Speed,
+    Stop,
+    Age,
+}
+
+impl SortColumn {
+    fn next(self) -> Self {
+        match self {
+            SortColumn::Vehicle => SortColumn::Speed,
+            SortColumn::Speed => SortColumn::Stop,
+            SortColumn::Stop => SortColumn::Age,
+            SortColumn::Age => SortColumn::Vehicle,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SortColumn::Vehicle => "vehicle",
+            SortColumn::Speed => "speed",
+            SortColumn::Stop => "stop",
+            SortColumn::Age => "age",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    Table,
+    Map,
+}
+
+struct VehicleRow {
+    bus_no: String,
+    speed: f64,
+    nearest_stop: String,
+    age_seconds: Option<i64>,
+}
+
+struct Dashboard {
+    routes: Vec<String>,
+    selected: usize,
+    paused: bool,
+    sort: SortColumn,
+    view: View,
+    vehicles: HashMap<String, BusPosition>,
+    stops: Vec<Stop>,
+}
+
+impl Dashboard {
+    fn apply(&mut self, buses: Vec<BusPosition>) {
+        if self.paused {
+            return;
+        }
+        for mut bus in buses {
+            // Garbage fixes would stretch the map viewport across the planet.
+            if !validate_coordinates(&mut bus, &MALAYSIA_BBOX).is_accepted() {
+                continue;
+            }
+            self.vehicles.insert(bus.bus_no.clone(), bus);
+        }
+    }
+
+    fn visible_vehicles(&self) -> impl Iterator<Item = &BusPosition> {
+        let route = self.selected_route();
+        self.vehicles
+            .values()
+            .filter(move |bus| route.is_none_or(|route| is_bus_on_route(&bus.route, route)))
+    }
+
+    fn selected_route(&self) -> Option<&str> {
+        self.routes.get(self.selected).map(String::as_str)
+    }
+
+    fn rows(&self) -> Vec<VehicleRow> {
+        let now_ms = now_unix_ms();
+        let mut rows: Vec<VehicleRow> = self
+            .visible_vehicles()
+            .map(|bus| {
+                let mut bus = bus.clone();
+                refresh_age(&mut bus, now_ms);
+                VehicleRow {
+                    bus_no: bus.bus_no.clone(),
+                    speed: bus.speed,
+                    nearest_stop: nearest_stop_label(&self.stops, &bus),
+                    age_seconds: bus.age_seconds,
+                }
+            })
+            .collect();
+
+        rows.sort_by(|a, b| match self.sort {
+            SortColumn::Vehicle => a.bus_no.cmp(&b.bus_no),
+            SortColumn::Speed => b
+                .speed
+                .partial_cmp(&a.speed)
+                .unwrap_or(std::cmp::Ordering::Equal),
+            SortColumn::Stop => a.nearest_stop.cmp(&b.nearest_stop),
+            SortColumn::Age => a.age_seconds.cmp(&b.age_seconds),
+        });
+        rows
+    }
+}
+
+fn nearest_stop_label(stops: &[Stop], bus: &BusPosition) -> String {
+    stops
+        .iter()
+        .map(|stop| {
+            let distance_km =
+                haversine_distance(bus.latitude, bus.longitude, stop.stop_lat, stop.stop_lon);
+            (stop, distance_km)
+        })
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(stop, distance_km)| format!("{} ({:.0}m)", stop.stop_name, distance_km * 1000.0))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+// Draws whatever arrives on `events`, a subscription to the clients' broadcast channel;
+// the sockets behind it belong to the caller. Returns the process exit code.
+pub async fn run_tui(routes: Vec<String>, mut events: BoxStream<'static, ClientEvent>) -> i32 {
+    let stops: Vec<Stop> = load_stops()
+        .map(|stops| stops.into_values().collect())
+        .unwrap_or_default();
+
+    let mut dashboard = Dashboard {
+        routes,
+        selected: 0,
+        paused: false,
+        sort: SortColumn::Vehicle,
+        view: View::Table,
+        vehicles: HashMap::new(),
+        stops,
+    };
+
+    if !std::io::stdout().is_terminal() {
+        return run_plain(&mut dashboard, &mut events).await;
+    }
+
+    let mut terminal = ratatui::init();
+    let result = run_terminal(&mut terminal, &mut dashboard, &mut events).await;
+    ratatui::restore();
+
+    match result {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("tui failed: {}", error);
+            1
+        }
+    }
+}
+
+// Fallback for pipes and log files: one line per vehicle update.
+async fn run_plain(
+    dashboard: &mut Dashboard,
+    events: &mut (impl futures_util::Stream<Item = ClientEvent> + Unpin),
+) -> i32 {
+    while let Some(event) = events.next().await {
+        let ClientEvent::Buses { buses, .. } = event else {
+            continue;
+        };
+        dashboard.apply(buses);
+        for row in dashboard.rows() {
+            println!(
+                "{} speed={:.0}km/h stop={} age={}",
+                row.bus_no,
+                row.speed,
+                row.nearest_stop,
+                row.age_seconds
+                    .map(|age| format!("{}s", age))
+                    .unwrap_or_else(|| "-".to_string())
+            );
+        }
+    }
+    0
+}
+
+async fn run_terminal(
+    terminal: &mut DefaultTerminal,
+    dashboard: &mut Dashboard,
+    events: &mut (impl futures_util::Stream<Item = ClientEvent> + Unpin),
+) -> std::io::Result<()> {
+    let mut redraw = tokio::time::interval(REDRAW_INTERVAL);
+
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                match event {
+                    Some(ClientEvent::Buses { buses, .. }) => dashboard.apply(buses),
+                    Some(_) => {}
+                    None => return Ok(()),
+                }
+            }
+            _ = redraw.tick() => {
+                while event::poll(Duration::ZERO)? {
+                    let Event::Key(key) = event::read()? else {
+                        continue;
+                    };
+                    if key.kind != KeyEventKind::Press {
+                        continue;
+                    }
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                        KeyCode::Char('p') => dashboard.paused = !dashboard.paused,
+                        KeyCode::Char('s') => dashboard.sort = dashboard.sort.next(),
+                        KeyCode::Char('m') => {
+                            dashboard.view = match dashboard.view {
+                                View::Table => View::Map,
+                                View::Map => View::Table,
+                            };
+                        }
+                        KeyCode::Tab | KeyCode::Right if !dashboard.routes.is_empty() => {
+                            dashboard.selected = (dashboard.selected + 1) % dashboard.routes.len();
+                        }
+                        KeyCode::BackTab | KeyCode::Left if !dashboard.routes.is_empty() => {
+                            dashboard.selected = (dashboard.selected + dashboard.routes.len() - 1)
+                                % dashboard.routes.len();
+                        }
+                        _ => {}
+                    }
+                }
+                terminal.draw(|frame| draw(frame, dashboard))?;
+            }
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, dashboard: &Dashboard) {
+    let [tabs_area, table_area, footer_area] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let titles: Vec<String> = if dashboard.routes.is_empty() {
+        vec!["all routes".to_string()]
+    } else {
+        dashboard.routes.clone()
+    };
+    frame.render_widget(
+        Tabs::new(titles)
+            .select(dashboard.selected)
+            .block(Block::default().borders(Borders::ALL).title("rapidbro"))
+            .highlight_style(Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED)),
+        tabs_area,
+    );
+
+    match dashboard.view {
+        View::Table => draw_table(frame, table_area, dashboard),
+        View::Map => draw_map(frame, table_area, dashboard),
+    }
+
+    let footer = format!(
+        "q quit  ←/→ route  m {}  p {}  s sort ({})",
+        match dashboard.view {
+            View::Table => "map",
+            View::Map => "table",
+        },
+        if dashboard.paused { "resume" } else { "pause" },
+        dashboard.sort.label()
+    );
+    frame.render_widget(Paragraph::new(footer), footer_area);
+}
+
+fn draw_table(frame: &mut Frame, area: Rect, dashboard: &Dashboard) {
+    let rows: Vec<Row> = dashboard
+        .rows()
+        .into_iter()
+        .map(|row| {
+            let is_stale = row.age_seconds.is_none_or(|age| age > STALE_FIX_SECONDS);
+            let style = if is_stale {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default()
+            };
+            Row::new(vec![
+                row.bus_no,
+                format!("{:.0} km/h", row.speed),
+                row.nearest_stop,
+                row.age_seconds
+                    .map(|age| format!("{}s", age))
+                    .unwrap_or_else(|| "-".to_string()),
+            ])
+            .style(style)
+        })
+        .collect();
+    let vehicle_count = rows.len();
+    frame.render_widget(
+        Table::new(
+            rows,
+            [
+                Constraint::Length(12),
+                Constraint::Length(10),
+                Constraint::Min(20),
+                Constraint::Length(8),
+            ],
+        )
+        .header(
+            Row::new(vec!["Vehicle", "Speed", "Nearest stop", "Age"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("{} vehicles", vehicle_count)),
+        ),
+        area,
+    );
+}
+
+fn draw_map(frame: &mut Frame, area: Rect, dashboard: &Dashboard) {
+    let vehicles: Vec<&BusPosition> = dashboard.visible_vehicles().collect();
+    let Some((min_lon, min_lat, max_lon, max_lat)) = map_viewport(&vehicles) else {
+        frame.render_widget(
+            Paragraph::new("waiting for vehicle positions…")
+                .block(Block::default().borders(Borders::ALL).title("map")),
+            area,
+        );
+        return;
+    };
+
+    let mut by_route: HashMap<&str, Vec<(f64, f64)>> = HashMap::new();
+    for bus in &vehicles {
+        by_route
+            .entry(bus.route.as_str())
+            .or_default()
+            .push((bus.longitude, bus.latitude));
+    }
+    let stop_points: Vec<(f64, f64)> = dashboard
+        .stops
+        .iter()
+        .filter(|stop| {
+            (min_lon..=max_lon).contains(&stop.stop_lon)
+                && (min_lat..=max_lat).contains(&stop.stop_lat)
+        })
+        .map(|stop| (stop.stop_lon, stop.stop_lat))
+        .collect();
+
+    frame.render_widget(
+        Canvas::default()
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!("map · {} vehicles", vehicles.len())),
+            )
+            .marker(Marker::Braille)
+            .x_bounds([min_lon, max_lon])
+            .y_bounds([min_lat, max_lat])
+            .paint(|ctx| {
+                ctx.draw(&Points {
+                    coords: &stop_points,
+                    color: Color::DarkGray,
+                });
+                ctx.layer();
+                for (route, coords) in &by_route {
+                    ctx.draw(&Points {
+                        coords,
+                        color: route_color(route),
+                    });
+                }
+            }),
+        area,
+    );
+}
+
+// Bounding box of the vehicles with padding, as (min_lon, min_lat, max_lon, max_lat).
+fn map_viewport(vehicles: &[&BusPosition]) -> Option<(f64, f64, f64, f64)> {
+    let first = vehicles.first()?;
+    let (mut min_lon, mut min_lat, mut max_lon, mut max_lat) = (
+        first.longitude,
+        first.latitude,
+        first.longitude,
+        first.latitude,
+    );
+    for bus in vehicles {
+        min_lon = min_lon.min(bus.longitude);
+        max_lon = max_lon.max(bus.longitude);
+        min_lat = min_lat.min(bus.latitude);
+        max_lat = max_lat.max(bus.latitude);
+    }
+
+    let half_lon = (max_lon - min_lon).max(MIN_MAP_SPAN_DEGREES) * (0.5 + MAP_PADDING_RATIO);
+    let half_lat = (max_lat - min_lat).max(MIN_MAP_SPAN_DEGREES) * (0.5 + MAP_PADDING_RATIO);
+    let (center_lon, center_lat) = ((min_lon + max_lon) / 2.0, (min_lat + max_lat) / 2.0);
+    Some((
+        center_lon - half_lon,
+        center_lat - half_lat,
+        center_lon + half_lon,
+        center_lat + half_lat,
+    ))
+}
+
+fn route_color(route: &str) -> Color {
+    let (hex, _) = fallback_route_colors(route);
+    let channel = |range: std::ops::Range<usize>| {
+        hex.get(range)
+            .and_then(|value| u8::from_str_radix(value, 16).ok())
+            .unwrap_or(255)
+    };
+    Color::Rgb(channel(1..3), channel(3..5), channel(5..7))
+}
@@ -0,0 +1,95 @@
+use crate::client::ClientEvent;
+use crate::feed::BusPosition;
+use futures_util::stream::{self, BoxStream};
+use futures_util::{Stream, StreamExt};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone)]
+pub enum BusUpdate {
+    Position(BusPosition),
+    // The stream fell behind and `missed` client events were dropped, each typically a
+    // batch of positions. Whatever a consumer derives from continuity (e.g. a speed from
+    // consecutive fixes) should start over.
+    Gap { missed: u64 },
+}
+
+impl BusUpdate {
+    pub fn position(&self) -> Option<&BusPosition> {
+        match self {
+            BusUpdate::Position(bus) => Some(bus),
+            BusUpdate::Gap { .. } => None,
+        }
+    }
+}
+
+// Every position the client publishes, one at a time, from RapidbroClient::updates. Ends
+// once the client is stopped and the positions already published have been read.
+//
+// A clone is an independent stream of the positions published after it was made; a slow
+// stream gets a Gap instead of holding up the client or the other streams.
+pub struct BusUpdates {
+    // Only used to subscribe clones.
+    events: broadcast::Sender<ClientEvent>,
+    stop: CancellationToken,
+    updates: BoxStream<'static, BusUpdate>,
+}
+
+impl BusUpdates {
+    pub(crate) fn new(events: broadcast::Sender<ClientEvent>, stop: CancellationToken) -> Self {
+        let updates = bus_updates(events.subscribe(), stop.clone());
+        Self {
+            events,
+            stop,
+            updates,
+        }
+    }
+}
+
+impl Clone for BusUpdates {
+    fn clone(&self) -> Self {
+        Self::new(self.events.clone(), self.stop.clone())
+    }
+}
+
+impl Stream for BusUpdates {
+    type Item = BusUpdate;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().updates.poll_next_unpin(cx)
+    }
+}
+
+fn bus_updates(
+    receiver: broadcast::Receiver<ClientEvent>,
+    stop: CancellationToken,
+) -> BoxStream<'static, BusUpdate> {
+    let pending: VecDeque<BusPosition> = VecDeque::new();
+    stream::unfold(
+        (receiver, stop, pending),
+        |(mut receiver, stop, mut pending)| async move {
+            loop {
+                if let Some(bus) = pending.pop_front() {
+                    return Some((BusUpdate::Position(bus), (receiver, stop, pending)));
+                }
+                let event = tokio::select! {
+                    biased;
+                    event = receiver.recv() => event,
+                    _ = stop.cancelled() => return None,
+                };
+                match event {
+                    Ok(ClientEvent::Buses { buses, .. }) => pending.extend(buses),
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        return Some((BusUpdate::Gap { missed }, (receiver, stop, pending)));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        },
+    )
+    .boxed()
+}
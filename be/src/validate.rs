@@ -0,0 +1,78 @@
+use crate::feed::BusPosition;
+
+pub const COORDINATES_SWAPPED_TOTAL: &str = "rapidbro_coordinates_swapped_total";
+// Labelled by reason: zero_fix or out_of_bounds.
+pub const COORDINATES_REJECTED_TOTAL: &str = "rapidbro_coordinates_rejected_total";
+
+// Generous box around Peninsular Malaysia, Sabah and Sarawak.
+pub const MALAYSIA_BBOX: BoundingBox = BoundingBox {
+    min_lon: 99.6,
+    min_lat: 0.85,
+    max_lon: 119.3,
+    max_lat: 7.4,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl BoundingBox {
+    // Parses "min_lon,min_lat,max_lon,max_lat", the same order GeoJSON uses.
+    pub fn parse(value: &str) -> Option<Self> {
+        let parts: Vec<f64> = value
+            .split(',')
+            .map(|part| part.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        let [min_lon, min_lat, max_lon, max_lat] = parts[..] else {
+            return None;
+        };
+
+        (min_lon <= max_lon && min_lat <= max_lat).then_some(Self {
+            min_lon,
+            min_lat,
+            max_lon,
+            max_lat,
+        })
+    }
+
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        (self.min_lat..=self.max_lat).contains(&lat) && (self.min_lon..=self.max_lon).contains(&lon)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoordinateCheck {
+    Valid,
+    Swapped,
+    ZeroFix,
+    OutOfBounds,
+}
+
+impl CoordinateCheck {
+    pub fn is_accepted(&self) -> bool {
+        matches!(self, CoordinateCheck::Valid | CoordinateCheck::Swapped)
+    }
+}
+
+// Swapped fixes are repaired in place; the caller drops anything that isn't accepted.
+pub fn validate_coordinates(bus: &mut BusPosition, bounds: &BoundingBox) -> CoordinateCheck {
+    if bus.latitude == 0.0 && bus.longitude == 0.0 {
+        return CoordinateCheck::ZeroFix;
+    }
+
+    if bounds.contains(bus.latitude, bus.longitude) {
+        return CoordinateCheck::Valid;
+    }
+
+    if bounds.contains(bus.longitude, bus.latitude) {
+        std::mem::swap(&mut bus.latitude, &mut bus.longitude);
+        return CoordinateCheck::Swapped;
+    }
+
+    CoordinateCheck::OutOfBounds
+}
@@ -0,0 +1,206 @@
+use crate::feed::BusPosition;
+use crate::layover::TripEvent;
+use crate::presence::PresenceEvent;
+use crate::queue::{BoundedQueue, OverflowPolicy};
+use hmac::{Hmac, Mac};
+use metrics::counter;
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::Duration;
+
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(1);
+pub const DEFAULT_QUEUE_CAPACITY: usize = 10_000;
+pub const DEFAULT_MAX_RETRIES: u32 = 5;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+pub const SIGNATURE_HEADER: &str = "X-Rapidbro-Signature";
+
+pub const WEBHOOK_DELIVERED_TOTAL: &str = "rapidbro_webhook_delivered_total";
+pub const WEBHOOK_RETRIED_TOTAL: &str = "rapidbro_webhook_retried_total";
+pub const WEBHOOK_DROPPED_TOTAL: &str = "rapidbro_webhook_dropped_total";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    Position(Box<BusPosition>),
+    Trip(TripEvent),
+    Presence(PresenceEvent),
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    pub secret: Option<String>,
+    pub max_batch_size: usize,
+    pub max_delay: Duration,
+    pub queue_capacity: usize,
+    pub overflow_policy: OverflowPolicy,
+    pub max_retries: u32,
+    // Also deliver VehicleLost/VehicleReturned; off so existing receivers see no new types.
+    pub presence_events: bool,
+}
+
+impl WebhookConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            secret: None,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            max_delay: DEFAULT_MAX_DELAY,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            overflow_policy: OverflowPolicy::DropOldest,
+            max_retries: DEFAULT_MAX_RETRIES,
+            presence_events: false,
+        }
+    }
+}
+
+enum DeliveryError {
+    // 5xx, timeouts and connection failures: the receiver may recover.
+    Retryable(String),
+    // 4xx: the receiver rejected the batch and resending won't change that.
+    Rejected(String),
+}
+
+// POSTs events as JSON arrays, batched by size or delay, whichever comes first. While the
+// receiver is down the queue sheds its oldest events so delivery resumes with fresh data.
+#[derive(Debug, Clone)]
+pub struct WebhookSink {
+    config: WebhookConfig,
+    http: reqwest::Client,
+    queue: BoundedQueue<WebhookEvent>,
+}
+
+impl WebhookSink {
+    pub fn new(config: WebhookConfig) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+        let queue = BoundedQueue::new(
+            config.queue_capacity,
+            config.overflow_policy,
+            Duration::ZERO,
+        )
+        .named("webhook");
+        Self {
+            config,
+            http,
+            queue,
+        }
+    }
+
+    pub async fn enqueue(&self, event: WebhookEvent) {
+        if matches!(event, WebhookEvent::Presence(_)) && !self.config.presence_events {
+            return;
+        }
+        if !self.queue.push(event).await {
+            counter!(WEBHOOK_DROPPED_TOTAL, "reason" => "queue_full").increment(1);
+        }
+    }
+
+    pub async fn run(&self) {
+        let max_batch_size = self.config.max_batch_size.max(1);
+        loop {
+            let mut batch = vec![self.queue.pop().await];
+            let deadline = tokio::time::Instant::now() + self.config.max_delay;
+            while batch.len() < max_batch_size {
+                match tokio::time::timeout_at(deadline, self.queue.pop()).await {
+                    Ok(event) => batch.push(event),
+                    Err(_) => break,
+                }
+            }
+            self.deliver(&batch).await;
+        }
+    }
+
+    async fn deliver(&self, batch: &[WebhookEvent]) {
+        let body = match serde_json::to_vec(batch) {
+            Ok(body) => body,
+            Err(error) => {
+                println!("Failed to serialize webhook batch: {}", error);
+                counter!(WEBHOOK_DROPPED_TOTAL, "reason" => "serialize")
+                    .increment(batch.len() as u64);
+                return;
+            }
+        };
+        let signature = self
+            .config
+            .secret
+            .as_deref()
+            .map(|secret| sign_payload(secret, &body));
+
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let error = match self.post(&body, signature.as_deref()).await {
+                Ok(()) => {
+                    counter!(WEBHOOK_DELIVERED_TOTAL).increment(batch.len() as u64);
+                    return;
+                }
+                Err(DeliveryError::Rejected(reason)) => {
+                    println!("Webhook rejected batch of {}: {}", batch.len(), reason);
+                    counter!(WEBHOOK_DROPPED_TOTAL, "reason" => "rejected")
+                        .increment(batch.len() as u64);
+                    return;
+                }
+                Err(DeliveryError::Retryable(reason)) => reason,
+            };
+
+            if attempt >= self.config.max_retries {
+                println!(
+                    "Webhook gave up on batch of {} after {} retries: {}",
+                    batch.len(),
+                    attempt,
+                    error
+                );
+                counter!(WEBHOOK_DROPPED_TOTAL, "reason" => "retries_exhausted")
+                    .increment(batch.len() as u64);
+                return;
+            }
+            attempt += 1;
+            counter!(WEBHOOK_RETRIED_TOTAL).increment(1);
+            println!(
+                "Webhook delivery failed ({}), retry {} in {:?}",
+                error, attempt, backoff
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    async fn post(&self, body: &[u8], signature: Option<&str>) -> Result<(), DeliveryError> {
+        let mut request = self
+            .http
+            .post(&self.config.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_vec());
+        if let Some(signature) = signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|error| DeliveryError::Retryable(error.to_string()))?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else if status.is_client_error() {
+            Err(DeliveryError::Rejected(status.to_string()))
+        } else {
+            Err(DeliveryError::Retryable(status.to_string()))
+        }
+    }
+}
+
+// "sha256=<hex HMAC of the body>", so receivers can check it with the shared secret.
+pub fn sign_payload(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
@@ -0,0 +1,16 @@
+use be::gtfs_rt::ApiToken;
+
+#[test]
+fn debug_output_never_shows_the_token() {
+    let token: ApiToken = "s3cr3t-key".parse().unwrap();
+    let printed = format!("{:?}", Some(&token));
+    assert!(!printed.contains("s3cr3t"), "{}", printed);
+    assert!(printed.contains("redacted"));
+}
+
+#[test]
+fn blank_tokens_are_rejected() {
+    assert!(ApiToken::new("  ").is_none());
+    assert!("".parse::<ApiToken>().is_err());
+    assert_eq!(ApiToken::new(" key\n"), ApiToken::new("key"));
+}
@@ -0,0 +1,129 @@
+#![cfg(feature = "parquet")]
+
+mod common;
+
+use be::archive::{
+    archive_files, read_records, read_summary, write_parquet, ArchiveRecord, ArchiveSink,
+    HourlyBuffer,
+};
+use be::feed::BusPosition;
+use be::queue::OverflowPolicy;
+use common::ROUTE;
+use std::path::PathBuf;
+
+// 2024-05-01T08:00:00Z
+const START_MS: i64 = 1_714_550_400_000;
+const MS_PER_HOUR: i64 = 3_600_000;
+
+fn bus(bus_no: &str, dt_gps: &str) -> BusPosition {
+    let mut bus = common::bus(bus_no, ROUTE);
+    bus.dt_gps = Some(dt_gps.to_string());
+    bus
+}
+
+fn temp_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("rapidbro-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    root
+}
+
+#[test]
+fn written_records_read_back_unchanged() {
+    let root = temp_root("archive-round-trip");
+    let mut off_route = ArchiveRecord::from_bus(&bus("WYY5678", "garbage"), START_MS + 1_000);
+    off_route.off_route = true;
+    let records = vec![
+        ArchiveRecord::from_bus(&bus("WXX1234", "2024-05-01 07:59:58"), START_MS),
+        off_route,
+    ];
+
+    let path = write_parquet(&root, &records).unwrap();
+    assert!(path.ends_with(format!("year=2024/month=05/day=01/08-{}.parquet", START_MS)));
+    assert_eq!(archive_files(&root).unwrap(), [path.clone()]);
+
+    let read = read_records(&path).unwrap();
+    assert_eq!(read.len(), 2);
+    for (read, written) in read.iter().zip(&records) {
+        assert_eq!(
+            (&read.provider, &read.route, &read.vehicle_id),
+            (&written.provider, &written.route, &written.vehicle_id)
+        );
+        assert_eq!(
+            (read.lat, read.lon, read.speed, read.bearing),
+            (written.lat, written.lon, written.speed, written.bearing)
+        );
+        assert_eq!(
+            (read.gps_ts, read.recv_ts, read.off_route),
+            (written.gps_ts, written.recv_ts, written.off_route)
+        );
+    }
+    // The unparseable fix is stored as null rather than a made-up time.
+    assert_eq!(read[1].gps_ts, None);
+
+    let summary = read_summary(&path).unwrap();
+    assert_eq!(summary.rows, 2);
+    assert_eq!(
+        (summary.first_recv_ts, summary.last_recv_ts),
+        (Some(START_MS), Some(START_MS + 1_000))
+    );
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn a_full_buffer_hands_back_the_hour_so_far() {
+    let mut buffer = HourlyBuffer::new(2);
+    let record = |recv_ts| ArchiveRecord::from_bus(&bus("WXX1234", ""), recv_ts);
+    assert!(buffer.push(record(START_MS)).is_none());
+    assert!(buffer.push(record(START_MS + 1_000)).is_none());
+
+    let finished = buffer.push(record(START_MS + 2_000)).unwrap();
+    assert_eq!(finished.len(), 2);
+    let finished = buffer.push(record(START_MS + MS_PER_HOUR)).unwrap();
+    assert_eq!(finished.len(), 1);
+    assert_eq!(buffer.take().len(), 1);
+}
+
+#[tokio::test]
+async fn stopping_the_writer_then_flushing_keeps_every_record() {
+    let root = temp_root("archive-shutdown");
+    let sink = ArchiveSink::new(&root, OverflowPolicy::DropOldest);
+    let runner = sink.clone();
+    let run = tokio::spawn(async move { runner.run().await });
+
+    // The first hour is finished by the second and written by the task; the second hour
+    // is still buffered or queued at shutdown.
+    sink.enqueue(&bus("WXX1234", ""), START_MS).await;
+    sink.enqueue(&bus("WXX1234", ""), START_MS + MS_PER_HOUR)
+        .await;
+    sink.enqueue(&bus("WXX1234", ""), START_MS + MS_PER_HOUR + 1_000)
+        .await;
+
+    sink.stop();
+    tokio::time::timeout(std::time::Duration::from_secs(5), run)
+        .await
+        .expect("the writer kept running after stop")
+        .unwrap();
+    sink.flush().await;
+
+    let mut received = Vec::new();
+    for path in archive_files(&root).unwrap() {
+        received.extend(
+            read_records(&path)
+                .unwrap()
+                .into_iter()
+                .map(|record| record.recv_ts),
+        );
+    }
+    received.sort();
+    assert_eq!(
+        received,
+        [
+            START_MS,
+            START_MS + MS_PER_HOUR,
+            START_MS + MS_PER_HOUR + 1_000
+        ]
+    );
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,40 @@
+use be::auth::{AllowedOrigins, ApiKeyCheck, ApiKeys};
+
+#[test]
+fn no_keys_leaves_the_api_open() {
+    let keys = ApiKeys::parse("");
+    assert!(keys.is_empty());
+    assert_eq!(keys.check(None), ApiKeyCheck::Allowed);
+    assert_eq!(keys.check(Some("anything")), ApiKeyCheck::Allowed);
+}
+
+#[test]
+fn missing_and_wrong_keys_are_told_apart() {
+    let keys = ApiKeys::parse("alpha, beta,,");
+    assert_eq!(keys.len(), 2);
+    assert_eq!(keys.check(Some("alpha")), ApiKeyCheck::Allowed);
+    assert_eq!(keys.check(Some(" beta ")), ApiKeyCheck::Allowed);
+    assert_eq!(keys.check(None), ApiKeyCheck::Missing);
+    assert_eq!(keys.check(Some("  ")), ApiKeyCheck::Missing);
+    assert_eq!(keys.check(Some("alph")), ApiKeyCheck::Rejected);
+    assert_eq!(keys.check(Some("alphaa")), ApiKeyCheck::Rejected);
+    assert_eq!(keys.check(Some("ALPHA")), ApiKeyCheck::Rejected);
+}
+
+#[test]
+fn origins_parse_as_a_list_or_wildcard() {
+    assert_eq!(AllowedOrigins::parse("*").unwrap(), AllowedOrigins::Any);
+    assert_eq!(
+        AllowedOrigins::parse("https://bus.example.com/, http://localhost:5173").unwrap(),
+        AllowedOrigins::List(vec![
+            "https://bus.example.com".to_string(),
+            "http://localhost:5173".to_string(),
+        ])
+    );
+    assert_eq!(
+        AllowedOrigins::parse("https://bus.example.com,*").unwrap(),
+        AllowedOrigins::Any
+    );
+    assert!(AllowedOrigins::parse("bus.example.com").is_err());
+    assert!(AllowedOrigins::parse(" , ").is_err());
+}
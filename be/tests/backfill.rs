@@ -0,0 +1,65 @@
+mod common;
+
+use be::backfill::BackfillGuard;
+use be::feed::{BusPosition, PositionSource};
+use common::{bus, ROUTE};
+
+const MIN_INTERVAL_MS: i64 = 20_000;
+
+fn bus_nos(buses: &[BusPosition]) -> Vec<&str> {
+    buses.iter().map(|bus| bus.bus_no.as_str()).collect()
+}
+
+#[test]
+fn the_snapshot_skips_vehicles_the_socket_already_refreshed() {
+    let mut guard = BackfillGuard::new(MIN_INTERVAL_MS);
+    assert!(guard.start(0));
+    guard.observe_live(&[bus("WYY5678", ROUTE)]);
+
+    let mut snapshot = vec![
+        bus("WXX1234", ROUTE),
+        bus("WYY5678", ROUTE),
+        bus("WZZ9012", ROUTE),
+    ];
+    assert_eq!(guard.finish(&mut snapshot), 1);
+    assert_eq!(bus_nos(&snapshot), ["WXX1234", "WZZ9012"]);
+    assert!(snapshot
+        .iter()
+        .all(|bus| bus.source == PositionSource::Gtfs));
+    assert!(!guard.is_pending());
+}
+
+#[test]
+fn socket_positions_before_the_reconnect_do_not_count() {
+    let mut guard = BackfillGuard::new(MIN_INTERVAL_MS);
+    guard.observe_live(&[bus("WXX1234", ROUTE)]);
+    assert!(guard.start(0));
+
+    let mut snapshot = vec![bus("WXX1234", ROUTE)];
+    assert_eq!(guard.finish(&mut snapshot), 0);
+    assert_eq!(bus_nos(&snapshot), ["WXX1234"]);
+}
+
+#[test]
+fn one_snapshot_at_a_time_and_not_too_often() {
+    let mut guard = BackfillGuard::new(MIN_INTERVAL_MS);
+    assert!(guard.start(0));
+    assert!(!guard.start(1_000));
+
+    guard.finish(&mut Vec::new());
+    assert!(!guard.start(MIN_INTERVAL_MS - 1));
+    assert!(guard.start(MIN_INTERVAL_MS));
+}
+
+#[test]
+fn a_failed_fetch_clears_what_was_seen() {
+    let mut guard = BackfillGuard::new(0);
+    assert!(guard.start(0));
+    guard.observe_live(&[bus("WXX1234", ROUTE)]);
+    guard.cancel();
+    assert!(!guard.is_pending());
+
+    assert!(guard.start(1_000));
+    let mut snapshot = vec![bus("WXX1234", ROUTE)];
+    assert_eq!(guard.finish(&mut snapshot), 0);
+}
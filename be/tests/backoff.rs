@@ -0,0 +1,59 @@
+use be::backoff::{Backoff, DEFAULT_MAX_BACKOFF};
+use std::time::Duration;
+
+fn seconds(delays: &[Duration]) -> Vec<f64> {
+    delays.iter().map(Duration::as_secs_f64).collect()
+}
+
+#[test]
+fn delays_double_from_one_second_and_cap_at_sixty() {
+    let mut backoff = Backoff::default();
+    let delays: Vec<Duration> = (0..9).map(|_| backoff.next_delay()).collect();
+    assert_eq!(
+        seconds(&delays),
+        [1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 60.0, 60.0, 60.0]
+    );
+}
+
+#[test]
+fn reset_starts_the_sequence_over() {
+    let mut backoff = Backoff::default();
+    for _ in 0..4 {
+        backoff.next_delay();
+    }
+    backoff.reset();
+    assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    assert_eq!(backoff.next_delay(), Duration::from_secs(2));
+}
+
+#[test]
+fn a_max_below_the_initial_delay_is_raised_to_it() {
+    let mut backoff = Backoff::new(Duration::from_secs(5), Duration::from_secs(1));
+    assert_eq!(backoff.next_delay(), Duration::from_secs(5));
+    assert_eq!(backoff.next_delay(), Duration::from_secs(5));
+}
+
+#[test]
+fn jitter_stays_within_the_fraction_of_each_nominal_delay() {
+    let mut low = Backoff::default().with_jitter(0.2);
+    let mut high = Backoff::default().with_jitter(0.2);
+    let mut middle = Backoff::default().with_jitter(0.2);
+    for nominal in [1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 60.0, 60.0] {
+        assert!((low.next_delay_with(0.0).as_secs_f64() - nominal * 0.8).abs() < 1e-6);
+        assert!((high.next_delay_with(1.0).as_secs_f64() - nominal * 1.2).abs() < 1e-6);
+        assert!((middle.next_delay_with(0.5).as_secs_f64() - nominal).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn random_jitter_never_leaves_the_bounds() {
+    let mut jittered = Backoff::default().with_jitter(0.1);
+    let mut plain = Backoff::default();
+    for _ in 0..200 {
+        let nominal = plain.next_delay();
+        let delay = jittered.next_delay();
+        assert!(delay >= nominal.mul_f64(0.9));
+        assert!(delay <= nominal.mul_f64(1.1));
+        assert!(delay <= DEFAULT_MAX_BACKOFF.mul_f64(1.1));
+    }
+}
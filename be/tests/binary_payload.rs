@@ -0,0 +1,59 @@
+mod common;
+
+use base64::Engine;
+use be::feed::{parse_bus_positions, parse_bus_positions_from_payload};
+use common::gzip;
+use rust_socketio::Payload;
+
+const FIXTURE: &str = r#"[
+    {"dt_received":"2024-05-01 08:15:02","dt_gps":"2024-05-01 08:15:00","latitude":3.1478,"longitude":101.6953,"dir":"0","speed":32.0,"angle":90.0,"route":"300","bus_no":"WXX1234","trip_no":null,"captain_id":null,"trip_rev_kind":null,"engine_status":1,"accessibility":1,"busstop_id":null,"provider":"RKL"},
+    {"dt_received":"2024-05-01 08:15:05","dt_gps":"2024-05-01 08:15:04","latitude":3.1390,"longitude":101.6869,"dir":"1","speed":0.0,"angle":270.0,"route":"302","bus_no":"WYY5678","trip_no":null,"captain_id":null,"trip_rev_kind":null,"engine_status":1,"accessibility":0,"busstop_id":"1001","provider":"RKL"}
+]"#;
+
+#[test]
+fn binary_and_text_payloads_decode_identically() {
+    let compressed = gzip(FIXTURE.as_bytes());
+    let encoded = base64::engine::general_purpose::STANDARD.encode(&compressed);
+
+    let (text_buses, text_failures) =
+        parse_bus_positions_from_payload(Payload::Text(vec![serde_json::Value::String(encoded)]));
+    let (binary_buses, binary_failures) =
+        parse_bus_positions_from_payload(Payload::Binary(compressed.into()));
+
+    assert_eq!(text_failures, 0);
+    assert_eq!(binary_failures, 0);
+    assert_eq!(text_buses.len(), 2);
+    assert_eq!(
+        serde_json::to_value(&text_buses).unwrap(),
+        serde_json::to_value(&binary_buses).unwrap()
+    );
+}
+
+#[test]
+fn corrupt_binary_payload_counts_as_decode_failure() {
+    let (buses, failures) =
+        parse_bus_positions_from_payload(Payload::Binary(b"not gzip".to_vec().into()));
+
+    assert!(buses.is_empty());
+    assert_eq!(failures, 1);
+}
+
+#[test]
+fn unknown_fields_are_kept_only_when_capture_is_enabled() {
+    let fixture = FIXTURE.replace(
+        r#""provider":"RKL"}"#,
+        r#""provider":"RKL","occupancy":"low"}"#,
+    );
+    let payload = || Payload::Binary(gzip(fixture.as_bytes()).into());
+
+    let (plain, _) = parse_bus_positions(payload(), false);
+    let (captured, failures) = parse_bus_positions(payload(), true);
+
+    assert_eq!(failures, 0);
+    assert!(plain[0].extra.is_empty());
+    assert_eq!(captured[0].extra["occupancy"], "low");
+    assert_eq!(captured[0].bus_no, "WXX1234");
+    let serialized = serde_json::to_value(&captured[0]).unwrap();
+    assert_eq!(serialized["extra"]["occupancy"], "low");
+    assert!(serialized.get("occupancy").is_none());
+}
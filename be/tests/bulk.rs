@@ -0,0 +1,118 @@
+mod common;
+
+use be::bulk::{feature_collection, group_by_route, route_map, FieldSelection};
+use be::feed::{BusPosition, BUS_POSITION_FIELDS};
+use be::normalize_route_code;
+use be::validate::BoundingBox;
+use common::bus_at;
+use std::collections::{BTreeSet, HashMap};
+
+fn fleet() -> Vec<BusPosition> {
+    vec![
+        bus_at("WXX1234", "T7890", 3.14, 101.69),
+        bus_at("WYY5678", "T789", 3.15, 101.70),
+        bus_at("WZZ9012", "300", 3.30, 101.50),
+    ]
+}
+
+#[test]
+fn buses_are_grouped_by_route_key_and_tracked_routes_are_listed() {
+    let tracked = vec!["T789".to_string(), "402".to_string()];
+    let groups = group_by_route(
+        fleet(),
+        &tracked,
+        &HashMap::new(),
+        None,
+        normalize_route_code,
+    );
+    assert_eq!(
+        groups.keys().map(String::as_str).collect::<Vec<_>>(),
+        ["3", "402", "T789"]
+    );
+    assert_eq!(groups["T789"].len(), 2);
+    assert!(groups["402"].is_empty());
+}
+
+#[test]
+fn bbox_applies_across_routes() {
+    let bbox = BoundingBox::parse("101.6,3.1,101.8,3.2").unwrap();
+    let groups = group_by_route(
+        fleet(),
+        &[],
+        &HashMap::new(),
+        Some(&bbox),
+        normalize_route_code,
+    );
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups["T789"].len(), 2);
+}
+
+#[test]
+fn reassigned_vehicles_are_also_listed_on_their_previous_route() {
+    let previous = HashMap::from([("WZZ9012".to_string(), vec!["T789".to_string()])]);
+    let groups = group_by_route(fleet(), &[], &previous, None, normalize_route_code);
+    assert_eq!(groups["T789"].len(), 3);
+    assert_eq!(groups["3"].len(), 1);
+
+    // One feature per vehicle, however many routes it is listed on.
+    let collection = feature_collection(&groups, None);
+    assert_eq!(collection["features"].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn fields_project_each_bus() {
+    let fields = FieldSelection::parse("lat, lng,route,lat").unwrap();
+    let groups = group_by_route(fleet(), &[], &HashMap::new(), None, normalize_route_code);
+    let map = route_map(&groups, Some(&fields));
+    assert_eq!(
+        serde_json::to_value(&map["3"]).unwrap(),
+        serde_json::json!([{"latitude": 3.30, "longitude": 101.50, "route": "300"}])
+    );
+
+    assert!(FieldSelection::parse("lat,colour").is_err());
+    assert!(FieldSelection::parse(" , ").is_err());
+}
+
+#[test]
+fn geojson_features_are_points_with_projected_properties() {
+    let fields = FieldSelection::parse("bus_no").unwrap();
+    let groups = group_by_route(fleet(), &[], &HashMap::new(), None, normalize_route_code);
+    let collection = feature_collection(&groups, Some(&fields));
+    assert_eq!(collection["type"], "FeatureCollection");
+    let feature = &collection["features"][0];
+    assert_eq!(feature["type"], "Feature");
+    assert_eq!(feature["id"], "WZZ9012");
+    assert_eq!(feature["geometry"]["type"], "Point");
+    assert_eq!(
+        feature["geometry"]["coordinates"],
+        serde_json::json!([101.50, 3.30])
+    );
+    assert_eq!(
+        feature["properties"],
+        serde_json::json!({"bus_no": "WZZ9012"})
+    );
+}
+
+#[test]
+fn known_fields_match_the_serialized_struct() {
+    let mut full = bus_at("WXX1234", "T789", 3.14, 101.69);
+    full.timestamp_parse_error = true;
+    full.off_route = true;
+    full.inactive = true;
+    full.trip_id = Some("trip".to_string());
+    full.route_id = Some("T789".to_string());
+    full.current_stop_sequence = Some(4);
+    full.extra
+        .insert("odometer".to_string(), serde_json::json!(12));
+    let serialized = serde_json::to_value(&full).unwrap();
+    let keys: BTreeSet<&str> = serialized
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(String::as_str)
+        .collect();
+    assert_eq!(
+        keys,
+        BUS_POSITION_FIELDS.iter().copied().collect::<BTreeSet<_>>()
+    );
+}
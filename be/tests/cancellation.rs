@@ -0,0 +1,107 @@
+use be::client::RapidbroClient;
+use futures_util::StreamExt;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+const PROMPTLY: Duration = Duration::from_secs(2);
+
+// Accepts connections and never answers, like a kiosk that has stopped responding.
+async fn silent_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((connection, _)) = listener.accept().await {
+            held.push(connection);
+        }
+    });
+    url
+}
+
+async fn client_against_silent_server(token: &CancellationToken) -> RapidbroClient {
+    let url = silent_server().await;
+    RapidbroClient::builder()
+        .kiosk_url(url.clone())
+        .socket_url(url)
+        .route("T789")
+        .cancellation_token(token.clone())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn cancelling_the_token_ends_run_during_session_setup() {
+    let token = CancellationToken::new();
+    let client = client_against_silent_server(&token).await;
+    let runner = client.clone();
+    let run = tokio::spawn(async move { runner.run().await });
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!run.is_finished());
+    token.cancel();
+
+    tokio::time::timeout(PROMPTLY, run)
+        .await
+        .expect("run did not return after cancellation")
+        .unwrap();
+    assert!(client.is_stopped());
+}
+
+#[tokio::test]
+async fn cancelling_the_token_ends_the_reconnect_backoff() {
+    // Nothing listens on a port that was just released, so every session fails.
+    let url = {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    };
+    let token = CancellationToken::new();
+    let client = RapidbroClient::builder()
+        .kiosk_url(url.clone())
+        .socket_url(url)
+        .route("T789")
+        .cancellation_token(token.clone())
+        .build()
+        .unwrap();
+    let runner = client.clone();
+    let run = tokio::spawn(async move { runner.run().await });
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    token.cancel();
+
+    tokio::time::timeout(PROMPTLY, run)
+        .await
+        .expect("run did not return after cancellation")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn stopping_one_client_leaves_the_token_and_its_other_clients_alone() {
+    let token = CancellationToken::new();
+    let first = client_against_silent_server(&token).await;
+    let second = client_against_silent_server(&token).await;
+
+    first.stop();
+    assert!(first.is_stopped());
+    assert!(!second.is_stopped());
+    assert!(!token.is_cancelled());
+
+    token.cancel();
+    assert!(second.is_stopped());
+}
+
+#[tokio::test]
+async fn cancelling_the_token_ends_update_streams() {
+    let token = CancellationToken::new();
+    let client = RapidbroClient::builder()
+        .cancellation_token(token.clone())
+        .build()
+        .unwrap();
+    let mut updates = client.updates();
+    token.cancel();
+
+    let next = tokio::time::timeout(PROMPTLY, updates.next())
+        .await
+        .expect("stream stalled");
+    assert!(next.is_none());
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use be::channels::ChannelRegistry;
+use common::bus;
+use std::time::{Duration, Instant};
+
+const IDLE_GRACE: Duration = Duration::from_secs(60);
+
+#[test]
+fn subscribers_only_receive_their_route() {
+    let registry = ChannelRegistry::new(4, IDLE_GRACE);
+    let mut t789 = registry.subscribe("T789");
+    let mut t790 = registry.subscribe("T790");
+
+    assert_eq!(registry.publish("T789", vec![bus("WXX1234", "T789")]), 1);
+    // Nobody ever subscribed to 300, so there is no channel to send on.
+    assert_eq!(registry.publish("300", vec![bus("WYY5678", "300")]), 0);
+    assert!(!registry.has_subscribers("300"));
+
+    assert_eq!(t789.try_recv().unwrap()[0].bus_no, "WXX1234");
+    assert!(t790.try_recv().is_err());
+}
+
+#[test]
+fn an_idle_channel_is_swept_after_the_grace_period() {
+    let registry = ChannelRegistry::new(4, IDLE_GRACE);
+    let subscriber = registry.subscribe("T789");
+    let start = Instant::now();
+    assert_eq!(registry.sweep(start), 0);
+
+    drop(subscriber);
+    assert!(!registry.has_subscribers("T789"));
+    assert_eq!(registry.sweep(start), 0);
+    // A reconnect inside the grace period keeps the channel.
+    let subscriber = registry.subscribe("T789");
+    assert_eq!(registry.sweep(start + IDLE_GRACE), 0);
+
+    drop(subscriber);
+    assert_eq!(registry.sweep(start + IDLE_GRACE), 0);
+    assert_eq!(
+        registry.sweep(start + IDLE_GRACE * 2 - Duration::from_secs(1)),
+        0
+    );
+    assert_eq!(registry.sweep(start + IDLE_GRACE * 2), 1);
+    assert_eq!(registry.publish("T789", vec![bus("WXX1234", "T789")]), 0);
+}
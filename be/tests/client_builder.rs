@@ -0,0 +1,82 @@
+use be::client::{ClientConfigError, RapidbroClient, MAX_ROUTE_LEN};
+use std::time::Duration;
+
+#[test]
+fn the_defaults_build() {
+    assert!(RapidbroClient::builder().build().is_ok());
+    assert!(RapidbroClient::builder().route("T789").build().is_ok());
+    assert!(RapidbroClient::builder().route("300").build().is_ok());
+}
+
+#[test]
+fn routes_must_look_like_kiosk_route_codes() {
+    for route in ["T789 ", "T7/89", &"9".repeat(MAX_ROUTE_LEN + 1)] {
+        assert_eq!(
+            RapidbroClient::builder().route(route).build().err(),
+            Some(ClientConfigError::InvalidRoute(route.to_string()))
+        );
+    }
+}
+
+#[test]
+fn the_reload_interval_must_not_be_zero() {
+    let error = RapidbroClient::builder()
+        .reload_interval(Duration::ZERO)
+        .build()
+        .err();
+    assert_eq!(error, Some(ClientConfigError::ZeroReloadInterval));
+}
+
+#[test]
+fn the_decompression_limit_must_not_be_zero() {
+    let error = RapidbroClient::builder()
+        .max_decompressed_bytes(0)
+        .build()
+        .err();
+    assert_eq!(error, Some(ClientConfigError::ZeroDecompressionLimit));
+}
+
+#[test]
+fn urls_must_be_absolute_http_or_websocket() {
+    let error = RapidbroClient::builder()
+        .socket_url("rapidbus-socketio-avl.prasarana.com.my")
+        .build()
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        ClientConfigError::InvalidUrl {
+            name: "socket URL",
+            ..
+        }
+    ));
+
+    let error = RapidbroClient::builder()
+        .kiosk_url("ftp://example.com/kiosk")
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "invalid kiosk URL `ftp://example.com/kiosk`: unsupported scheme `ftp`"
+    );
+
+    assert!(RapidbroClient::builder()
+        .socket_url("wss://example.com")
+        .kiosk_url("http://127.0.0.1:8080/kiosk")
+        .build()
+        .is_ok());
+}
+
+#[test]
+fn event_names_and_headers_are_checked() {
+    assert_eq!(
+        RapidbroClient::builder().data_event("").build().err(),
+        Some(ClientConfigError::EmptyEventName("data event"))
+    );
+    assert!(matches!(
+        RapidbroClient::builder()
+            .header("Bad Name", "value")
+            .build()
+            .err(),
+        Some(ClientConfigError::InvalidHeader { .. })
+    ));
+}
@@ -0,0 +1,42 @@
+// Fixtures shared by the integration tests. Each test binary compiles its own copy and
+// uses only some of them.
+#![allow(dead_code)]
+
+use base64::Engine;
+use be::feed::BusPosition;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+pub const ROUTE: &str = "T789";
+
+// A bus in service, heading east at 32 km/h.
+pub fn bus(bus_no: &str, route: &str) -> BusPosition {
+    bus_at(bus_no, route, 3.1478, 101.6953)
+}
+
+pub fn bus_at(bus_no: &str, route: &str, latitude: f64, longitude: f64) -> BusPosition {
+    serde_json::from_value(serde_json::json!({
+        "latitude": latitude,
+        "longitude": longitude,
+        "speed": 32.0,
+        "angle": 90.0,
+        "route": route,
+        "bus_no": bus_no,
+        "engine_status": 1,
+        "accessibility": 1,
+        "provider": "RKL",
+    }))
+    .unwrap()
+}
+
+pub fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+// A data-event payload as the socket sends it: gzipped, then base64.
+pub fn encode(json: &str) -> String {
+    base64::engine::general_purpose::STANDARD.encode(gzip(json.as_bytes()))
+}
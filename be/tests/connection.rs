@@ -0,0 +1,128 @@
+use be::connection::{ConnectionInput, ConnectionMachine, ConnectionState};
+
+fn drive(inputs: &[ConnectionInput]) -> (ConnectionMachine, Vec<ConnectionState>) {
+    let mut machine = ConnectionMachine::new();
+    let transitions = inputs
+        .iter()
+        .filter_map(|input| machine.apply(*input))
+        .collect();
+    (machine, transitions)
+}
+
+#[test]
+fn happy_path_goes_through_session_refresh_to_connected() {
+    let (machine, transitions) = drive(&[
+        ConnectionInput::SessionRefreshStarted,
+        ConnectionInput::Connected,
+        ConnectionInput::MessageReceived,
+        ConnectionInput::MessageReceived,
+    ]);
+
+    assert_eq!(
+        transitions,
+        [
+            ConnectionState::SessionRefreshing,
+            ConnectionState::Connected
+        ]
+    );
+    assert_eq!(machine.state(), ConnectionState::Connected);
+}
+
+#[test]
+fn silence_degrades_and_the_next_message_recovers() {
+    let (machine, transitions) = drive(&[
+        ConnectionInput::SessionRefreshStarted,
+        ConnectionInput::Connected,
+        ConnectionInput::SilenceTimeout,
+        // Still silent: already degraded, no repeat transition.
+        ConnectionInput::SilenceTimeout,
+        ConnectionInput::MessageReceived,
+    ]);
+
+    assert_eq!(
+        transitions,
+        [
+            ConnectionState::SessionRefreshing,
+            ConnectionState::Connected,
+            ConnectionState::Degraded,
+            ConnectionState::Connected,
+        ]
+    );
+    assert_eq!(machine.state(), ConnectionState::Connected);
+}
+
+#[test]
+fn failures_move_to_reconnecting_until_the_next_refresh() {
+    for failure in [
+        ConnectionInput::EmitFailed,
+        ConnectionInput::Disconnected,
+        ConnectionInput::SessionFailed,
+    ] {
+        let (machine, transitions) = drive(&[
+            ConnectionInput::SessionRefreshStarted,
+            ConnectionInput::Connected,
+            failure,
+            ConnectionInput::SessionRefreshStarted,
+        ]);
+        assert_eq!(
+            transitions,
+            [
+                ConnectionState::SessionRefreshing,
+                ConnectionState::Connected,
+                ConnectionState::Reconnecting,
+                ConnectionState::SessionRefreshing,
+            ],
+            "{:?}",
+            failure
+        );
+        assert_eq!(machine.state(), ConnectionState::SessionRefreshing);
+    }
+}
+
+#[test]
+fn degraded_socket_that_drops_is_reconnecting() {
+    let (machine, _) = drive(&[
+        ConnectionInput::SessionRefreshStarted,
+        ConnectionInput::Connected,
+        ConnectionInput::SilenceTimeout,
+        ConnectionInput::Disconnected,
+    ]);
+    assert_eq!(machine.state(), ConnectionState::Reconnecting);
+}
+
+#[test]
+fn timeouts_and_stray_messages_outside_a_connection_change_nothing() {
+    let (machine, transitions) = drive(&[
+        ConnectionInput::SilenceTimeout,
+        ConnectionInput::MessageReceived,
+        ConnectionInput::SessionRefreshStarted,
+        ConnectionInput::SilenceTimeout,
+        ConnectionInput::MessageReceived,
+    ]);
+    assert_eq!(transitions, [ConnectionState::SessionRefreshing]);
+    assert_eq!(machine.state(), ConnectionState::SessionRefreshing);
+}
+
+#[test]
+fn stopped_is_final() {
+    let (machine, transitions) = drive(&[
+        ConnectionInput::SessionRefreshStarted,
+        ConnectionInput::Connected,
+        ConnectionInput::Stopped,
+        ConnectionInput::Disconnected,
+        ConnectionInput::SessionRefreshStarted,
+        ConnectionInput::Stopped,
+    ]);
+    assert_eq!(transitions.last(), Some(&ConnectionState::Stopped));
+    assert_eq!(transitions.len(), 3);
+    assert_eq!(machine.state(), ConnectionState::Stopped);
+}
+
+#[test]
+fn states_serialize_in_snake_case() {
+    assert_eq!(
+        serde_json::to_value(ConnectionState::SessionRefreshing).unwrap(),
+        "session_refreshing"
+    );
+    assert_eq!(ConnectionState::Degraded.to_string(), "degraded");
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use base64::Engine;
+use be::feed::{decode_bus_data, parse_bus_positions_from_json, DecodeContext};
+use common::{encode, gzip};
+use rust_socketio::Payload;
+
+fn fleet(vehicles: usize) -> String {
+    let fleet: Vec<serde_json::Value> = (0..vehicles)
+        .map(|index| {
+            serde_json::json!({
+                "dt_received": "2024-05-01 08:15:02",
+                "dt_gps": "2024-05-01 08:15:00",
+                "latitude": 3.1478 + index as f64 * 0.0001,
+                "longitude": 101.6953,
+                "dir": "0",
+                "speed": index as f64,
+                "angle": 90.0,
+                "route": "300",
+                "bus_no": format!("WXX{:04}", index),
+                "engine_status": 1,
+                "accessibility": 1,
+                "provider": "RKL",
+            })
+        })
+        .collect();
+    serde_json::to_string(&fleet).unwrap()
+}
+
+// The pre-DecodeContext path: allocate per value, inflate into a String, parse with from_str.
+fn reference(values: &[String]) -> (String, u64) {
+    let mut buses = Vec::new();
+    let mut failures = 0;
+    for value in values {
+        match decode_bus_data(value).and_then(|json| parse_bus_positions_from_json(&json)) {
+            Some(mut parsed) => buses.append(&mut parsed),
+            None => failures += 1,
+        }
+    }
+    (serde_json::to_string(&buses).unwrap(), failures)
+}
+
+#[test]
+fn reused_context_matches_fresh_decoding_byte_for_byte() {
+    let truncated = {
+        let compressed = gzip(fleet(20).as_bytes());
+        base64::engine::general_purpose::STANDARD.encode(&compressed[..compressed.len() / 2])
+    };
+    let single =
+        serde_json::to_string(&serde_json::from_str::<serde_json::Value>(&fleet(1)).unwrap()[0])
+            .unwrap();
+    // Big payloads first so later, smaller ones would expose stale bytes left in the buffers.
+    let batches: Vec<Vec<String>> = vec![
+        vec![encode(&fleet(2_000))],
+        vec![encode(&fleet(3)), encode(&fleet(40)), encode(&fleet(1))],
+        vec![encode(&single)],
+        vec!["not base64!".to_string(), encode(&fleet(5))],
+        vec![truncated, encode("[]"), encode(&fleet(2))],
+        vec![encode(&fleet(7))],
+    ];
+
+    let mut context = DecodeContext::default();
+    for values in batches {
+        let payload = Payload::Text(
+            values
+                .iter()
+                .cloned()
+                .map(serde_json::Value::String)
+                .collect(),
+        );
+        let (buses, failures) = context.parse(payload, false);
+        let (expected, expected_failures) = reference(&values);
+        assert_eq!(serde_json::to_string(&buses).unwrap(), expected);
+        assert_eq!(failures, expected_failures);
+    }
+}
+
+#[test]
+fn binary_payloads_reuse_the_same_buffers() {
+    let mut context = DecodeContext::default();
+    for vehicles in [500, 2, 60] {
+        let json = fleet(vehicles);
+        let (buses, failures) = context.parse(Payload::Binary(gzip(json.as_bytes()).into()), false);
+        assert_eq!(failures, 0);
+        assert_eq!(
+            serde_json::to_string(&buses).unwrap(),
+            serde_json::to_string(&parse_bus_positions_from_json(&json).unwrap()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,177 @@
+// Throws arbitrary input at every stage of the decode path: whatever arrives on the socket,
+// decoding ends in None or a counted failure, never a panic.
+
+mod common;
+
+use base64::Engine;
+use be::feed::{
+    decode_bus_data, decode_gzip, decode_raw_payload, parse_bus_positions_from_json, DecodeContext,
+};
+use be::field_map::RAPID_KL_FIELDS;
+use be::timestamp::normalize_timestamp;
+use common::gzip;
+use proptest::prelude::*;
+use rust_socketio::Payload;
+use serde_json::{json, Value};
+
+// 2026-10-16T00:16:00Z
+const NOW_MS: i64 = 1_792_109_760_000;
+
+fn base64(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+fn position(bus_no: &str) -> Value {
+    json!({
+        "dt_received": "2026-10-16 08:15:02",
+        "dt_gps": "2026-10-16 08:15:00",
+        "latitude": 3.1478,
+        "longitude": 101.6953,
+        "speed": 32.0,
+        "angle": 90.0,
+        "route": "T789",
+        "bus_no": bus_no,
+        "engine_status": 1,
+        "accessibility": 1,
+        "provider": "RKL",
+    })
+}
+
+// Runs a decoded payload the rest of the way, as the pipeline would.
+fn decode_all(context: &mut DecodeContext, payload: Payload) -> (usize, u64) {
+    let (mut buses, failures) = context.parse(payload, true);
+    for bus in &mut buses {
+        normalize_timestamp(bus, NOW_MS);
+    }
+    (buses.len(), failures)
+}
+
+// Any JSON, leaning towards the keys the parser looks for so objects get near a position.
+fn any_json() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::Bool),
+        any::<i64>().prop_map(Value::from),
+        any::<f64>().prop_filter_map("JSON has no NaN or infinity", |number| {
+            serde_json::Number::from_f64(number).map(Value::Number)
+        }),
+        ".{0,24}".prop_map(Value::String),
+    ];
+    let key = prop_oneof![
+        prop::sample::select(RAPID_KL_FIELDS.to_vec()).prop_map(str::to_string),
+        "[a-z_]{1,12}",
+    ];
+    leaf.prop_recursive(4, 64, 8, move |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..8).prop_map(Value::Array),
+            prop::collection::vec((key.clone(), inner), 0..12)
+                .prop_map(|entries| Value::Object(entries.into_iter().collect())),
+        ]
+    })
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_strings_are_rejected(input in ".{0,512}") {
+        let _ = decode_raw_payload(&input);
+        prop_assert!(decode_bus_data(&input)
+            .and_then(|json| parse_bus_positions_from_json(&json))
+            .is_none());
+        let mut context = DecodeContext::default();
+        let (buses, _) = decode_all(&mut context, Payload::Text(vec![Value::String(input)]));
+        prop_assert_eq!(buses, 0);
+    }
+
+    #[test]
+    fn valid_base64_that_is_not_gzip_is_rejected(
+        bytes in prop::collection::vec(any::<u8>(), 1..4_096)
+    ) {
+        // 0x1f opens the gzip magic number; anything else fails in the header.
+        prop_assume!(bytes[0] != 0x1f);
+        prop_assert!(decode_gzip(&bytes).is_none());
+        prop_assert!(decode_bus_data(&base64(&bytes)).is_none());
+
+        let mut context = DecodeContext::default();
+        let text = Payload::Text(vec![Value::String(base64(&bytes))]);
+        prop_assert_eq!(decode_all(&mut context, text), (0, 1));
+        prop_assert_eq!(decode_all(&mut context, Payload::Binary(bytes.into())), (0, 1));
+    }
+
+    #[test]
+    fn truncated_gzip_streams_are_rejected(
+        fleet_size in 1..40usize,
+        cut in 0.0..1.0f64,
+    ) {
+        let fleet: Vec<Value> = (0..fleet_size)
+            .map(|i| position(&format!("WXX{:04}", i)))
+            .collect();
+        let compressed = gzip(Value::Array(fleet).to_string().as_bytes());
+        // Never the whole stream: at least the trailer's last byte goes.
+        let truncated = &compressed[..((compressed.len() as f64 * cut) as usize).max(1)];
+
+        prop_assert!(decode_gzip(truncated).is_none());
+        prop_assert!(decode_bus_data(&base64(truncated)).is_none());
+        let mut context = DecodeContext::default();
+        let text = Payload::Text(vec![Value::String(base64(truncated))]);
+        prop_assert_eq!(decode_all(&mut context, text), (0, 1));
+        prop_assert_eq!(
+            decode_all(&mut context, Payload::Binary(truncated.to_vec().into())),
+            (0, 1)
+        );
+    }
+
+    #[test]
+    fn gzipped_garbage_fails_in_the_parser(
+        bytes in prop::collection::vec(any::<u8>(), 0..4_096)
+    ) {
+        let encoded = base64(&gzip(&bytes));
+        if let Some(json) = decode_bus_data(&encoded) {
+            let _ = parse_bus_positions_from_json(&json);
+        }
+        let mut context = DecodeContext::default();
+        let (_, failures) =
+            decode_all(&mut context, Payload::Text(vec![Value::String(encoded)]));
+        prop_assert!(failures <= 1);
+    }
+
+    #[test]
+    fn arbitrary_json_never_panics_the_parser(payload in any_json()) {
+        let encoded = base64(&gzip(payload.to_string().as_bytes()));
+        let json = decode_bus_data(&encoded).unwrap();
+        let _ = parse_bus_positions_from_json(&json);
+
+        let mut context = DecodeContext::default();
+        let (buses, failures) =
+            decode_all(&mut context, Payload::Text(vec![Value::String(encoded)]));
+        // One value is at most one failure, and a failure yields nothing.
+        prop_assert!(failures <= 1);
+        prop_assert!(failures == 0 || buses == 0);
+    }
+
+    #[test]
+    fn a_position_with_any_field_replaced_never_panics(
+        field in prop::sample::select(RAPID_KL_FIELDS.to_vec()),
+        value in any_json(),
+    ) {
+        let mut entry = position("WXX1234");
+        entry[field] = value;
+        let payload = json!([entry, position("WYY5678")]);
+        let mut context = DecodeContext::default();
+        let encoded = base64(&gzip(payload.to_string().as_bytes()));
+        let text = Payload::Text(vec![Value::String(encoded)]);
+        let (buses, failures) = decode_all(&mut context, text);
+        // The untouched entry always survives the lenient array parse.
+        prop_assert!(buses >= 1);
+        prop_assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn any_timestamp_text_normalizes_or_is_flagged(dt_gps in ".{0,40}") {
+        let mut entry = position("WXX1234");
+        entry["dt_gps"] = Value::String(dt_gps);
+        let json = entry.to_string();
+        let mut buses = parse_bus_positions_from_json(&json).unwrap();
+        normalize_timestamp(&mut buses[0], NOW_MS);
+        prop_assert!(buses[0].timestamp_rfc3339.is_some() != buses[0].timestamp_parse_error);
+    }
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use base64::Engine;
+use be::feed::{
+    decode_gzip, decode_gzip_within, DecodeContext, DecodeError, DEFAULT_MAX_DECOMPRESSED_BYTES,
+};
+use be::gtfs_rt::{decode_feed, decode_feed_within};
+use common::gzip;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rust_socketio::Payload;
+use serde_json::Value;
+use std::io::Write;
+
+// 64 MiB of zeros, which gzip squeezes into about 64 KiB.
+fn gzip_bomb() -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    let chunk = vec![0u8; 1024 * 1024];
+    for _ in 0..64 {
+        encoder.write_all(&chunk).unwrap();
+    }
+    encoder.finish().unwrap()
+}
+
+// An empty position list padded with whitespace to exactly `len` bytes.
+fn padded_empty_list(len: usize) -> Vec<u8> {
+    let mut json = b"[".to_vec();
+    json.resize(len - 1, b' ');
+    json.push(b']');
+    json
+}
+
+#[test]
+fn a_gzip_bomb_is_rejected_with_its_compressed_size() {
+    let bomb = gzip_bomb();
+    assert!(bomb.len() < 1024 * 1024);
+
+    let mut context = DecodeContext::default();
+    let encoded = base64::engine::general_purpose::STANDARD.encode(&bomb);
+    let (buses, failures) = context.parse(Payload::Text(vec![Value::String(encoded)]), false);
+    assert_eq!((buses.len(), failures), (0, 1));
+    let failure = context.take_failures().remove(0);
+    assert_eq!(
+        failure.error,
+        DecodeError::TooLarge {
+            limit: DEFAULT_MAX_DECOMPRESSED_BYTES,
+            compressed_bytes: bomb.len(),
+        }
+    );
+    assert_eq!(
+        failure.error.to_string(),
+        format!(
+            "{} bytes of gzip inflate past the {}-byte limit",
+            bomb.len(),
+            DEFAULT_MAX_DECOMPRESSED_BYTES
+        )
+    );
+
+    let (buses, failures) = context.parse(Payload::Binary(bomb.clone().into()), false);
+    assert_eq!((buses.len(), failures), (0, 1));
+    assert!(decode_gzip(&bomb).is_none());
+}
+
+#[test]
+fn the_limit_is_inclusive() {
+    let at_limit = gzip(&padded_empty_list(1_024));
+    assert_eq!(decode_gzip_within(&at_limit, 1_024).unwrap().len(), 1_024);
+    assert_eq!(
+        decode_gzip_within(&at_limit, 1_023),
+        Err(DecodeError::TooLarge {
+            limit: 1_023,
+            compressed_bytes: at_limit.len(),
+        })
+    );
+
+    let mut context = DecodeContext::default().max_decompressed_bytes(1_024);
+    let (_, failures) = context.parse(Payload::Binary(at_limit.clone().into()), false);
+    assert_eq!(failures, 0);
+    let mut context = DecodeContext::default().max_decompressed_bytes(1_023);
+    let (_, failures) = context.parse(Payload::Binary(at_limit.into()), false);
+    assert_eq!(failures, 1);
+}
+
+#[test]
+fn a_raised_limit_lets_a_large_payload_through() {
+    let large = gzip(&padded_empty_list(DEFAULT_MAX_DECOMPRESSED_BYTES + 1));
+    assert!(decode_gzip(&large).is_none());
+
+    let mut context = DecodeContext::default().max_decompressed_bytes(32 * 1024 * 1024);
+    let (buses, failures) = context.parse(Payload::Binary(large.into()), false);
+    assert_eq!((buses.len(), failures), (0, 0));
+}
+
+#[test]
+fn a_gzipped_gtfs_rt_bomb_is_rejected() {
+    let bomb = gzip_bomb();
+    let error = decode_feed(&bomb).unwrap_err();
+    assert!(error.contains("limit"), "{}", error);
+
+    // An empty feed inflates fine, and only up to the limit it is given.
+    let empty = gzip(&[]);
+    assert!(decode_feed(&empty).unwrap().entity.is_empty());
+    let small = gzip(&[0x0a, 0x00]);
+    assert!(decode_feed_within(&small, 2).is_ok());
+    assert!(decode_feed_within(&small, 1).unwrap_err().contains("limit"));
+}
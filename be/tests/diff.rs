@@ -0,0 +1,62 @@
+mod common;
+
+use be::diff::{DiffEngine, VehicleChange};
+use be::feed::BusPosition;
+
+fn bus(bus_no: &str, route: &str, latitude: f64, speed: f64) -> BusPosition {
+    let mut bus = common::bus_at(bus_no, route, latitude, 101.6953);
+    bus.speed = speed;
+    bus
+}
+
+fn lines(changes: Vec<VehicleChange>) -> Vec<String> {
+    changes.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn only_material_changes_are_printed() {
+    let mut engine = DiffEngine::new(3);
+    assert_eq!(
+        lines(engine.apply(&[bus("WXX1234", "300", 3.1478, 18.0)])),
+        ["WXX1234 route=300 appeared"]
+    );
+
+    // About 11m and half a km/h: GPS jitter, not a change.
+    assert!(engine
+        .apply(&[bus("WXX1234", "300", 3.1479, 18.5)])
+        .is_empty());
+
+    // Measured from the last printed position, so the jitter doesn't eat into it.
+    assert_eq!(
+        lines(engine.apply(&[bus("WXX1234", "300", 3.14888, 32.0)])),
+        ["WXX1234 route=300 moved 120m speed 18→32 km/h"]
+    );
+    assert_eq!(
+        lines(engine.apply(&[bus("WXX1234", "301", 3.14888, 32.0)])),
+        ["WXX1234 route=301 route 300→301"]
+    );
+}
+
+#[test]
+fn a_vehicle_missing_for_several_batches_is_lost_once() {
+    let mut engine = DiffEngine::new(3);
+    engine.apply(&[
+        bus("WXX1234", "300", 3.1478, 18.0),
+        bus("WYY5678", "300", 3.15, 0.0),
+    ]);
+
+    let only_second = [bus("WYY5678", "300", 3.15, 0.0)];
+    assert!(engine.apply(&only_second).is_empty());
+    assert!(engine.apply(&only_second).is_empty());
+    assert_eq!(
+        lines(engine.apply(&only_second)),
+        ["WXX1234 route=300 lost"]
+    );
+    assert!(engine.apply(&only_second).is_empty());
+
+    // Coming back after being lost counts as appearing again.
+    assert_eq!(
+        lines(engine.apply(&[bus("WXX1234", "300", 3.1478, 18.0)])),
+        ["WXX1234 route=300 appeared"]
+    );
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use be::client::ClientEvent;
+use be::feed::DecodeContext;
+use be::pipeline::{decode_frame_events, emptied_route, DecodeOptions, RawFrame};
+use be::session::SessionInfo;
+use common::encode;
+use rust_socketio::Payload;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+fn frame(values: Vec<Value>, received_at_unix_ms: i64) -> RawFrame {
+    RawFrame {
+        event: "onFts-client".to_string(),
+        payload: Payload::Text(values),
+        received_at_unix_ms,
+    }
+}
+
+fn position(bus_no: &str) -> Value {
+    json!({
+        "latitude": 3.1478,
+        "longitude": 101.6953,
+        "speed": 32.0,
+        "angle": 90.0,
+        "route": "T789",
+        "bus_no": bus_no,
+        "engine_status": 1,
+        "accessibility": 1,
+        "provider": "RKL",
+    })
+}
+
+fn decode(frame: RawFrame, options: &DecodeOptions) -> Vec<(usize, u64)> {
+    decode_frame_events(frame, options, &mut DecodeContext::default())
+        .into_iter()
+        .filter_map(|event| match event {
+            ClientEvent::Buses {
+                buses,
+                decode_failures,
+                ..
+            } => Some((buses.len(), decode_failures)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn options() -> (DecodeOptions, Arc<Mutex<SessionInfo>>) {
+    let session_info = Arc::new(Mutex::new(SessionInfo::default()));
+    let options = DecodeOptions {
+        session_info: Some(session_info.clone()),
+        ..DecodeOptions::default()
+    };
+    (options, session_info)
+}
+
+#[test]
+fn an_empty_list_counts_as_data() {
+    let (options, session_info) = options();
+    assert_eq!(
+        decode(
+            frame(vec![encode(&json!([]).to_string()).into()], 1_000),
+            &options
+        ),
+        [(0, 0)]
+    );
+
+    let info = session_info.lock().unwrap().clone();
+    assert_eq!(info.last_data_unix_ms, Some(1_000));
+    assert_eq!(info.last_valid_empty_unix_ms, Some(1_000));
+}
+
+#[test]
+fn a_frame_that_fails_to_decode_does_not() {
+    let (options, session_info) = options();
+    let broken = Value::String("bm90IGd6aXA=".to_string());
+    assert_eq!(decode(frame(vec![broken], 1_000), &options), [(0, 1)]);
+
+    let info = session_info.lock().unwrap().clone();
+    assert_eq!(info.last_data_unix_ms, None);
+    assert_eq!(info.last_valid_empty_unix_ms, None);
+}
+
+#[test]
+fn buses_count_as_data_without_marking_the_feed_empty() {
+    let (options, session_info) = options();
+    decode(
+        frame(vec![encode(&json!([]).to_string()).into()], 1_000),
+        &options,
+    );
+    let batch = Value::String(encode(&json!([position("WXX1234")]).to_string()));
+    assert_eq!(decode(frame(vec![batch], 2_000), &options), [(1, 0)]);
+
+    let info = session_info.lock().unwrap().clone();
+    assert_eq!(info.last_data_unix_ms, Some(2_000));
+    assert_eq!(info.last_valid_empty_unix_ms, Some(1_000));
+}
+
+#[test]
+fn a_frame_with_nothing_to_decode_is_not_published() {
+    let (options, session_info) = options();
+    assert!(decode(frame(vec![json!(42)], 1_000), &options).is_empty());
+    assert_eq!(session_info.lock().unwrap().last_data_unix_ms, None);
+}
+
+#[test]
+fn only_a_routes_own_socket_can_empty_it() {
+    assert_eq!(emptied_route("T789", 0, 0), Some("T789"));
+    assert_eq!(emptied_route("T789", 2, 0), None);
+    assert_eq!(emptied_route("T789", 0, 1), None);
+    // The all-buses socket and GTFS-rt.
+    assert_eq!(emptied_route("", 0, 0), None);
+}
@@ -0,0 +1,76 @@
+use be::enrich::GtfsEnricher;
+use be::feed::{BusPosition, PositionSource};
+
+const MAX_AGE_MS: i64 = 120_000;
+const TOLERANCE_METERS: f64 = 500.0;
+
+fn socket_bus(bus_no: &str, latitude: f64) -> BusPosition {
+    serde_json::from_value(serde_json::json!({
+        "latitude": latitude,
+        "longitude": 101.6869,
+        "speed": 25.0,
+        "angle": 0.0,
+        "route": "300",
+        "bus_no": bus_no,
+        "engine_status": 1,
+        "accessibility": 1,
+        "provider": "RKL",
+    }))
+    .unwrap()
+}
+
+fn gtfs_bus(bus_no: &str, latitude: f64) -> BusPosition {
+    let mut bus = socket_bus(bus_no, latitude);
+    bus.source = PositionSource::Gtfs;
+    bus.trip_id = Some("weekday_300_0800".to_string());
+    bus.route_id = Some("30000".to_string());
+    bus.current_stop_sequence = Some(12);
+    bus
+}
+
+#[test]
+fn a_recent_nearby_record_fills_in_the_trip_metadata() {
+    let mut enricher = GtfsEnricher::new(MAX_AGE_MS, TOLERANCE_METERS);
+    enricher.update(&[gtfs_bus("wxx 1234", 3.1390)], 1_000);
+
+    // ~111m away and a minute later: still a match.
+    let mut bus = socket_bus("WXX1234", 3.1400);
+    assert!(enricher.enrich(&mut bus, 61_000));
+    assert_eq!(bus.trip_id.as_deref(), Some("weekday_300_0800"));
+    assert_eq!(bus.route_id.as_deref(), Some("30000"));
+    assert_eq!(bus.current_stop_sequence, Some(12));
+    assert_eq!(bus.route, "300");
+}
+
+#[test]
+fn unmatched_stale_or_distant_updates_pass_through_unchanged() {
+    let mut enricher = GtfsEnricher::new(MAX_AGE_MS, TOLERANCE_METERS);
+    enricher.update(&[gtfs_bus("WXX1234", 3.1390)], 0);
+
+    let mut unknown = socket_bus("WYY5678", 3.1390);
+    assert!(!enricher.enrich(&mut unknown, 1_000));
+    assert!(unknown.trip_id.is_none());
+
+    let mut stale = socket_bus("WXX1234", 3.1390);
+    assert!(!enricher.enrich(&mut stale, MAX_AGE_MS));
+    assert!(stale.trip_id.is_none());
+
+    // ~1.1km away: the same id on a different bus, or a bad fix.
+    let mut distant = socket_bus("WXX1234", 3.1490);
+    assert!(!enricher.enrich(&mut distant, 1_000));
+    assert!(distant.current_stop_sequence.is_none());
+
+    let serialized = serde_json::to_value(&distant).unwrap();
+    assert!(serialized.get("trip_id").is_none());
+}
+
+#[test]
+fn expired_records_are_dropped_on_the_next_update() {
+    let mut enricher = GtfsEnricher::new(MAX_AGE_MS, TOLERANCE_METERS);
+    enricher.update(&[gtfs_bus("WXX1234", 3.1390)], 0);
+    enricher.update(&[gtfs_bus("WYY5678", 3.1390)], MAX_AGE_MS);
+    assert_eq!(enricher.len(), 1);
+
+    let mut bus = socket_bus("WYY5678", 3.1390);
+    assert!(enricher.enrich(&mut bus, MAX_AGE_MS + 1_000));
+}
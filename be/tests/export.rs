@@ -0,0 +1,83 @@
+#![cfg(feature = "parquet")]
+
+use be::export::{to_gpx, to_kml, TrackPoint};
+
+// 2024-05-01T08:30:00Z
+const START_MS: i64 = 1_714_552_200_000;
+
+fn track() -> Vec<TrackPoint> {
+    vec![
+        TrackPoint {
+            lat: 3.1478,
+            lon: 101.6953,
+            speed_kmh: 36.0,
+            bearing: 90.0,
+            time_ms: START_MS,
+        },
+        TrackPoint {
+            lat: 3.14812345,
+            lon: 101.6961,
+            speed_kmh: 18.0,
+            bearing: -90.0,
+            time_ms: START_MS + 20_500,
+        },
+    ]
+}
+
+#[test]
+fn gpx_has_one_track_point_per_position() {
+    let gpx = to_gpx("WXX<1234>", &track());
+    assert!(gpx.contains("<name>WXX&lt;1234&gt;</name>"), "{}", gpx);
+    assert_eq!(gpx.matches("<trkpt ").count(), 2);
+
+    assert!(gpx.contains("<trkpt lat=\"3.147800\" lon=\"101.695300\">"));
+    assert!(gpx.contains("<time>2024-05-01T08:30:00Z</time>"));
+    // Metres per second, and a course within 0..360.
+    assert!(gpx.contains("<gpxtpx:speed>10.00</gpxtpx:speed><gpxtpx:course>90.0</gpxtpx:course>"));
+    assert!(gpx.contains("<trkpt lat=\"3.148123\" lon=\"101.696100\">"));
+    assert!(gpx.contains("<time>2024-05-01T08:30:20Z</time>"));
+    assert!(gpx.contains("<gpxtpx:speed>5.00</gpxtpx:speed><gpxtpx:course>270.0</gpxtpx:course>"));
+    assert!(gpx.trim_end().ends_with("</gpx>"));
+}
+
+#[test]
+fn kml_lists_times_coordinates_and_speeds_in_the_same_order() {
+    let kml = to_kml("WXX&1234", &track());
+    assert_eq!(kml.matches("<name>WXX&amp;1234</name>").count(), 2);
+
+    let when: Vec<&str> = kml
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("<when>"))
+        .collect();
+    assert_eq!(
+        when,
+        ["2024-05-01T08:30:00Z</when>", "2024-05-01T08:30:20Z</when>"]
+    );
+    // Longitude first, as KML has it.
+    let coords: Vec<&str> = kml
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("<gx:coord>"))
+        .collect();
+    assert_eq!(
+        coords,
+        [
+            "101.695300 3.147800 0</gx:coord>",
+            "101.696100 3.148123 0</gx:coord>"
+        ]
+    );
+    let speeds: Vec<&str> = kml
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("<gx:value>"))
+        .collect();
+    assert_eq!(speeds, ["36.0</gx:value>", "18.0</gx:value>"]);
+    assert!(kml.trim_end().ends_with("</kml>"));
+}
+
+#[test]
+fn an_empty_track_is_still_a_valid_document() {
+    let gpx = to_gpx("WXX1234", &[]);
+    assert!(gpx.contains("<trkseg>\n    </trkseg>"), "{}", gpx);
+    let kml = to_kml("WXX1234", &[]);
+    assert!(!kml.contains("<when>"));
+    assert!(kml.contains("<gx:Track>"));
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use be::feed::{parse_bus_positions, parse_bus_positions_mapped};
+use be::field_map::FieldMap;
+use common::gzip;
+use rust_socketio::Payload;
+use std::collections::HashMap;
+
+// The Rapid KL fixture from binary_payload.rs with another provider's key names.
+const RENAMED_FIXTURE: &str = r#"[
+    {"lat":3.1478,"lon":101.6953,"speed":32.0,"angle":90.0,"no_route":"300","bus_no":"WXX1234","engine_status":1,"accessibility":1,"provider":"XYZ","odometer":1200},
+    {"lat":3.1390,"lon":101.6869,"speed":0.0,"angle":270.0,"no_route":"302","bus_no":"WYY5678","engine_status":1,"accessibility":0,"provider":"XYZ"}
+]"#;
+
+fn field_map(entries: &[(&str, &str)]) -> Result<FieldMap, String> {
+    let entries: HashMap<String, String> = entries
+        .iter()
+        .map(|(source, target)| (source.to_string(), target.to_string()))
+        .collect();
+    FieldMap::from_entries(&entries)
+}
+
+#[test]
+fn mapped_keys_fill_the_canonical_fields() {
+    let map = field_map(&[
+        ("lat", "latitude"),
+        ("lon", "longitude"),
+        ("no_route", "route"),
+    ])
+    .unwrap();
+    let (buses, failures) = parse_bus_positions_mapped(
+        Payload::Binary(gzip(RENAMED_FIXTURE.as_bytes()).into()),
+        false,
+        &map,
+    );
+
+    assert_eq!(failures, 0);
+    assert_eq!(buses.len(), 2);
+    assert_eq!(buses[0].latitude, 3.1478);
+    assert_eq!(buses[0].longitude, 101.6953);
+    assert_eq!(buses[0].route, "300");
+    assert_eq!(buses[1].route, "302");
+}
+
+#[test]
+fn unmapped_keys_still_reach_extra() {
+    let map = field_map(&[
+        ("lat", "latitude"),
+        ("lon", "longitude"),
+        ("no_route", "route"),
+    ])
+    .unwrap();
+    let (buses, _) = parse_bus_positions_mapped(
+        Payload::Binary(gzip(RENAMED_FIXTURE.as_bytes()).into()),
+        true,
+        &map,
+    );
+
+    assert_eq!(
+        buses[0].extra.get("odometer"),
+        Some(&serde_json::json!(1200))
+    );
+    assert!(!buses[0].extra.contains_key("lat"));
+}
+
+#[test]
+fn without_a_map_the_renamed_payload_does_not_parse() {
+    let (buses, failures) = parse_bus_positions(
+        Payload::Binary(gzip(RENAMED_FIXTURE.as_bytes()).into()),
+        false,
+    );
+    assert!(buses.is_empty());
+    assert_eq!(failures, 1);
+}
+
+#[test]
+fn the_default_map_is_the_identity() {
+    assert!(FieldMap::rapid_kl().is_identity());
+    assert!(field_map(&[("latitude", "latitude")])
+        .unwrap()
+        .is_identity());
+}
+
+#[test]
+fn bad_maps_are_rejected() {
+    assert!(field_map(&[("lat", "lattitude")])
+        .unwrap_err()
+        .contains("unknown field"));
+    assert!(field_map(&[("lat", "latitude"), ("y", "latitude")])
+        .unwrap_err()
+        .contains("both"));
+}
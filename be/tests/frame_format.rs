@@ -0,0 +1,75 @@
+#![cfg(feature = "server")]
+
+use be::feed::BusPosition;
+use be::frame_format::{from_msgpack, to_msgpack, FrameFormat};
+use serde_json::{json, Value};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/payloads");
+
+// The T789 golden positions, plus one with every optional field set.
+fn batch() -> Vec<BusPosition> {
+    let golden: Value = serde_json::from_str(
+        &std::fs::read_to_string(format!("{}/t789_batch.golden.json", FIXTURES)).unwrap(),
+    )
+    .unwrap();
+    let mut buses: Vec<BusPosition> = serde_json::from_value(golden["positions"].clone()).unwrap();
+    let mut full = buses[0].clone();
+    full.bus_no = "WZZ9999".to_string();
+    full.smoothed_speed_kmh = Some(28.4);
+    full.local_time = Some("2026-10-16T08:15:00+08:00".to_string());
+    full.timestamp_parse_error = true;
+    full.progress_m = Some(4_210.5);
+    full.progress_pct = Some(37.2);
+    full.off_route = true;
+    full.inactive = true;
+    full.delay_min = Some(-1.5);
+    full.trip_id = Some("T789_WD_0815".to_string());
+    full.route_id = Some("T789".to_string());
+    full.current_stop_sequence = Some(12);
+    full.extra.insert(
+        "occupancy".to_string(),
+        json!({ "level": "low", "seats": 21 }),
+    );
+    buses.push(full);
+    buses
+}
+
+#[test]
+fn a_batch_round_trips_through_msgpack() {
+    let buses = batch();
+    let decoded = from_msgpack(&to_msgpack(&buses).unwrap()).unwrap();
+    assert_eq!(
+        serde_json::to_value(&decoded).unwrap(),
+        serde_json::to_value(&buses).unwrap()
+    );
+}
+
+#[test]
+fn msgpack_frames_are_smaller_than_json() {
+    let buses = batch();
+    let json = serde_json::to_vec(&buses).unwrap();
+    assert!(to_msgpack(&buses).unwrap().len() < json.len());
+}
+
+#[test]
+fn the_format_parameter_wins_over_accept() {
+    let negotiate = FrameFormat::negotiate;
+    assert_eq!(negotiate(None, None), Ok(FrameFormat::Json));
+    assert_eq!(
+        negotiate(None, Some("application/json")),
+        Ok(FrameFormat::Json)
+    );
+    assert_eq!(
+        negotiate(None, Some("text/html, application/msgpack;q=0.9")),
+        Ok(FrameFormat::MessagePack)
+    );
+    assert_eq!(
+        negotiate(Some("json"), Some("application/msgpack")),
+        Ok(FrameFormat::Json)
+    );
+    assert_eq!(
+        negotiate(Some("msgpack"), None),
+        Ok(FrameFormat::MessagePack)
+    );
+    assert!(negotiate(Some("cbor"), None).is_err());
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use be::feed::BusPosition;
+use be::gaps::{Gap, GapCause, GapDetector, GapLog};
+use common::ROUTE;
+
+// Three 10s reload intervals.
+const THRESHOLD_MS: i64 = 30_000;
+
+fn bus(bus_no: &str, timestamp_rfc3339: &str) -> BusPosition {
+    let mut bus = common::bus(bus_no, ROUTE);
+    bus.timestamp_rfc3339 = Some(timestamp_rfc3339.to_string());
+    bus
+}
+
+#[test]
+fn a_silence_longer_than_the_threshold_is_an_upstream_gap() {
+    let mut detector = GapDetector::new(THRESHOLD_MS);
+    assert_eq!(
+        detector.observe(&bus("WXX1234", "2024-05-01T08:00:00Z"), "T789", 0),
+        None
+    );
+    // Exactly the threshold is still on time.
+    assert_eq!(
+        detector.observe(&bus("WXX1234", "2024-05-01T08:00:30Z"), "T789", 30_000),
+        None
+    );
+
+    let gap = detector
+        .observe(&bus("WXX1234", "2024-05-01T08:01:15Z"), "T789", 75_000)
+        .unwrap();
+    assert_eq!(
+        gap,
+        Gap {
+            vehicle_id: "WXX1234".to_string(),
+            route: "T789".to_string(),
+            from_unix_ms: 30_000,
+            to_unix_ms: 75_000,
+            duration_ms: 45_000,
+            cause: GapCause::Upstream,
+        }
+    );
+    assert_eq!(gap.to_string(), "WXX1234 route=T789 gap of 45s (upstream)");
+}
+
+#[test]
+fn a_replayed_fix_does_not_end_the_gap() {
+    let mut detector = GapDetector::new(THRESHOLD_MS);
+    detector.observe(&bus("WXX1234", "2024-05-01T08:00:00Z"), "T789", 0);
+    assert_eq!(
+        detector.observe(&bus("WXX1234", "2024-05-01T08:00:00Z"), "T789", 20_000),
+        None
+    );
+    assert_eq!(
+        detector.observe(&bus("WXX1234", "2024-05-01T08:00:00Z"), "T789", 40_000),
+        None
+    );
+
+    let gap = detector
+        .observe(&bus("WXX1234", "2024-05-01T08:00:50Z"), "T789", 50_000)
+        .unwrap();
+    assert_eq!((gap.from_unix_ms, gap.duration_ms), (0, 50_000));
+}
+
+#[test]
+fn a_gap_overlapping_our_own_disconnect_is_labelled_local() {
+    let mut detector = GapDetector::new(THRESHOLD_MS);
+    detector.observe(&bus("WXX1234", "2024-05-01T08:00:00Z"), "T789", 0);
+    detector.observe(&bus("WYY5678", "2024-05-01T08:00:00Z"), "T789", 0);
+    detector.on_disconnected("T789", 10_000);
+    detector.on_connected("T789", 20_000);
+
+    let gap = detector
+        .observe(&bus("WXX1234", "2024-05-01T08:01:00Z"), "T789", 60_000)
+        .unwrap();
+    assert_eq!(gap.cause, GapCause::LocalDisconnect);
+
+    // A later gap for the other bus, after the outage was over.
+    detector.observe(&bus("WYY5678", "2024-05-01T08:01:00Z"), "T789", 60_000);
+    let gap = detector
+        .observe(&bus("WYY5678", "2024-05-01T08:02:00Z"), "T789", 120_000)
+        .unwrap();
+    assert_eq!(gap.cause, GapCause::Upstream);
+}
+
+#[test]
+fn a_disconnect_still_in_progress_counts() {
+    let mut detector = GapDetector::new(THRESHOLD_MS);
+    detector.observe(&bus("WXX1234", "2024-05-01T08:00:00Z"), "T789", 0);
+    detector.on_disconnected("T789", 50_000);
+
+    // Decoded from a frame queued just before the drop.
+    let gap = detector
+        .observe(&bus("WXX1234", "2024-05-01T08:01:00Z"), "T789", 60_000)
+        .unwrap();
+    assert_eq!(gap.cause, GapCause::LocalDisconnect);
+}
+
+#[test]
+fn another_routes_disconnect_does_not_excuse_a_gap() {
+    let mut detector = GapDetector::new(THRESHOLD_MS);
+    detector.observe(&bus("WXX1234", "2024-05-01T08:00:00Z"), "T789", 0);
+    detector.on_disconnected("T790", 10_000);
+    detector.on_connected("T790", 20_000);
+
+    let gap = detector
+        .observe(&bus("WXX1234", "2024-05-01T08:01:00Z"), "T789", 60_000)
+        .unwrap();
+    assert_eq!(gap.cause, GapCause::Upstream);
+}
+
+#[test]
+fn logged_gaps_are_queried_by_vehicle_and_window() {
+    let dir = std::env::temp_dir().join(format!("rapidbro-gaps-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let log = GapLog::new(&dir);
+    assert!(log.query("WXX1234", i64::MIN, i64::MAX).unwrap().is_empty());
+
+    let gap = |vehicle_id: &str, from_unix_ms: i64, to_unix_ms: i64| Gap {
+        vehicle_id: vehicle_id.to_string(),
+        route: "T789".to_string(),
+        from_unix_ms,
+        to_unix_ms,
+        duration_ms: to_unix_ms - from_unix_ms,
+        cause: GapCause::Upstream,
+    };
+    log.append(&gap("WXX1234", 300_000, 400_000)).unwrap();
+    log.append(&gap("WYY5678", 0, 100_000)).unwrap();
+    log.append(&gap("WXX1234", 0, 100_000)).unwrap();
+
+    let gaps = log.query("wxx1234", i64::MIN, i64::MAX).unwrap();
+    let windows: Vec<(i64, i64)> = gaps
+        .iter()
+        .map(|gap| (gap.from_unix_ms, gap.to_unix_ms))
+        .collect();
+    assert_eq!(windows, [(0, 100_000), (300_000, 400_000)]);
+    // Overlapping the window is enough.
+    assert_eq!(log.query("WXX1234", 350_000, 500_000).unwrap().len(), 1);
+    assert!(log.query("WXX1234", 150_000, 250_000).unwrap().is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
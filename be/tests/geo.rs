@@ -0,0 +1,139 @@
+use be::feed::BusPosition;
+use be::geo::{bearing_deg, haversine_meters, interpolate, slerp};
+
+const KL_SENTRAL: (f64, f64) = (3.1343, 101.6865);
+const KLCC: (f64, f64) = (3.1579, 101.7116);
+const BIG_BEN: (f64, f64) = (51.5007, -0.1246);
+const EIFFEL_TOWER: (f64, f64) = (48.8584, 2.2945);
+
+fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+    assert!(
+        (actual - expected).abs() <= tolerance,
+        "expected {} ± {}, got {}",
+        expected,
+        tolerance,
+        actual
+    );
+}
+
+#[test]
+fn haversine_matches_reference_distances() {
+    assert_close(haversine_meters(KL_SENTRAL, KLCC), 3_827.9, 1.0);
+    assert_close(haversine_meters(BIG_BEN, EIFFEL_TOWER), 340_539.0, 10.0);
+    // One degree of longitude on the equator.
+    assert_close(haversine_meters((0.0, 0.0), (0.0, 1.0)), 111_194.9, 0.5);
+}
+
+#[test]
+fn haversine_is_symmetric_and_zero_for_the_same_point() {
+    assert_eq!(haversine_meters(KLCC, KLCC), 0.0);
+    assert_close(
+        haversine_meters(KL_SENTRAL, KLCC),
+        haversine_meters(KLCC, KL_SENTRAL),
+        1e-9,
+    );
+}
+
+#[test]
+fn haversine_handles_antipodes_and_the_antimeridian() {
+    let half_circumference = std::f64::consts::PI * be::geo::EARTH_RADIUS_METERS;
+    assert_close(
+        haversine_meters((0.0, 0.0), (0.0, 180.0)),
+        half_circumference,
+        1.0,
+    );
+    assert_close(
+        haversine_meters((0.0, 179.5), (0.0, -179.5)),
+        haversine_meters((0.0, 0.0), (0.0, 1.0)),
+        1e-6,
+    );
+}
+
+#[test]
+fn bearing_matches_reference_headings() {
+    assert_close(bearing_deg(KL_SENTRAL, KLCC), 46.72, 0.01);
+    assert_close(bearing_deg(KLCC, KL_SENTRAL), 226.72, 0.01);
+    assert_close(bearing_deg(BIG_BEN, EIFFEL_TOWER), 148.68, 0.01);
+}
+
+#[test]
+fn bearing_covers_the_cardinal_directions_in_range() {
+    assert_close(bearing_deg((0.0, 0.0), (1.0, 0.0)), 0.0, 1e-9);
+    assert_close(bearing_deg((0.0, 0.0), (0.0, 1.0)), 90.0, 1e-9);
+    assert_close(bearing_deg((0.0, 0.0), (-1.0, 0.0)), 180.0, 1e-9);
+    assert_close(bearing_deg((0.0, 0.0), (0.0, -1.0)), 270.0, 1e-9);
+    let heading = bearing_deg((3.0, 101.0), (3.0001, 100.9999));
+    assert!((0.0..360.0).contains(&heading));
+}
+
+#[test]
+fn web_mercator_matches_epsg_3857_reference_values() {
+    let (x, y) = be::geo::web_mercator((0.0, 0.0));
+    assert_close(x, 0.0, 1e-9);
+    assert_close(y, 0.0, 1e-9);
+
+    // One degree north-east of null island.
+    let (x, y) = be::geo::web_mercator((1.0, 1.0));
+    assert_close(x, 111_319.491, 0.001);
+    assert_close(y, 111_325.143, 0.001);
+
+    // Near Pasar Seni, Kuala Lumpur.
+    let (x, y) = be::geo::web_mercator((3.1390, 101.6869));
+    assert_close(x, 11_319_733.928, 0.001);
+    assert_close(y, 349_606.816, 0.001);
+
+    // The projection's corner, and latitudes past it clamp to the same edge.
+    let (x, y) = be::geo::web_mercator((89.9, 180.0));
+    assert_close(x, 20_037_508.343, 0.001);
+    assert_close(y, 20_037_508.343, 0.01);
+}
+
+fn bus_at(point: (f64, f64)) -> BusPosition {
+    serde_json::from_value(serde_json::json!({
+        "latitude": point.0,
+        "longitude": point.1,
+        "speed": 30.0,
+        "angle": 0.0,
+        "route": "T789",
+        "bus_no": "WXX1234",
+        "engine_status": 1,
+        "accessibility": 1,
+        "provider": "RKL",
+    }))
+    .unwrap()
+}
+
+#[test]
+fn interpolate_starts_and_ends_on_the_fixes() {
+    let (prev, next) = (bus_at(KL_SENTRAL), bus_at(KLCC));
+    assert_eq!(interpolate(&prev, &next, 0.0), KL_SENTRAL);
+    assert_eq!(interpolate(&prev, &next, 1.0), KLCC);
+    // Outside [0, 1] holds at the nearer fix rather than extrapolating.
+    assert_eq!(interpolate(&prev, &next, -0.5), KL_SENTRAL);
+    assert_eq!(interpolate(&prev, &next, 1.5), KLCC);
+}
+
+#[test]
+fn interpolate_halfway_is_equidistant_from_both_fixes() {
+    let midpoint = interpolate(&bus_at(KL_SENTRAL), &bus_at(KLCC), 0.5);
+    let total = haversine_meters(KL_SENTRAL, KLCC);
+    assert_close(haversine_meters(KL_SENTRAL, midpoint), total / 2.0, 0.01);
+    assert_close(haversine_meters(midpoint, KLCC), total / 2.0, 0.01);
+
+    let quarter = interpolate(&bus_at(KL_SENTRAL), &bus_at(KLCC), 0.25);
+    assert_close(haversine_meters(KL_SENTRAL, quarter), total / 4.0, 0.01);
+}
+
+#[test]
+fn slerp_follows_the_great_circle_across_the_antimeridian() {
+    let (lat, lon) = slerp((0.0, 0.0), (0.0, 10.0), 0.5);
+    assert_close(lat, 0.0, 1e-9);
+    assert_close(lon, 5.0, 1e-9);
+
+    let (lat, lon) = slerp((0.0, 179.0), (0.0, -179.0), 0.5);
+    assert_close(lat, 0.0, 1e-9);
+    assert_close(lon.abs(), 180.0, 1e-9);
+
+    // Identical fixes stay put.
+    assert_eq!(slerp(KLCC, KLCC, 0.5), KLCC);
+}
@@ -0,0 +1,135 @@
+// Decodes every payload in tests/fixtures/payloads end to end and compares the result
+// with its golden file. A fixture is `<name>.b64`, one base64+gzip payload value as the
+// socket sends it (`cargo run --bin make-fixture` cuts one from a --payload-log-dir
+// capture); `<name>.golden.json` is the positions it decodes to after timestamp
+// normalization and coordinate validation, or null when it doesn't parse.
+//
+// After an intended change to the output, rewrite the golden files and review the diff:
+//
+//     UPDATE_GOLDEN=1 cargo test --test golden
+
+use be::feed::{decode_bus_data, parse_bus_positions_from_json, BusPosition, DecodeContext};
+use be::timestamp::normalize_timestamp;
+use be::validate::{validate_coordinates, MALAYSIA_BBOX};
+use rust_socketio::Payload;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/payloads");
+// 2026-10-16T00:16:00Z, a minute after most of the fixtures' fix times.
+const NOW_MS: i64 = 1_792_109_760_000;
+
+fn fixtures() -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(FIXTURES)
+        .unwrap()
+        .filter_map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name()?.to_str()?.strip_suffix(".b64")?;
+            Some(name.to_string())
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+fn fixture_path(name: &str, extension: &str) -> PathBuf {
+    Path::new(FIXTURES).join(format!("{}.{}", name, extension))
+}
+
+fn encoded(name: &str) -> String {
+    std::fs::read_to_string(fixture_path(name, "b64"))
+        .unwrap()
+        .trim()
+        .to_string()
+}
+
+fn decode(name: &str) -> Option<Vec<BusPosition>> {
+    let json = decode_bus_data(&encoded(name))
+        .unwrap_or_else(|| panic!("{} is not base64-encoded gzip", name));
+    parse_bus_positions_from_json(&json)
+}
+
+// What the golden file holds: the accepted positions and every vehicle's coordinate check.
+fn processed(name: &str) -> Value {
+    let Some(mut buses) = decode(name) else {
+        return json!({ "positions": null, "coordinates": {} });
+    };
+    let mut coordinates = BTreeMap::new();
+    buses.retain_mut(|bus| {
+        normalize_timestamp(bus, NOW_MS);
+        let check = validate_coordinates(bus, &MALAYSIA_BBOX);
+        coordinates.insert(bus.bus_no.clone(), format!("{:?}", check));
+        check.is_accepted()
+    });
+    json!({ "positions": buses, "coordinates": coordinates })
+}
+
+#[test]
+fn fixtures_match_their_golden_files() {
+    let names = fixtures();
+    assert!(!names.is_empty(), "no fixtures in {}", FIXTURES);
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    for name in names {
+        let actual = processed(&name);
+        let golden_path = fixture_path(&name, "golden.json");
+        if update {
+            let mut pretty = serde_json::to_string_pretty(&actual).unwrap();
+            pretty.push('\n');
+            std::fs::write(&golden_path, pretty).unwrap();
+            continue;
+        }
+        let golden = std::fs::read_to_string(&golden_path)
+            .unwrap_or_else(|_| panic!("{} has no golden file; run with UPDATE_GOLDEN=1", name));
+        let expected: Value = serde_json::from_str(&golden).unwrap();
+        assert_eq!(actual, expected, "{} differs from its golden file", name);
+    }
+}
+
+#[test]
+fn the_socket_decode_path_agrees_with_decode_bus_data() {
+    for name in fixtures() {
+        let mut context = DecodeContext::default();
+        let (buses, failures) =
+            context.parse(Payload::Text(vec![Value::String(encoded(&name))]), false);
+        match decode(&name) {
+            Some(expected) => {
+                assert_eq!(failures, 0, "{}", name);
+                assert_eq!(
+                    serde_json::to_value(&buses).unwrap(),
+                    serde_json::to_value(&expected).unwrap(),
+                    "{}",
+                    name
+                );
+            }
+            None => assert_eq!((buses.len(), failures), (0, 1), "{}", name),
+        }
+    }
+}
+
+#[test]
+fn golden_positions_round_trip_through_the_serde_model() {
+    for name in fixtures() {
+        let golden: Value = serde_json::from_str(
+            &std::fs::read_to_string(fixture_path(&name, "golden.json")).unwrap(),
+        )
+        .unwrap();
+        let Value::Array(positions) = &golden["positions"] else {
+            continue;
+        };
+        for position in positions {
+            let bus: BusPosition = serde_json::from_value(position.clone()).unwrap();
+            assert_eq!(&serde_json::to_value(&bus).unwrap(), position, "{}", name);
+        }
+    }
+}
+
+#[test]
+fn the_edge_cases_decode_as_expected() {
+    assert_eq!(decode("empty_list").map(|buses| buses.len()), Some(0));
+    assert_eq!(decode("route_300_single").map(|buses| buses.len()), Some(1));
+    assert!(decode("malformed_json").is_none());
+    // Malformed JSON still inflates; only parsing fails.
+    let inflated = decode_bus_data(&encoded("malformed_json")).unwrap();
+    assert!(inflated.starts_with("[{"));
+}
@@ -0,0 +1,105 @@
+#![cfg(feature = "grpc")]
+
+mod common;
+
+use be::feed::BusPosition;
+use be::grpc::proto::bus_feed_server::BusFeed;
+use be::grpc::proto::{SnapshotRequest, SubscribeRequest};
+use be::grpc::{GrpcFeed, SnapshotLoader};
+use be::normalize_route_code;
+use common::bus;
+use futures_util::StreamExt;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::{Code, Request};
+
+fn snapshot(buses: Vec<BusPosition>) -> SnapshotLoader {
+    Arc::new(move || {
+        let buses = buses.clone();
+        Box::pin(async move { Ok(buses) })
+    })
+}
+
+#[tokio::test]
+async fn subscribe_only_streams_the_requested_routes() {
+    let feed = GrpcFeed::new(16, normalize_route_code);
+    let handler = feed.handler(snapshot(Vec::new()));
+    let mut stream = handler
+        .subscribe(Request::new(SubscribeRequest {
+            routes: vec!["t789".to_string()],
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(
+        feed.publish(&[
+            bus("WXX1", "T789"),
+            bus("WXX2", "T790"),
+            bus("WXX3", "T789")
+        ]),
+        1
+    );
+    let first = stream.next().await.unwrap().unwrap();
+    let second = stream.next().await.unwrap().unwrap();
+    assert_eq!(first.bus_no, "WXX1");
+    assert_eq!(second.bus_no, "WXX3");
+    assert_eq!(second.source, "websocket");
+}
+
+#[tokio::test]
+async fn slow_clients_are_disconnected() {
+    let feed = GrpcFeed::new(2, normalize_route_code);
+    let handler = feed.handler(snapshot(Vec::new()));
+    let mut stream = handler
+        .subscribe(Request::new(SubscribeRequest { routes: Vec::new() }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let buses: Vec<BusPosition> = (0..5)
+        .map(|index| bus(&format!("WXX{}", index), "T789"))
+        .collect();
+    feed.publish(&buses);
+
+    let mut received = 0;
+    let status = loop {
+        match tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+        {
+            Ok(_) => received += 1,
+            Err(status) => break status,
+        }
+    };
+    assert_eq!(received, 2);
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    assert!(stream.next().await.is_none());
+    // The forwarder has let go of its broadcast receiver.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(feed.subscribers(), 0);
+}
+
+#[tokio::test]
+async fn snapshot_is_filtered_by_route() {
+    let feed = GrpcFeed::new(16, normalize_route_code);
+    let handler = feed.handler(snapshot(vec![bus("WXX1", "T789"), bus("WXX2", "T790")]));
+
+    let all = handler
+        .get_snapshot(Request::new(SnapshotRequest { routes: Vec::new() }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(all.buses.len(), 2);
+
+    let t790 = handler
+        .get_snapshot(Request::new(SnapshotRequest {
+            routes: vec!["T790".to_string()],
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(t790.buses.len(), 1);
+    assert_eq!(t790.buses[0].bus_no, "WXX2");
+}
@@ -0,0 +1,95 @@
+use be::feed::BusPosition;
+use be::gtfs_rt::{decode_feed, feed_to_vehicles, Vehicle};
+use be::timestamp::{format_feed_timestamp, normalize_timestamp, parse_feed_timestamp};
+
+// A gzipped VehiclePositions feed as some data.gov.my responses arrive: compressed, but
+// without a Content-Encoding header. Two buses and an alert-only entity.
+const FEED: &[u8] = include_bytes!("fixtures/gtfs_rt/vehicle_positions.pb.gz");
+
+#[test]
+fn a_gzipped_feed_decodes_to_its_vehicles() {
+    let feed = decode_feed(FEED).unwrap();
+    assert_eq!(feed.header.gtfs_realtime_version, "2.0");
+    assert_eq!(feed.header.timestamp, Some(1_792_109_700));
+    assert_eq!(feed.entity.len(), 3);
+
+    let vehicles = feed_to_vehicles(&feed);
+    assert_eq!(
+        vehicles,
+        [
+            Vehicle {
+                id: "WXX1234".to_string(),
+                route_id: Some("T789".to_string()),
+                trip_id: Some("T789_W_0800".to_string()),
+                latitude: 3.1478_f32 as f64,
+                longitude: 101.6953_f32 as f64,
+                bearing: Some(90.0),
+                // 10 m/s.
+                speed: Some(36.0),
+                timestamp: Some(1_792_109_640),
+                occupancy: Some("MANY_SEATS_AVAILABLE".to_string()),
+                current_stop_sequence: Some(12),
+            },
+            Vehicle {
+                // No vehicle id, so the label stands in.
+                id: "WYY5678".to_string(),
+                route_id: Some("T789".to_string()),
+                trip_id: None,
+                latitude: 3.15_f32 as f64,
+                longitude: 101.7_f32 as f64,
+                bearing: Some(180.0),
+                speed: Some(0.0),
+                timestamp: Some(1_792_109_650),
+                occupancy: Some("FEW_SEATS_AVAILABLE".to_string()),
+                current_stop_sequence: Some(3),
+            },
+        ]
+    );
+
+    let bus = BusPosition::from(&vehicles[0]);
+    assert_eq!(
+        (bus.bus_no.as_str(), bus.route.as_str()),
+        ("WXX1234", "T789")
+    );
+    assert_eq!(bus.dt_gps.as_deref(), Some("2026-10-16 08:14:00"));
+    assert_eq!(
+        bus.timestamp_rfc3339.as_deref(),
+        Some("2026-10-16T00:14:00+00:00")
+    );
+}
+
+#[test]
+fn socket_and_feed_fixes_share_one_dt_gps_format() {
+    let feed = decode_feed(FEED).unwrap();
+    let mut from_feed = BusPosition::from(&feed_to_vehicles(&feed)[0]);
+    // The same fix as the socket reports it: Kuala Lumpur time, no offset.
+    let mut from_socket: BusPosition = serde_json::from_value(serde_json::json!({
+        "latitude": 3.1478,
+        "longitude": 101.6953,
+        "speed": 36.0,
+        "angle": 90.0,
+        "route": "T789",
+        "bus_no": "WXX1234",
+        "engine_status": 1,
+        "accessibility": 1,
+        "provider": "RKL",
+        "dt_gps": "2026-10-16 08:14:00",
+    }))
+    .unwrap();
+
+    normalize_timestamp(&mut from_feed, 0);
+    normalize_timestamp(&mut from_socket, 0);
+    assert_eq!(from_feed.dt_gps, from_socket.dt_gps);
+    assert_eq!(from_feed.timestamp_rfc3339, from_socket.timestamp_rfc3339);
+    assert!(!from_feed.timestamp_parse_error);
+
+    let fix = parse_feed_timestamp("2026-10-16 08:14:00").unwrap();
+    assert_eq!(fix.timestamp(), 1_792_109_640);
+    assert_eq!(format_feed_timestamp(fix), "2026-10-16 08:14:00");
+}
+
+#[test]
+fn a_truncated_gzipped_feed_is_an_error() {
+    let error = decode_feed(&FEED[..FEED.len() / 2]).unwrap_err();
+    assert!(error.contains("gunzip"), "{}", error);
+}
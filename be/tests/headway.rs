@@ -0,0 +1,61 @@
+mod common;
+
+use be::direction::Direction;
+use be::feed::BusPosition;
+use be::headway::HeadwayBoard;
+use be::normalize_route_code;
+
+fn bus(bus_no: &str, route: &str, progress_m: f64) -> BusPosition {
+    let mut bus = common::bus(bus_no, route);
+    bus.speed = 36.0;
+    bus.progress_m = Some(progress_m);
+    bus.direction = Direction::Outbound;
+    bus
+}
+
+fn routes(board: &HeadwayBoard) -> Vec<String> {
+    let mut routes: Vec<String> = board
+        .summaries()
+        .map(|summary| summary.route.clone())
+        .collect();
+    routes.sort();
+    routes
+}
+
+#[test]
+fn a_batch_only_replaces_the_routes_it_carries() {
+    let mut board = HeadwayBoard::default();
+    board.update(
+        &[bus("WXX1", "T789", 1_000.0), bus("WXX2", "T789", 0.0)],
+        normalize_route_code,
+    );
+    let summaries = board.update(
+        &[bus("WYY1", "300", 2_000.0), bus("WYY2", "300", 500.0)],
+        normalize_route_code,
+    );
+    assert_eq!(summaries.len(), 1);
+    assert_eq!(routes(&board), ["300", "T789"]);
+
+    let t789 = board
+        .summaries()
+        .find(|summary| summary.route == "T789")
+        .unwrap();
+    assert_eq!(t789.headways[0].leading_bus, "WXX1");
+    // 1000m at 36 km/h.
+    assert_eq!(t789.headways[0].seconds, 100);
+}
+
+#[test]
+fn a_route_down_to_one_bus_loses_its_headways() {
+    let mut board = HeadwayBoard::default();
+    board.update(
+        &[bus("WXX1", "T789", 1_000.0), bus("WXX2", "T789", 0.0)],
+        normalize_route_code,
+    );
+    board.update(
+        &[bus("WYY1", "300", 2_000.0), bus("WYY2", "300", 500.0)],
+        normalize_route_code,
+    );
+    board.update(&[bus("WXX1", "T789", 1_200.0)], normalize_route_code);
+    assert_eq!(routes(&board), ["300"]);
+}
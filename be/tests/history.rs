@@ -0,0 +1,91 @@
+#![cfg(feature = "parquet")]
+
+use be::archive::{write_parquet, ArchiveRecord};
+use be::export::{query_track, query_track_page, TrackQuery};
+
+// 2024-05-01T08:00:00Z
+const START_MS: i64 = 1_714_550_400_000;
+const MS_PER_HOUR: i64 = 3_600_000;
+
+fn record(vehicle_id: &str, recv_ts: i64) -> ArchiveRecord {
+    ArchiveRecord {
+        provider: "RKL".to_string(),
+        route: "T789".to_string(),
+        vehicle_id: vehicle_id.to_string(),
+        lat: 3.1478,
+        lon: 101.6953,
+        speed: 32.0,
+        bearing: 90.0,
+        // Fixes lag receipt a little, as they do on the socket.
+        gps_ts: Some(recv_ts - 2_000),
+        recv_ts,
+        off_route: false,
+    }
+}
+
+#[test]
+fn paging_with_the_cursor_walks_the_whole_track_once() {
+    let root = std::env::temp_dir().join(format!("rapidbro-history-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    // Four hourly files, one point a minute per bus.
+    for hour in 0..4 {
+        let records: Vec<ArchiveRecord> = (0..60)
+            .flat_map(|minute| {
+                let recv_ts = START_MS + hour * MS_PER_HOUR + minute * 60_000;
+                [record("WXX1234", recv_ts), record("WYY5678", recv_ts)]
+            })
+            .collect();
+        write_parquet(&root, &records).unwrap();
+    }
+
+    let query = |from_ms: i64| TrackQuery {
+        vehicle: "WXX1234".to_string(),
+        from_ms,
+        to_ms: START_MS + 4 * MS_PER_HOUR,
+        include_invalid: false,
+    };
+    let full = query_track(&root, &query(START_MS - MS_PER_HOUR)).unwrap();
+    assert_eq!(full.len(), 240);
+
+    // Pages that don't line up with the files, resumed the way the history handler does.
+    let mut paged = Vec::new();
+    let mut cursor = None;
+    let mut pages = 0;
+    loop {
+        let from_ms = cursor.map_or(START_MS - MS_PER_HOUR, |cursor: i64| cursor + 1);
+        let (points, more) = query_track_page(&root, &query(from_ms), 70).unwrap();
+        pages += 1;
+        cursor = points.last().map(|point| point.time_ms);
+        paged.extend(points);
+        if !more {
+            break;
+        }
+    }
+    assert_eq!(pages, 4);
+    assert_eq!(paged, full);
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn a_page_that_ends_the_track_has_nothing_more() {
+    let root = std::env::temp_dir().join(format!("rapidbro-history-last-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let records: Vec<ArchiveRecord> = (0..5)
+        .map(|minute| record("WXX1234", START_MS + minute * 60_000))
+        .collect();
+    write_parquet(&root, &records).unwrap();
+
+    let query = TrackQuery {
+        vehicle: "wxx1234".to_string(),
+        from_ms: START_MS - MS_PER_HOUR,
+        to_ms: START_MS + MS_PER_HOUR,
+        include_invalid: false,
+    };
+    let (points, more) = query_track_page(&root, &query, 5).unwrap();
+    assert_eq!((points.len(), more), (5, false));
+    let (points, more) = query_track_page(&root, &query, 4).unwrap();
+    assert_eq!((points.len(), more), (4, true));
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
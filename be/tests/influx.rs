@@ -0,0 +1,41 @@
+mod common;
+
+use be::influx::line_protocol;
+use common::{bus, ROUTE};
+
+// 2024-05-01T08:30:00Z
+const FIX_NS: i64 = 1_714_552_200_000_000_000;
+
+#[test]
+fn a_point_is_tagged_by_vehicle_and_stamped_with_its_fix() {
+    let mut position = bus("WXX1234", ROUTE);
+    // Kuala Lumpur time, as the feed writes it.
+    position.dt_gps = Some("2024-05-01 16:30:00".to_string());
+    assert_eq!(
+        line_protocol(&position, 0).unwrap(),
+        format!(
+            "bus_position,provider=RKL,route=T789,vehicle=WXX1234 \
+             lat=3.1478,lon=101.6953,speed=32,bearing=90 {}",
+            FIX_NS
+        )
+    );
+}
+
+#[test]
+fn tag_values_are_escaped_and_empty_ones_left_off() {
+    let mut position = bus(" WXX 1234 ", "T789,x=1");
+    position.provider = String::new();
+    let line = line_protocol(&position, 1_714_552_200_000).unwrap();
+    assert!(
+        line.starts_with(r"bus_position,route=T789\,x\=1,vehicle=WXX\ 1234 lat="),
+        "{}",
+        line
+    );
+    // No fix time, so the receive time stands in.
+    assert!(line.ends_with(&format!(" {}", FIX_NS)), "{}", line);
+}
+
+#[test]
+fn vehicles_without_an_id_are_skipped() {
+    assert_eq!(line_protocol(&bus(" ", ROUTE), 0), None);
+}
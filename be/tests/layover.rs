@@ -0,0 +1,108 @@
+mod common;
+
+use be::feed::BusPosition;
+use be::layover::{
+    LayoverDetector, Terminal, TripEvent, DEFAULT_DWELL_MS, DEFAULT_TERMINAL_RADIUS_METERS,
+};
+use common::ROUTE;
+use std::collections::HashMap;
+
+// Hab Titiwangsa, T789's terminus.
+const TERMINUS: (f64, f64) = (3.1735, 101.6953);
+// About 330m north, past the departure margin.
+const AWAY: (f64, f64) = (3.1765, 101.6953);
+
+fn bus(position: (f64, f64), speed: f64) -> BusPosition {
+    let mut bus = common::bus_at("WXX1234", ROUTE, position.0, position.1);
+    bus.speed = speed;
+    bus
+}
+
+fn detector() -> LayoverDetector {
+    let termini = HashMap::from([(
+        "T789".to_string(),
+        vec![Terminal {
+            stop_id: "1000123".to_string(),
+            lat: TERMINUS.0,
+            lon: TERMINUS.1,
+        }],
+    )]);
+    LayoverDetector::new(
+        termini,
+        |route| route.to_uppercase(),
+        DEFAULT_TERMINAL_RADIUS_METERS,
+        DEFAULT_DWELL_MS,
+    )
+}
+
+#[test]
+fn a_dwell_at_the_terminus_ends_the_trip_and_pulling_away_starts_the_next() {
+    let mut detector = detector();
+    assert!(detector.observe(&bus(TERMINUS, 0.0), 0).is_none());
+    assert!(detector.observe(&bus(TERMINUS, 1.0), 60_000).is_none());
+
+    let ended = detector.observe(&bus(TERMINUS, 0.0), DEFAULT_DWELL_MS);
+    assert!(matches!(
+        ended,
+        Some(TripEvent::TripEnded { ref stop_id, layover_started_unix_ms: 0, .. })
+            if stop_id == "1000123"
+    ));
+    // Still at the stand: the layover carries on without another event.
+    assert!(detector.observe(&bus(TERMINUS, 0.0), 300_000).is_none());
+
+    let started = detector.observe(&bus(AWAY, 25.0), 600_000).unwrap();
+    assert!(matches!(
+        started,
+        TripEvent::TripStarted {
+            layover_started_unix_ms: 0,
+            departed_unix_ms: 600_000,
+            ..
+        }
+    ));
+    assert_eq!(
+        started.to_string(),
+        "WXX1234 route=T789 trip started from 1000123 after 10m layover"
+    );
+}
+
+#[test]
+fn passing_through_the_terminus_is_not_a_layover() {
+    let mut detector = detector();
+    // Moving too fast to count as dwelling.
+    assert!(detector.observe(&bus(TERMINUS, 30.0), 0).is_none());
+    assert!(detector
+        .observe(&bus(TERMINUS, 30.0), DEFAULT_DWELL_MS)
+        .is_none());
+
+    // Stopping briefly and leaving before the dwell time is up.
+    assert!(detector.observe(&bus(TERMINUS, 0.0), 200_000).is_none());
+    assert!(detector.observe(&bus(AWAY, 25.0), 260_000).is_none());
+    assert!(detector
+        .observe(&bus(AWAY, 25.0), 200_000 + DEFAULT_DWELL_MS)
+        .is_none());
+}
+
+#[test]
+fn a_bus_that_goes_dark_at_the_terminus_is_reset() {
+    let mut detector = detector();
+    detector.observe(&bus(TERMINUS, 0.0), 0);
+    assert!(detector
+        .observe(&bus(TERMINUS, 0.0), DEFAULT_DWELL_MS)
+        .is_some());
+
+    // Silent for over ten minutes, then seen on the road: when it left is unknown, so no
+    // TripStarted with a made-up departure time.
+    let back_ms = DEFAULT_DWELL_MS + 700_000;
+    assert!(detector.observe(&bus(AWAY, 25.0), back_ms).is_none());
+
+    // Going dark mid-dwell restarts the dwell rather than ending the trip on return.
+    detector.observe(&bus(TERMINUS, 0.0), 2_000_000);
+    assert!(detector.observe(&bus(TERMINUS, 0.0), 2_700_000).is_none());
+    assert!(matches!(
+        detector.observe(&bus(TERMINUS, 0.0), 2_700_000 + DEFAULT_DWELL_MS),
+        Some(TripEvent::TripEnded {
+            layover_started_unix_ms: 2_700_000,
+            ..
+        })
+    ));
+}
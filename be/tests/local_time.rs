@@ -0,0 +1,48 @@
+mod common;
+
+use be::feed::BusPosition;
+use be::timestamp::{localize_timestamp, normalize_timestamp, DEFAULT_TIMEZONE};
+use common::ROUTE;
+
+fn bus(dt_gps: &str) -> BusPosition {
+    let mut bus = common::bus("WXX1234", ROUTE);
+    bus.dt_gps = Some(dt_gps.to_string());
+    bus
+}
+
+#[test]
+fn local_time_is_the_utc_timestamp_in_kuala_lumpur() {
+    let mut bus = bus("2024-05-01T08:30:00Z");
+    normalize_timestamp(&mut bus, 0);
+    localize_timestamp(&mut bus, DEFAULT_TIMEZONE);
+
+    assert_eq!(
+        bus.timestamp_rfc3339.as_deref(),
+        Some("2024-05-01T08:30:00+00:00")
+    );
+    assert_eq!(bus.local_time.as_deref(), Some("2024-05-01T16:30:00+08:00"));
+}
+
+#[test]
+fn local_time_follows_the_configured_zone() {
+    let mut bus = bus("2024-01-15T23:00:00Z");
+    normalize_timestamp(&mut bus, 0);
+    localize_timestamp(&mut bus, chrono_tz::Europe::London);
+    assert_eq!(bus.local_time.as_deref(), Some("2024-01-15T23:00:00+00:00"));
+
+    localize_timestamp(&mut bus, chrono_tz::Asia::Tokyo);
+    assert_eq!(bus.local_time.as_deref(), Some("2024-01-16T08:00:00+09:00"));
+}
+
+#[test]
+fn no_local_time_without_a_timestamp() {
+    let mut bus = bus("not a time");
+    normalize_timestamp(&mut bus, 0);
+    localize_timestamp(&mut bus, DEFAULT_TIMEZONE);
+    assert!(bus.local_time.is_none());
+    assert!(!serde_json::to_value(&bus)
+        .unwrap()
+        .as_object()
+        .unwrap()
+        .contains_key("local_time"));
+}
@@ -0,0 +1,121 @@
+#![cfg(feature = "nats")]
+
+mod common;
+
+use be::feed::BusPosition;
+use be::nats::{NatsMode, NatsSink, DEFAULT_STREAM};
+use common::bus;
+use futures_util::StreamExt;
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+// Kills the spawned nats-server when the test ends, pass or fail.
+struct NatsServer {
+    child: Child,
+    url: String,
+    _store_dir: TempDir,
+}
+
+impl Drop for NatsServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+struct TempDir(std::path::PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// None when nats-server isn't installed, so the tests skip instead of failing.
+async fn spawn_nats_server() -> Option<NatsServer> {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .ok()?
+        .local_addr()
+        .ok()?
+        .port();
+    let store_dir = TempDir(std::env::temp_dir().join(format!("rapidbro-nats-{}", port)));
+    let child = Command::new("nats-server")
+        .args(["-js", "-a", "127.0.0.1", "-p", &port.to_string(), "-sd"])
+        .arg(&store_dir.0)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    let Ok(child) = child else {
+        eprintln!("nats-server not found; skipping");
+        return None;
+    };
+    let server = NatsServer {
+        child,
+        url: format!("nats://127.0.0.1:{}", port),
+        _store_dir: store_dir,
+    };
+
+    for _ in 0..50 {
+        if async_nats::connect(&server.url).await.is_ok() {
+            return Some(server);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("nats-server did not start on {}", server.url);
+}
+
+#[tokio::test]
+async fn core_nats_round_trips_updates_by_subject() {
+    let Some(server) = spawn_nats_server().await else {
+        return;
+    };
+    let subscriber = async_nats::connect(&server.url).await.unwrap();
+    let mut subscription = subscriber.subscribe("rapidbro.>").await.unwrap();
+    subscriber.flush().await.unwrap();
+
+    let sink = NatsSink::connect(&server.url, NatsMode::Core)
+        .await
+        .unwrap();
+    let buses = [
+        bus("WXX1234", "300"),
+        bus("WYY5678", "302"),
+        bus("WZZ9012", "T789"),
+    ];
+    for bus in &buses {
+        sink.publish(bus).await.unwrap();
+    }
+
+    for expected in &buses {
+        let message = tokio::time::timeout(Duration::from_secs(5), subscription.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            message.subject.as_str(),
+            format!("rapidbro.RKL.{}.{}", expected.route, expected.bus_no)
+        );
+        let received: BusPosition = serde_json::from_slice(&message.payload).unwrap();
+        assert_eq!(received.bus_no, expected.bus_no);
+    }
+}
+
+#[tokio::test]
+async fn jetstream_persists_every_update() {
+    let Some(server) = spawn_nats_server().await else {
+        return;
+    };
+    let sink = NatsSink::connect(&server.url, NatsMode::JetStream)
+        .await
+        .unwrap();
+    for bus_no in ["WXX1234", "WYY5678", "WZZ9012"] {
+        sink.publish(&bus(bus_no, "300")).await.unwrap();
+    }
+
+    let client = async_nats::connect(&server.url).await.unwrap();
+    let mut stream = async_nats::jetstream::new(client)
+        .get_stream(DEFAULT_STREAM)
+        .await
+        .unwrap();
+    assert_eq!(stream.info().await.unwrap().state.messages, 3);
+}
@@ -0,0 +1,61 @@
+#![cfg(feature = "server")]
+
+use std::process::Command;
+
+fn spec() -> serde_json::Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_be"))
+        .arg("openapi")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn spec_documents_the_main_endpoints() {
+    let spec = spec();
+    let paths = spec["paths"].as_object().unwrap();
+    for path in [
+        "/get-all",
+        "/buses",
+        "/buses/{route_id}",
+        "/buses/nearest",
+        "/routes/{route_id}/vehicles",
+        "/routes/{route_id}/stream",
+        "/routes/{route_id}/ws",
+        "/history/vehicles/{vehicle_id}",
+        "/vehicles/{vehicle_id}/gaps",
+        "/route/{route_id}/eta/{stop_id}",
+        "/stops/{stop_id}/eta",
+        "/stops/nearest",
+        "/health",
+        "/ready",
+        "/healthz",
+        "/readyz",
+        "/metrics",
+    ] {
+        assert!(paths.contains_key(path), "missing {}", path);
+        assert!(paths[path]["get"].is_object(), "no GET for {}", path);
+    }
+}
+
+#[test]
+fn schemas_follow_the_runtime_structs() {
+    let spec = spec();
+    let schemas = &spec["components"]["schemas"];
+    let bus = &schemas["BusPosition"]["properties"];
+    for field in [
+        "bus_no",
+        "route",
+        "latitude",
+        "longitude",
+        "dt_gps",
+        "source",
+    ] {
+        assert!(bus[field].is_object(), "BusPosition.{} missing", field);
+    }
+    let eta = &schemas["BusEta"]["properties"];
+    for field in ["route_id", "bus_no", "stops_away", "eta_minutes"] {
+        assert!(eta[field].is_object(), "BusEta.{} missing", field);
+    }
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use be::feed::BusPosition;
+use be::ordering::{OrderingGuard, DEFAULT_GRACE_MS};
+use common::ROUTE;
+
+fn bus(bus_no: &str, timestamp_rfc3339: Option<&str>) -> BusPosition {
+    let mut bus = common::bus(bus_no, ROUTE);
+    bus.timestamp_rfc3339 = timestamp_rfc3339.map(str::to_string);
+    bus
+}
+
+#[test]
+fn fixes_older_than_the_newest_by_more_than_the_grace_are_rejected() {
+    let mut guard = OrderingGuard::new(DEFAULT_GRACE_MS);
+    assert!(guard.accept(&bus("WXX1234", Some("2024-05-01T08:30:00Z"))));
+    assert!(guard.accept(&bus("WXX1234", Some("2024-05-01T08:30:20Z"))));
+
+    // A replay from before the newest fix, past the 5s grace.
+    assert!(!guard.accept(&bus("WXX1234", Some("2024-05-01T08:30:00Z"))));
+    // Within the grace: clock skew, not a replay. It doesn't move the newest fix back.
+    assert!(guard.accept(&bus("WXX1234", Some("2024-05-01T08:30:16Z"))));
+    assert!(!guard.accept(&bus("WXX1234", Some("2024-05-01T08:30:14Z"))));
+
+    // Other vehicles and fixes without a time aren't held to it.
+    assert!(guard.accept(&bus("WYY5678", Some("2024-05-01T08:00:00Z"))));
+    assert!(guard.accept(&bus("WXX1234", None)));
+}
+
+#[test]
+fn out_of_order_positions_are_dropped_from_a_batch() {
+    let mut guard = OrderingGuard::new(0);
+    guard.accept(&bus("WXX1234", Some("2024-05-01T08:30:00Z")));
+
+    let mut buses = vec![
+        bus("WXX1234", Some("2024-05-01T08:29:59Z")),
+        bus("WYY5678", Some("2024-05-01T08:29:59Z")),
+        bus("WXX1234", Some("2024-05-01T08:30:00Z")),
+    ];
+    assert_eq!(guard.retain_in_order(&mut buses), 1);
+    assert_eq!(
+        buses
+            .iter()
+            .map(|bus| bus.bus_no.as_str())
+            .collect::<Vec<_>>(),
+        ["WYY5678", "WXX1234"]
+    );
+}
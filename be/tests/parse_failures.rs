@@ -0,0 +1,102 @@
+mod common;
+
+use base64::Engine;
+use be::feed::{DecodeContext, DecodeError, ParseFailure};
+use be::parse_failures::ParseFailureLog;
+use common::encode;
+use rust_socketio::Payload;
+
+fn single_failure(values: Vec<serde_json::Value>) -> ParseFailure {
+    let mut context = DecodeContext::default();
+    let (_, failures) = context.parse(Payload::Text(values), false);
+    assert_eq!(failures, 1);
+    let mut recorded = context.take_failures();
+    assert_eq!(recorded.len(), 1);
+    recorded.remove(0)
+}
+
+#[test]
+fn syntax_errors_carry_line_and_column() {
+    let failure = single_failure(vec![encode("[\n  {\"bus_no\": \"WXX1234\",}\n]").into()]);
+    match failure.error {
+        DecodeError::Json {
+            category,
+            line,
+            column,
+            ..
+        } => {
+            assert_eq!(category, "syntax");
+            assert_eq!(line, 2);
+            assert_eq!(column, 24);
+        }
+        other => panic!("expected a JSON error, got {:?}", other),
+    }
+    assert_eq!(failure.payload, "[\n  {\"bus_no\": \"WXX1234\",}\n]");
+}
+
+#[test]
+fn wrong_shapes_are_data_errors() {
+    let failure = single_failure(vec![encode("{\"bus_no\": 1234}").into()]);
+    assert!(matches!(
+        failure.error,
+        DecodeError::Json { ref category, .. } if category == "data"
+    ));
+}
+
+#[test]
+fn base64_and_gzip_failures_name_their_stage() {
+    let failure = single_failure(vec![serde_json::Value::String("not base64!".into())]);
+    assert!(matches!(failure.error, DecodeError::Base64 { .. }));
+    assert_eq!(failure.payload, "not base64!");
+
+    let not_gzip = base64::engine::general_purpose::STANDARD.encode(b"plain text");
+    let failure = single_failure(vec![serde_json::Value::String(not_gzip)]);
+    assert!(matches!(failure.error, DecodeError::Gzip { .. }));
+}
+
+#[test]
+fn take_failures_drains_only_new_failures() {
+    let mut context = DecodeContext::default();
+    let (buses, failures) = context.parse(
+        Payload::Text(vec![
+            encode("{").into(),
+            encode("[]").into(),
+            encode("nope").into(),
+        ]),
+        false,
+    );
+    assert!(buses.is_empty());
+    assert_eq!(failures, 2);
+    assert_eq!(context.take_failures().len(), 2);
+    assert!(context.take_failures().is_empty());
+}
+
+#[test]
+fn large_payloads_are_truncated_but_sized() {
+    let payload = format!("[{}", "x".repeat(10_000));
+    let failure = single_failure(vec![encode(&payload).into()]);
+    assert_eq!(failure.payload_bytes, payload.len());
+    assert!(failure.payload.len() < payload.len());
+}
+
+#[test]
+fn failure_log_keeps_the_newest_entries() {
+    let log = ParseFailureLog::new(2);
+    for (index, json) in ["{", "[", "nope"].into_iter().enumerate() {
+        log.record(
+            "300",
+            "onFts-reload",
+            index as i64,
+            single_failure(vec![encode(json).into()]),
+        );
+    }
+    let recent = log.recent();
+    assert_eq!(recent.len(), 2);
+    assert_eq!(recent[0].received_at_unix_ms, 2);
+    assert_eq!(recent[1].received_at_unix_ms, 1);
+    assert_eq!(recent[0].route, "300");
+
+    let json = serde_json::to_value(&recent[0]).unwrap();
+    assert_eq!(json["error"]["stage"], "json");
+    assert_eq!(json["payload"], "nope");
+}
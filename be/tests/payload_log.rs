@@ -0,0 +1,82 @@
+use be::feed::RawPayload;
+use be::payload_log::{payload_line, PayloadLog, RotationPeriod};
+use std::path::PathBuf;
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("rapidbro-{}-{}", name, std::process::id())))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn payload(json: &str) -> RawPayload {
+    RawPayload {
+        base64_len: 120,
+        gzip_len: 90,
+        json: json.to_string(),
+    }
+}
+
+#[test]
+fn lines_embed_parsable_json_and_quote_the_rest() {
+    let line = payload_line(
+        "onFts-client",
+        1_700_000_000_000,
+        &payload(r#"[{"bus_no":"WXX1234"}]"#),
+    );
+    assert!(line.ends_with('\n'));
+    let parsed: serde_json::Value = serde_json::from_str(line.trim_end()).unwrap();
+    assert_eq!(parsed["event"], "onFts-client");
+    assert_eq!(parsed["received_at"], "2023-11-14T22:13:20+00:00");
+    assert_eq!(parsed["gzip_bytes"], 90);
+    assert_eq!(parsed["payload"][0]["bus_no"], "WXX1234");
+
+    let line = payload_line("onFts-client", 0, &payload("[{\"bus_no\":"));
+    let parsed: serde_json::Value = serde_json::from_str(line.trim_end()).unwrap();
+    assert_eq!(parsed["payload"], "[{\"bus_no\":");
+}
+
+#[test]
+fn payloads_reach_the_file_once_the_guard_is_dropped() {
+    let dir = TempDir::new("payload-log");
+    let (log, guard) = PayloadLog::open(&dir.0, RotationPeriod::Hourly, 4).unwrap();
+    for bus_no in ["WXX1234", "WYY5678"] {
+        log.write(
+            "onFts-client",
+            1_700_000_000_000,
+            &payload(&format!(r#"[{{"bus_no":"{}"}}]"#, bus_no)),
+        );
+    }
+    drop(guard);
+
+    let files: Vec<PathBuf> = std::fs::read_dir(&dir.0)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1, "{:?}", files);
+    let name = files[0].file_name().unwrap().to_string_lossy().into_owned();
+    assert!(
+        name.starts_with("payloads.") && name.ends_with(".jsonl"),
+        "{}",
+        name
+    );
+    let contents = std::fs::read_to_string(&files[0]).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains("WYY5678"));
+    assert_eq!(log.dropped_lines(), 0);
+}
+
+#[test]
+fn rotation_periods_parse_case_insensitively() {
+    assert_eq!("Hourly".parse(), Ok(RotationPeriod::Hourly));
+    assert_eq!("daily".parse(), Ok(RotationPeriod::Daily));
+    assert!("weekly".parse::<RotationPeriod>().is_err());
+}
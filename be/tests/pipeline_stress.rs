@@ -0,0 +1,138 @@
+use base64::Engine;
+use be::client::ClientEvent;
+use be::feed::BusPosition;
+use be::pipeline::{run_decode_pipeline, DecodeOptions, RawFrame};
+use be::queue::{BoundedQueue, OverflowPolicy};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rust_socketio::Payload;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+const PRODUCERS: u64 = 4;
+const FRAMES_PER_PRODUCER: u64 = 2_500;
+
+fn payload(index: u64) -> Payload {
+    let json = serde_json::json!([{
+        "latitude": 3.1478,
+        "longitude": 101.6953,
+        "speed": 32.0,
+        "angle": 90.0,
+        "route": "300",
+        "bus_no": format!("WXX{}", index),
+        "engine_status": 1,
+        "accessibility": 1,
+        "provider": "RKL",
+    }]);
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(json.to_string().as_bytes()).unwrap();
+    let encoded = base64::engine::general_purpose::STANDARD.encode(encoder.finish().unwrap());
+    Payload::Text(vec![serde_json::Value::String(encoded)])
+}
+
+// Socket-callback-sized producers against a small frame queue, a decode pipeline and a sink
+// that is deliberately slower than the feed. Every frame must be either decoded or counted
+// as dropped, and nothing may wedge.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn pipeline_sheds_load_without_deadlocking() {
+    let frames = BoundedQueue::new(64, OverflowPolicy::DropOldest, Duration::ZERO);
+    let sink = BoundedQueue::<BusPosition>::new(128, OverflowPolicy::DropNewest, Duration::ZERO);
+    let (events, mut receiver) = broadcast::channel(1_024);
+    let pipeline = tokio::spawn(run_decode_pipeline(
+        frames.clone(),
+        events.clone(),
+        DecodeOptions::default(),
+    ));
+
+    let decoded = Arc::new(AtomicU64::new(0));
+    let lagged = Arc::new(AtomicU64::new(0));
+    let enqueued = Arc::new(AtomicU64::new(0));
+    let consumer = {
+        let sink = sink.clone();
+        let decoded = decoded.clone();
+        let lagged = lagged.clone();
+        let enqueued = enqueued.clone();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(ClientEvent::Buses { buses, .. }) => {
+                        decoded.fetch_add(1, Ordering::SeqCst);
+                        for bus in buses {
+                            sink.push(bus).await;
+                            enqueued.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                    Ok(_) => {}
+                    // A lagging subscriber still accounts for the frames it missed.
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        lagged.fetch_add(missed, Ordering::SeqCst);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    };
+    let sink_writer = {
+        let sink = sink.clone();
+        tokio::spawn(async move {
+            loop {
+                sink.pop().await;
+                tokio::time::sleep(Duration::from_micros(200)).await;
+            }
+        })
+    };
+
+    let producers: Vec<_> = (0..PRODUCERS)
+        .map(|producer| {
+            let frames = frames.clone();
+            tokio::spawn(async move {
+                for index in 0..FRAMES_PER_PRODUCER {
+                    frames
+                        .push(RawFrame {
+                            event: "onFleetDataUpdate".to_string(),
+                            payload: payload(producer * FRAMES_PER_PRODUCER + index),
+                            received_at_unix_ms: 1_714_551_300_000,
+                        })
+                        .await;
+                }
+            })
+        })
+        .collect();
+
+    let pushed = PRODUCERS * FRAMES_PER_PRODUCER;
+    tokio::time::timeout(Duration::from_secs(30), async {
+        for producer in producers {
+            producer.await.unwrap();
+        }
+        while decoded.load(Ordering::SeqCst)
+            + lagged.load(Ordering::SeqCst)
+            + frames.dropped_count()
+            < pushed
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("pipeline stalled");
+
+    pipeline.abort();
+    drop(events);
+    tokio::time::timeout(Duration::from_secs(5), consumer)
+        .await
+        .expect("consumer stalled")
+        .unwrap();
+    sink_writer.abort();
+
+    let decoded = decoded.load(Ordering::SeqCst);
+    assert_eq!(
+        decoded + lagged.load(Ordering::SeqCst) + frames.dropped_count(),
+        pushed
+    );
+    // One bus per frame; each either waits in the sink queue, was written, or was shed.
+    assert_eq!(enqueued.load(Ordering::SeqCst), decoded);
+    assert!(sink.len() <= 128);
+    assert!(decoded > 0);
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use be::feed::BusPosition;
+use be::timestamp::retain_recent;
+use common::ROUTE;
+
+// 2024-05-01T08:30:00Z
+const NOW_MS: i64 = 1_714_552_200_000;
+
+fn bus(bus_no: &str, timestamp_rfc3339: Option<&str>) -> BusPosition {
+    let mut bus = common::bus(bus_no, ROUTE);
+    bus.timestamp_rfc3339 = timestamp_rfc3339.map(str::to_string);
+    bus
+}
+
+#[test]
+fn positions_older_than_the_maximum_are_dropped() {
+    let mut buses = vec![
+        bus("WXX1234", Some("2024-05-01T08:29:30Z")),
+        bus("WYY5678", Some("2024-05-01T08:25:00Z")),
+        bus("WZZ9012", Some("2024-05-01T08:28:00Z")),
+    ];
+    assert_eq!(retain_recent(&mut buses, 120, NOW_MS), 1);
+
+    let kept: Vec<(&str, Option<i64>)> = buses
+        .iter()
+        .map(|bus| (bus.bus_no.as_str(), bus.age_seconds))
+        .collect();
+    // Exactly the maximum age is still served.
+    assert_eq!(kept, [("WXX1234", Some(30)), ("WZZ9012", Some(120))]);
+}
+
+#[test]
+fn positions_of_unknown_age_are_kept() {
+    let mut buses = vec![bus("WXX1234", None), bus("WYY5678", Some("yesterday"))];
+    assert_eq!(retain_recent(&mut buses, 60, NOW_MS), 0);
+    assert_eq!(buses.len(), 2);
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use be::presence::{PresenceEvent, PresenceTracker};
+use common::{bus, ROUTE};
+
+const CYCLE_MS: i64 = 20_000;
+const GRACE_MS: i64 = 60_000;
+
+fn lost_ids(events: &[PresenceEvent]) -> Vec<&str> {
+    events
+        .iter()
+        .filter(|event| matches!(event, PresenceEvent::VehicleLost { .. }))
+        .map(PresenceEvent::vehicle_id)
+        .collect()
+}
+
+#[test]
+fn a_vehicle_missing_for_n_cycles_is_lost_once() {
+    let mut tracker = PresenceTracker::new(3, GRACE_MS);
+    tracker.observe(&bus("WXX1234", ROUTE), "T789", 0);
+    tracker.observe(&bus("WYY5678", ROUTE), "T789", 0);
+    assert!(tracker.end_cycle(CYCLE_MS).is_empty());
+
+    for cycle in 2..=3 {
+        tracker.observe(&bus("WYY5678", ROUTE), "T789", cycle * CYCLE_MS);
+        assert!(tracker.end_cycle(cycle * CYCLE_MS).is_empty());
+    }
+    tracker.observe(&bus("WYY5678", ROUTE), "T789", 4 * CYCLE_MS);
+    let events = tracker.end_cycle(4 * CYCLE_MS);
+    assert_eq!(lost_ids(&events), ["WXX1234"]);
+    match &events[0] {
+        PresenceEvent::VehicleLost {
+            last_seen_unix_ms,
+            route,
+            ..
+        } => {
+            assert_eq!(*last_seen_unix_ms, 0);
+            assert_eq!(route, "T789");
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(tracker.is_lost("WXX1234"));
+    assert!(!tracker.is_lost("WYY5678"));
+
+    // Reported once, not again every cycle it stays missing.
+    assert!(tracker.end_cycle(5 * CYCLE_MS).is_empty());
+}
+
+#[test]
+fn a_lost_vehicle_that_reappears_is_returned() {
+    let mut tracker = PresenceTracker::new(1, GRACE_MS);
+    tracker.observe(&bus("WXX1234", ROUTE), "T789", 0);
+    tracker.end_cycle(CYCLE_MS);
+    assert_eq!(lost_ids(&tracker.end_cycle(2 * CYCLE_MS)), ["WXX1234"]);
+
+    match tracker.observe(&bus("WXX1234", ROUTE), "T789", 5 * CYCLE_MS) {
+        Some(PresenceEvent::VehicleReturned {
+            vehicle_id,
+            lost_for_ms,
+            ..
+        }) => {
+            assert_eq!(vehicle_id, "WXX1234");
+            assert_eq!(lost_for_ms, 5 * CYCLE_MS);
+        }
+        other => panic!("expected VehicleReturned, got {:?}", other),
+    }
+    assert!(!tracker.is_lost("WXX1234"));
+    assert!(tracker
+        .observe(&bus("WXX1234", ROUTE), "T789", 6 * CYCLE_MS)
+        .is_none());
+}
+
+#[test]
+fn nothing_is_lost_while_disconnected_or_during_the_reconnect_grace() {
+    let mut tracker = PresenceTracker::new(2, GRACE_MS);
+    tracker.observe(&bus("WXX1234", ROUTE), "T789", 0);
+    tracker.observe(&bus("WYY5678", ROUTE), "T789", 0);
+    tracker.end_cycle(CYCLE_MS);
+
+    tracker.on_disconnected("T789");
+    for cycle in 2..10 {
+        assert!(tracker.end_cycle(cycle * CYCLE_MS).is_empty());
+    }
+    let reconnected_at = 10 * CYCLE_MS;
+    tracker.on_connected("T789", reconnected_at);
+    // The feed resends WYY5678 after the reconnect but never WXX1234. Inside the grace
+    // period that doesn't count; past it, WXX1234 is lost after two more cycles.
+    for cycle in 1..=4 {
+        let now = reconnected_at + cycle * CYCLE_MS;
+        tracker.observe(&bus("WYY5678", ROUTE), "T789", now);
+        let events = tracker.end_cycle(now);
+        if cycle == 4 {
+            assert_eq!(lost_ids(&events), ["WXX1234"]);
+        } else {
+            assert!(events.is_empty(), "cycle {}: {:?}", cycle, events);
+        }
+    }
+}
+
+#[test]
+fn only_the_reconnecting_route_gets_the_grace() {
+    let mut tracker = PresenceTracker::new(2, GRACE_MS);
+    tracker.observe(&bus("WXX1234", "T789"), "T789", 0);
+    tracker.observe(&bus("WYY5678", "T790"), "T790", 0);
+    tracker.end_cycle(CYCLE_MS);
+
+    // T789's socket drops and comes back; T790's stays up throughout.
+    tracker.on_disconnected("T789");
+    tracker.on_connected("T789", 2 * CYCLE_MS);
+    assert!(tracker.end_cycle(2 * CYCLE_MS).is_empty());
+
+    // Neither bus is resent. WYY5678 is lost on schedule while WXX1234 is still in its
+    // route's grace period.
+    let events = tracker.end_cycle(3 * CYCLE_MS);
+    assert_eq!(lost_ids(&events), ["WYY5678"]);
+    assert!(!tracker.is_lost("WXX1234"));
+
+    let past_grace = 2 * CYCLE_MS + GRACE_MS;
+    tracker.end_cycle(past_grace + CYCLE_MS);
+    assert_eq!(
+        lost_ids(&tracker.end_cycle(past_grace + 2 * CYCLE_MS)),
+        ["WXX1234"]
+    );
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use be::feed::BusPosition;
+use be::progress::{RouteShape, RouteShapes, DEFAULT_OFF_ROUTE_METERS};
+use common::ROUTE;
+use std::collections::HashMap;
+
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+// East along 3.10N for 0.01 degrees, then north for 0.01 degrees.
+fn l_shape() -> RouteShape {
+    RouteShape::new(vec![(3.10, 101.60), (3.10, 101.61), (3.11, 101.61)]).unwrap()
+}
+
+fn bus(latitude: f64, longitude: f64) -> BusPosition {
+    common::bus_at("WXX1234", ROUTE, latitude, longitude)
+}
+
+fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+    assert!(
+        (actual - expected).abs() <= tolerance,
+        "{} is not within {} of {}",
+        actual,
+        tolerance,
+        expected
+    );
+}
+
+#[test]
+fn a_position_snaps_onto_the_nearest_segment() {
+    let shape = l_shape();
+    let east_leg = 0.01 * METERS_PER_DEGREE * 3.10_f64.to_radians().cos();
+
+    // 0.0005 degrees north of the middle of the first leg.
+    let projection = shape.project(3.1005, 101.605);
+    assert_close(projection.lat, 3.10, 1e-9);
+    assert_close(projection.lon, 101.605, 1e-6);
+    assert_close(projection.progress_meters, east_leg / 2.0, 0.5);
+    assert_close(projection.distance_meters, 0.0005 * METERS_PER_DEGREE, 0.5);
+
+    // East of the second leg, a quarter of the way up it.
+    let projection = shape.project(3.1025, 101.611);
+    assert_close(projection.lat, 3.1025, 1e-9);
+    assert_close(projection.lon, 101.61, 1e-9);
+    assert_close(
+        projection.progress_meters,
+        east_leg + 0.0025 * METERS_PER_DEGREE,
+        0.5,
+    );
+    assert_close(
+        shape.length_meters(),
+        east_leg + 0.01 * METERS_PER_DEGREE,
+        0.5,
+    );
+}
+
+#[test]
+fn a_position_past_the_ends_snaps_to_the_end_points() {
+    let shape = l_shape();
+    let before = shape.project(3.10, 101.59);
+    assert_eq!(
+        (before.lat, before.lon, before.progress_meters),
+        (3.10, 101.60, 0.0)
+    );
+
+    let after = shape.project(3.13, 101.61);
+    assert_close(after.lat, 3.11, 1e-9);
+    assert_close(after.lon, 101.61, 1e-9);
+    assert_close(after.progress_meters, shape.length_meters(), 1e-6);
+    assert_close(after.distance_meters, 0.02 * METERS_PER_DEGREE, 1.0);
+}
+
+#[test]
+fn the_index_finds_the_same_segment_as_measuring_every_one() {
+    // A long zigzag through many grid cells, doubling back on itself.
+    let points: Vec<(f64, f64)> = (0..400)
+        .map(|index| {
+            let step = index as f64;
+            (3.05 + (step * 0.37).sin() * 0.03, 101.55 + step * 0.0004)
+        })
+        .collect();
+    let shape = RouteShape::new(points.clone()).unwrap();
+    let segments: Vec<RouteShape> = points
+        .windows(2)
+        .map(|pair| RouteShape::new(pair.to_vec()).unwrap())
+        .collect();
+
+    for probe in 0..200 {
+        let step = probe as f64;
+        // Near the shape, well off it, and far outside its extent.
+        let (lat, lon) = match probe % 3 {
+            0 => (3.05 + (step * 0.11).cos() * 0.035, 101.55 + step * 0.0008),
+            1 => (3.20 + step * 0.001, 101.50 + step * 0.001),
+            _ => (1.30 + step * 0.01, 103.80),
+        };
+        let nearest = segments
+            .iter()
+            .map(|segment| segment.project(lat, lon).distance_meters)
+            .fold(f64::MAX, f64::min);
+        // Equirectangular offsets aren't quite a metric, so allow for a near tie between
+        // two segments going the other way.
+        assert_close(
+            shape.project(lat, lon).distance_meters,
+            nearest,
+            nearest * 1e-3 + 1e-6,
+        );
+    }
+}
+
+#[test]
+fn only_positions_near_the_shape_are_snapped() {
+    let shapes = RouteShapes::new(
+        HashMap::from([("T789".to_string(), l_shape())]),
+        |route| route.to_uppercase(),
+        DEFAULT_OFF_ROUTE_METERS,
+    );
+    let (lat, lon) = shapes.snap(&bus(3.1005, 101.605)).unwrap();
+    assert_close(lat, 3.10, 1e-9);
+    assert_close(lon, 101.605, 1e-6);
+    // About 550m off the first leg.
+    assert_eq!(shapes.snap(&bus(3.105, 101.605)), None);
+
+    let mut off_route = bus(3.105, 101.605);
+    shapes.annotate(&mut off_route);
+    assert!(off_route.off_route);
+    assert_eq!(off_route.progress_m, None);
+
+    let mut on_route = bus(3.1025, 101.611);
+    shapes.annotate(&mut on_route);
+    assert!(!on_route.off_route);
+    let progress_pct = on_route.progress_pct.unwrap();
+    assert!((62.0..63.0).contains(&progress_pct), "{}", progress_pct);
+}
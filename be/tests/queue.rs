@@ -0,0 +1,67 @@
+use be::queue::{BoundedQueue, OverflowPolicy};
+use std::time::Duration;
+
+async fn full_queue(policy: OverflowPolicy, block_timeout: Duration) -> BoundedQueue<u32> {
+    let queue = BoundedQueue::new(2, policy, block_timeout);
+    assert!(queue.push(1).await);
+    assert!(queue.push(2).await);
+    queue
+}
+
+#[tokio::test]
+async fn drop_oldest_keeps_the_freshest_items() {
+    let queue = full_queue(OverflowPolicy::DropOldest, Duration::ZERO).await;
+    assert!(!queue.push(3).await);
+    assert_eq!(queue.drain(10), [2, 3]);
+    assert_eq!(queue.dropped_count(), 1);
+}
+
+#[tokio::test]
+async fn drop_newest_turns_the_new_item_away() {
+    let queue = full_queue(OverflowPolicy::DropNewest, Duration::ZERO).await;
+    assert!(!queue.push(3).await);
+    assert_eq!(queue.drain(10), [1, 2]);
+    assert_eq!(queue.dropped_count(), 1);
+}
+
+#[tokio::test]
+async fn block_waits_for_room_then_sheds_the_oldest() {
+    let queue = full_queue(OverflowPolicy::Block, Duration::from_millis(200)).await;
+    let consumer = queue.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        consumer.pop().await
+    });
+    // The pop makes room before the timeout, so nothing is lost.
+    assert!(queue.push(3).await);
+    assert_eq!(queue.dropped_count(), 0);
+
+    // Nobody pops this time.
+    let queue = full_queue(OverflowPolicy::Block, Duration::from_millis(20)).await;
+    assert!(!queue.push(3).await);
+    assert_eq!(queue.drain(10), [2, 3]);
+}
+
+#[tokio::test]
+async fn drain_takes_at_most_what_was_asked_without_waiting() {
+    let queue = full_queue(OverflowPolicy::DropOldest, Duration::ZERO).await;
+    assert_eq!(queue.drain(1), [1]);
+    assert_eq!(queue.len(), 1);
+    assert_eq!(queue.drain(5), [2]);
+    assert!(queue.drain(5).is_empty());
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn policies_parse_from_env_values() {
+    assert_eq!(
+        " Drop-Oldest ".parse::<OverflowPolicy>(),
+        Ok(OverflowPolicy::DropOldest)
+    );
+    assert_eq!(
+        "drop-newest".parse::<OverflowPolicy>(),
+        Ok(OverflowPolicy::DropNewest)
+    );
+    assert_eq!("block".parse::<OverflowPolicy>(), Ok(OverflowPolicy::Block));
+    assert!("drop-all".parse::<OverflowPolicy>().is_err());
+}
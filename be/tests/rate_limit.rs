@@ -0,0 +1,158 @@
+use be::rate_limit::{
+    jittered, random_unit, spread_offset, ClientRateLimiter, EmitLimiter, TrustedProxies,
+    RELOAD_JITTER,
+};
+use governor::clock::FakeRelativeClock;
+use std::net::IpAddr;
+use std::time::Duration;
+
+const INTERVAL: Duration = Duration::from_secs(20);
+
+#[test]
+fn limiter_throttles_past_the_burst_and_recovers_as_the_clock_advances() {
+    let clock = FakeRelativeClock::default();
+    let limiter = EmitLimiter::with_clock(2, clock.clone());
+
+    assert_eq!(limiter.try_acquire(), Ok(()));
+    assert_eq!(limiter.try_acquire(), Ok(()));
+    assert_eq!(limiter.try_acquire(), Err(Duration::from_millis(500)));
+    assert_eq!(limiter.throttled_count(), 1);
+
+    clock.advance(Duration::from_millis(499));
+    assert!(limiter.try_acquire().is_err());
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(limiter.try_acquire(), Ok(()));
+    assert_eq!(limiter.throttled_count(), 2);
+}
+
+#[test]
+fn clones_share_one_bucket() {
+    let clock = FakeRelativeClock::default();
+    let limiter = EmitLimiter::with_clock(1, clock.clone());
+    let other = limiter.clone();
+
+    assert_eq!(limiter.try_acquire(), Ok(()));
+    assert!(other.try_acquire().is_err());
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(other.try_acquire(), Ok(()));
+}
+
+#[test]
+fn a_zero_rate_still_lets_one_emit_through_per_second() {
+    let clock = FakeRelativeClock::default();
+    let limiter = EmitLimiter::with_clock(0, clock.clone());
+
+    assert_eq!(limiter.try_acquire(), Ok(()));
+    assert_eq!(limiter.try_acquire(), Err(Duration::from_secs(1)));
+}
+
+fn assert_secs(actual: Duration, expected: f64) {
+    assert!(
+        (actual.as_secs_f64() - expected).abs() < 1e-6,
+        "expected {}s, got {:?}",
+        expected,
+        actual
+    );
+}
+
+#[test]
+fn jitter_stays_within_ten_percent() {
+    assert_secs(jittered(INTERVAL, RELOAD_JITTER, 0.0), 18.0);
+    assert_secs(jittered(INTERVAL, RELOAD_JITTER, 0.5), 20.0);
+    assert_secs(jittered(INTERVAL, RELOAD_JITTER, 1.0), 22.0);
+    for _ in 0..100 {
+        let delay = jittered(INTERVAL, RELOAD_JITTER, random_unit()).as_secs_f64();
+        assert!((18.0 - 1e-6..=22.0 + 1e-6).contains(&delay), "{}", delay);
+    }
+}
+
+#[test]
+fn offsets_spread_clients_evenly_across_the_interval() {
+    let offsets: Vec<Duration> = (0..4)
+        .map(|index| spread_offset(INTERVAL, index, 4))
+        .collect();
+    assert_eq!(offsets, [5, 10, 15, 20].map(Duration::from_secs).to_vec());
+    assert_eq!(spread_offset(INTERVAL, 0, 0), INTERVAL);
+}
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+#[test]
+fn each_client_ip_gets_its_own_bucket() {
+    let clock = FakeRelativeClock::default();
+    let limiter = ClientRateLimiter::with_clock(60, 2, 100, clock.clone());
+
+    assert_eq!(limiter.check(ip("203.0.113.1")), Ok(()));
+    assert_eq!(limiter.check(ip("203.0.113.1")), Ok(()));
+    assert_eq!(
+        limiter.check(ip("203.0.113.1")),
+        Err(Duration::from_secs(1))
+    );
+    assert_eq!(limiter.check(ip("203.0.113.2")), Ok(()));
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(limiter.check(ip("203.0.113.1")), Ok(()));
+}
+
+#[test]
+fn the_least_recently_seen_client_is_evicted_past_the_cap() {
+    let clock = FakeRelativeClock::default();
+    let limiter = ClientRateLimiter::with_clock(60, 1, 2, clock.clone());
+
+    assert_eq!(limiter.check(ip("203.0.113.1")), Ok(()));
+    assert_eq!(limiter.check(ip("203.0.113.2")), Ok(()));
+    // .1 is seen again, so .2 is the one to go.
+    assert!(limiter.check(ip("203.0.113.1")).is_err());
+    assert_eq!(limiter.check(ip("203.0.113.3")), Ok(()));
+    assert_eq!(limiter.tracked_clients(), 2);
+
+    assert!(limiter.check(ip("203.0.113.1")).is_err());
+    // Forgotten, so it starts again with a full bucket.
+    assert_eq!(limiter.check(ip("203.0.113.2")), Ok(()));
+    assert_eq!(limiter.tracked_clients(), 2);
+}
+
+#[test]
+fn forwarded_for_is_ignored_from_untrusted_peers() {
+    let proxies = TrustedProxies::parse("10.0.0.0/8, 127.0.0.1").unwrap();
+    assert_eq!(
+        proxies.client_ip(ip("198.51.100.7"), Some("203.0.113.1")),
+        ip("198.51.100.7")
+    );
+    assert_eq!(
+        TrustedProxies::default().client_ip(ip("10.0.0.2"), Some("203.0.113.1")),
+        ip("10.0.0.2")
+    );
+}
+
+#[test]
+fn forwarded_for_is_walked_past_trusted_hops() {
+    let proxies = TrustedProxies::parse("10.0.0.0/8,127.0.0.1").unwrap();
+    // The client could have prepended anything; only hops added by trusted proxies count.
+    assert_eq!(
+        proxies.client_ip(ip("127.0.0.1"), Some("1.2.3.4, 203.0.113.1, 10.1.2.3")),
+        ip("203.0.113.1")
+    );
+    assert_eq!(
+        proxies.client_ip(ip("::ffff:10.0.0.1"), Some("203.0.113.9")),
+        ip("203.0.113.9")
+    );
+    // Nothing usable past the proxy: limit the proxy itself.
+    assert_eq!(proxies.client_ip(ip("10.0.0.1"), None), ip("10.0.0.1"));
+    assert_eq!(
+        proxies.client_ip(ip("10.0.0.1"), Some("garbage")),
+        ip("10.0.0.1")
+    );
+}
+
+#[test]
+fn trusted_proxies_reject_bad_ranges() {
+    assert!(TrustedProxies::parse("10.0.0.0/33").is_err());
+    assert!(TrustedProxies::parse("proxy.internal").is_err());
+    assert!(TrustedProxies::parse("").unwrap().is_empty());
+    let proxies = TrustedProxies::parse("2001:db8::/32").unwrap();
+    assert!(proxies.contains(ip("2001:db8:1::1")));
+    assert!(!proxies.contains(ip("2001:db9::1")));
+}
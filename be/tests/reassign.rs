@@ -0,0 +1,119 @@
+mod common;
+
+use be::normalize_route_code;
+use be::reassign::{ReassignPolicy, RouteAssignments};
+use common::bus;
+
+const GRACE_MS: i64 = 60_000;
+
+#[test]
+fn a_vehicle_on_a_new_route_is_reported_once() {
+    let mut assignments =
+        RouteAssignments::new(ReassignPolicy::Move, GRACE_MS, normalize_route_code);
+    assert!(assignments.observe(&bus("WXX1234", "T789"), 0).is_none());
+    assert!(assignments
+        .observe(&bus("WXX1234", "T789"), 10_000)
+        .is_none());
+
+    let reassignment = assignments
+        .observe(&bus("WXX1234", "T790"), 25_000)
+        .expect("reassigned");
+    assert_eq!(reassignment.from_route, "T789");
+    assert_eq!(reassignment.to_route, "T790");
+    assert_eq!(reassignment.gap_ms, 15_000);
+    assert_eq!(
+        reassignment.to_string(),
+        "WXX1234 reassigned from route T789 to T790 after 15s"
+    );
+    assert!(assignments
+        .observe(&bus("WXX1234", "T790"), 30_000)
+        .is_none());
+}
+
+#[test]
+fn route_codes_are_compared_through_the_route_key() {
+    let mut assignments =
+        RouteAssignments::new(ReassignPolicy::Move, GRACE_MS, normalize_route_code);
+    assignments.observe(&bus("WXX1234", "T7890"), 0);
+    assert!(assignments
+        .observe(&bus("WXX1234", "t789"), 1_000)
+        .is_none());
+    // Without a route there is nothing to be reassigned from.
+    assert!(assignments.observe(&bus("WXX1234", ""), 2_000).is_none());
+    assert!(assignments.observe(&bus("", "T790"), 3_000).is_none());
+}
+
+#[test]
+fn move_releases_the_old_route_after_the_grace_period() {
+    let mut assignments =
+        RouteAssignments::new(ReassignPolicy::Move, GRACE_MS, normalize_route_code);
+    assignments.observe(&bus("WXX1234", "T789"), 0);
+    assignments.observe(&bus("WXX1234", "T790"), 10_000);
+    assert_eq!(assignments.previous_routes("WXX1234"), ["T789"]);
+
+    assert!(assignments.release(10_000 + GRACE_MS - 1).is_empty());
+    assert_eq!(
+        assignments.release(10_000 + GRACE_MS),
+        [("WXX1234".to_string(), "T789".to_string())]
+    );
+    assert!(assignments.previous_routes("WXX1234").is_empty());
+    assert!(assignments.release(10_000 + 2 * GRACE_MS).is_empty());
+}
+
+#[test]
+fn keep_both_never_releases() {
+    let mut assignments =
+        RouteAssignments::new(ReassignPolicy::KeepBoth, GRACE_MS, normalize_route_code);
+    assignments.observe(&bus("WXX1234", "T789"), 0);
+    assert!(assignments
+        .observe(&bus("WXX1234", "T790"), 10_000)
+        .is_some());
+    assert!(assignments.release(10 * GRACE_MS).is_empty());
+    assert_eq!(assignments.previous_routes("WXX1234"), ["T789"]);
+}
+
+#[test]
+fn returning_to_a_route_makes_it_current_again() {
+    let mut assignments =
+        RouteAssignments::new(ReassignPolicy::Move, GRACE_MS, normalize_route_code);
+    assignments.observe(&bus("WXX1234", "T789"), 0);
+    assignments.observe(&bus("WXX1234", "T790"), 10_000);
+    assert!(assignments
+        .observe(&bus("WXX1234", "T789"), 20_000)
+        .is_some());
+    assert_eq!(assignments.previous_routes("WXX1234"), ["T790"]);
+
+    // The first move's release finds T789 current and T790 not yet due.
+    assert!(assignments.release(10_000 + GRACE_MS).is_empty());
+    assert_eq!(
+        assignments.release(20_000 + GRACE_MS),
+        [("WXX1234".to_string(), "T790".to_string())]
+    );
+}
+
+#[test]
+fn leaving_a_route_again_restarts_its_grace_period() {
+    let mut assignments =
+        RouteAssignments::new(ReassignPolicy::Move, GRACE_MS, normalize_route_code);
+    assignments.observe(&bus("WXX1234", "T789"), 0);
+    assignments.observe(&bus("WXX1234", "T790"), 10_000);
+    assignments.observe(&bus("WXX1234", "T789"), 20_000);
+    assignments.observe(&bus("WXX1234", "T790"), 30_000);
+
+    assert!(assignments.release(10_000 + GRACE_MS).is_empty());
+    assert!(assignments.release(20_000 + GRACE_MS).is_empty());
+    assert_eq!(
+        assignments.release(30_000 + GRACE_MS),
+        [("WXX1234".to_string(), "T789".to_string())]
+    );
+}
+
+#[test]
+fn policies_parse_from_env_values() {
+    assert_eq!(
+        "keep-both".parse::<ReassignPolicy>(),
+        Ok(ReassignPolicy::KeepBoth)
+    );
+    assert_eq!(" Move ".parse::<ReassignPolicy>(), Ok(ReassignPolicy::Move));
+    assert!("both".parse::<ReassignPolicy>().is_err());
+}
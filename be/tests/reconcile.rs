@@ -0,0 +1,69 @@
+mod common;
+
+use be::reconcile::{normalize_vehicle_id, reconcile};
+use common::bus_at;
+
+#[test]
+fn ids_are_compared_without_case_or_separators() {
+    assert_eq!(normalize_vehicle_id("wxx 1234"), "WXX1234");
+    assert_eq!(normalize_vehicle_id("WXX-1234"), "WXX1234");
+    assert_eq!(normalize_vehicle_id(" - "), "");
+}
+
+#[test]
+fn vehicles_split_into_matched_and_one_sided() {
+    let websocket = [
+        bus_at("WXX1234", "300", 3.1390, 101.6869),
+        bus_at("WYY5678", "300", 3.1500, 101.7000),
+        bus_at("VAB1111", "300", 3.1600, 101.7100),
+    ];
+    let gtfs = [
+        // ~111m north of the websocket fix.
+        bus_at("wxx 1234", "300", 3.1400, 101.6869),
+        bus_at("WYY-5678", "300", 3.1500, 101.7000),
+        bus_at("WCD2222", "300", 3.1700, 101.7200),
+    ];
+
+    let reconciliation = reconcile(&websocket, &gtfs);
+    assert_eq!(reconciliation.websocket_count, 3);
+    assert_eq!(reconciliation.gtfs_count, 3);
+    assert_eq!(reconciliation.only_websocket, ["VAB1111"]);
+    assert_eq!(reconciliation.only_gtfs, ["WCD2222"]);
+
+    let matched: Vec<&str> = reconciliation
+        .matched
+        .iter()
+        .map(|matched| matched.vehicle_id.as_str())
+        .collect();
+    assert_eq!(matched, ["WXX1234", "WYY5678"]);
+    assert!((reconciliation.matched[0].divergence_meters - 111.2).abs() < 1.0);
+    assert!(reconciliation.matched[1].divergence_meters < 0.01);
+    let median = reconciliation.median_divergence_meters().unwrap();
+    assert!((median - 55.6).abs() < 1.0);
+}
+
+#[test]
+fn duplicates_count_once_and_blank_ids_are_ignored() {
+    let websocket = [
+        bus_at("WXX1234", "300", 3.0, 101.0),
+        bus_at("WXX1234", "300", 3.1390, 101.6869),
+        bus_at("", "300", 3.2, 101.2),
+    ];
+    let gtfs = [bus_at("WXX1234", "300", 3.1390, 101.6869)];
+
+    let reconciliation = reconcile(&websocket, &gtfs);
+    assert_eq!(reconciliation.websocket_count, 1);
+    assert!(reconciliation.only_websocket.is_empty());
+    assert!(reconciliation.matched[0].divergence_meters < 0.01);
+}
+
+#[test]
+fn empty_feeds_reconcile_to_an_empty_report() {
+    let reconciliation = reconcile(&[], &[]);
+    assert!(reconciliation.matched.is_empty());
+    assert_eq!(reconciliation.median_divergence_meters(), None);
+    assert_eq!(
+        reconciliation.to_string(),
+        "websocket=0 gtfs-rt=0 matched=0"
+    );
+}
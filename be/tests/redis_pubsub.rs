@@ -0,0 +1,146 @@
+#![cfg(feature = "redis")]
+
+mod common;
+
+use be::feed::BusPosition;
+use be::queue::OverflowPolicy;
+use be::redis_pubsub::{channel_for, snapshot_key_for, RedisPubSubSink};
+use common::bus;
+use futures_util::StreamExt;
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+// Kills the spawned redis-server when the test ends, pass or fail.
+struct RedisServer {
+    child: Child,
+    url: String,
+}
+
+impl Drop for RedisServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// None when redis-server isn't installed, so the tests skip instead of failing.
+async fn spawn_redis_server() -> Option<RedisServer> {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .ok()?
+        .local_addr()
+        .ok()?
+        .port();
+    let child = Command::new("redis-server")
+        .args([
+            "--port",
+            &port.to_string(),
+            "--save",
+            "",
+            "--appendonly",
+            "no",
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    let Ok(child) = child else {
+        eprintln!("redis-server not found; skipping");
+        return None;
+    };
+    let server = RedisServer {
+        child,
+        url: format!("redis://127.0.0.1:{}/", port),
+    };
+
+    let client = redis::Client::open(server.url.as_str()).unwrap();
+    for _ in 0..50 {
+        if client.get_multiplexed_async_connection().await.is_ok() {
+            return Some(server);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("redis-server did not start on {}", server.url);
+}
+
+#[test]
+fn channels_and_snapshot_keys_are_namespaced_by_route() {
+    assert_eq!(channel_for("T789"), "rapidbro:buses:T789");
+    assert_eq!(snapshot_key_for("T789"), "rapidbro:snapshot:T789");
+}
+
+#[tokio::test]
+async fn batches_are_published_per_route_and_snapshotted() {
+    let Some(server) = spawn_redis_server().await else {
+        return;
+    };
+    let client = redis::Client::open(server.url.as_str()).unwrap();
+    let mut pubsub = client.get_async_pubsub().await.unwrap();
+    pubsub.subscribe(channel_for("300")).await.unwrap();
+
+    let sink = RedisPubSubSink::new(
+        client.clone(),
+        Some(Duration::from_secs(60)),
+        OverflowPolicy::DropOldest,
+    );
+    let runner = sink.clone();
+    let run = tokio::spawn(async move { runner.run().await });
+    sink.enqueue("300".to_string(), vec![bus("WXX1234", "300")])
+        .await;
+    sink.enqueue("300".to_string(), vec![bus("WYY5678", "300")])
+        .await;
+
+    let mut messages = pubsub.on_message();
+    for expected in ["WXX1234", "WYY5678"] {
+        let message = tokio::time::timeout(Duration::from_secs(5), messages.next())
+            .await
+            .unwrap()
+            .unwrap();
+        let payload: String = message.get_payload().unwrap();
+        let received: Vec<BusPosition> = serde_json::from_str(&payload).unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].bus_no, expected);
+    }
+
+    // The snapshot accumulates both buses, not just the latest batch.
+    let mut snapshot = sink.read_snapshot("300").await.unwrap().unwrap();
+    snapshot.sort_by(|left, right| left.bus_no.cmp(&right.bus_no));
+    let bus_nos: Vec<&str> = snapshot.iter().map(|bus| bus.bus_no.as_str()).collect();
+    assert_eq!(bus_nos, ["WXX1234", "WYY5678"]);
+    assert!(sink.read_snapshot("302").await.unwrap().is_none());
+    run.abort();
+}
+
+#[tokio::test]
+async fn clearing_a_route_empties_its_snapshot() {
+    let Some(server) = spawn_redis_server().await else {
+        return;
+    };
+    let client = redis::Client::open(server.url.as_str()).unwrap();
+    let mut pubsub = client.get_async_pubsub().await.unwrap();
+    pubsub.subscribe(channel_for("300")).await.unwrap();
+
+    let sink = RedisPubSubSink::new(
+        client.clone(),
+        Some(Duration::from_secs(60)),
+        OverflowPolicy::DropOldest,
+    );
+    let runner = sink.clone();
+    let run = tokio::spawn(async move { runner.run().await });
+    sink.enqueue("300".to_string(), vec![bus("WXX1234", "300")])
+        .await;
+    sink.clear("300".to_string()).await;
+
+    let mut messages = pubsub.on_message();
+    let mut payloads = Vec::new();
+    for _ in 0..2 {
+        let message = tokio::time::timeout(Duration::from_secs(5), messages.next())
+            .await
+            .unwrap()
+            .unwrap();
+        payloads.push(message.get_payload::<String>().unwrap());
+    }
+    assert_eq!(payloads[1], "[]");
+    let snapshot = sink.read_snapshot("300").await.unwrap().unwrap();
+    assert!(snapshot.is_empty());
+    run.abort();
+}
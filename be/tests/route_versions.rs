@@ -0,0 +1,61 @@
+use be::normalize_route_code;
+use be::route_versions::{if_none_match, RouteVersions};
+use std::sync::Arc;
+
+#[test]
+fn versions_bump_per_route() {
+    let versions = RouteVersions::new(1, normalize_route_code);
+    assert_eq!(versions.version("T789"), 0);
+    assert_eq!(versions.bump("t789"), 1);
+    assert_eq!(versions.bump("T789 "), 2);
+    assert_eq!(versions.version("T789"), 2);
+    assert_eq!(versions.version("T790"), 0);
+
+    versions.bump("T790");
+    versions.bump_all();
+    assert_eq!(versions.version("T789"), 3);
+    assert_eq!(versions.version("T790"), 2);
+}
+
+#[test]
+fn concurrent_bumps_are_not_lost() {
+    let versions = Arc::new(RouteVersions::new(1, normalize_route_code));
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            let versions = versions.clone();
+            std::thread::spawn(move || {
+                for _ in 0..1_000 {
+                    versions.bump("T789");
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(versions.version("T789"), 8_000);
+}
+
+#[test]
+fn etags_change_with_the_version_epoch_and_variant() {
+    let versions = RouteVersions::new(1, normalize_route_code);
+    let before = versions.etag("T789", 7);
+    assert_eq!(before, versions.etag("T789", 7));
+    assert_ne!(before, versions.etag("T789", 8));
+    assert_ne!(
+        before,
+        RouteVersions::new(2, normalize_route_code).etag("T789", 7)
+    );
+    versions.bump("T789");
+    assert_ne!(before, versions.etag("T789", 7));
+}
+
+#[test]
+fn if_none_match_compares_weakly() {
+    let etag = "W/\"1-2-3\"";
+    assert!(if_none_match(etag, etag));
+    assert!(if_none_match("\"1-2-3\"", etag));
+    assert!(if_none_match("\"0-0-0\", W/\"1-2-3\"", etag));
+    assert!(if_none_match("*", etag));
+    assert!(!if_none_match("W/\"1-2-4\"", etag));
+}
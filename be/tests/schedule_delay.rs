@@ -0,0 +1,121 @@
+use be::delay::{match_trip, parse_gtfs_time, MatchOutcome, ScheduledStop, ScheduledTrip};
+
+const MARGIN_SECS: i64 = 180;
+
+// Three stops 1 km apart, 5 minutes between each.
+fn trip(trip_id: &str, direction_id: Option<u8>, start_times: &[&str]) -> ScheduledTrip {
+    let progress = match direction_id {
+        Some(1) => [2_000.0, 1_000.0, 0.0],
+        _ => [0.0, 1_000.0, 2_000.0],
+    };
+    ScheduledTrip {
+        trip_id: trip_id.to_string(),
+        service_id: "weekday".to_string(),
+        direction_id,
+        stops: ["A", "B", "C"]
+            .iter()
+            .zip(progress)
+            .enumerate()
+            .map(|(index, (stop_id, progress_m))| ScheduledStop {
+                stop_id: stop_id.to_string(),
+                progress_m,
+                offset_secs: index as i64 * 300,
+            })
+            .collect(),
+        start_times: start_times
+            .iter()
+            .map(|time| parse_gtfs_time(time).unwrap())
+            .collect(),
+    }
+}
+
+fn secs(time: &str) -> i64 {
+    parse_gtfs_time(time).unwrap()
+}
+
+fn matched(outcome: MatchOutcome) -> (String, String, f64) {
+    match outcome {
+        MatchOutcome::Matched { estimate } => {
+            (estimate.trip_id, estimate.next_stop_id, estimate.delay_min)
+        }
+        other => panic!("expected a match, got {:?}", other),
+    }
+}
+
+#[test]
+fn on_time_bus_matches_with_zero_delay() {
+    let trips = [trip("t1", Some(0), &["08:00:00"])];
+    // Halfway between A and B is scheduled for 08:02:30.
+    let outcome = match_trip(&trips, Some(0), 500.0, secs("08:02:30"), MARGIN_SECS);
+
+    let (trip_id, next_stop_id, delay_min) = matched(outcome);
+    assert_eq!(trip_id, "t1");
+    assert_eq!(next_stop_id, "B");
+    assert_eq!(delay_min, 0.0);
+}
+
+#[test]
+fn late_bus_reports_positive_delay() {
+    let trips = [trip("t1", Some(0), &["08:00:00"])];
+    let outcome = match_trip(&trips, Some(0), 1_500.0, secs("08:11:30"), MARGIN_SECS);
+
+    let (_, next_stop_id, delay_min) = matched(outcome);
+    assert_eq!(next_stop_id, "C");
+    assert_eq!(delay_min, 4.0);
+}
+
+#[test]
+fn frequency_runs_pick_the_closest_start() {
+    let trips = [trip("t1", Some(0), &["08:00:00", "08:15:00", "08:30:00"])];
+    let outcome = match_trip(&trips, Some(0), 0.0, secs("08:16:00"), MARGIN_SECS);
+
+    let MatchOutcome::Matched { estimate } = outcome else {
+        panic!("expected a match");
+    };
+    assert_eq!(estimate.scheduled_start, "08:15:00");
+    assert_eq!(estimate.delay_min, 1.0);
+    assert!(estimate.confidence > 0.8);
+}
+
+#[test]
+fn refuses_to_guess_between_equally_plausible_trips() {
+    let trips = [
+        trip("t1", Some(0), &["08:00:00"]),
+        trip("t2", Some(0), &["08:05:00"]),
+    ];
+    // Between both schedules: 2.5 minutes late for one, early for the other.
+    let outcome = match_trip(&trips, Some(0), 0.0, secs("08:02:30"), MARGIN_SECS);
+
+    assert!(matches!(outcome, MatchOutcome::Ambiguous { candidates: 2 }));
+}
+
+#[test]
+fn direction_filters_out_opposite_trips() {
+    let trips = [
+        trip("outbound", Some(0), &["08:00:00"]),
+        trip("inbound", Some(1), &["08:00:00"]),
+    ];
+    let outcome = match_trip(&trips, Some(1), 1_500.0, secs("08:02:30"), MARGIN_SECS);
+
+    let (trip_id, next_stop_id, delay_min) = matched(outcome);
+    assert_eq!(trip_id, "inbound");
+    assert_eq!(next_stop_id, "B");
+    assert_eq!(delay_min, 0.0);
+}
+
+#[test]
+fn trips_after_midnight_match_gtfs_24h_times() {
+    let trips = [trip("late", Some(0), &["24:10:00"])];
+    let outcome = match_trip(&trips, Some(0), 0.0, secs("00:12:00"), MARGIN_SECS);
+
+    let (_, _, delay_min) = matched(outcome);
+    assert_eq!(delay_min, 2.0);
+}
+
+#[test]
+fn no_candidate_outside_the_plausible_window() {
+    let trips = [trip("t1", Some(0), &["08:00:00"])];
+    let outcome = match_trip(&trips, Some(0), 500.0, secs("12:00:00"), MARGIN_SECS);
+
+    assert!(matches!(outcome, MatchOutcome::NoCandidate));
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use be::feed::{DecodeContext, DecodeError};
+use be::field_map::FieldMap;
+use be::schema::{check_position, SchemaMonitor, SchemaViolation};
+use common::encode;
+use rust_socketio::Payload;
+use serde_json::json;
+use std::collections::HashMap;
+
+fn position(bus_no: &str) -> serde_json::Value {
+    json!({
+        "latitude": 3.1478,
+        "longitude": 101.6953,
+        "speed": 32.0,
+        "angle": 90.0,
+        "route": "T789",
+        "bus_no": bus_no,
+        "engine_status": 1,
+        "accessibility": 1,
+        "provider": "RKL",
+    })
+}
+
+#[test]
+fn a_kiosk_position_has_no_violations() {
+    assert!(check_position(&position("WXX1234")).is_empty());
+}
+
+#[test]
+fn missing_and_mistyped_fields_are_reported() {
+    let mut entry = position("WXX1234");
+    entry.as_object_mut().unwrap().remove("longitude");
+    entry["latitude"] = json!("3.1478");
+    entry["occupancy"] = json!("low");
+
+    let violations = check_position(&entry);
+    assert_eq!(violations.len(), 3);
+    assert!(violations.contains(&SchemaViolation::UnknownField("occupancy".to_string())));
+    assert!(violations.contains(&SchemaViolation::MissingField("longitude")));
+    assert!(violations.contains(&SchemaViolation::WrongType {
+        field: "latitude",
+        expected: "number",
+        found: "string",
+    }));
+    assert_eq!(
+        SchemaViolation::UnknownField("occupancy".to_string()).to_string(),
+        "unknown field `occupancy` observed in payload"
+    );
+}
+
+#[test]
+fn unknown_fields_are_counted_per_occurrence_and_positions_still_decode() {
+    let monitor = SchemaMonitor::default();
+    let mut context = DecodeContext::with_schema(Some(monitor.clone()));
+    let mut first = position("WXX1234");
+    first["occupancy"] = json!("low");
+    let mut second = position("WYY5678");
+    second["occupancy"] = json!("high");
+
+    for _ in 0..2 {
+        let (buses, failures) = context.parse(
+            Payload::Text(vec![encode(&json!([first, second]).to_string()).into()]),
+            false,
+        );
+        assert_eq!(buses.len(), 2);
+        assert_eq!(failures, 0);
+    }
+    assert_eq!(monitor.unknown_fields(), [("occupancy".to_string(), 4)]);
+}
+
+#[test]
+fn a_strict_monitor_fails_the_payload() {
+    let monitor = SchemaMonitor::new(true);
+    let mut context = DecodeContext::with_schema(Some(monitor.clone()));
+    let mut entry = position("WXX1234");
+    entry.as_object_mut().unwrap().remove("bus_no");
+
+    let (buses, failures) = context.parse(
+        Payload::Text(vec![encode(&entry.to_string()).into()]),
+        false,
+    );
+    assert!(buses.is_empty());
+    assert_eq!(failures, 1);
+    let recorded = context.take_failures();
+    assert_eq!(
+        recorded[0].error,
+        DecodeError::Schema {
+            message: "expected field `bus_no` missing from payload".to_string()
+        }
+    );
+    assert_eq!(
+        monitor.violations(),
+        [(SchemaViolation::MissingField("bus_no"), 1)]
+    );
+
+    // A clean payload still decodes.
+    let (buses, failures) = context.parse(
+        Payload::Text(vec![encode(&position("WXX1234").to_string()).into()]),
+        false,
+    );
+    assert_eq!((buses.len(), failures), (1, 0));
+}
+
+#[test]
+fn fields_are_checked_after_the_field_map_renames_them() {
+    let field_map = FieldMap::from_entries(&HashMap::from([
+        ("lat".to_string(), "latitude".to_string()),
+        ("lon".to_string(), "longitude".to_string()),
+    ]))
+    .unwrap();
+    let mut entry = position("WXX1234");
+    let object = entry.as_object_mut().unwrap();
+    let latitude = object.remove("latitude").unwrap();
+    let longitude = object.remove("longitude").unwrap();
+    object.insert("lat".to_string(), latitude);
+    object.insert("lon".to_string(), longitude);
+
+    let monitor = SchemaMonitor::new(true);
+    assert_eq!(
+        monitor.check(entry.to_string().as_bytes(), &field_map),
+        Ok(())
+    );
+    assert!(monitor
+        .check(entry.to_string().as_bytes(), &FieldMap::rapid_kl())
+        .is_err());
+}
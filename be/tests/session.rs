@@ -0,0 +1,196 @@
+use be::session::{
+    classify_block_page, extract_session, ExtractError, FallbackExtractor, RegexExtractor, Session,
+    SessionExtractor, SessionExtractors,
+};
+use regex::Regex;
+use std::sync::Arc;
+
+// Kiosk pages as served, one per layout we have seen.
+const KIOSK_FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/kiosk");
+
+const KIOSK_PAGE: &str = r#"<script>
+    var sid = "abc123";
+    var prm = 'p-9';
+    var no_route = "T789";
+</script>"#;
+
+fn session(sid: &str, prm: &str, route: &str) -> Session {
+    Session {
+        sid: sid.to_string(),
+        prm: prm.to_string(),
+        route: route.to_string(),
+    }
+}
+
+fn kiosk_page(name: &str) -> String {
+    std::fs::read_to_string(format!("{}/{}.html", KIOSK_FIXTURES, name)).unwrap()
+}
+
+#[test]
+fn the_default_extractor_reads_script_variables() {
+    assert_eq!(
+        RegexExtractor::default().extract(KIOSK_PAGE).unwrap(),
+        session("abc123", "p-9", "T789")
+    );
+    // Minified declarations.
+    assert_eq!(
+        extract_session(r#"let a=1,sid="s1",prm="p1",no_route="300";"#).unwrap(),
+        session("s1", "p1", "300")
+    );
+}
+
+#[test]
+fn the_fallback_reads_a_config_object() {
+    let page = r#"<script>window.kiosk = {"sid": "s2", "prm": "p2", "no_route": "T789"};</script>"#;
+    assert!(matches!(
+        RegexExtractor::default().extract(page),
+        Err(ExtractError::MissingVariable("sid"))
+    ));
+    assert_eq!(
+        FallbackExtractor::default().extract(page).unwrap(),
+        session("s2", "p2", "T789")
+    );
+    assert_eq!(extract_session(page).unwrap(), session("s2", "p2", "T789"));
+}
+
+#[test]
+fn the_fallback_reads_data_attributes_and_query_parameters() {
+    let page = r#"<div id="kiosk" data-sid="s3" data-no_route="T789"></div>
+        <script src="/socket.js?prm=p3&v=2"></script>"#;
+    assert_eq!(extract_session(page).unwrap(), session("s3", "p3", "T789"));
+}
+
+#[test]
+fn custom_patterns_are_tried_first_and_default_the_rest() {
+    let page = r#"<script>var sid = "old"; var prm = "p4"; var no_route = "T789";
+        var session = { token: "new" };</script>"#;
+    let custom = RegexExtractor::custom(
+        Some(Regex::new(r#"token:\s*"([^"]+)""#).unwrap()),
+        None,
+        None,
+    );
+    let extractors = SessionExtractors::default().try_first(Arc::new(custom));
+
+    assert_eq!(
+        extractors.names(),
+        ["custom-patterns", "script-variables", "fallback"]
+    );
+    assert_eq!(
+        extractors.extract(page).unwrap(),
+        session("new", "p4", "T789")
+    );
+}
+
+#[test]
+fn an_empty_sid_moves_on_to_the_next_extractor() {
+    let page = r#"<script>var sid = ""; var prm = "p5"; var no_route = "T789";</script>
+        <script>boot({sid: "s5"})</script>"#;
+    let extractors = SessionExtractors::new(vec![
+        Arc::new(RegexExtractor::default()),
+        Arc::new(FallbackExtractor::default()),
+    ]);
+    assert_eq!(extractors.extract(page).unwrap().sid, "");
+
+    let sid_only =
+        RegexExtractor::custom(Some(Regex::new(r#"sid:\s*"([^"]+)""#).unwrap()), None, None);
+    let extractors = extractors.try_first(Arc::new(sid_only));
+    assert_eq!(
+        extractors.extract(page).unwrap(),
+        session("s5", "p5", "T789")
+    );
+}
+
+#[test]
+fn a_page_without_any_sid_reports_it_missing() {
+    assert!(matches!(
+        extract_session("<html><body>Access denied</body></html>"),
+        Err(ExtractError::MissingVariable("sid"))
+    ));
+    // The sid was found, so the missing prm is what gets reported.
+    assert!(matches!(
+        extract_session(r#"var sid = "s6"; var no_route = "T789";"#),
+        Err(ExtractError::MissingVariable("prm"))
+    ));
+}
+
+#[test]
+fn recorded_kiosk_pages_yield_their_sessions() {
+    let cases = [
+        (
+            "script_variables",
+            session("5e1c0b7a9f3d4e2c8a6b1d0f7e9c3a52", "rkl-kiosk-2f8c", "T789"),
+        ),
+        (
+            "minified",
+            session("9a4f7c2e1b8d4f60a3c5e7b9d1f2a4c6", "rkl-kiosk-77d1", "300"),
+        ),
+        (
+            "config_object",
+            session("c3d9e1f0a7b24c86b5e3d2f1a0c9b8e7", "rkl-kiosk-0b3e", "T789"),
+        ),
+        (
+            "data_attributes",
+            session("1f2e3d4c5b6a47988a7b6c5d4e3f2a1b", "rkl-kiosk-5c6d", "T789"),
+        ),
+    ];
+    for (name, expected) in cases {
+        assert_eq!(
+            extract_session(&kiosk_page(name)).unwrap(),
+            expected,
+            "{}",
+            name
+        );
+    }
+    // Only the inline-script layouts are read without the fallback.
+    assert!(RegexExtractor::default()
+        .extract(&kiosk_page("script_variables"))
+        .is_ok());
+    assert!(RegexExtractor::default()
+        .extract(&kiosk_page("config_object"))
+        .is_err());
+}
+
+#[test]
+fn a_recorded_block_page_has_no_session() {
+    let page = kiosk_page("access_denied");
+    assert!(matches!(
+        extract_session(&page),
+        Err(ExtractError::MissingVariable("sid"))
+    ));
+    assert_eq!(classify_block_page(200, &page), "access denied");
+}
+
+#[test]
+fn block_pages_are_named_by_their_markers_then_their_status() {
+    let cases = [
+        (
+            200,
+            "<div class=\"cf-challenge\">Checking your browser</div>",
+            "captcha challenge",
+        ),
+        // A challenge wins over a denial status.
+        (
+            403,
+            "<script src=\"/cdn-cgi/challenge-platform/h/b\"></script>",
+            "captcha challenge",
+        ),
+        (200, "<h1>Attention Required!</h1>", "access denied"),
+        (403, "<html></html>", "access denied"),
+        (429, "<html>slow down</html>", "rate limited"),
+        (502, "<h1>Bad Gateway</h1>", "server error page"),
+        (
+            200,
+            "<html>maintenance</html>",
+            "unexpected page without session marker",
+        ),
+    ];
+    for (status, html, expected) in cases {
+        assert_eq!(
+            classify_block_page(status, html),
+            expected,
+            "{} {}",
+            status,
+            html
+        );
+    }
+}
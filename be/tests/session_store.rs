@@ -0,0 +1,64 @@
+use be::session::Session;
+use be::session_store::SessionStore;
+use std::path::PathBuf;
+
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("rapidbro-{}-{}.json", name, std::process::id())))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn session(sid: &str) -> Session {
+    Session {
+        sid: sid.to_string(),
+        prm: "prm-token".to_string(),
+        route: "T789".to_string(),
+    }
+}
+
+#[test]
+fn sessions_survive_a_reload_of_the_state_file() {
+    let file = TempFile::new("session-roundtrip");
+    let store = SessionStore::load(&file.0);
+    store.save("t789", &session("abc123"), "PHPSESSID=xyz; lang=en");
+    store.save("300", &session("def456"), "");
+
+    let reloaded = SessionStore::load(&file.0);
+    let cached = reloaded.get("t789").unwrap();
+    assert_eq!(cached.session(), session("abc123"));
+    assert_eq!(cached.cookies, "PHPSESSID=xyz; lang=en");
+    assert!(reloaded.get("300").is_some());
+
+    reloaded.forget("t789");
+    assert!(SessionStore::load(&file.0).get("t789").is_none());
+    assert!(SessionStore::load(&file.0).get("300").is_some());
+}
+
+#[cfg(unix)]
+#[test]
+fn state_file_is_owner_only() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let file = TempFile::new("session-mode");
+    SessionStore::load(&file.0).save("t789", &session("abc123"), "");
+    let mode = std::fs::metadata(&file.0).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn unreadable_state_file_starts_empty() {
+    let file = TempFile::new("session-corrupt");
+    std::fs::write(&file.0, "not json").unwrap();
+    assert!(SessionStore::load(&file.0).get("t789").is_none());
+    assert!(SessionStore::load(file.0.join("missing"))
+        .get("t789")
+        .is_none());
+}
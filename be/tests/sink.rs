@@ -0,0 +1,196 @@
+mod common;
+
+use async_trait::async_trait;
+use be::client::{ClientEvent, RapidbroClient};
+use be::feed::BusPosition;
+use be::queue::OverflowPolicy;
+use be::sink::{JsonLinesSink, Sink, SinkError, SinkSet};
+use be::updates::BusUpdate;
+use common::{bus, ROUTE};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("rapidbro-{}-{}.jsonl", name, std::process::id())))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[derive(Clone, Default)]
+struct Recording {
+    bus_nos: Arc<Mutex<Vec<String>>>,
+    shut_down: Arc<Mutex<bool>>,
+}
+
+#[async_trait]
+impl Sink for Recording {
+    fn name(&self) -> &str {
+        "recording"
+    }
+
+    async fn handle(&self, batch: &[BusUpdate]) -> Result<(), SinkError> {
+        let mut bus_nos = self.bus_nos.lock().unwrap();
+        bus_nos.extend(
+            batch
+                .iter()
+                .filter_map(BusUpdate::position)
+                .map(|bus| bus.bus_no.clone()),
+        );
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> Result<(), SinkError> {
+        *self.shut_down.lock().unwrap() = true;
+        Ok(())
+    }
+}
+
+struct Failing;
+
+#[async_trait]
+impl Sink for Failing {
+    fn name(&self) -> &str {
+        "failing"
+    }
+
+    async fn handle(&self, _batch: &[BusUpdate]) -> Result<(), SinkError> {
+        Err(SinkError::Other(
+            "downstream rejected the batch".to_string(),
+        ))
+    }
+}
+
+// Never finishes a batch, like a sink whose downstream has hung.
+struct Stalled;
+
+#[async_trait]
+impl Sink for Stalled {
+    fn name(&self) -> &str {
+        "stalled"
+    }
+
+    async fn handle(&self, _batch: &[BusUpdate]) -> Result<(), SinkError> {
+        std::future::pending().await
+    }
+}
+
+#[tokio::test]
+async fn a_failing_sink_is_counted_and_does_not_stop_the_others() {
+    let recording = Recording::default();
+    let mut sinks = SinkSet::default();
+    sinks.push(Box::new(Failing));
+    sinks.push(Box::new(recording.clone()));
+    let workers = sinks.spawn();
+
+    workers
+        .send(vec![BusUpdate::Position(bus("WXX1234", ROUTE))])
+        .await;
+    workers
+        .send(vec![BusUpdate::Position(bus("WYY5678", ROUTE))])
+        .await;
+    workers.shutdown().await;
+
+    assert_eq!(*recording.bus_nos.lock().unwrap(), ["WXX1234", "WYY5678"]);
+    assert!(*recording.shut_down.lock().unwrap());
+    assert_eq!(
+        sinks.error_counts(),
+        [("failing".to_string(), 2), ("recording".to_string(), 0)]
+    );
+}
+
+#[tokio::test]
+async fn a_stalled_sink_does_not_hold_up_the_others() {
+    let recording = Recording::default();
+    let mut sinks = SinkSet::default();
+    sinks.push_queued(Box::new(Stalled), 1, OverflowPolicy::DropOldest);
+    sinks.push(Box::new(recording.clone()));
+    let workers = sinks.spawn();
+
+    for bus_no in ["WXX1234", "WYY5678", "WZZ9012"] {
+        workers
+            .send(vec![BusUpdate::Position(bus(bus_no, ROUTE))])
+            .await;
+    }
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while recording.bus_nos.lock().unwrap().len() < 3 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the recording sink waited on the stalled one");
+    assert_eq!(
+        *recording.bus_nos.lock().unwrap(),
+        ["WXX1234", "WYY5678", "WZZ9012"]
+    );
+}
+
+#[tokio::test]
+async fn json_lines_are_written_per_position_on_flush() {
+    let file = TempFile::new("sink-json-lines");
+    let sink = JsonLinesSink::open(&file.0).await.unwrap();
+    sink.handle(&[
+        BusUpdate::Position(bus("WXX1234", ROUTE)),
+        BusUpdate::Gap { missed: 3 },
+        BusUpdate::Position(bus("WYY5678", ROUTE)),
+    ])
+    .await
+    .unwrap();
+    sink.flush().await.unwrap();
+
+    let written = std::fs::read_to_string(&file.0).unwrap();
+    let bus_nos: Vec<String> = written
+        .lines()
+        .map(|line| serde_json::from_str::<BusPosition>(line).unwrap().bus_no)
+        .collect();
+    assert_eq!(bus_nos, ["WXX1234", "WYY5678"]);
+}
+
+#[tokio::test]
+async fn a_running_client_feeds_its_sinks_and_shuts_them_down() {
+    // Holds the session fetch open so the client stays running without a real kiosk.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((connection, _)) = listener.accept().await {
+            held.push(connection);
+        }
+    });
+
+    let recording = Recording::default();
+    let client = RapidbroClient::builder()
+        .kiosk_url(url.clone())
+        .socket_url(url)
+        .route("T789")
+        .sink(Box::new(recording.clone()))
+        .build()
+        .unwrap();
+    let runner = client.clone();
+    let run = tokio::spawn(async move { runner.run().await });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    client.publish(ClientEvent::Buses {
+        buses: vec![bus("WXX1234", ROUTE), bus("WYY5678", ROUTE)],
+        decode_failures: 0,
+        received_at_unix_ms: 0,
+    });
+    client.stop();
+    tokio::time::timeout(Duration::from_secs(2), run)
+        .await
+        .expect("run did not return")
+        .unwrap();
+
+    assert_eq!(*recording.bus_nos.lock().unwrap(), ["WXX1234", "WYY5678"]);
+    assert!(*recording.shut_down.lock().unwrap());
+    assert_eq!(client.sink_errors(), [("recording".to_string(), 0)]);
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use be::feed::BusPosition;
+use be::speed::SpeedSmoother;
+use common::ROUTE;
+
+// 2024-05-01T08:30:00Z
+const START_MS: i64 = 1_714_552_200_000;
+const RESET_AFTER_MS: i64 = 120_000;
+
+fn smoothed(smoother: &mut SpeedSmoother, speed: f64, received_at_unix_ms: i64) -> Option<f64> {
+    let mut bus: BusPosition = common::bus("WXX1234", ROUTE);
+    bus.speed = speed;
+    smoother.annotate(&mut bus, received_at_unix_ms);
+    bus.smoothed_speed_kmh
+}
+
+#[test]
+fn the_average_covers_the_last_few_fixes() {
+    let mut smoother = SpeedSmoother::new(3, RESET_AFTER_MS);
+    assert_eq!(smoothed(&mut smoother, 30.0, START_MS), Some(30.0));
+    assert_eq!(smoothed(&mut smoother, 20.0, START_MS + 10_000), Some(25.0));
+    assert_eq!(smoothed(&mut smoother, 10.0, START_MS + 20_000), Some(20.0));
+    // The 30 falls out of the window.
+    assert_eq!(smoothed(&mut smoother, 0.0, START_MS + 30_000), Some(10.0));
+    // Rounded to one decimal.
+    assert_eq!(smoothed(&mut smoother, 0.0, START_MS + 40_000), Some(3.3));
+}
+
+#[test]
+fn a_repeated_fix_adds_no_sample() {
+    let mut smoother = SpeedSmoother::new(3, RESET_AFTER_MS);
+    let mut bus = common::bus("WXX1234", ROUTE);
+    bus.timestamp_rfc3339 = Some("2024-05-01T08:30:00Z".to_string());
+    smoother.annotate(&mut bus, START_MS);
+
+    // The same fix again, delivered later with a different speed.
+    bus.speed = 0.0;
+    smoother.annotate(&mut bus, START_MS + 20_000);
+    assert_eq!(bus.smoothed_speed_kmh, Some(32.0));
+}
+
+#[test]
+fn a_long_silence_starts_a_fresh_window() {
+    let mut smoother = SpeedSmoother::new(3, RESET_AFTER_MS);
+    smoothed(&mut smoother, 40.0, START_MS);
+    smoothed(&mut smoother, 40.0, START_MS + 10_000);
+    assert_eq!(
+        smoothed(&mut smoother, 10.0, START_MS + 10_000 + RESET_AFTER_MS + 1),
+        Some(10.0)
+    );
+}
+
+#[test]
+fn unusable_speeds_are_left_unsmoothed() {
+    let mut smoother = SpeedSmoother::new(3, RESET_AFTER_MS);
+    assert_eq!(smoothed(&mut smoother, f64::NAN, START_MS), None);
+    assert_eq!(smoothed(&mut smoother, -1.0, START_MS), None);
+
+    let mut anonymous = common::bus("", ROUTE);
+    smoother.annotate(&mut anonymous, START_MS);
+    assert_eq!(anonymous.smoothed_speed_kmh, None);
+}
@@ -0,0 +1,70 @@
+use be::client::{ClientEvent, RapidbroClient};
+use futures_util::StreamExt;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+// Two attempts are one backoff (about 1s) apart.
+const PROMPTLY: Duration = Duration::from_secs(5);
+
+// Nothing listens on a port that was just released, so every kiosk fetch fails.
+async fn unreachable_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+#[tokio::test]
+async fn an_unreachable_kiosk_gives_up_after_the_startup_budget() {
+    let url = unreachable_url().await;
+    let client = RapidbroClient::builder()
+        .kiosk_url(url.clone())
+        .socket_url(url)
+        .route("T789")
+        .startup_attempts(2)
+        .build()
+        .unwrap();
+    let mut events = client.subscribe().await;
+    let runner = client.clone();
+    let run = tokio::spawn(async move { runner.run().await });
+
+    tokio::time::timeout(PROMPTLY, run)
+        .await
+        .expect("run kept retrying past the startup budget")
+        .unwrap();
+
+    let mut session_failures = 0;
+    let mut gave_up = None;
+    while let Ok(Some(event)) = tokio::time::timeout(Duration::ZERO, events.next()).await {
+        match event {
+            ClientEvent::SessionFailed { .. } => session_failures += 1,
+            ClientEvent::StartupFailed {
+                route,
+                attempts,
+                reason,
+            } => gave_up = Some((route, attempts, reason)),
+            _ => {}
+        }
+    }
+    assert_eq!(session_failures, 2);
+    let (route, attempts, reason) = gave_up.expect("no StartupFailed event");
+    assert_eq!((route.as_str(), attempts), ("T789", 2));
+    assert!(!reason.is_empty());
+}
+
+#[tokio::test]
+async fn a_zero_budget_keeps_retrying() {
+    let url = unreachable_url().await;
+    let client = RapidbroClient::builder()
+        .kiosk_url(url.clone())
+        .socket_url(url)
+        .route("T789")
+        .startup_attempts(0)
+        .build()
+        .unwrap();
+    let runner = client.clone();
+    let run = tokio::spawn(async move { runner.run().await });
+
+    tokio::time::sleep(Duration::from_millis(1_500)).await;
+    assert!(!run.is_finished());
+    client.stop();
+    tokio::time::timeout(PROMPTLY, run).await.unwrap().unwrap();
+}
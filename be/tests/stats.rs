@@ -0,0 +1,57 @@
+mod common;
+
+use be::feed::BusPosition;
+use be::stats::ServiceStats;
+use common::ROUTE;
+
+// 2024-05-01T08:30:00Z
+const NOW_MS: i64 = 1_714_552_200_000;
+
+fn bus(bus_no: &str, timestamp_rfc3339: &str) -> BusPosition {
+    let mut bus = common::bus(bus_no, ROUTE);
+    bus.timestamp_rfc3339 = Some(timestamp_rfc3339.to_string());
+    bus
+}
+
+#[test]
+fn latency_percentiles_cover_the_recent_fixes() {
+    let mut stats = ServiceStats::new(300);
+    // Twenty vehicles whose fixes arrive 1..=20 seconds late.
+    for delay in 1..=20 {
+        let fix = chrono::DateTime::from_timestamp_millis(NOW_MS - delay * 1_000).unwrap();
+        stats.record(&bus(&format!("WXX{:04}", delay), &fix.to_rfc3339()), NOW_MS);
+    }
+
+    let route = &stats.summary()["T789"];
+    assert_eq!(route.latency_p50_seconds, Some(10.0));
+    assert_eq!(route.latency_p95_seconds, Some(19.0));
+    assert_eq!((route.skewed_records, route.clock_skew_seconds), (0, None));
+    assert!(route
+        .to_string()
+        .contains("latency_p50=10.0s latency_p95=19.0s"));
+}
+
+#[test]
+fn fixes_from_the_future_are_skew_not_latency() {
+    let mut stats = ServiceStats::new(300);
+    stats.record(&bus("WXX1234", "2024-05-01T08:29:55Z"), NOW_MS);
+    stats.record(&bus("WYY5678", "2024-05-01T08:30:04Z"), NOW_MS);
+    stats.record(&bus("WZZ9012", "2024-05-01T08:30:06Z"), NOW_MS);
+
+    let route = &stats.summary()["T789"];
+    assert_eq!(route.latency_p50_seconds, Some(5.0));
+    assert_eq!(route.latency_p95_seconds, Some(5.0));
+    assert_eq!(route.skewed_records, 2);
+    assert_eq!(route.clock_skew_seconds, Some(4.0));
+}
+
+#[test]
+fn a_replayed_fix_adds_no_latency_sample() {
+    let mut stats = ServiceStats::new(300);
+    stats.record(&bus("WXX1234", "2024-05-01T08:29:58Z"), NOW_MS);
+    stats.record(&bus("WXX1234", "2024-05-01T08:29:58Z"), NOW_MS + 60_000);
+
+    let route = &stats.summary()["T789"];
+    assert_eq!(route.duplicate_records, 1);
+    assert_eq!(route.latency_p95_seconds, Some(2.0));
+}
@@ -0,0 +1,76 @@
+use be::stop_index::{StopIndex, StopLocation};
+use be::validate::BoundingBox;
+
+fn stop(stop_id: &str, lat: f64, lon: f64) -> StopLocation {
+    StopLocation {
+        stop_id: stop_id.to_string(),
+        name: format!("Stop {}", stop_id),
+        lat,
+        lon,
+    }
+}
+
+fn klang_valley() -> Vec<StopLocation> {
+    vec![
+        stop("1000100", 3.0738, 101.5183),
+        stop("1002311", 3.1390, 101.6869),
+        stop("1002345", 3.1478, 101.6953),
+        stop("1002399", 3.1204, 101.6542),
+        stop("1004001", 3.2101, 101.7802),
+    ]
+}
+
+fn ids(stops: Vec<&StopLocation>) -> Vec<&str> {
+    stops.iter().map(|stop| stop.stop_id.as_str()).collect()
+}
+
+#[test]
+fn a_bbox_returns_only_the_stops_inside_it() {
+    let index = StopIndex::new(klang_valley());
+    let city_centre = BoundingBox::parse("101.68,3.13,101.70,3.15").unwrap();
+    assert_eq!(ids(index.within(&city_centre)), ["1002311", "1002345"]);
+}
+
+#[test]
+fn stops_on_the_edge_of_the_bbox_are_included() {
+    let index = StopIndex::new(klang_valley());
+    let exact = BoundingBox::parse("101.6953,3.1478,101.6953,3.1478").unwrap();
+    assert_eq!(ids(index.within(&exact)), ["1002345"]);
+}
+
+#[test]
+fn grid_lookups_match_a_full_scan() {
+    let stops: Vec<StopLocation> = (0..400)
+        .map(|i| {
+            stop(
+                &format!("S{:04}", i),
+                3.0 + (i % 20) as f64 * 0.013,
+                101.5 + (i / 20) as f64 * 0.017,
+            )
+        })
+        .collect();
+    let index = StopIndex::with_cell_size(stops.clone(), 0.01);
+    for bbox in [
+        "101.51,3.01,101.53,3.05",
+        "101.6,3.1,101.7,3.2",
+        "101.0,2.0,102.0,4.0",
+        "100.0,1.0,100.1,1.1",
+    ] {
+        let bbox = BoundingBox::parse(bbox).unwrap();
+        let expected: Vec<&str> = stops
+            .iter()
+            .filter(|stop| bbox.contains(stop.lat, stop.lon))
+            .map(|stop| stop.stop_id.as_str())
+            .collect();
+        assert_eq!(ids(index.within(&bbox)), expected);
+    }
+}
+
+#[test]
+fn stops_without_a_position_are_left_out() {
+    let mut stops = klang_valley();
+    stops.push(stop("1009999", f64::NAN, 101.7));
+    let index = StopIndex::new(stops);
+    assert_eq!(index.len(), 5);
+    assert!(index.all().iter().all(|stop| stop.stop_id != "1009999"));
+}
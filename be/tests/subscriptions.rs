@@ -0,0 +1,38 @@
+mod common;
+
+use be::subscriptions::{RouteChange, RouteControl};
+use common::ROUTE;
+
+#[test]
+fn routes_past_the_connection_limit_are_refused() {
+    let (control, mut changes) = RouteControl::new(&[ROUTE.to_string()], Some(2));
+    assert!(control.add("300"));
+    assert!(!control.add("302"));
+    assert_eq!(control.routes(), ["300", ROUTE]);
+
+    // Removing one makes room again.
+    assert!(control.remove("300"));
+    assert!(control.add("302"));
+
+    let mut seen = Vec::new();
+    while let Ok(change) = changes.try_recv() {
+        seen.push(change);
+    }
+    assert_eq!(
+        seen,
+        [
+            RouteChange::Add("300".to_string()),
+            RouteChange::Remove("300".to_string()),
+            RouteChange::Add("302".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn without_a_limit_any_number_of_routes_is_taken() {
+    let (control, _changes) = RouteControl::new(&[], None);
+    for route in 0..50 {
+        assert!(control.add(&route.to_string()));
+    }
+    assert_eq!(control.max_routes(), None);
+}
@@ -0,0 +1,77 @@
+use be::gtfs_rt::stretched_interval;
+use be::throttle::{
+    parse_retry_after, throttle_wait, DEFAULT_FORBIDDEN_WAIT, DEFAULT_RATE_LIMITED_WAIT,
+    MAX_THROTTLE_WAIT,
+};
+use chrono::{TimeZone, Utc};
+use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+use std::time::Duration;
+
+fn retry_after(value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+    headers
+}
+
+#[test]
+fn retry_after_accepts_seconds_and_http_dates() {
+    let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 27, 0).unwrap();
+    assert_eq!(
+        parse_retry_after("120", now),
+        Some(Duration::from_secs(120))
+    );
+    assert_eq!(
+        parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now),
+        Some(Duration::from_secs(60))
+    );
+    assert_eq!(
+        parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+        Some(Duration::ZERO)
+    );
+    assert_eq!(parse_retry_after("soon", now), None);
+}
+
+#[test]
+fn only_429_and_403_are_throttles() {
+    assert_eq!(throttle_wait(200, &HeaderMap::new()), None);
+    assert_eq!(throttle_wait(503, &retry_after("10")), None);
+    assert_eq!(
+        throttle_wait(429, &HeaderMap::new()),
+        Some(DEFAULT_RATE_LIMITED_WAIT)
+    );
+    assert_eq!(
+        throttle_wait(403, &HeaderMap::new()),
+        Some(DEFAULT_FORBIDDEN_WAIT)
+    );
+}
+
+#[test]
+fn retry_after_wins_over_the_default_but_is_capped() {
+    assert_eq!(
+        throttle_wait(429, &retry_after("5")),
+        Some(Duration::from_secs(5))
+    );
+    assert_eq!(
+        throttle_wait(429, &retry_after("86400")),
+        Some(MAX_THROTTLE_WAIT)
+    );
+    assert_eq!(
+        throttle_wait(429, &retry_after("garbage")),
+        Some(DEFAULT_RATE_LIMITED_WAIT)
+    );
+}
+
+#[test]
+fn poll_interval_stretches_on_repeated_throttles() {
+    let base = Duration::from_secs(20);
+    let first = stretched_interval(base, Duration::from_secs(5));
+    assert_eq!(first, Duration::from_secs(40));
+    assert_eq!(
+        stretched_interval(first, Duration::from_secs(120)),
+        Duration::from_secs(120)
+    );
+    assert_eq!(
+        stretched_interval(Duration::from_secs(600), Duration::ZERO),
+        MAX_THROTTLE_WAIT
+    );
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use be::client::{ClientEvent, RapidbroClient};
+use be::updates::{BusUpdate, BusUpdates};
+use common::{bus, ROUTE};
+use futures_util::StreamExt;
+use std::time::Duration;
+
+fn batch(bus_nos: &[&str]) -> ClientEvent {
+    ClientEvent::Buses {
+        buses: bus_nos.iter().map(|bus_no| bus(bus_no, ROUTE)).collect(),
+        decode_failures: 0,
+        received_at_unix_ms: 0,
+    }
+}
+
+async fn next(updates: &mut BusUpdates) -> Option<BusUpdate> {
+    tokio::time::timeout(Duration::from_secs(1), updates.next())
+        .await
+        .expect("stream stalled")
+}
+
+async fn next_bus_no(updates: &mut BusUpdates) -> String {
+    match next(updates).await {
+        Some(BusUpdate::Position(bus)) => bus.bus_no,
+        other => panic!("expected a position, got {:?}", other),
+    }
+}
+
+#[test]
+fn updates_can_move_into_spawned_tasks() {
+    fn assert_send_static<T: Send + 'static>() {}
+    assert_send_static::<BusUpdates>();
+}
+
+#[tokio::test]
+async fn batches_are_flattened_and_other_events_skipped() {
+    let client = RapidbroClient::builder().build().unwrap();
+    let mut updates = client.updates();
+    client.publish(ClientEvent::Connected);
+    client.publish(batch(&["WXX1234", "WYY5678"]));
+    client.publish(batch(&["WZZ9012"]));
+
+    assert_eq!(next_bus_no(&mut updates).await, "WXX1234");
+    assert_eq!(next_bus_no(&mut updates).await, "WYY5678");
+    assert_eq!(next_bus_no(&mut updates).await, "WZZ9012");
+}
+
+#[tokio::test]
+async fn clones_are_independent_streams() {
+    let client = RapidbroClient::builder().build().unwrap();
+    let mut first = client.updates();
+    client.publish(batch(&["WXX1234"]));
+    let mut second = first.clone();
+    client.publish(batch(&["WYY5678"]));
+
+    assert_eq!(next_bus_no(&mut first).await, "WXX1234");
+    assert_eq!(next_bus_no(&mut first).await, "WYY5678");
+    // Only what was published after the clone was made.
+    assert_eq!(next_bus_no(&mut second).await, "WYY5678");
+}
+
+#[tokio::test]
+async fn falling_behind_yields_a_gap() {
+    let client = RapidbroClient::builder().build().unwrap();
+    let mut updates = client.updates();
+    for index in 0..300 {
+        client.publish(batch(&[&format!("W{}", index)]));
+    }
+    match next(&mut updates).await {
+        Some(BusUpdate::Gap { missed }) => assert!(missed > 0),
+        other => panic!("expected a gap, got {:?}", other),
+    }
+    // The oldest batches are the ones dropped.
+    assert_ne!(next_bus_no(&mut updates).await, "W0");
+}
+
+#[tokio::test]
+async fn stopping_the_client_ends_the_stream_after_what_was_published() {
+    let client = RapidbroClient::builder().build().unwrap();
+    let mut updates = client.updates();
+    client.publish(batch(&["WXX1234"]));
+    client.stop();
+
+    assert_eq!(next_bus_no(&mut updates).await, "WXX1234");
+    assert!(next(&mut updates).await.is_none());
+}
+
+#[tokio::test]
+async fn filter_picks_out_one_vehicle() {
+    let client = RapidbroClient::builder().build().unwrap();
+    let updates = client.updates();
+    let task = tokio::spawn(async move {
+        updates
+            .filter(|update| {
+                std::future::ready(update.position().is_some_and(|bus| bus.bus_no == "WYY5678"))
+            })
+            .take(2)
+            .collect::<Vec<_>>()
+            .await
+    });
+    client.publish(batch(&["WXX1234", "WYY5678"]));
+    client.publish(batch(&["WYY5678", "WZZ9012"]));
+
+    let seen = tokio::time::timeout(Duration::from_secs(1), task)
+        .await
+        .expect("filter stalled")
+        .unwrap();
+    assert_eq!(seen.len(), 2);
+}
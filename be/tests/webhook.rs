@@ -0,0 +1,94 @@
+#![cfg(feature = "webhook")]
+
+mod common;
+
+use be::webhook::{sign_payload, WebhookConfig, WebhookEvent, WebhookSink, SIGNATURE_HEADER};
+use common::{bus, ROUTE};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+
+// Accepts one POST, answers 200 and hands back its headers (names lowercased) and body.
+async fn receive_one(listener: TcpListener) -> (Vec<(String, String)>, Vec<u8>) {
+    let (mut connection, _) = listener.accept().await.unwrap();
+    let mut request = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        let read = connection.read(&mut chunk).await.unwrap();
+        assert!(read > 0, "connection closed before the headers ended");
+        request.extend_from_slice(&chunk[..read]);
+        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+
+    let head = String::from_utf8(request[..header_end].to_vec()).unwrap();
+    let headers: Vec<(String, String)> = head
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+    let length: usize = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .map(|(_, value)| value.parse().unwrap())
+        .unwrap();
+    while request.len() < header_end + length {
+        let read = connection.read(&mut chunk).await.unwrap();
+        assert!(read > 0, "connection closed before the body ended");
+        request.extend_from_slice(&chunk[..read]);
+    }
+
+    connection
+        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    (headers, request[header_end..header_end + length].to_vec())
+}
+
+#[test]
+fn signatures_are_the_hex_hmac_sha256_of_the_body() {
+    // RFC 4231, test case 2.
+    assert_eq!(
+        sign_payload("Jefe", b"what do ya want for nothing?"),
+        "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+    assert_ne!(
+        sign_payload("Jefe", b"what do ya want for nothing?"),
+        sign_payload("jefe", b"what do ya want for nothing?")
+    );
+}
+
+#[tokio::test]
+async fn a_delivered_batch_carries_the_signature_of_its_body() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut config = WebhookConfig::new(format!("http://{}/hook", listener.local_addr().unwrap()));
+    config.secret = Some("s3cret".to_string());
+    config.max_delay = Duration::from_millis(10);
+
+    let (received_tx, received_rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let _ = received_tx.send(receive_one(listener).await);
+    });
+    let sink = WebhookSink::new(config);
+    let runner = sink.clone();
+    tokio::spawn(async move { runner.run().await });
+    sink.enqueue(WebhookEvent::Position(Box::new(bus("WXX1234", ROUTE))))
+        .await;
+
+    let (headers, body) = tokio::time::timeout(Duration::from_secs(5), received_rx)
+        .await
+        .expect("nothing was delivered")
+        .unwrap();
+    let signature = headers
+        .iter()
+        .find(|(name, _)| *name == SIGNATURE_HEADER.to_lowercase())
+        .map(|(_, value)| value.as_str());
+    assert_eq!(signature, Some(sign_payload("s3cret", &body).as_str()));
+
+    let events: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(events[0]["type"], "position");
+    assert_eq!(events[0]["bus_no"], "WXX1234");
+}